@@ -1,27 +1,51 @@
-use crate::config::{CliEngine, ResolvedTlsConfig};
+use crate::config::{CaBundle, CliEngine, NetworkConfig, ReasoningStyle, ResolvedTlsConfig};
+use crate::formatting::stdout_closed;
+use crate::{debug_log, logging};
 use anyhow::{Context, Result, anyhow};
 use bytes::Bytes;
+use flate2::Compression;
+use flate2::write::GzEncoder;
 use fs_err as fs;
 use futures_util::StreamExt;
-use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
-use reqwest::{Certificate, Client, RequestBuilder};
+use reqwest::header::{ACCEPT, CONTENT_ENCODING, CONTENT_TYPE, HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Certificate, Client, RequestBuilder, StatusCode};
 use rustls_pemfile::certs;
 use serde::Deserialize;
-use serde_json::{Value, json};
-use std::collections::HashMap;
-use std::io::Cursor;
+use serde_json::{Map, Value, json};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Cursor, Write};
 use std::path::Path;
-use std::time::Duration;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
 
 const DEFAULT_MAX_COMPLETION_TOKENS: u32 = 800;
 pub const DEFAULT_REQUEST_TIMEOUT_SECS: u64 = 180;
-const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 10;
+pub const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 10;
 const DEFAULT_TEMPERATURE: f32 = 0.15;
 
 /// Minimal OpenAI-compatible chat streaming delta payload
 #[derive(Debug, Deserialize)]
 struct ChatStreamChunkChoiceDelta {
     content: Option<String>,
+    /// Reasoning text as OpenRouter (`reasoning`) or DeepSeek-style APIs (`reasoning_content`) send it.
+    #[serde(alias = "reasoning_content")]
+    reasoning: Option<String>,
+    #[serde(default)]
+    tool_calls: Option<Vec<ToolCallChunk>>,
+}
+
+/// One fragment of a streamed tool call; `arguments` arrive split across chunks.
+#[derive(Debug, Deserialize)]
+struct ToolCallChunk {
+    #[serde(default)]
+    index: u32,
+    function: Option<ToolFunctionChunk>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ToolFunctionChunk {
+    name: Option<String>,
+    arguments: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -29,11 +53,96 @@ struct ChatStreamChunkChoice {
     delta: Option<ChatStreamChunkChoiceDelta>,
     #[allow(dead_code)]
     index: Option<u32>,
+    finish_reason: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
 struct ChatStreamChunk {
+    #[serde(default)]
     choices: Vec<ChatStreamChunkChoice>,
+    usage: Option<Usage>,
+}
+
+/// One piece of a streamed chat completion, as passed to [`ChatClient::chat_stream_events`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamEvent {
+    /// A delta of answer text.
+    Token(String),
+    /// A delta of reasoning text, for providers that stream it separately from the answer.
+    Reasoning(String),
+    /// Token counts, when the provider includes them in the stream (OpenAI only does with
+    /// `stream_options.include_usage`).
+    Usage(Usage),
+    /// A fragment of a streamed tool call. Fragments with the same `index` belong to one
+    /// call; [`ToolCallAccumulator`] joins them.
+    ToolCall {
+        index: u32,
+        name: Option<String>,
+        arguments: String,
+    },
+    /// The stream finished; always the last event of a successful call.
+    Done { finish_reason: Option<String> },
+}
+
+/// Rebuilds streamed tool calls from [`StreamEvent::ToolCall`] fragments by concatenating
+/// each call's name and argument text in arrival order.
+#[derive(Debug, Default)]
+pub struct ToolCallAccumulator {
+    content: String,
+    calls: BTreeMap<u32, (String, String)>,
+    usage: Option<Usage>,
+}
+
+impl ToolCallAccumulator {
+    pub fn push(&mut self, event: &StreamEvent) {
+        match event {
+            StreamEvent::Token(token) => self.content.push_str(token),
+            StreamEvent::ToolCall {
+                index,
+                name,
+                arguments,
+            } => {
+                let (call_name, call_args) = self.calls.entry(*index).or_default();
+                if let Some(name) = name {
+                    call_name.push_str(name);
+                }
+                call_args.push_str(arguments);
+            }
+            StreamEvent::Usage(usage) => self.usage = Some(usage.clone()),
+            _ => {}
+        }
+    }
+
+    /// The first named call (lowest index) with any streamed text as its side content,
+    /// or the text alone when no call arrived.
+    pub fn finish(self) -> AssistantReply {
+        self.finish_with_usage().0
+    }
+
+    /// [`ToolCallAccumulator::finish`] plus the usage the stream reported, if any.
+    pub fn finish_with_usage(self) -> (AssistantReply, Option<Usage>) {
+        let usage = self.usage;
+        let Some((name, arguments_json)) =
+            self.calls.into_values().find(|(name, _)| !name.is_empty())
+        else {
+            return (AssistantReply::Content(self.content), usage);
+        };
+        let reply = AssistantReply::ToolCall {
+            name,
+            arguments_json,
+            content: (!self.content.trim().is_empty()).then_some(self.content),
+        };
+        (reply, usage)
+    }
+}
+
+/// Pass only [`StreamEvent::Token`] text on to a plain token callback.
+fn token_sink<F: FnMut(&str)>(on_token: &mut F) -> impl FnMut(StreamEvent) + '_ {
+    move |event| {
+        if let StreamEvent::Token(token) = event {
+            on_token(&token);
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -87,15 +196,75 @@ struct ChatResponseWithTools {
     choices: Vec<ChatChoiceWithTools>,
 }
 
+/// Token counts a provider reports for one completion; fields it leaves out stay `None`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct Usage {
+    pub prompt_tokens: Option<u64>,
+    pub completion_tokens: Option<u64>,
+    pub total_tokens: Option<u64>,
+}
+
+impl Usage {
+    /// `total_tokens`, or prompt plus completion tokens when only those are reported.
+    pub fn total(&self) -> Option<u64> {
+        self.total_tokens
+            .or(match (self.prompt_tokens, self.completion_tokens) {
+                (None, None) => None,
+                (prompt, completion) => Some(prompt.unwrap_or(0) + completion.unwrap_or(0)),
+            })
+    }
+}
+
 /// A simplified representation of the assistant's first choice.
 pub enum AssistantReply {
     Content(String),
     ToolCall {
         name: String,
         arguments_json: String,
+        /// Any explanatory text the model sent alongside the call.
+        content: Option<String>,
     },
 }
 
+/// Result of `ChatClient::ping`.
+#[derive(Debug)]
+pub struct PingReport {
+    pub status: StatusCode,
+    pub elapsed: Duration,
+    /// Raw response body, useful for explaining a non-2xx status.
+    pub body: String,
+}
+
+/// A non-2xx chat response; displays as `API error (<status>): <body>`.
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    message: String,
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+/// Whether `err` is the provider's fault rather than the request's: rejected credentials
+/// (401/403), a timeout or rate limit (408/429), a server error (5xx), or a connection
+/// that failed or timed out. qq moves on to `fallback_profiles` only for these.
+pub fn is_provider_failure(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        if let Some(api) = cause.downcast_ref::<ApiError>() {
+            return matches!(api.status.as_u16(), 401 | 403 | 408 | 429)
+                || api.status.is_server_error();
+        }
+        cause
+            .downcast_ref::<reqwest::Error>()
+            .is_some_and(|e| e.is_timeout() || e.is_connect())
+    })
+}
+
 #[derive(Clone, Copy)]
 enum TemperatureDirective {
     Omit,
@@ -107,9 +276,36 @@ pub struct ChatClient {
     base_url: String,
     api_key: String,
     reasoning_effort: Option<String>,
+    reasoning_style: ReasoningStyle,
     temperature_override: Option<f32>,
-    temperature_user_override: bool,
+    frequency_penalty: Option<f32>,
+    presence_penalty: Option<f32>,
+    extra_body: Map<String, Value>,
+    request_params: Map<String, Value>,
     default_headers: HeaderMap,
+    min_request_interval: Duration,
+    max_retries: u32,
+    plain_text_responses: bool,
+    stream_tool_calls: bool,
+    compress_request: bool,
+}
+
+/// When the most recently scheduled request (from any client in this process) may start.
+fn last_request_slot() -> &'static Mutex<Option<Instant>> {
+    static SLOT: OnceLock<Mutex<Option<Instant>>> = OnceLock::new();
+    SLOT.get_or_init(|| Mutex::new(None))
+}
+
+/// Reserve the next start time at least `interval` after the previous reservation.
+/// Reserving under the lock keeps concurrent callers (e.g. batch mode) evenly spaced.
+fn reserve_request_slot(interval: Duration, now: Instant) -> Instant {
+    let mut last = last_request_slot().lock().unwrap();
+    let start = match *last {
+        Some(prev) => now.max(prev + interval),
+        None => now,
+    };
+    *last = Some(start);
+    start
 }
 
 impl ChatClient {
@@ -119,16 +315,65 @@ impl ChatClient {
         headers: HashMap<String, String>,
         tls: Option<&ResolvedTlsConfig>,
         request_timeout: Option<Duration>,
+    ) -> Result<Self> {
+        Self::with_network(
+            base_url,
+            api_key,
+            headers,
+            tls,
+            request_timeout,
+            &NetworkConfig::default(),
+        )
+    }
+
+    /// Like [`ChatClient::new`], with connection pooling, keep-alive, and HTTP/2 tuning.
+    /// Fields left as `None` keep reqwest's defaults, so reuse across calls on the
+    /// same client works out of the box.
+    pub fn with_network(
+        base_url: String,
+        api_key: String,
+        headers: HashMap<String, String>,
+        tls: Option<&ResolvedTlsConfig>,
+        request_timeout: Option<Duration>,
+        network: &NetworkConfig,
     ) -> Result<Self> {
         // Use rustls for TLS; set useful timeouts for robustness.
         let timeout = request_timeout.unwrap_or(Duration::from_secs(DEFAULT_REQUEST_TIMEOUT_SECS));
         let mut builder = Client::builder()
             .timeout(timeout)
-            .connect_timeout(Duration::from_secs(DEFAULT_CONNECT_TIMEOUT_SECS));
+            .connect_timeout(Duration::from_secs(
+                network
+                    .connect_timeout_secs
+                    .unwrap_or(DEFAULT_CONNECT_TIMEOUT_SECS),
+            ));
+        if let Some(secs) = network.pool_idle_timeout_secs {
+            builder = builder.pool_idle_timeout(Duration::from_secs(secs));
+        }
+        if let Some(max) = network.pool_max_idle_per_host {
+            builder = builder.pool_max_idle_per_host(max);
+        }
+        if let Some(secs) = network.tcp_keepalive_secs {
+            builder = builder.tcp_keepalive(Duration::from_secs(secs));
+        }
+        if network.http2_prior_knowledge == Some(true) {
+            builder = builder.http2_prior_knowledge();
+        }
         if let Some(tls_cfg) = tls {
-            for cert in load_root_certificates(&tls_cfg.ca_bundle_path)? {
+            let certs = match &tls_cfg.ca_bundle {
+                Some(CaBundle::Path(path)) => load_root_certificates(path)?,
+                Some(CaBundle::Pem(pem)) => parse_root_certificates(pem.as_bytes(), "inline PEM")?,
+                None => Vec::new(),
+            };
+            for cert in certs {
                 builder = builder.add_root_certificate(cert);
             }
+            if tls_cfg.danger_accept_invalid_certs {
+                eprintln!(
+                    "[warn] TLS certificate verification is DISABLED for {} (danger_accept_invalid_certs). Anyone on the network can intercept this traffic; use only for local development.",
+                    base_url
+                );
+                builder = builder.danger_accept_invalid_certs(true);
+            }
         }
         let client = builder.build()?;
         let mut default_headers = HeaderMap::new();
@@ -144,9 +389,20 @@ impl ChatClient {
             base_url,
             api_key,
             reasoning_effort: None,
+            reasoning_style: ReasoningStyle::Flat,
             temperature_override: None,
-            temperature_user_override: false,
+            frequency_penalty: None,
+            presence_penalty: None,
+            extra_body: Map::new(),
+            request_params: Map::new(),
             default_headers,
+            min_request_interval: Duration::from_millis(
+                network.min_request_interval_ms.unwrap_or(0),
+            ),
+            max_retries: network.max_retries.unwrap_or(0),
+            plain_text_responses: false,
+            stream_tool_calls: false,
+            compress_request: false,
         })
     }
 
@@ -155,18 +411,239 @@ impl ChatClient {
         self
     }
 
-    pub fn with_temperature(mut self, temperature: Option<f32>, user_provided: bool) -> Self {
+    pub fn with_reasoning_style(mut self, reasoning_style: ReasoningStyle) -> Self {
+        self.reasoning_style = reasoning_style;
+        self
+    }
+
+    /// `_user_provided` no longer changes anything: the warning for a temperature a model
+    /// ignores now comes from `run::ignored_temperature_warning`.
+    pub fn with_temperature(mut self, temperature: Option<f32>, _user_provided: bool) -> Self {
         self.temperature_override = temperature;
-        self.temperature_user_override = user_provided && temperature.is_some();
         self
     }
 
+    /// Frequency/presence penalties; omitted for gpt-5/o1/o3 models, which reject them.
+    pub fn with_penalties(
+        mut self,
+        frequency_penalty: Option<f32>,
+        presence_penalty: Option<f32>,
+    ) -> Self {
+        self.frequency_penalty = frequency_penalty;
+        self.presence_penalty = presence_penalty;
+        self
+    }
+
+    /// Extra profile-level body fields; they never replace keys qqqa sets itself.
+    pub fn with_extra_body(mut self, extra_body: Option<Map<String, Value>>) -> Self {
+        self.extra_body = extra_body.unwrap_or_default();
+        self
+    }
+
+    /// Per-run `--param` body fields. Unlike `extra_body` they replace what qqqa sets, except
+    /// the [`PROTECTED_BODY_KEYS`].
+    pub fn with_request_params(mut self, params: Map<String, Value>) -> Self {
+        self.request_params = params;
+        self
+    }
+
+    /// Treat a successful response that is neither JSON nor SSE as the whole answer text
+    /// (the provider's `plain_text_responses`).
+    pub fn with_plain_text_responses(mut self, enabled: bool) -> Self {
+        self.plain_text_responses = enabled;
+        self
+    }
+
+    /// Send tool-call requests through [`ChatClient::chat_stream_messages_with_tools`]
+    /// (the provider's `stream_tool_calls`).
+    pub fn with_stream_tool_calls(mut self, enabled: bool) -> Self {
+        self.stream_tool_calls = enabled;
+        self
+    }
+
+    /// gzip chat request bodies (the provider's `compress_request`). Pings stay plain.
+    pub fn with_compress_request(mut self, enabled: bool) -> Self {
+        self.compress_request = enabled;
+        self
+    }
+
+    /// Whether a 2xx `resp` should be read as plain answer text rather than JSON or SSE.
+    fn expects_plain_text(&self, resp: &reqwest::Response) -> bool {
+        self.plain_text_responses
+            && !resp
+                .headers()
+                .get(CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .is_some_and(|ct| ct.contains("json") || ct.contains("event-stream"))
+    }
+
+    /// POST a non-streaming chat body and return the raw JSON text of a 2xx response.
+    /// With `QQQA_MOCK_RESPONSE_FILE` set, the file stands in for the response.
+    async fn post_chat(&self, body: &Value, debug: bool) -> Result<String> {
+        let url = self.chat_url();
+        logging::traced("http", &url, async {
+            if let Some(canned) = mock_response(debug)? {
+                return Ok(mock_completion_json(&canned, debug));
+            }
+            self.wait_for_request_slot().await;
+            let resp = self
+                .chat_body(self.request_builder(), body, debug)?
+                .send()
+                .await
+                .with_context(|| "HTTP request failed")?;
+            let status = resp.status();
+            let plain = self.expects_plain_text(&resp);
+            let text = resp.text().await?;
+            if !status.is_success() {
+                return Err(api_error(&self.base_url, status, &text));
+            }
+            if plain && serde_json::from_str::<Value>(&text).is_err() {
+                debug_log!(debug, "non-JSON response; using the body as the answer");
+                return Ok(completion_json(text.trim_end_matches(['\r', '\n'])));
+            }
+            Ok(text)
+        })
+        .await
+    }
+
+    /// POST a streaming chat body and feed each event to `on_event`, ending with
+    /// [`StreamEvent::Done`]. A stream that ends without any content (a transient provider
+    /// glitch) is retried once without streaming when `max_retries` is above 0.
+    async fn post_chat_stream<F>(&self, body: &Value, debug: bool, on_event: &mut F) -> Result<()>
+    where
+        F: FnMut(StreamEvent),
+    {
+        let mut produced = false;
+        let mut finish_reason = None;
+        let mut tracking = |event: StreamEvent| match event {
+            // Providers may repeat finish_reason or send usage after it, so Done waits for the end.
+            StreamEvent::Done { finish_reason: r } => finish_reason = r.or(finish_reason.take()),
+            event => {
+                produced |= matches!(&event, StreamEvent::Token(t) if !t.is_empty());
+                on_event(event);
+            }
+        };
+        self.read_chat_stream(body, debug, &mut tracking).await?;
+        if produced || self.max_retries == 0 || stdout_closed() {
+            on_event(StreamEvent::Done { finish_reason });
+            return Ok(());
+        }
+        debug_log!(
+            debug,
+            "stream ended without content; retrying once without streaming"
+        );
+        let mut retry = body.clone();
+        retry["stream"] = json!(false);
+        let content = first_choice_content(&self.post_chat(&retry, debug).await?)?;
+        if !content.is_empty() {
+            on_event(StreamEvent::Token(content));
+        }
+        on_event(StreamEvent::Done {
+            finish_reason: None,
+        });
+        Ok(())
+    }
+
+    async fn read_chat_stream<F>(&self, body: &Value, debug: bool, on_event: &mut F) -> Result<()>
+    where
+        F: FnMut(StreamEvent),
+    {
+        let url = self.chat_url();
+        logging::traced("http_stream", &url, async {
+            if let Some(canned) = mock_response(debug)? {
+                replay_mock_stream(&canned, debug, on_event)?;
+                return Ok(());
+            }
+            self.wait_for_request_slot().await;
+            let builder = self.request_builder().header(ACCEPT, "text/event-stream");
+            let resp = self
+                .chat_body(builder, body, debug)?
+                .send()
+                .await
+                .with_context(|| "HTTP request failed")?;
+
+            if !resp.status().is_success() {
+                let status = resp.status();
+                let text = resp.text().await.unwrap_or_default();
+                return Err(api_error(&self.base_url, status, &text));
+            }
+            if self.expects_plain_text(&resp) {
+                // Same handling as a canned file: SSE lines replay, anything else is one token.
+                let text = resp.text().await?;
+                replay_mock_stream(&text, debug, on_event)?;
+                return Ok(());
+            }
+
+            // The OpenAI-compatible API returns text/event-stream with lines prefixed by "data:".
+            // We read chunks and split by newlines; we accumulate and parse JSON lines.
+            let mut stream = resp.bytes_stream();
+            let mut buffer = Vec::<u8>::new();
+            while let Some(item) = stream.next().await {
+                let chunk: Bytes = item?;
+                buffer.extend_from_slice(&chunk);
+                if drain_sse_lines(&mut buffer, debug, on_event) {
+                    return Ok(());
+                }
+                // Dropping the response closes the connection, so no more tokens are generated.
+                if stdout_closed() {
+                    debug_log!(debug, "stdout closed (broken pipe); stopping the stream");
+                    return Ok(());
+                }
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// Sleep until this request's slot when `min_request_interval_ms` is set.
+    async fn wait_for_request_slot(&self) {
+        if self.min_request_interval.is_zero() {
+            return;
+        }
+        let start = reserve_request_slot(self.min_request_interval, Instant::now());
+        tokio::time::sleep_until(start.into()).await;
+    }
+
     fn request_builder(&self) -> RequestBuilder {
         let mut builder = self.client.post(self.chat_url());
         if !self.default_headers.is_empty() {
             builder = builder.headers(self.default_headers.clone());
         }
-        builder.bearer_auth(&self.api_key)
+        // An explicit Authorization header (e.g. from `--header`) replaces the bearer key.
+        if self
+            .default_headers
+            .contains_key(reqwest::header::AUTHORIZATION)
+        {
+            builder
+        } else {
+            builder.bearer_auth(&self.api_key)
+        }
+    }
+
+    /// Attach `body` as JSON, gzip-encoded when `compress_request` is set.
+    fn chat_body(
+        &self,
+        builder: RequestBuilder,
+        body: &Value,
+        debug: bool,
+    ) -> Result<RequestBuilder> {
+        if !self.compress_request {
+            return Ok(builder.json(body));
+        }
+        let json = serde_json::to_vec(body)?;
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&json)?;
+        let gz = encoder.finish()?;
+        debug_log!(
+            debug,
+            "gzip request body: {} -> {} bytes",
+            json.len(),
+            gz.len()
+        );
+        Ok(builder
+            .header(CONTENT_TYPE, "application/json")
+            .header(CONTENT_ENCODING, "gzip")
+            .body(gz))
     }
 
     fn is_new_style_model(model: &str) -> bool {
@@ -175,7 +652,7 @@ impl ChatClient {
         PREFIXES.iter().any(|prefix| lower.starts_with(prefix))
     }
 
-    fn is_gpt5_model(model: &str) -> bool {
+    pub fn is_gpt5_model(model: &str) -> bool {
         model.to_ascii_lowercase().starts_with("gpt-5")
     }
 
@@ -220,15 +697,15 @@ impl ChatClient {
                     obj.remove("temperature");
                 }
             }
-            if debug
-                && Self::is_gpt5_model(model)
-                && self.temperature_user_override
-                && self.temperature_override.is_some()
-            {
-                eprintln!(
-                    "[warn] Overriding requested temperature to 1.0 for GPT-5 model '{}'.",
-                    model
-                );
+            obj.remove("frequency_penalty");
+            obj.remove("presence_penalty");
+            if !Self::is_new_style_model(model) {
+                if let Some(penalty) = self.frequency_penalty {
+                    obj.insert("frequency_penalty".into(), json!(penalty));
+                }
+                if let Some(penalty) = self.presence_penalty {
+                    obj.insert("presence_penalty".into(), json!(penalty));
+                }
             }
             let reasoning = if Self::default_reasoning_effort(model).is_some() {
                 self.reasoning_effort
@@ -237,10 +714,30 @@ impl ChatClient {
             } else {
                 None
             };
+            obj.remove("reasoning_effort");
+            obj.remove("reasoning");
             if let Some(effort) = reasoning {
-                obj.insert("reasoning_effort".into(), json!(effort));
-            } else {
-                obj.remove("reasoning_effort");
+                match self.reasoning_style {
+                    ReasoningStyle::Flat => {
+                        obj.insert("reasoning_effort".into(), json!(effort));
+                    }
+                    ReasoningStyle::Object => {
+                        obj.insert("reasoning".into(), json!({ "effort": effort }));
+                    }
+                }
+            }
+            for (key, value) in &self.extra_body {
+                if obj.contains_key(key) {
+                    debug_log!(debug, "extra_body: ignoring '{}' (already set)", key);
+                    continue;
+                }
+                obj.insert(key.clone(), value.clone());
+            }
+            for (key, value) in &self.request_params {
+                if PROTECTED_BODY_KEYS.contains(&key.as_str()) {
+                    continue;
+                }
+                obj.insert(key.clone(), value.clone());
             }
         }
     }
@@ -249,19 +746,26 @@ impl ChatClient {
         format!("{}/chat/completions", self.base_url.trim_end_matches('/'))
     }
 
-    /// Non-streaming chat completion: returns the full assistant message.
-    pub async fn chat_once(&self, model: &str, prompt: &str, debug: bool) -> Result<String> {
+    /// Send a minimal 1-token request and report the HTTP status and round-trip time.
+    /// Non-2xx responses are returned as a report, not an error; transport failures are errors.
+    pub async fn ping(&self, model: &str, debug: bool) -> Result<PingReport> {
         let mut body = json!({
             "model": model,
             "messages": [
-                {"role": "user", "content": prompt}
+                {"role": "user", "content": "ping"}
             ]
         });
-        self.apply_model_defaults(&mut body, model, DEFAULT_MAX_COMPLETION_TOKENS, debug);
-        if debug {
-            let bytes = serde_json::to_vec(&body).unwrap();
-            eprintln!("[debug] POST {} ({} bytes)", self.chat_url(), bytes.len());
+        self.apply_model_defaults(&mut body, model, 1, debug);
+        debug_log!(debug, "POST {} (ping)", self.chat_url());
+        if let Some(canned) = mock_response(debug)? {
+            return Ok(PingReport {
+                status: StatusCode::OK,
+                elapsed: Duration::ZERO,
+                body: canned,
+            });
         }
+        self.wait_for_request_slot().await;
+        let started = Instant::now();
         let resp = self
             .request_builder()
             .json(&body)
@@ -269,18 +773,30 @@ impl ChatClient {
             .await
             .with_context(|| "HTTP request failed")?;
         let status = resp.status();
-        let text = resp.text().await?;
-        if !status.is_success() {
-            return Err(anyhow!("API error ({}): {}", status, text));
-        }
-        let parsed: ChatResponse = serde_json::from_str(&text)
-            .with_context(|| format!("Failed to parse chat response JSON: {}", text))?;
-        let choice = parsed
-            .choices
-            .into_iter()
-            .next()
-            .ok_or_else(|| anyhow!("No choices in response"))?;
-        Ok(choice.message.content)
+        let body = resp.text().await.unwrap_or_default();
+        Ok(PingReport {
+            status,
+            elapsed: started.elapsed(),
+            body,
+        })
+    }
+
+    /// Non-streaming chat completion: returns the full assistant message.
+    pub async fn chat_once(&self, model: &str, prompt: &str, debug: bool) -> Result<String> {
+        let mut body = json!({
+            "model": model,
+            "messages": [
+                {"role": "user", "content": prompt}
+            ]
+        });
+        self.apply_model_defaults(&mut body, model, DEFAULT_MAX_COMPLETION_TOKENS, debug);
+        debug_log!(
+            debug,
+            "POST {} ({} bytes)",
+            self.chat_url(),
+            JsonSize(&body)
+        );
+        first_choice_content(&self.post_chat(&body, debug).await?)
     }
 
     /// Non-streaming chat completion using explicit messages.
@@ -290,34 +806,32 @@ impl ChatClient {
         messages: &[Msg<'_>],
         debug: bool,
     ) -> Result<String> {
+        Ok(self
+            .chat_once_messages_with_usage(model, messages, debug)
+            .await?
+            .0)
+    }
+
+    /// [`ChatClient::chat_once_messages`] plus the response's `usage`, when reported.
+    pub async fn chat_once_messages_with_usage(
+        &self,
+        model: &str,
+        messages: &[Msg<'_>],
+        debug: bool,
+    ) -> Result<(String, Option<Usage>)> {
         let mut body = json!({
             "model": model,
             "messages": messages
         });
         self.apply_model_defaults(&mut body, model, DEFAULT_MAX_COMPLETION_TOKENS, debug);
-        if debug {
-            let bytes = serde_json::to_vec(&body).unwrap();
-            eprintln!("[debug] POST {} ({} bytes)", self.chat_url(), bytes.len());
-        }
-        let resp = self
-            .request_builder()
-            .json(&body)
-            .send()
-            .await
-            .with_context(|| "HTTP request failed")?;
-        let status = resp.status();
-        let text = resp.text().await?;
-        if !status.is_success() {
-            return Err(anyhow!("API error ({}): {}", status, text));
-        }
-        let parsed: ChatResponse = serde_json::from_str(&text)
-            .with_context(|| format!("Failed to parse chat response JSON: {}", text))?;
-        let choice = parsed
-            .choices
-            .into_iter()
-            .next()
-            .ok_or_else(|| anyhow!("No choices in response"))?;
-        Ok(choice.message.content)
+        debug_log!(
+            debug,
+            "POST {} ({} bytes)",
+            self.chat_url(),
+            JsonSize(&body)
+        );
+        let text = self.post_chat(&body, debug).await?;
+        Ok((first_choice_content(&text)?, response_usage(&text)))
     }
 
     /// Non-streaming chat completion allowing tool specs; returns either content or a tool call.
@@ -328,27 +842,40 @@ impl ChatClient {
         tools: serde_json::Value,
         debug: bool,
     ) -> Result<AssistantReply> {
+        Ok(self
+            .chat_once_with_tools_and_usage(model, messages, tools, debug)
+            .await?
+            .0)
+    }
+
+    /// [`ChatClient::chat_once_messages_with_tools`] plus the response's `usage`, when
+    /// reported (streamed tool calls included).
+    pub async fn chat_once_with_tools_and_usage(
+        &self,
+        model: &str,
+        messages: &[Msg<'_>],
+        tools: serde_json::Value,
+        debug: bool,
+    ) -> Result<(AssistantReply, Option<Usage>)> {
+        if self.stream_tool_calls {
+            return self
+                .stream_tool_request(model, messages, tools, debug)
+                .await;
+        }
         let mut body = json!({
             "model": model,
             "messages": messages,
             "tools": tools
         });
         self.apply_model_defaults(&mut body, model, DEFAULT_MAX_COMPLETION_TOKENS, debug);
-        if debug {
-            let bytes = serde_json::to_vec(&body).unwrap();
-            eprintln!("[debug] POST {} ({} bytes)", self.chat_url(), bytes.len());
-        }
-        let resp = self
-            .request_builder()
-            .json(&body)
-            .send()
-            .await
-            .with_context(|| "HTTP request failed")?;
-        let status = resp.status();
-        let text = resp.text().await?;
-        if !status.is_success() {
-            return Err(anyhow!("API error ({}): {}", status, text));
-        }
+        debug_log!(
+            debug,
+            "POST {} ({} bytes)",
+            self.chat_url(),
+            JsonSize(&body)
+        );
+        let text = self.post_chat(&body, debug).await?;
+        let usage = response_usage(&text);
 
         // Try to parse as tool-aware response first
         let parsed_tools: ChatResponseWithTools = serde_json::from_str(&text)
@@ -359,21 +886,26 @@ impl ChatClient {
             .next()
             .ok_or_else(|| anyhow!("No choices in response"))?;
 
+        let content = choice.message.content.unwrap_or_default();
+        let side_text = (!content.trim().is_empty()).then(|| content.clone());
         if let Some(calls) = choice.message.tool_calls {
             if let Some(first) = calls.into_iter().next() {
-                return Ok(AssistantReply::ToolCall {
+                let reply = AssistantReply::ToolCall {
                     name: first.function.name,
                     arguments_json: first.function.arguments,
-                });
+                    content: side_text,
+                };
+                return Ok((reply, usage));
             }
         } else if let Some(func) = choice.message.function_call {
-            return Ok(AssistantReply::ToolCall {
+            let reply = AssistantReply::ToolCall {
                 name: func.name,
                 arguments_json: func.arguments,
-            });
+                content: side_text,
+            };
+            return Ok((reply, usage));
         }
-        let content = choice.message.content.unwrap_or_default();
-        Ok(AssistantReply::Content(content))
+        Ok((AssistantReply::Content(content), usage))
     }
 
     /// Streaming chat completion. Calls `on_token` for each token/delta of content.
@@ -395,67 +927,14 @@ impl ChatClient {
             "stream": true
         });
         self.apply_model_defaults(&mut body, model, DEFAULT_MAX_COMPLETION_TOKENS, debug);
-        if debug {
-            let bytes = serde_json::to_vec(&body).unwrap();
-            eprintln!(
-                "[debug] POST {} ({} bytes, stream)",
-                self.chat_url(),
-                bytes.len()
-            );
-        }
-        let resp = self
-            .request_builder()
-            .json(&body)
-            .send()
+        debug_log!(
+            debug,
+            "POST {} ({} bytes, stream)",
+            self.chat_url(),
+            JsonSize(&body)
+        );
+        self.post_chat_stream(&body, debug, &mut token_sink(&mut on_token))
             .await
-            .with_context(|| "HTTP request failed")?;
-
-        if !resp.status().is_success() {
-            let status = resp.status();
-            let text = resp.text().await.unwrap_or_default();
-            return Err(anyhow!("API error ({}): {}", status, text));
-        }
-
-        // The OpenAI-compatible API returns text/event-stream with lines prefixed by "data: ".
-        // We read chunks and split by newlines; we accumulate and parse JSON lines.
-        let mut stream = resp.bytes_stream();
-        let mut buffer = Vec::<u8>::new();
-
-        while let Some(item) = stream.next().await {
-            let chunk: Bytes = item?;
-            buffer.extend_from_slice(&chunk);
-            // Process complete lines
-            while let Some(pos) =
-                find_double_newline(&buffer).or_else(|| find_single_newline(&buffer))
-            {
-                let line = buffer.drain(..=pos).collect::<Vec<u8>>();
-                let s = String::from_utf8_lossy(&line);
-                for raw in s.split('\n') {
-                    let data = raw.trim();
-                    if data.is_empty() {
-                        continue;
-                    }
-                    if let Some(rest) = data.strip_prefix("data: ") {
-                        if rest == "[DONE]" {
-                            return Ok(());
-                        }
-                        if let Ok(parsed) = serde_json::from_str::<ChatStreamChunk>(rest) {
-                            for c in parsed.choices.into_iter() {
-                                if let Some(delta) = c.delta {
-                                    if let Some(token) = delta.content {
-                                        on_token(&token);
-                                    }
-                                }
-                            }
-                        } else if debug {
-                            eprintln!("[debug] Unparsed stream line: {}", rest);
-                        }
-                    }
-                }
-            }
-        }
-
-        Ok(())
     }
 
     /// Streaming chat completion with explicit messages (supports system+user for qq).
@@ -468,6 +947,22 @@ impl ChatClient {
     ) -> Result<()>
     where
         F: FnMut(&str),
+    {
+        self.chat_stream_events(model, messages, debug, token_sink(&mut on_token))
+            .await
+    }
+
+    /// Streaming chat completion that reports answer and reasoning deltas, usage, and the
+    /// finish reason as [`StreamEvent`]s, for embedders that need more than the text.
+    pub async fn chat_stream_events<F>(
+        &self,
+        model: &str,
+        messages: &[Msg<'_>],
+        debug: bool,
+        mut on_event: F,
+    ) -> Result<()>
+    where
+        F: FnMut(StreamEvent),
     {
         let mut body = json!({
             "model": model,
@@ -475,91 +970,86 @@ impl ChatClient {
             "stream": true
         });
         self.apply_model_defaults(&mut body, model, DEFAULT_MAX_COMPLETION_TOKENS, debug);
-        if debug {
-            let bytes = serde_json::to_vec(&body).unwrap();
-            eprintln!(
-                "[debug] POST {} ({} bytes, stream)",
-                self.chat_url(),
-                bytes.len()
-            );
-        }
-        let resp = self
-            .request_builder()
-            .json(&body)
-            .send()
-            .await
-            .with_context(|| "HTTP request failed")?;
-
-        if !resp.status().is_success() {
-            let status = resp.status();
-            let text = resp.text().await.unwrap_or_default();
-            return Err(anyhow!("API error ({}): {}", status, text));
-        }
-
-        // Reuse the same SSE parsing as non-message streaming
-        let mut stream = resp.bytes_stream();
-        let mut buffer = Vec::<u8>::new();
+        debug_log!(
+            debug,
+            "POST {} ({} bytes, stream)",
+            self.chat_url(),
+            JsonSize(&body)
+        );
+        self.post_chat_stream(&body, debug, &mut on_event).await
+    }
 
-        while let Some(item) = stream.next().await {
-            let chunk: Bytes = item?;
-            buffer.extend_from_slice(&chunk);
-            while let Some(pos) =
-                find_double_newline(&buffer).or_else(|| find_single_newline(&buffer))
-            {
-                let line = buffer.drain(..=pos).collect::<Vec<u8>>();
-                let s = String::from_utf8_lossy(&line);
-                for raw in s.split('\n') {
-                    let data = raw.trim();
-                    if data.is_empty() {
-                        continue;
-                    }
-                    if let Some(rest) = data.strip_prefix("data: ") {
-                        if rest == "[DONE]" {
-                            return Ok(());
-                        }
-                        if let Ok(parsed) = serde_json::from_str::<ChatStreamChunk>(rest) {
-                            for c in parsed.choices.into_iter() {
-                                if let Some(delta) = c.delta {
-                                    if let Some(token) = delta.content {
-                                        on_token(&token);
-                                    }
-                                }
-                            }
-                        } else if debug {
-                            eprintln!("[debug] Unparsed stream line: {}", rest);
-                        }
-                    }
-                }
-            }
-        }
+    /// Tool-aware request sent with `stream: true`: the answer text and the
+    /// `delta.tool_calls[].function` fragments are joined by [`ToolCallAccumulator`] once
+    /// the stream ends, for providers that only return tool calls when streaming.
+    pub async fn chat_stream_messages_with_tools(
+        &self,
+        model: &str,
+        messages: &[Msg<'_>],
+        tools: serde_json::Value,
+        debug: bool,
+    ) -> Result<AssistantReply> {
+        Ok(self
+            .stream_tool_request(model, messages, tools, debug)
+            .await?
+            .0)
+    }
 
-        Ok(())
+    async fn stream_tool_request(
+        &self,
+        model: &str,
+        messages: &[Msg<'_>],
+        tools: serde_json::Value,
+        debug: bool,
+    ) -> Result<(AssistantReply, Option<Usage>)> {
+        let mut body = json!({
+            "model": model,
+            "messages": messages,
+            "tools": tools,
+            "stream": true
+        });
+        self.apply_model_defaults(&mut body, model, DEFAULT_MAX_COMPLETION_TOKENS, debug);
+        debug_log!(
+            debug,
+            "POST {} ({} bytes, stream with tools)",
+            self.chat_url(),
+            JsonSize(&body)
+        );
+        let mut calls = ToolCallAccumulator::default();
+        self.read_chat_stream(&body, debug, &mut |event| calls.push(&event))
+            .await?;
+        Ok(calls.finish_with_usage())
     }
 }
 
 fn load_root_certificates(path: &Path) -> Result<Vec<Certificate>> {
     let data = fs::read(path)
         .with_context(|| format!("Reading TLS certificate(s) at {}", path.display()))?;
-    let mut cursor = Cursor::new(&data);
+    parse_root_certificates(&data, &path.display().to_string())
+}
+
+/// Parse a PEM bundle (one or more certificates) or a single DER certificate.
+/// `origin` names the source in error messages.
+fn parse_root_certificates(data: &[u8], origin: &str) -> Result<Vec<Certificate>> {
+    let mut cursor = Cursor::new(data);
     let mut parsed = Vec::new();
     for pem in certs(&mut cursor) {
-        let der =
-            pem.with_context(|| format!("Parsing PEM certificate from {}", path.display()))?;
+        let der = pem.with_context(|| format!("Parsing PEM certificate from {}", origin))?;
         parsed.push(
             Certificate::from_der(der.as_ref())
-                .with_context(|| format!("Parsing PEM certificate from {}", path.display()))?,
+                .with_context(|| format!("Parsing PEM certificate from {}", origin))?,
         );
     }
     if !parsed.is_empty() {
         return Ok(parsed);
     }
 
-    if let Ok(cert) = Certificate::from_pem(&data) {
+    if let Ok(cert) = Certificate::from_pem(data) {
         return Ok(vec![cert]);
     }
 
-    let cert = Certificate::from_der(&data)
-        .with_context(|| format!("Parsing DER certificate from {}", path.display()))?;
+    let cert = Certificate::from_der(data)
+        .with_context(|| format!("Parsing DER certificate from {}", origin))?;
     Ok(vec![cert])
 }
 
@@ -570,6 +1060,236 @@ pub struct Msg<'a> {
     pub content: &'a str,
 }
 
+/// Split an SSE `data:` line into its payloads. Accepts `data:` with or without the
+/// trailing space and lines that pack several `data:` events back to back.
+fn sse_data_payloads(line: &str) -> Vec<&str> {
+    let mut payloads = Vec::new();
+    let Some(mut rest) = line.strip_prefix("data:") else {
+        return payloads;
+    };
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            break;
+        }
+        let end = if rest.starts_with("[DONE]") {
+            "[DONE]".len()
+        } else {
+            let mut objects =
+                serde_json::Deserializer::from_str(rest).into_iter::<serde::de::IgnoredAny>();
+            match objects.next() {
+                Some(Ok(_)) => objects.byte_offset(),
+                _ => rest.len(),
+            }
+        };
+        payloads.push(&rest[..end]);
+        rest = rest[end..].trim_start();
+        match rest.strip_prefix("data:") {
+            Some(next) => rest = next,
+            None if rest.is_empty() => break,
+            // Bare concatenated JSON objects after the first payload.
+            None => {}
+        }
+    }
+    payloads
+}
+
+/// Explanation appended to a 404 from api.anthropic.com, which only serves chat completions
+/// through its OpenAI compatibility layer.
+const ANTHROPIC_NOT_FOUND_HINT: &str = "Anthropic answered 404. qqqa speaks the OpenAI-compatible Chat Completions API, not Anthropic's native Messages API: keep base_url at https://api.anthropic.com/v1 (requests go to /v1/chat/completions) and use a current Claude model ID, or ask Claude via the `claude_cli` profile (Claude Code) or OpenRouter (`-p openrouter -m anthropic/claude-3.5-sonnet`).";
+
+/// `API error (<status>): <body>`, plus a hint for failures with a known cause.
+fn api_error(base_url: &str, status: StatusCode, body: &str) -> anyhow::Error {
+    let mut message = format!("API error ({}): {}", status, body);
+    if status == StatusCode::NOT_FOUND && is_anthropic_host(base_url) {
+        message.push('\n');
+        message.push_str(ANTHROPIC_NOT_FOUND_HINT);
+    }
+    ApiError { status, message }.into()
+}
+
+fn is_anthropic_host(base_url: &str) -> bool {
+    reqwest::Url::parse(base_url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_ascii_lowercase))
+        .is_some_and(|host| host == "anthropic.com" || host.ends_with(".anthropic.com"))
+}
+
+/// Request body fields `--param` may not replace.
+pub const PROTECTED_BODY_KEYS: &[&str] = &["model", "messages", "stream"];
+
+/// Top-level `usage` of a chat completion response, if present.
+fn response_usage(text: &str) -> Option<Usage> {
+    #[derive(Deserialize)]
+    struct WithUsage {
+        usage: Option<Usage>,
+    }
+    serde_json::from_str::<WithUsage>(text).ok()?.usage
+}
+
+/// Content of the first choice in a non-streaming chat completion body.
+fn first_choice_content(text: &str) -> Result<String> {
+    let parsed: ChatResponse = serde_json::from_str(text)
+        .with_context(|| format!("Failed to parse chat response JSON: {}", text))?;
+    let choice = parsed
+        .choices
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("No choices in response"))?;
+    Ok(choice.message.content)
+}
+
+/// Feed every chat chunk on one SSE line to `on_event`; returns true at `[DONE]`. A
+/// `finish_reason` is reported as [`StreamEvent::Done`] and may arrive more than once.
+fn dispatch_sse_line<F>(line: &str, debug: bool, on_event: &mut F) -> bool
+where
+    F: FnMut(StreamEvent),
+{
+    for payload in sse_data_payloads(line) {
+        if payload == "[DONE]" {
+            return true;
+        }
+        if let Ok(parsed) = serde_json::from_str::<ChatStreamChunk>(payload) {
+            for c in parsed.choices.into_iter() {
+                if let Some(delta) = c.delta {
+                    if let Some(reasoning) = delta.reasoning.filter(|r| !r.is_empty()) {
+                        on_event(StreamEvent::Reasoning(reasoning));
+                    }
+                    if let Some(token) = delta.content {
+                        on_event(StreamEvent::Token(token));
+                    }
+                    for call in delta.tool_calls.into_iter().flatten() {
+                        let function = call.function.unwrap_or_default();
+                        on_event(StreamEvent::ToolCall {
+                            index: call.index,
+                            name: function.name,
+                            arguments: function.arguments.unwrap_or_default(),
+                        });
+                    }
+                }
+                if let Some(reason) = c.finish_reason {
+                    on_event(StreamEvent::Done {
+                        finish_reason: Some(reason),
+                    });
+                }
+            }
+            if let Some(usage) = parsed.usage {
+                on_event(StreamEvent::Usage(usage));
+            }
+        } else {
+            debug_log!(debug, "Unparsed stream line: {}", payload);
+        }
+    }
+    false
+}
+
+/// Process every complete line in `buffer`, leaving a partial trailing line in place.
+/// Returns true once `[DONE]` arrives.
+fn drain_sse_lines<F>(buffer: &mut Vec<u8>, debug: bool, on_event: &mut F) -> bool
+where
+    F: FnMut(StreamEvent),
+{
+    while let Some(pos) = find_double_newline(buffer).or_else(|| find_single_newline(buffer)) {
+        let line = buffer.drain(..=pos).collect::<Vec<u8>>();
+        let s = String::from_utf8_lossy(&line);
+        for raw in s.split('\n') {
+            let data = raw.trim();
+            // Blank separators and `:` keep-alive comments carry no data.
+            if data.is_empty() || data.starts_with(':') {
+                continue;
+            }
+            if dispatch_sse_line(data, debug, on_event) {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// Serialized size of a request body, computed only when a debug line is formatted.
+struct JsonSize<'a>(&'a Value);
+
+impl std::fmt::Display for JsonSize<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let len = serde_json::to_vec(self.0)
+            .map(|bytes| bytes.len())
+            .unwrap_or(0);
+        write!(f, "{}", len)
+    }
+}
+
+/// Env var naming a file whose contents replace every HTTP chat response, for offline
+/// demos, reproducible bug reports, and tests against the real binaries.
+pub const MOCK_RESPONSE_ENV: &str = "QQQA_MOCK_RESPONSE_FILE";
+
+/// The canned response from `QQQA_MOCK_RESPONSE_FILE`, if the variable is set.
+fn mock_response(debug: bool) -> Result<Option<String>> {
+    let Some(path) = std::env::var_os(MOCK_RESPONSE_ENV).filter(|p| !p.is_empty()) else {
+        return Ok(None);
+    };
+    let path = Path::new(&path);
+    debug_log!(
+        debug,
+        "{} set; answering from {} instead of the network",
+        MOCK_RESPONSE_ENV,
+        path.display()
+    );
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Reading {} ({})", MOCK_RESPONSE_ENV, path.display()))?;
+    Ok(Some(text))
+}
+
+fn is_sse_body(text: &str) -> bool {
+    text.lines()
+        .any(|line| line.trim_start().starts_with("data:"))
+}
+
+/// Shape a canned response as a chat completion body. A file that already is one (has
+/// `choices`) is used verbatim; an SSE transcript is joined; anything else is the answer text.
+fn mock_completion_json(canned: &str, debug: bool) -> String {
+    let trimmed = canned.trim();
+    if serde_json::from_str::<Value>(trimmed).is_ok_and(|v| v.get("choices").is_some()) {
+        return trimmed.to_string();
+    }
+    let content = if is_sse_body(canned) {
+        let mut out = String::new();
+        let mut buffer = format!("{}\n", canned).into_bytes();
+        drain_sse_lines(
+            &mut buffer,
+            debug,
+            &mut token_sink(&mut |token: &str| out.push_str(token)),
+        );
+        out
+    } else {
+        canned.trim_end_matches(['\r', '\n']).to_string()
+    };
+    completion_json(&content)
+}
+
+/// A minimal chat completion body whose only choice answers `content`.
+fn completion_json(content: &str) -> String {
+    json!({"choices": [{"message": {"role": "assistant", "content": content}}]}).to_string()
+}
+
+/// Stream a canned response: SSE transcripts replay delta by delta, other files arrive as
+/// a single token.
+fn replay_mock_stream<F>(canned: &str, debug: bool, on_event: &mut F) -> Result<()>
+where
+    F: FnMut(StreamEvent),
+{
+    if is_sse_body(canned) {
+        let mut buffer = format!("{}\n", canned).into_bytes();
+        drain_sse_lines(&mut buffer, debug, on_event);
+        return Ok(());
+    }
+    let parsed: ChatResponse = serde_json::from_str(&mock_completion_json(canned, debug))
+        .with_context(|| format!("Failed to parse {} as a chat response", MOCK_RESPONSE_ENV))?;
+    if let Some(choice) = parsed.choices.into_iter().next() {
+        on_event(StreamEvent::Token(choice.message.content));
+    }
+    Ok(())
+}
+
 fn find_double_newline(buf: &[u8]) -> Option<usize> {
     // Find position to cut at a blank line (\n\n). Return index of the second newline.
     buf.windows(2).position(|w| w == b"\n\n").map(|i| i + 1)
@@ -621,6 +1341,24 @@ mod cli_backend {
         }
     }
 
+    /// Explain a failed spawn: what was run, for which engine, and how to fix it.
+    fn spawn_error(err: std::io::Error, req: &CliCompletionRequest<'_>) -> anyhow::Error {
+        let engine = req.engine.name();
+        if err.kind() == std::io::ErrorKind::NotFound {
+            anyhow!(
+                "CLI provider binary '{}' ({} engine) was not found on your PATH. Install {}, or set `cli.binary` for this provider in ~/.qq/config.json to the full path of the executable.",
+                req.binary,
+                engine,
+                engine
+            )
+        } else {
+            anyhow!(err).context(format!(
+                "Failed to spawn CLI provider '{}' ({} engine)",
+                req.binary, engine
+            ))
+        }
+    }
+
     async fn run_codex(req: CliCompletionRequest<'_>) -> Result<String> {
         let mut cmd = Command::new(req.binary);
         if req.base_args.is_empty() {
@@ -645,30 +1383,29 @@ mod cli_backend {
         cmd.arg("tools.web_search=false");
         cmd.arg("-");
 
-        let prompt = format!(
-            "{}\n\n{}\n",
-            tagged_system_prompt(req.system_prompt),
-            tagged_user_prompt(req.user_prompt)
-        );
+        let prompt = if req.system_prompt.is_empty() {
+            format!("{}\n", tagged_user_prompt(req.user_prompt))
+        } else {
+            format!(
+                "{}\n\n{}\n",
+                tagged_system_prompt(req.system_prompt),
+                tagged_user_prompt(req.user_prompt)
+            )
+        };
 
-        if req.debug {
-            eprintln!(
-                "[debug] Running CLI provider '{}' with args: {:?}",
-                req.binary, cmd
-            );
-        }
+        debug_log!(
+            req.debug,
+            "Running CLI provider '{}' with args: {:?}",
+            req.binary,
+            cmd
+        );
 
         let mut child = cmd
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
             .spawn()
-            .with_context(|| {
-                format!(
-                    "Failed to spawn CLI provider '{}'. Is it installed and on your PATH?",
-                    req.binary
-                )
-            })?;
+            .map_err(|err| spawn_error(err, &req))?;
 
         if let Some(mut stdin) = child.stdin.take() {
             stdin
@@ -710,23 +1447,18 @@ mod cli_backend {
     async fn run_claude(req: CliCompletionRequest<'_>) -> Result<String> {
         let mut cmd = build_claude_command(&req, false);
 
-        if req.debug {
-            eprintln!(
-                "[debug] Running CLI provider '{}' with args: {:?}",
-                req.binary, cmd
-            );
-        }
+        debug_log!(
+            req.debug,
+            "Running CLI provider '{}' with args: {:?}",
+            req.binary,
+            cmd
+        );
 
         let child = cmd
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
             .spawn()
-            .with_context(|| {
-                format!(
-                    "Failed to spawn CLI provider '{}'. Is it installed and on your PATH?",
-                    req.binary
-                )
-            })?;
+            .map_err(|err| spawn_error(err, &req))?;
 
         let output = wait_child_output_with_timeout(child, req.timeout, req.binary).await?;
 
@@ -767,23 +1499,18 @@ mod cli_backend {
     {
         let mut cmd = build_claude_command(&req, true);
 
-        if req.debug {
-            eprintln!(
-                "[debug] Running CLI provider '{}' with args: {:?}",
-                req.binary, cmd
-            );
-        }
+        debug_log!(
+            req.debug,
+            "Running CLI provider '{}' with args: {:?}",
+            req.binary,
+            cmd
+        );
 
         let mut child = cmd
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
             .spawn()
-            .with_context(|| {
-                format!(
-                    "Failed to spawn CLI provider '{}'. Is it installed and on your PATH?",
-                    req.binary
-                )
-            })?;
+            .map_err(|err| spawn_error(err, &req))?;
 
         let stdout = child
             .stdout
@@ -823,25 +1550,34 @@ mod cli_backend {
                     }
                     match parse_claude_stream_line(trimmed) {
                         Ok((token, result_text)) => {
-                            if let Some(t) = token {
-                                if !t.is_empty() {
-                                    aggregated.push_str(&t);
-                                    on_token(&t);
-                                }
+                            if let Some(t) = token
+                                && !t.is_empty()
+                            {
+                                aggregated.push_str(&t);
+                                on_token(&t);
+                            }
+                            if stdout_closed() {
+                                debug_log!(
+                                    req.debug,
+                                    "stdout closed (broken pipe); stopping the CLI provider"
+                                );
+                                let _ = child.start_kill();
+                                let _ = child.wait().await;
+                                return Ok(aggregated);
                             }
-                            if let Some(res) = result_text {
-                                if !res.trim().is_empty() {
-                                    fallback = Some(res);
-                                }
+                            if let Some(res) = result_text
+                                && !res.trim().is_empty()
+                            {
+                                fallback = Some(res);
                             }
                         }
                         Err(e) => {
-                            if req.debug {
-                                eprintln!(
-                                    "[debug] Failed to parse Claude stream line '{}': {}",
-                                    trimmed, e
-                                );
-                            }
+                            debug_log!(
+                                req.debug,
+                                "Failed to parse Claude stream line '{}': {}",
+                                trimmed,
+                                e
+                            );
                         }
                     }
                 }
@@ -905,8 +1641,10 @@ mod cli_backend {
             cmd.arg("--model");
             cmd.arg(req.model);
         }
-        cmd.arg("--append-system-prompt");
-        cmd.arg(tagged_system_prompt(req.system_prompt));
+        if !req.system_prompt.is_empty() {
+            cmd.arg("--append-system-prompt");
+            cmd.arg(tagged_system_prompt(req.system_prompt));
+        }
         cmd.arg("--disallowed-tools");
         cmd.arg("Bash(*) Edit");
         cmd.arg("--");
@@ -971,10 +1709,7 @@ mod cli_backend {
 
     async fn take_stderr_output(stderr: &mut Option<tokio::task::JoinHandle<String>>) -> String {
         if let Some(handle) = stderr.take() {
-            match handle.await {
-                Ok(text) => text,
-                Err(_) => String::new(),
-            }
+            handle.await.unwrap_or_default()
         } else {
             String::new()
         }
@@ -982,7 +1717,7 @@ mod cli_backend {
 
     fn timeout_error(binary: &str, timeout: Duration) -> anyhow::Error {
         anyhow!(
-            "CLI provider '{}' timed out after {}s",
+            "CLI provider '{}' timed out after {}s (raise the profile's `timeout` to wait longer)",
             binary,
             timeout.as_secs()
         )
@@ -1013,16 +1748,13 @@ mod cli_backend {
             }
             match serde_json::from_str::<CodexEvent>(trimmed) {
                 Ok(event) => {
-                    if event.event_type == "item.completed" {
-                        if let Some(item) = event.item {
-                            if item.item_type == "agent_message" {
-                                if let Some(text) = item.text {
-                                    if !text.trim().is_empty() {
-                                        messages.push(text);
-                                    }
-                                }
-                            }
-                        }
+                    if event.event_type == "item.completed"
+                        && let Some(item) = event.item
+                        && item.item_type == "agent_message"
+                        && let Some(text) = item.text
+                        && !text.trim().is_empty()
+                    {
+                        messages.push(text);
                     }
                 }
                 Err(_) => {
@@ -1040,10 +1772,10 @@ mod cli_backend {
         let value = parse_single_json_value(stdout)
             .with_context(|| "CLI provider returned non-JSON output")?;
 
-        if let Some(result_field) = value.get("result") {
-            if let Some(text) = extract_text(result_field) {
-                return Ok(text);
-            }
+        if let Some(result_field) = value.get("result")
+            && let Some(text) = extract_text(result_field)
+        {
+            return Ok(text);
         }
 
         if let Some(text) = extract_text(&value) {
@@ -1087,23 +1819,19 @@ mod cli_backend {
         let mut result_text = None;
 
         if env.event_type == "stream_event" {
-            if let Some(event) = env.event {
-                if event.event_type == "content_block_delta" {
-                    if let Some(delta) = event.delta {
-                        if let Some(text) = delta.text {
-                            if !text.is_empty() {
-                                token = Some(text);
-                            }
-                        }
-                    }
-                }
-            }
-        } else if env.event_type == "result" {
-            if let Some(result) = env.result {
-                if let Some(text) = extract_text(&result) {
-                    result_text = Some(text);
-                }
+            if let Some(event) = env.event
+                && event.event_type == "content_block_delta"
+                && let Some(delta) = event.delta
+                && let Some(text) = delta.text
+                && !text.is_empty()
+            {
+                token = Some(text);
             }
+        } else if env.event_type == "result"
+            && let Some(result) = env.result
+            && let Some(text) = extract_text(&result)
+        {
+            result_text = Some(text);
         }
 
         Ok((token, result_text))
@@ -1111,10 +1839,10 @@ mod cli_backend {
 
     fn parse_single_json_value(stdout: &str) -> Result<Value> {
         let trimmed = stdout.trim();
-        if !trimmed.is_empty() {
-            if let Ok(value) = serde_json::from_str::<Value>(trimmed) {
-                return Ok(value);
-            }
+        if !trimmed.is_empty()
+            && let Ok(value) = serde_json::from_str::<Value>(trimmed)
+        {
+            return Ok(value);
         }
 
         for line in stdout.lines().rev() {
@@ -1153,16 +1881,16 @@ mod cli_backend {
                 }
             }
             Value::Object(map) => {
-                if let Some(text) = map.get("text").and_then(|t| t.as_str()) {
-                    if !text.trim().is_empty() {
-                        return Some(text.to_string());
-                    }
+                if let Some(text) = map.get("text").and_then(|t| t.as_str())
+                    && !text.trim().is_empty()
+                {
+                    return Some(text.to_string());
                 }
                 for key in ["content", "messages", "output_text", "result"] {
-                    if let Some(val) = map.get(key) {
-                        if let Some(text) = extract_text(val) {
-                            return Some(text);
-                        }
+                    if let Some(val) = map.get(key)
+                        && let Some(text) = extract_text(val)
+                    {
+                        return Some(text);
                     }
                 }
                 None
@@ -1205,11 +1933,79 @@ mod tests {
         parse_claude_response_for_test, parse_claude_stream_line_for_test,
         parse_codex_response_for_test,
     };
-    use super::load_root_certificates;
+    use super::{
+        StreamEvent, api_error, is_provider_failure, load_root_certificates, mock_completion_json,
+        parse_root_certificates, replay_mock_stream,
+    };
     use rcgen::{CertifiedKey, generate_simple_self_signed};
+    use reqwest::StatusCode;
     use std::fs;
     use tempfile::tempdir;
 
+    #[test]
+    fn anthropic_404_explains_the_compat_endpoint() {
+        let body = r#"{"type":"error","error":{"type":"not_found_error"}}"#;
+        let msg =
+            api_error("https://api.anthropic.com/v1", StatusCode::NOT_FOUND, body).to_string();
+        assert!(msg.starts_with("API error (404 Not Found): {"), "{msg}");
+        assert!(msg.contains("native Messages API"), "{msg}");
+        assert!(msg.contains("claude_cli"), "{msg}");
+
+        let other =
+            api_error("https://openrouter.ai/api/v1", StatusCode::NOT_FOUND, body).to_string();
+        assert!(!other.contains("Messages API"), "{other}");
+        let auth =
+            api_error("https://api.anthropic.com/v1", StatusCode::UNAUTHORIZED, "").to_string();
+        assert!(!auth.contains("Messages API"), "{auth}");
+    }
+
+    #[test]
+    fn provider_failures_cover_auth_rate_limits_and_server_errors() {
+        let base = "https://example.test/v1";
+        for status in [401, 403, 429, 500, 503] {
+            let status = StatusCode::from_u16(status).unwrap();
+            let err = api_error(base, status, "").context("qq request");
+            assert!(is_provider_failure(&err), "{status}");
+        }
+        for status in [400, 404, 422] {
+            let status = StatusCode::from_u16(status).unwrap();
+            assert!(
+                !is_provider_failure(&api_error(base, status, "")),
+                "{status}"
+            );
+        }
+        assert!(!is_provider_failure(&anyhow::anyhow!(
+            "No choices in response"
+        )));
+    }
+
+    #[test]
+    fn mock_completion_json_accepts_json_sse_or_plain_text() {
+        let verbatim = r#"{"choices":[{"message":{"content":null,"tool_calls":[]}}]}"#;
+        assert_eq!(mock_completion_json(verbatim, false), verbatim);
+
+        let plain: serde_json::Value =
+            serde_json::from_str(&mock_completion_json("hello\n", false)).unwrap();
+        assert_eq!(plain["choices"][0]["message"]["content"], "hello");
+
+        let sse = "data: {\"choices\":[{\"delta\":{\"content\":\"a\"}}]}\ndata: {\"choices\":[{\"delta\":{\"content\":\"b\"}}]}\ndata: [DONE]";
+        let joined: serde_json::Value =
+            serde_json::from_str(&mock_completion_json(sse, false)).unwrap();
+        assert_eq!(joined["choices"][0]["message"]["content"], "ab");
+
+        let mut tokens = Vec::new();
+        {
+            let mut push = |event| {
+                if let StreamEvent::Token(t) = event {
+                    tokens.push(t)
+                }
+            };
+            replay_mock_stream(sse, false, &mut push).unwrap();
+            replay_mock_stream("whole answer", false, &mut push).unwrap();
+        }
+        assert_eq!(tokens, ["a", "b", "whole answer"]);
+    }
+
     #[test]
     fn load_root_certificates_supports_multiple_pem_entries() {
         let bundle_dir = tempdir().unwrap();
@@ -1225,6 +2021,13 @@ mod tests {
         assert_eq!(certs.len(), 2);
     }
 
+    #[test]
+    fn parse_root_certificates_accepts_inline_pem() {
+        let CertifiedKey { cert, .. } = generate_simple_self_signed(["inline.lan".into()]).unwrap();
+        let certs = parse_root_certificates(cert.pem().as_bytes(), "inline PEM").expect("certs");
+        assert_eq!(certs.len(), 1);
+    }
+
     #[test]
     fn load_root_certificates_handles_der_files() {
         let dir = tempdir().unwrap();
@@ -1242,8 +2045,7 @@ mod tests {
         let payload = r#"{"type":"item.completed","item":{"id":"item_0","type":"reasoning","text":"Reasoning"}}
 {"type":"item.completed","item":{"id":"item_1","type":"agent_message","text":"First"}}
 {"type":"item.completed","item":{"id":"item_2","type":"agent_message","text":"Second"}}"#;
-        let merged = payload.replace('\n', "\n");
-        let parsed = parse_codex_response_for_test(&merged).expect("parse");
+        let parsed = parse_codex_response_for_test(payload).expect("parse");
         assert_eq!(parsed, "First\n\nSecond");
     }
 
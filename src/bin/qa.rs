@@ -1,22 +1,25 @@
 use anyhow::{Context, Result, anyhow};
 use clap::{ArgAction, Parser};
-use qqqa::ai::{
-    AssistantReply, ChatClient, CliCompletionRequest, DEFAULT_REQUEST_TIMEOUT_SECS, Msg,
-    run_cli_completion,
+use nu_ansi_term::{Color, Style};
+use qqqa::config::{Config, InitExistsError, config_dir};
+use qqqa::last_session::{StoredRun, load_last_run, save_last_run};
+use qqqa::prompt::decode_stdin_lossy;
+use qqqa::run::{QaHooks, prepare_prompt_inputs, run_qa_with_hooks};
+use qqqa::shell::ShellKind;
+use qqqa::tools::execute_command::{
+    self, ExecuteCommandResult, InteractiveOptions, run_with_allowlist_prompt,
 };
-use qqqa::config::{Config, InitExistsError, ProviderConnection};
-use qqqa::history::read_recent_history;
-use qqqa::perms;
-use qqqa::prompt::{build_qa_system_prompt, build_qa_user_message, coalesce_prompt_inputs};
-use qqqa::shell::{ShellKind, detect_shell, shell_hint_for_prompt};
-use qqqa::tools::{ToolCall, parse_tool_call};
-use std::io::{Read, Stdin, Write};
+use qqqa::tools::{PlanStep, TOOL_NAMES, parse_plan, read_file};
+use qqqa::{QaOptions, QaOutcome, debug_log, logging};
+use std::ffi::OsString;
+use std::io::{Read, Stdin};
 use std::path::{Path, PathBuf};
-use std::time::Duration;
 
 /// qa — single-step agent that may use one tool
 #[derive(Debug, Parser)]
-#[command(name = "qa", disable_colored_help = false, version, about)]
+#[command(name = "qa", disable_colored_help = false, version = qqqa::version::LONG_VERSION, about)]
+// A repeated flag keeps its last value, so `--replay -m other` overrides the stored `-m`.
+#[command(args_override_self = true)]
 struct Cli {
     /// Initialize or reinitialize configuration (~/.qq/config.json) and exit
     #[arg(long = "init", action = ArgAction::SetTrue)]
@@ -26,9 +29,13 @@ struct Cli {
     profile: Option<String>,
 
     /// Override the resolved provider base URL (e.g., http://localhost:11434/v1)
-    #[arg(long = "api-base")]
+    #[arg(long = "api-base", alias = "base-url")]
     api_base: Option<String>,
 
+    /// API key for this run (overrides config and provider env vars; also QQQA_API_KEY)
+    #[arg(long = "api-key", value_name = "KEY")]
+    api_key: Option<String>,
+
     /// Override the model ID from profile
     #[arg(short = 'm', long = "model")]
     model: Option<String>,
@@ -37,6 +44,22 @@ struct Cli {
     #[arg(long = "temperature")]
     temperature: Option<f32>,
 
+    /// Frequency penalty for this run, -2.0 to 2.0 (overrides profile; not sent to gpt-5/o1/o3)
+    #[arg(
+        long = "frequency-penalty",
+        value_name = "N",
+        allow_hyphen_values = true
+    )]
+    frequency_penalty: Option<f32>,
+
+    /// Presence penalty for this run, -2.0 to 2.0 (overrides profile; not sent to gpt-5/o1/o3)
+    #[arg(
+        long = "presence-penalty",
+        value_name = "N",
+        allow_hyphen_values = true
+    )]
+    presence_penalty: Option<f32>,
+
     /// Disable terminal history context
     #[arg(short = 'n', long = "no-history", action = ArgAction::SetTrue)]
     no_history: bool,
@@ -44,14 +67,142 @@ struct Cli {
     #[arg(long = "history", action = ArgAction::SetTrue, conflicts_with = "no_history")]
     history: bool,
 
+    /// Send history and piped stdin verbatim instead of masking likely secrets
+    #[arg(long = "no-redact", action = ArgAction::SetTrue)]
+    no_redact: bool,
+
+    /// Only include history from the last N minutes (implies --history; zsh timestamps)
+    #[arg(
+        long = "history-since",
+        value_name = "MINUTES",
+        conflicts_with = "no_history"
+    )]
+    history_since: Option<u64>,
+
+    /// Cap history + piped input context at this many bytes (keeps the most recent)
+    #[arg(long = "max-context-bytes", value_name = "BYTES")]
+    max_context_bytes: Option<usize>,
+
+    /// Label for piped input in the prompt (default "Input from pipe"), e.g. "JSON payload"
+    #[arg(long = "stdin-label", value_name = "TEXT")]
+    stdin_label: Option<String>,
+
+    /// Stamp the prompt's date/time in local time with its UTC offset instead of UTC
+    #[arg(long = "local-time")]
+    local_time: bool,
+
+    /// Add a capped listing of the current directory's top-level entries to the prompt
+    #[arg(long = "cwd-context", alias = "include-cwd-listing")]
+    cwd_context: bool,
+
+    /// Leave the operating system and date/time lines out of the prompt
+    #[arg(long = "no-os-line")]
+    no_os_line: bool,
+
+    /// Load provider keys from this env file (KEY=VALUE) in addition to ~/.qq/.env
+    #[arg(long = "env-file", value_name = "PATH")]
+    env_file: Option<PathBuf>,
+
+    /// Suppress warnings and hints on stderr
+    #[arg(short = 'q', long = "quiet", action = ArgAction::SetTrue)]
+    quiet: bool,
+
+    /// Extra request header "Name: Value" for this run (repeatable; overrides provider headers)
+    #[arg(long = "header", value_name = "NAME: VALUE")]
+    headers: Vec<String>,
+
+    /// Extra request body field KEY=VALUE for this run (repeatable; VALUE parsed as JSON, else a string)
+    #[arg(long = "param", value_name = "KEY=VALUE")]
+    params: Vec<String>,
+
+    /// Seconds to wait for the provider connection to open (default 10; overrides the provider's connect_timeout_secs)
+    #[arg(long = "connect-timeout", value_name = "SECS")]
+    connect_timeout: Option<u64>,
+
+    /// Force the shell flavor (posix, cmd, powershell) instead of detecting it; affects the prompt hint and execute_command
+    #[arg(long = "shell", value_name = "SHELL")]
+    shell: Option<ShellKind>,
+
     /// Verbose internal logs
     #[arg(short = 'd', long = "debug", action = ArgAction::SetTrue)]
     debug: bool,
 
+    /// Timeout in seconds for execute_command (overrides config; default 120)
+    #[arg(long = "command-timeout", value_name = "SECS")]
+    command_timeout: Option<u64>,
+
+    /// Comma-separated tools qa may use this run, e.g. `read_file,write_file` (default: all)
+    #[arg(long = "tools", value_name = "LIST")]
+    tools: Option<String>,
+
+    /// Re-run the last qa task with its flags under the current config; flags given here are added
+    #[arg(long = "replay", action = ArgAction::SetTrue, conflicts_with = "task")]
+    replay: bool,
+
+    /// Allow this program for execute_command during this run only (repeatable; not persisted)
+    #[arg(long = "allow", value_name = "PROGRAM")]
+    allow: Vec<String>,
+
+    /// Run execute_command in a pseudo-terminal even when stdout is not one (overrides QQQA_DISABLE_PTY)
+    #[arg(long = "pty", action = ArgAction::SetTrue)]
+    pty: bool,
+    /// Run execute_command with plain pipes instead of a pseudo-terminal (overrides QQQA_FORCE_PTY)
+    #[arg(long = "no-pty", action = ArgAction::SetTrue, conflicts_with = "pty")]
+    no_pty: bool,
+
+    /// Show live stderr from execute_command in dim red (only when stdout is a terminal)
+    #[arg(long = "color-stderr", action = ArgAction::SetTrue)]
+    color_stderr: bool,
+
+    /// Ask the model for a one-line reason with each tool call and print it before running
+    #[arg(long = "explain", action = ArgAction::SetTrue)]
+    explain: bool,
+
+    /// Ask for an ordered multi-step plan and print it without running any tool
+    #[arg(long = "plan", action = ArgAction::SetTrue)]
+    plan: bool,
+
+    /// After execute_command runs, send its output back and print the model's summary
+    #[arg(
+        long = "summarize",
+        alias = "summarize-output",
+        action = ArgAction::SetTrue,
+        conflicts_with = "plan"
+    )]
+    summarize: bool,
+
+    /// Refuse to dispatch a tool call once N have run (qa makes at most one, so 0 blocks it)
+    #[arg(long = "max-steps", value_name = "N")]
+    max_steps: Option<u32>,
+
+    /// Refuse the tool call or --summarize request once provider-reported tokens pass N (needs `usage`)
+    #[arg(long = "max-tokens-total", value_name = "N")]
+    max_tokens_total: Option<u64>,
+
+    /// Print tool results without the `[tool:NAME]` header lines (errors still go to stderr with theirs)
+    #[arg(long = "no-tool-headers", action = ArgAction::SetTrue)]
+    no_tool_headers: bool,
+
+    /// Number the lines of read_file results
+    #[arg(long = "line-numbers", action = ArgAction::SetTrue)]
+    line_numbers: bool,
+
+    /// Print tool results without ANSI colors (also honored: NO_COLOR)
+    #[arg(short = 'r', long = "raw", action = ArgAction::SetTrue)]
+    raw: bool,
+
     /// Auto-approve command execution (use with caution)
     #[arg(short = 'y', long = "yes", action = ArgAction::SetTrue)]
     yes: bool,
 
+    /// Auto-approve only commands that pass the safety checks; risky ones still prompt
+    #[arg(long = "auto-safe", action = ArgAction::SetTrue)]
+    auto_safe: bool,
+
+    /// Skip the extra confirmation for a working directory outside your home directory
+    #[arg(long = "allow-outside-home", action = ArgAction::SetTrue)]
+    allow_outside_home: bool,
+
     /// Disable emojis going forward (persists to config)
     #[arg(long = "no-fun", action = ArgAction::SetTrue)]
     no_fun: bool,
@@ -63,14 +214,23 @@ struct Cli {
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let cli = Cli::parse();
+    let mut argv: Vec<OsString> = std::env::args_os().collect();
+    let mut cli = Cli::parse_from(&argv);
+    if cli.replay {
+        let last = load_last_run(&config_dir()?, "qa")?;
+        eprintln!("Replaying: {}", last.describe("qa"));
+        if last.had_stdin_context {
+            eprintln!("(the original run's piped input is not replayed)");
+        }
+        argv = last.replay_args(argv[0].clone(), &argv[1..]);
+        cli = Cli::parse_from(&argv);
+    }
+    logging::init_cli(cli.debug);
 
     if cli.init {
         match Config::init_interactive(cli.debug) {
             Ok(path) => {
-                if cli.debug {
-                    eprintln!("[debug] Initialized config at {}", path.display());
-                }
+                debug_log!(cli.debug, "Initialized config at {}", path.display());
             }
             Err(e) => match e.downcast::<InitExistsError>() {
                 Ok(init_err) => {
@@ -88,12 +248,12 @@ async fn main() -> Result<()> {
     // Handle stdin piping for extra context.
     let stdin_is_tty = atty::is(atty::Stream::Stdin);
     let stdin_block = if !stdin_is_tty {
-        Some(read_all_stdin(std::io::stdin())?)
+        Some(read_all_stdin(std::io::stdin(), cli.debug)?)
     } else {
         None
     };
 
-    let prepared = coalesce_prompt_inputs(cli.task.join(" "), stdin_block);
+    let prepared = prepare_prompt_inputs(cli.task.join(" "), stdin_block, cli.no_redact, cli.debug);
     if prepared.question.trim().is_empty() {
         return Err(anyhow!("No input provided. Pass a task or pipe stdin."));
     }
@@ -102,351 +262,211 @@ async fn main() -> Result<()> {
         stdin_block,
     } = prepared;
 
+    // Kept for --no-fun and for saving allowlist choices; run_qa_with_hooks loads its own copy.
     let (mut cfg, path) = Config::load_or_init(cli.debug)?;
-    perms::set_custom_allowlist(cfg.command_allowlist());
     if cli.no_fun {
         cfg.no_emoji = Some("true".to_string());
         cfg.save(&path, cli.debug)?;
-        if cli.debug {
-            eprintln!(
-                "[debug] Disabled emojis in system prompt (persisted at {}).",
-                path.display()
-            );
-        }
-    }
-    let cfg_dir = path.parent();
-    let mut eff = match cfg.resolve_profile(cli.profile.as_deref(), cli.model.as_deref(), cfg_dir) {
-        Ok(eff) => eff,
-        Err(e) => {
-            let msg = e.to_string();
-            let mut out = msg.clone();
-            if msg.contains("Missing API key") {
-                out.push_str(
-                    "\n\nFix it quickly:\n- Run `qa --init` and choose provider; optionally paste the API key.\n- Or export an env var, e.g.\n    export OPENROUTER_API_KEY=... # OpenRouter (default)\n    export GROQ_API_KEY=...       # Groq\n    export OPENAI_API_KEY=...     # OpenAI\n    export ANTHROPIC_API_KEY=...  # Anthropic (Claude)",
-                );
-            }
-            return Err(anyhow!(out));
-        }
-    };
-    if let Some(base) = cli.api_base.as_deref() {
-        if let Some(http) = eff.http_mut() {
-            http.base_url = base.to_string();
-        } else {
-            return Err(anyhow!(
-                "--api-base override is only supported for HTTP providers (current: '{}').",
-                eff.provider_key
-            ));
-        }
-    }
-    if let Some(temp) = cli.temperature {
-        eff.temperature = Some(temp);
-    }
-    if cli.debug {
-        match &eff.connection {
-            ProviderConnection::Http(conn) => {
-                eprintln!(
-                    "[debug] Using provider='{}' base_url='{}' model='{}'",
-                    eff.provider_key, conn.base_url, eff.model
-                );
-            }
-            ProviderConnection::Cli(conn) => {
-                eprintln!(
-                    "[debug] Using provider='{}' cli_binary='{}' model='{}'",
-                    eff.provider_key, conn.binary, eff.model
-                );
-            }
+        debug_log!(
+            cli.debug,
+            "Disabled emojis in system prompt (persisted at {}).",
+            path.display()
+        );
+    }
+    if let Some(dir) = path.parent() {
+        let run = StoredRun::from_invocation(
+            &argv,
+            cli.task.len(),
+            &task,
+            stdin_block.as_deref().is_some_and(|b| !b.trim().is_empty()),
+        );
+        if let Err(err) = save_last_run(dir, "qa", run) {
+            debug_log!(cli.debug, "Could not save the run for --replay: {}", err);
         }
     }
 
     let include_history = if cli.no_history {
-        false
-    } else if cli.history {
-        true
+        Some(false)
     } else {
-        cfg.history_enabled()
+        cli.history.then_some(true)
     };
-    let history = if include_history {
-        read_recent_history(10, cli.debug)
-    } else {
-        Vec::new()
+    let opts = QaOptions {
+        task,
+        stdin: stdin_block,
+        profile: cli.profile,
+        model: cli.model,
+        api_base: cli.api_base,
+        api_key: cli.api_key,
+        temperature: cli.temperature,
+        frequency_penalty: cli.frequency_penalty,
+        presence_penalty: cli.presence_penalty,
+        headers: cli.headers,
+        params: cli.params,
+        connect_timeout_secs: cli.connect_timeout,
+        history: include_history,
+        history_since_minutes: cli.history_since,
+        no_redact: cli.no_redact,
+        max_context_bytes: cli.max_context_bytes,
+        stdin_label: cli.stdin_label,
+        local_time: cli.local_time,
+        cwd_context: cli.cwd_context,
+        no_os_line: cli.no_os_line,
+        yes: cli.yes,
+        auto_safe: cli.auto_safe,
+        allow_outside_home: cli.allow_outside_home,
+        pty: (cli.pty || cli.no_pty).then_some(cli.pty),
+        allow: cli.allow,
+        tools: cli.tools.map(|list| vec![list]),
+        command_timeout_secs: cli.command_timeout,
+        summarize: cli.summarize,
+        explain: cli.explain,
+        plan: cli.plan,
+        max_steps: cli.max_steps,
+        max_tokens_total: cli.max_tokens_total,
+        config: None,
+        env_file: cli.env_file,
+        shell: cli.shell,
+        debug: cli.debug,
     };
-    let os_details = os_info::get();
-    let os_type = os_details.os_type();
-    let shell_kind = detect_shell(os_type);
-    if cli.debug {
-        eprintln!("[debug] Inferred shell: {}", shell_kind.display_name(),);
-    }
-
-    let mut system_prompt = build_qa_system_prompt();
-    if cfg.no_emoji_enabled() {
-        system_prompt.push_str("\nHard rule: You MUST NOT use emojis anywhere in the response.\n");
-    }
-    let shell_hint = shell_hint_for_prompt(shell_kind);
-    let user_msg = build_qa_user_message(
-        Some(os_type),
-        Some(shell_hint),
-        &history,
-        stdin_block.as_deref(),
-        &task,
-    );
-
-    let http_client = match &eff.connection {
-        ProviderConnection::Http(conn) => Some(
-            ChatClient::new(
-                conn.base_url.clone(),
-                conn.api_key.clone(),
-                conn.headers.clone(),
-                conn.tls.as_ref(),
-                conn.request_timeout_secs.map(Duration::from_secs),
-            )?
-            .with_reasoning_effort(eff.reasoning_effort.clone())
-            .with_temperature(eff.temperature, eff.temperature.is_some()),
-        ),
-        ProviderConnection::Cli(_) => None,
+    let tool_opts = ToolOptions {
+        auto_yes: cli.yes,
+        debug: cli.debug,
+        color_stderr: cli.color_stderr && atty::is(atty::Stream::Stdout),
+        color: !cli.raw && qqqa::formatting::color_output_from_env(),
+        line_numbers: cli.line_numbers,
+        tool_headers: !cli.no_tool_headers,
     };
-    // Provide tool specs so the API can emit structured tool_calls instead of erroring.
-    let tools_spec = serde_json::json!([
-        {
-            "type": "function",
-            "function": {
-                "name": "read_file",
-                "description": "Read a UTF-8 text file up to a safe size.",
-                "parameters": {
-                    "type": "object",
-                    "properties": {"path": {"type": "string"}},
-                    "required": ["path"]
-                }
-            }
-        },
-        {
-            "type": "function",
-            "function": {
-                "name": "json",
-                "description": r#"Wrap another tool call as { "tool": string, "arguments": object }."#,
-                "parameters": {
-                    "type": "object",
-                    "properties": {
-                        "tool": {"type": "string"},
-                        "arguments": {"type": "object"}
-                    },
-                    "required": ["tool", "arguments"]
-                }
-            }
-        },
-        {
-            "type": "function",
-            "function": {
-                "name": "write_file",
-                "description": "Create or overwrite a UTF-8 text file.",
-                "parameters": {
-                    "type": "object",
-                    "properties": {"path": {"type": "string"}, "content": {"type": "string"}},
-                    "required": ["path", "content"]
-                }
-            }
-        },
-        {
-            "type": "function",
-            "function": {
-                "name": "execute_command",
-                "description": "Run a safe shell command in the workspace/home.",
-                "parameters": {
-                    "type": "object",
-                    "properties": {"command": {"type": "string"}, "cwd": {"type": "string"}},
-                    "required": ["command"]
-                }
-            }
-        }
-    ]);
-
-    let assistant_reply = match (&eff.connection, &http_client) {
-        (ProviderConnection::Http(_), Some(client)) => {
-            client
-                .chat_once_messages_with_tools(
-                    &eff.model,
-                    &[
-                        Msg {
-                            role: "system",
-                            content: &system_prompt,
-                        },
-                        Msg {
-                            role: "user",
-                            content: &user_msg,
-                        },
-                    ],
-                    tools_spec,
-                    cli.debug,
-                )
-                .await?
-        }
-        (ProviderConnection::Cli(cli_conn), _) => {
-            let timeout = cli_conn
-                .request_timeout_secs
-                .map(Duration::from_secs)
-                .unwrap_or_else(|| Duration::from_secs(DEFAULT_REQUEST_TIMEOUT_SECS));
-            let text = run_cli_completion(CliCompletionRequest {
-                engine: cli_conn.engine,
-                binary: &cli_conn.binary,
-                base_args: &cli_conn.base_args,
-                system_prompt: &system_prompt,
-                user_prompt: &user_msg,
-                model: &eff.model,
-                reasoning_effort: eff.reasoning_effort.as_deref(),
-                debug: cli.debug,
-                timeout,
-            })
-            .await?;
-            AssistantReply::Content(text)
-        }
-        _ => unreachable!("Provider/client mismatch"),
+    let mut terminal = Terminal {
+        opts: tool_opts,
+        quiet: cli.quiet,
+        cfg: &mut cfg,
+        cfg_path: &path,
     };
+    let outcome = run_qa_with_hooks(opts, &mut terminal)
+        .await
+        .map_err(with_api_key_hint)?;
 
-    match assistant_reply {
-        AssistantReply::ToolCall {
-            name,
-            arguments_json,
-        } => {
-            let handled = execute_tool_call(
-                name.as_str(),
-                &arguments_json,
-                cli.yes,
-                cli.debug,
-                shell_kind,
-                &mut cfg,
-                &path,
-            )
-            .await?;
-            if !handled {
-                println!("Unknown tool call: {}", name);
-            }
+    match outcome {
+        QaOutcome::Answer(text) => println!("{}", text),
+        QaOutcome::Plan(Some(reply)) => print_plan(&reply, tool_opts.color),
+        QaOutcome::Plan(None) => {
+            eprintln!("[warn] The model tried to call a tool in --plan mode; nothing was run.")
         }
-        AssistantReply::Content(assistant) => {
-            if cli.debug {
-                eprintln!("[debug] assistant raw: {}", assistant);
-            }
-            // Try to parse as a tool call per our plain-JSON protocol; else print the answer.
-            match parse_tool_call(assistant.trim()) {
-                Ok(call) => match call {
-                    ToolCall::ReadFile(args) => match qqqa::tools::read_file::run(args) {
-                        Ok(content) => print_tool_result("read_file", &content),
-                        Err(e) => print_tool_error("read_file", &e.to_string()),
-                    },
-                    ToolCall::WriteFile(args) => match qqqa::tools::write_file::run(args) {
-                        Ok(summary) => print_tool_result("write_file", &summary),
-                        Err(e) => print_tool_error("write_file", &e.to_string()),
-                    },
-                    ToolCall::ExecuteCommand(args) => match run_execute_command_with_allowlist(
-                        args, cli.yes, cli.debug, shell_kind, &mut cfg, &path,
-                    )
-                    .await
-                    {
-                        Ok(result) => print_execute_command_result(&result, cli.debug),
-                        Err(e) => print_tool_error("execute_command", &e.to_string()),
-                    },
-                },
-                Err(_) => {
-                    println!("{}", assistant.trim_end());
-                }
+        QaOutcome::UnknownTool(name) => println!("Unknown tool call: {}", name),
+        QaOutcome::CommandSummary { summary, .. } => {
+            if tool_opts.tool_headers {
+                println!("{}", format_summary_header(tool_opts.color));
             }
+            println!("{}", summary);
         }
+        QaOutcome::BudgetExceeded { reason, .. } => return Err(anyhow!(reason)),
+        // Already printed by the hooks when the tool finished.
+        QaOutcome::ToolOutput { .. } | QaOutcome::ToolError { .. } => {}
     }
 
     Ok(())
 }
 
-#[derive(Debug, Clone)]
-struct ExecuteCommandResult {
-    summary: String,
-    streamed_live: bool,
+/// Append setup steps to a missing-API-key error.
+fn with_api_key_hint(err: anyhow::Error) -> anyhow::Error {
+    let msg = err.to_string();
+    if !msg.contains("Missing API key") {
+        return err;
+    }
+    anyhow!(
+        "{}\n\nFix it quickly:\n- Run `qa --init` and choose provider; optionally paste the API key.\n- Or export an env var, e.g.\n    export OPENROUTER_API_KEY=... # OpenRouter (default)\n    export GROQ_API_KEY=...       # Groq\n    export OPENAI_API_KEY=...     # OpenAI\n    export ANTHROPIC_API_KEY=...  # Anthropic (Claude)",
+        msg
+    )
 }
 
-async fn run_execute_command_with_allowlist(
-    args: qqqa::tools::execute_command::Args,
+/// How tool results are shown.
+#[derive(Debug, Clone, Copy)]
+struct ToolOptions {
     auto_yes: bool,
     debug: bool,
-    shell: ShellKind,
-    cfg: &mut Config,
-    cfg_path: &Path,
-) -> Result<ExecuteCommandResult> {
-    let mut base_dir = std::env::current_dir().context("Failed to read current directory")?;
-    if let Ok(canon) = base_dir.canonicalize() {
-        base_dir = canon;
-    }
-
-    let original_args = args;
-    loop {
-        let mut stream_printer = |chunk: qqqa::tools::execute_command::StreamChunk| match chunk.kind
-        {
-            qqqa::tools::execute_command::StreamKind::Stdout => {
-                let mut handle = std::io::stdout();
-                let _ = handle.write_all(chunk.data);
-                let _ = handle.flush();
-            }
-            qqqa::tools::execute_command::StreamKind::Stderr => {
-                let mut handle = std::io::stderr();
-                let _ = handle.write_all(chunk.data);
-                let _ = handle.flush();
-            }
+    /// Paint live stderr chunks so they stand apart from stdout.
+    color_stderr: bool,
+    /// Style tool headers and execute_command sections (off for `--raw` / `NO_COLOR`).
+    color: bool,
+    /// Prefix read_file output with line numbers.
+    line_numbers: bool,
+    /// Print `[tool:NAME]` headers (off for `--no-tool-headers`).
+    tool_headers: bool,
+}
+
+/// [`QaHooks`] for a person at the terminal: prints tool output as it arrives and asks
+/// before oversized writes and non-allowlisted commands.
+struct Terminal<'a> {
+    opts: ToolOptions,
+    quiet: bool,
+    cfg: &'a mut Config,
+    cfg_path: &'a Path,
+}
+
+impl QaHooks for Terminal<'_> {
+    fn notice(&mut self, message: &str) {
+        if !self.quiet {
+            eprintln!("{}", message);
+        }
+    }
+
+    fn tool_requested(&mut self, _name: &str, preamble: Option<&str>, reason: Option<&str>) {
+        if let Some(text) = preamble {
+            println!("{}", format_tool_preamble(text, self.opts.color));
+        }
+        if let Some(reason) = reason {
+            println!("{}", format_tool_reason(reason, self.opts.color));
+        }
+    }
+
+    /// Only asks when a person is at the terminal and `--yes` was not given; otherwise the
+    /// size error stands.
+    fn confirm_large_write(&mut self, path: &str, size: usize, limit: usize) -> Result<bool> {
+        if self.opts.auto_yes || !atty::is(atty::Stream::Stdin) {
+            return Ok(false);
+        }
+        prompt_write_large_file(path, size, limit)
+    }
+
+    fn execute_command(
+        &mut self,
+        args: execute_command::Args,
+        opts: InteractiveOptions,
+    ) -> impl Future<Output = Result<ExecuteCommandResult>> {
+        let opts = InteractiveOptions {
+            color_stderr: self.opts.color_stderr,
+            ..opts
         };
-        let mut on_chunk: Option<
-            &mut dyn for<'chunk> FnMut(qqqa::tools::execute_command::StreamChunk<'chunk>),
-        > = Some(&mut stream_printer);
-        let streaming_enabled = on_chunk.is_some();
-        let exec_args = sanitize_execute_args(original_args.clone(), &base_dir, debug);
-        match qqqa::tools::execute_command::run(
-            exec_args,
-            auto_yes,
-            debug,
-            shell,
-            on_chunk.as_deref_mut(),
-        )
-        .await
-        {
-            Ok(summary) => {
-                return Ok(ExecuteCommandResult {
-                    summary,
-                    streamed_live: streaming_enabled,
-                });
-            }
-            Err(err) => {
-                if let Some(program) = err
-                    .downcast_ref::<perms::CommandNotAllowedError>()
-                    .map(|e| e.program.clone())
-                {
-                    if !atty::is(atty::Stream::Stdin) {
-                        return Err(err);
-                    }
-                    if prompt_add_command_to_allowlist(&program)? {
-                        let inserted = cfg.add_command_to_allowlist(&program);
-                        if inserted {
-                            cfg.save(cfg_path, debug)?;
-                        }
-                        perms::set_custom_allowlist(cfg.command_allowlist());
-                        if inserted {
-                            if debug {
-                                eprintln!("[debug] Added '{}' to qa command allowlist", program);
-                            } else {
-                                eprintln!("Added '{}' to qa command allowlist.", program);
-                            }
-                        }
-                        continue;
-                    } else {
-                        return Err(err);
-                    }
-                } else {
-                    return Err(err);
-                }
+        run_with_allowlist_prompt(args, opts, self.cfg, self.cfg_path)
+    }
+
+    fn tool_output(&mut self, tool: &str, output: &str, streamed_live: bool) {
+        match tool {
+            execute_command::NAME => print_execute_command_result(
+                &ExecuteCommandResult {
+                    summary: output.to_string(),
+                    streamed_live,
+                },
+                self.opts,
+            ),
+            read_file::NAME if self.opts.line_numbers => {
+                print_tool_result(tool, &number_lines(output), self.opts)
             }
+            _ => print_tool_result(tool, output, self.opts),
         }
     }
+
+    fn tool_error(&mut self, tool: &str, error: &str) {
+        print_tool_error(tool, error, self.opts);
+    }
 }
 
-fn prompt_add_command_to_allowlist(program: &str) -> Result<bool> {
+fn prompt_write_large_file(path: &str, size: usize, limit: usize) -> Result<bool> {
     use std::io::{self, Write};
 
     eprint!(
-        "Command '{}' is not in the qa allowlist. Add it now and retry? [y/N]: ",
-        program
+        "write_file wants to write {} bytes to {} (limit {} bytes). Write anyway? [y/N]: ",
+        size, path, limit
     );
     io::stderr().flush().context("Failed to flush prompt")?;
     let mut line = String::new();
@@ -457,178 +477,156 @@ fn prompt_add_command_to_allowlist(program: &str) -> Result<bool> {
     Ok(choice == "y" || choice == "yes")
 }
 
-fn sanitize_execute_args(
-    args: qqqa::tools::execute_command::Args,
-    base_dir: &Path,
-    debug: bool,
-) -> qqqa::tools::execute_command::Args {
-    let (sanitized_path, fell_back) = sanitize_cwd_path(args.cwd.as_deref(), base_dir);
-    let sanitized_str = sanitized_path.to_string_lossy().to_string();
-
-    if fell_back && debug {
-        if let Some(original) = args.cwd.as_deref() {
-            eprintln!(
-                "[debug] Ignoring requested working directory '{}' and using {} instead",
-                original, sanitized_str
-            );
-        } else {
-            eprintln!(
-                "[debug] Using current working directory {} for execute_command",
-                sanitized_str
-            );
-        }
+fn print_tool_result(tool: &str, result: &str, opts: ToolOptions) {
+    if opts.tool_headers {
+        println!("{}", format_tool_header(tool, false, opts.color));
     }
+    println!("{}", result.trim_end());
+}
 
-    qqqa::tools::execute_command::Args {
-        command: args.command,
-        cwd: Some(sanitized_str),
+/// Errors keep their header; without tool headers they move to stderr so piped stdout
+/// carries only results.
+fn print_tool_error(tool: &str, err: &str, opts: ToolOptions) {
+    let line = format!("{} {}", format_tool_header(tool, true, opts.color), err);
+    if opts.tool_headers {
+        println!("{}", line);
+    } else {
+        eprintln!("{}", line);
     }
 }
 
-fn sanitize_cwd_path(requested: Option<&str>, base_dir: &Path) -> (PathBuf, bool) {
-    let base = base_dir.to_path_buf();
-    let Some(raw) = requested else {
-        return (base, false);
-    };
-    let trimmed = raw.trim();
-    if trimmed.is_empty() {
-        return (base, false);
-    }
-    let path = Path::new(trimmed);
-    if path.is_absolute() {
-        if let Ok(canon) = std::fs::canonicalize(path) {
-            if canon.starts_with(base_dir) {
-                return (canon, false);
-            }
-        }
-        return (base, true);
-    }
-
-    let mut relative = PathBuf::new();
-    for component in path.components() {
-        match component {
-            std::path::Component::CurDir => {}
-            std::path::Component::Normal(seg) => relative.push(seg),
-            std::path::Component::ParentDir
-            | std::path::Component::RootDir
-            | std::path::Component::Prefix(_) => {
-                return (base, true);
-            }
-        }
-    }
-    if relative.as_os_str().is_empty() {
-        return (base, false);
+fn print_execute_command_result(result: &ExecuteCommandResult, opts: ToolOptions) {
+    if let Some(msg) = format_execute_command_result(result, opts.debug) {
+        let msg = if opts.color {
+            paint_execute_command_summary(&msg)
+        } else {
+            msg
+        };
+        print_tool_result("execute_command", &msg, opts);
     }
-    (base_dir.join(relative), false)
 }
 
-async fn execute_tool_call(
-    name: &str,
-    arguments_json: &str,
-    auto_yes: bool,
-    debug: bool,
-    shell: ShellKind,
-    cfg: &mut Config,
-    cfg_path: &Path,
-) -> Result<bool> {
-    if debug {
-        eprintln!("[debug] tool call '{}' args: {}", name, arguments_json);
-    }
-    let mut current_name = name.to_string();
-    let mut current_args = arguments_json.to_string();
-
-    loop {
-        match current_name.as_str() {
-            "read_file" => {
-                let normalized = normalize_tool_arguments(&current_args)?;
-                let args: qqqa::tools::read_file::Args = serde_json::from_str(&normalized)
-                    .map_err(|e| anyhow!("Failed to parse read_file args: {}", e))?;
-                match qqqa::tools::read_file::run(args) {
-                    Ok(content) => print_tool_result("read_file", &content),
-                    Err(e) => print_tool_error("read_file", &e.to_string()),
-                }
-                return Ok(true);
-            }
-            "write_file" => {
-                let normalized = normalize_tool_arguments(&current_args)?;
-                let args: qqqa::tools::write_file::Args = serde_json::from_str(&normalized)
-                    .map_err(|e| anyhow!("Failed to parse write_file args: {}", e))?;
-                match qqqa::tools::write_file::run(args) {
-                    Ok(summary) => print_tool_result("write_file", &summary),
-                    Err(e) => print_tool_error("write_file", &e.to_string()),
-                }
-                return Ok(true);
-            }
-            "execute_command" => {
-                let normalized = normalize_tool_arguments(&current_args)?;
-                let args: qqqa::tools::execute_command::Args = serde_json::from_str(&normalized)
-                    .map_err(|e| anyhow!("Failed to parse execute_command args: {}", e))?;
-                match run_execute_command_with_allowlist(
-                    args, auto_yes, debug, shell, cfg, cfg_path,
-                )
-                .await
-                {
-                    Ok(result) => print_execute_command_result(&result, debug),
-                    Err(e) => print_tool_error("execute_command", &e.to_string()),
-                }
-                return Ok(true);
-            }
-            "json" => {
-                let inner: serde_json::Value = serde_json::from_str(&current_args)
-                    .map_err(|e| anyhow!("Failed to parse json wrapper: {}", e))?;
-                let tool_name = inner
-                    .get("tool")
-                    .and_then(|v| v.as_str())
-                    .ok_or_else(|| anyhow!("Wrapped json tool missing 'tool' field"))?;
-                let arguments = inner
-                    .get("arguments")
-                    .cloned()
-                    .unwrap_or_else(|| serde_json::json!({}));
-                current_name = tool_name.to_string();
-                current_args = serde_json::to_string(&arguments)?;
-                continue;
-            }
-            _ => return Ok(false),
-        }
+/// Explanatory text sent alongside a tool call, dimmed so the tool output stays primary.
+fn format_tool_preamble(text: &str, color: bool) -> String {
+    let text = text.trim();
+    if color {
+        Style::new().dimmed().paint(text).to_string()
+    } else {
+        text.to_string()
     }
 }
 
-fn print_tool_result(tool: &str, result: &str) {
-    println!("[tool:{}]", tool);
-    println!("{}", result.trim_end());
+/// `why: <reason>` line printed before a tool runs under `--explain`.
+fn format_tool_reason(reason: &str, color: bool) -> String {
+    if color {
+        format!("{} {}", Color::Yellow.bold().paint("why:"), reason)
+    } else {
+        format!("why: {}", reason)
+    }
 }
 
-fn print_tool_error(tool: &str, err: &str) {
-    println!("[tool:{}:error] {}", tool, err);
+/// Print a `--plan` reply as numbered steps, or as-is when it is not a JSON plan.
+fn print_plan(reply: &str, color: bool) {
+    match parse_plan(reply) {
+        Ok(steps) => print!("{}", format_plan(&steps, color)),
+        Err(err) => {
+            eprintln!("[warn] {}; showing the reply as-is.", err);
+            println!("{}", reply.trim_end());
+        }
+    }
 }
 
-fn normalize_tool_arguments(raw: &str) -> Result<String> {
-    // If the raw blob already matches our expected schema, leave it alone.
-    // Otherwise, detect legacy wrappers of the shape {"tool": ..., "arguments": {...}}
-    // and unwrap them so serde can deserialize into the tool arg structs.
-    #[derive(serde::Deserialize)]
-    struct LegacyWrapper {
-        tool: Option<String>,
-        arguments: serde_json::Value,
+/// `--plan` view: one numbered line per step with its tool call indented below.
+fn format_plan(steps: &[PlanStep], color: bool) -> String {
+    let heading = "Plan (nothing was executed):";
+    let mut out = if color {
+        Style::new().bold().paint(heading).to_string()
+    } else {
+        heading.to_string()
+    };
+    out.push('\n');
+    if steps.is_empty() {
+        out.push_str("(no steps)\n");
     }
-
-    let trimmed = raw.trim_start();
-    if !trimmed.starts_with('{') {
-        return Ok(raw.to_string());
+    for (i, step) in steps.iter().enumerate() {
+        let description = if step.description.is_empty() {
+            step.tool.as_str()
+        } else {
+            step.description.as_str()
+        };
+        out.push_str(&format!("{}. {}\n", i + 1, description));
+        let mut call = format!("{} {}", step.tool, step.arguments);
+        if !TOOL_NAMES.contains(&step.tool.as_str()) {
+            call.push_str(" (unknown tool)");
+        }
+        if color {
+            call = Color::Cyan.paint(call).to_string();
+        }
+        out.push_str(&format!("   {}\n", call));
     }
+    out
+}
 
-    match serde_json::from_str::<LegacyWrapper>(trimmed) {
-        Ok(wrapper) if wrapper.tool.is_some() => Ok(wrapper.arguments.to_string()),
-        _ => Ok(raw.to_string()),
+/// `[tool:name]` (cyan) or `[tool:name:error]` (red).
+fn format_tool_header(tool: &str, error: bool, color: bool) -> String {
+    let header = if error {
+        format!("[tool:{}:error]", tool)
+    } else {
+        format!("[tool:{}]", tool)
+    };
+    match (color, error) {
+        (false, _) => header,
+        (true, false) => Color::Cyan.bold().paint(header).to_string(),
+        (true, true) => Color::Red.bold().paint(header).to_string(),
     }
 }
 
-fn print_execute_command_result(result: &ExecuteCommandResult, debug: bool) {
-    if let Some(msg) = format_execute_command_result(result, debug) {
-        print_tool_result("execute_command", &msg);
+/// `[summary]` header for `--summarize`, styled like the tool headers.
+fn format_summary_header(color: bool) -> String {
+    if color {
+        Color::Cyan.bold().paint("[summary]").to_string()
+    } else {
+        "[summary]".to_string()
     }
 }
 
+/// Right-aligned `N | ` gutter, sized to the last line number.
+fn number_lines(content: &str) -> String {
+    let total = content.lines().count();
+    let width = total.max(1).to_string().len();
+    content
+        .lines()
+        .enumerate()
+        .map(|(idx, line)| format!("{:>width$} | {}", idx + 1, line, width = width))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Color the structural lines of an execute_command summary: the exit code
+/// (green on 0, red otherwise) and the stdout/stderr section markers.
+fn paint_execute_command_summary(summary: &str) -> String {
+    summary
+        .lines()
+        .map(|line| {
+            if let Some(code) = line.strip_prefix("Exit code: ") {
+                let color = if code.trim() == "0" {
+                    Color::Green
+                } else {
+                    Color::Red
+                };
+                color.bold().paint(line).to_string()
+            } else if line == "--- stdout ---" {
+                Color::Cyan.paint(line).to_string()
+            } else if line == "--- stderr ---" {
+                Color::Yellow.paint(line).to_string()
+            } else {
+                line.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
 fn format_execute_command_result(result: &ExecuteCommandResult, debug: bool) -> Option<String> {
     let _ = debug;
     if result.streamed_live {
@@ -638,86 +636,99 @@ fn format_execute_command_result(result: &ExecuteCommandResult, debug: bool) ->
     }
 }
 
-fn read_all_stdin(mut stdin: Stdin) -> Result<String> {
-    let mut buf = String::new();
-    stdin.read_to_string(&mut buf)?;
-    Ok(buf)
+fn read_all_stdin(mut stdin: Stdin, debug: bool) -> Result<String> {
+    let mut buf = Vec::new();
+    stdin.read_to_end(&mut buf)?;
+    let (text, lossy) = decode_stdin_lossy(buf);
+    if lossy {
+        debug_log!(
+            debug,
+            "stdin was not valid UTF-8; invalid bytes were replaced with U+FFFD"
+        );
+    }
+    Ok(text)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use qqqa::tools::execute_command::{sanitize_cwd_path, sanitize_execute_args};
+    use qqqa::tools::normalize_tool_arguments;
     use std::path::Path;
     use tempfile::tempdir;
 
-    #[tokio::test]
-    async fn execute_tool_call_handles_json_wrapper() {
-        let dir = tempdir().unwrap();
-        unsafe {
-            std::env::set_var("HOME", dir.path());
-        }
-        std::env::set_current_dir(dir.path()).unwrap();
-
-        let file_path = dir.path().join("hello.txt");
-        std::fs::write(&file_path, "hi there").unwrap();
-
-        let mut cfg = Config::default();
-        let cfg_path = dir.path().join("config.json");
-
-        let payload = serde_json::json!({
-            "tool": "read_file",
-            "arguments": { "path": "hello.txt" }
-        });
-
-        let result = execute_tool_call(
-            "json",
-            &payload.to_string(),
-            false,
-            false,
-            ShellKind::Posix,
-            &mut cfg,
-            &cfg_path,
-        )
-        .await
-        .expect("json wrapper should succeed");
-        assert!(result, "json wrapper should dispatch an inner tool");
+    #[test]
+    fn format_tool_preamble_trims_and_dims_when_colored() {
+        assert_eq!(format_tool_preamble("  Checking.\n", false), "Checking.");
+        assert_eq!(
+            format_tool_preamble("Checking.", true),
+            Style::new().dimmed().paint("Checking.").to_string()
+        );
     }
 
-    #[tokio::test]
-    async fn execute_tool_call_handles_nested_json_wrapper() {
-        let dir = tempdir().unwrap();
-        unsafe {
-            std::env::set_var("HOME", dir.path());
-        }
-        std::env::set_current_dir(dir.path()).unwrap();
-
-        let file_path = dir.path().join("data.txt");
-        std::fs::write(&file_path, "content").unwrap();
-
-        let mut cfg = Config::default();
-        let cfg_path = dir.path().join("config.json");
-
-        let inner = serde_json::json!({
-            "tool": "read_file",
-            "arguments": { "path": "data.txt" }
-        });
-        let payload = serde_json::json!({
-            "tool": "json",
-            "arguments": inner
-        });
-
-        let result = execute_tool_call(
-            "json",
-            &payload.to_string(),
-            false,
-            false,
-            ShellKind::Posix,
-            &mut cfg,
-            &cfg_path,
-        )
-        .await
-        .expect("nested json wrapper should succeed");
-        assert!(result, "nested json wrapper should dispatch an inner tool");
+    #[test]
+    fn format_tool_header_stays_plain_without_color() {
+        assert_eq!(
+            format_tool_header("read_file", false, false),
+            "[tool:read_file]"
+        );
+        assert_eq!(
+            format_tool_header("read_file", true, false),
+            "[tool:read_file:error]"
+        );
+        let colored = format_tool_header("read_file", false, true);
+        assert!(colored.starts_with("\x1b["));
+        assert!(colored.contains("[tool:read_file]"));
+    }
+
+    #[test]
+    fn format_plan_numbers_steps_and_flags_unknown_tools() {
+        let steps = vec![
+            PlanStep {
+                description: "Read the manifest".into(),
+                tool: "read_file".into(),
+                arguments: serde_json::json!({"path": "Cargo.toml"}),
+            },
+            PlanStep {
+                description: String::new(),
+                tool: "delete_everything".into(),
+                arguments: serde_json::json!({}),
+            },
+        ];
+        assert_eq!(
+            format_plan(&steps, false),
+            "Plan (nothing was executed):\n1. Read the manifest\n   read_file {\"path\":\"Cargo.toml\"}\n2. delete_everything\n   delete_everything {} (unknown tool)\n"
+        );
+    }
+
+    #[test]
+    fn number_lines_pads_gutter_to_widest_number() {
+        let content = (1..=10)
+            .map(|n| format!("l{}", n))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let numbered = number_lines(&content);
+        let lines: Vec<&str> = numbered.lines().collect();
+        assert_eq!(lines[0], " 1 | l1");
+        assert_eq!(lines[9], "10 | l10");
+    }
+
+    #[test]
+    fn paint_execute_command_summary_colors_only_structural_lines() {
+        let painted = paint_execute_command_summary(
+            "Exit code: 2\n--- stdout ---\nhello\n--- stderr ---\nboom",
+        );
+        let lines: Vec<&str> = painted.lines().collect();
+        assert_eq!(
+            lines[0],
+            Color::Red.bold().paint("Exit code: 2").to_string()
+        );
+        assert_eq!(lines[1], Color::Cyan.paint("--- stdout ---").to_string());
+        assert_eq!(lines[2], "hello");
+        assert_eq!(lines[3], Color::Yellow.paint("--- stderr ---").to_string());
+        assert_eq!(lines[4], "boom");
+        let ok = paint_execute_command_summary("Exit code: 0");
+        assert_eq!(ok, Color::Green.bold().paint("Exit code: 0").to_string());
     }
 
     #[test]
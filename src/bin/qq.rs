@@ -1,29 +1,46 @@
-use anyhow::{Result, anyhow};
-use clap::{ArgAction, Parser};
+use anyhow::{Context, Result, anyhow};
+use clap::{ArgAction, ArgGroup, Parser};
+use futures_util::{StreamExt, stream};
 use qqqa::ai::{
-    ChatClient, CliCompletionRequest, DEFAULT_REQUEST_TIMEOUT_SECS, Msg, run_cli_completion,
-    run_cli_completion_streaming,
+    ChatClient, CliCompletionRequest, DEFAULT_REQUEST_TIMEOUT_SECS, Msg, is_provider_failure,
+    run_cli_completion, run_cli_completion_streaming,
 };
 use qqqa::clipboard;
-use qqqa::config::{Config, InitExistsError, ProviderConnection};
+use qqqa::config::{
+    Config, EffectiveProfile, InitExistsError, MODEL_ENV, ProfileDraft, ProfileSummary,
+    ProviderConnection, config_dir, env_model_override,
+};
 use qqqa::formatting::{
-    StreamingFormatter, print_assistant_text, print_stream_token, render_xmlish_to_ansi,
-    start_loading_animation,
+    OutputFormat, StreamingFormatter, print_assistant_text, render_answer, render_xmlish_to_ansi,
+    start_loading_animation, stdout_closed, write_stdout,
+};
+use qqqa::last_session::{StoredRun, load_last_run, save_last_run};
+use qqqa::prompt::{decode_stdin_lossy, is_topic_refusal, persona_directive};
+use qqqa::run::{
+    ContextFlags, ProfileOverrides, apply_command_policy, build_http_client, cli_completion,
+    command_timeout, context_options, log_resolved_profile, parse_param_flag,
+    prepare_prompt_inputs, prompt_history, qq_prompts, resolve_fallback_profile,
+    resolve_run_profile, run_notices,
 };
-use qqqa::history::read_recent_history;
-use qqqa::prompt::{build_qq_system_prompt, build_qq_user_message, coalesce_prompt_inputs};
-use qqqa::shell::{detect_shell, shell_hint_for_prompt};
+use qqqa::shell::{ShellKind, environment_debug_line, resolve_shell};
+use qqqa::tokens::TokenBreakdown;
+use qqqa::tools::execute_command::{self, InteractiveOptions, run_with_allowlist_prompt};
+use qqqa::{debug_log, logging};
+use serde::Serialize;
 use std::ffi::OsString;
-use std::io::Write as _;
 use std::io::{Read, Stdin};
-use std::time::Duration;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 /// qq — ask an LLM assistant a question
 ///
 /// - Reads optional terminal history and piped stdin as context.
 /// - Sends a single user prompt to an OpenAI-compatible endpoint.
 #[derive(Debug, Parser)]
-#[command(name = "qq", disable_colored_help = false, version, about)]
+#[command(name = "qq", disable_colored_help = false, version = qqqa::version::LONG_VERSION, about)]
+// A repeated flag keeps its last value, so `--replay -m other` overrides the stored `-m`.
+#[command(args_override_self = true)]
+#[command(group(ArgGroup::new("json_output").args(["batch", "dry_run"]).multiple(true)))]
 struct Cli {
     /// Initialize or reinitialize configuration (~/.qq/config.json) and exit
     #[arg(long = "init", action = ArgAction::SetTrue)]
@@ -45,9 +62,13 @@ struct Cli {
     profile: Option<String>,
 
     /// Override the resolved provider base URL (e.g., http://localhost:11434/v1)
-    #[arg(long = "api-base")]
+    #[arg(long = "api-base", alias = "base-url")]
     api_base: Option<String>,
 
+    /// API key for this run (overrides config and provider env vars; also QQQA_API_KEY)
+    #[arg(long = "api-key", value_name = "KEY")]
+    api_key: Option<String>,
+
     /// Override the model ID from profile
     #[arg(short = 'm', long = "model")]
     model: Option<String>,
@@ -59,15 +80,75 @@ struct Cli {
     #[arg(long = "history", action = ArgAction::SetTrue, conflicts_with = "no_history")]
     history: bool,
 
+    /// Send history and piped stdin verbatim instead of masking likely secrets
+    #[arg(long = "no-redact", action = ArgAction::SetTrue)]
+    no_redact: bool,
+
+    /// Only include history from the last N minutes (implies --history; zsh timestamps)
+    #[arg(
+        long = "history-since",
+        value_name = "MINUTES",
+        conflicts_with = "no_history"
+    )]
+    history_since: Option<u64>,
+
+    /// Cap history + piped input context at this many bytes (keeps the most recent)
+    #[arg(long = "max-context-bytes", value_name = "BYTES")]
+    max_context_bytes: Option<usize>,
+
+    /// Label for piped input in the prompt (default "Input from pipe"), e.g. "JSON payload"
+    #[arg(long = "stdin-label", value_name = "TEXT")]
+    stdin_label: Option<String>,
+
+    /// Use piped stdin as the whole question; trailing words are rejected instead of becoming the question
+    #[arg(
+        long = "stdin-only",
+        action = ArgAction::SetTrue,
+        conflicts_with_all = ["question", "stdin_label", "batch", "ping", "replay"]
+    )]
+    stdin_only: bool,
+
+    /// Stamp the prompt's date/time in local time with its UTC offset instead of UTC
+    #[arg(long = "local-time")]
+    local_time: bool,
+
+    /// Add a capped listing of the current directory's top-level entries to the prompt
+    #[arg(long = "cwd-context", alias = "include-cwd-listing")]
+    cwd_context: bool,
+
+    /// Leave the operating system and date/time lines out of the prompt
+    #[arg(long = "no-os-line")]
+    no_os_line: bool,
+
     /// Disable streaming and wait for the full response before printing
-    #[arg(long = "no-stream", action = ArgAction::SetTrue)]
+    #[arg(long = "no-stream", action = ArgAction::SetTrue, conflicts_with = "stream")]
     no_stream: bool,
 
+    /// Stream even if `default_no_stream` is set in config
+    #[arg(long = "stream", action = ArgAction::SetTrue, conflicts_with = "no_stream")]
+    stream: bool,
+
     /// Temperature for this run (overrides profile/default)
     #[arg(long = "temperature")]
     temperature: Option<f32>,
 
-    /// Auto-copy the first recommended command for this run
+    /// Frequency penalty for this run, -2.0 to 2.0 (overrides profile; not sent to gpt-5/o1/o3)
+    #[arg(
+        long = "frequency-penalty",
+        value_name = "N",
+        allow_hyphen_values = true
+    )]
+    frequency_penalty: Option<f32>,
+
+    /// Presence penalty for this run, -2.0 to 2.0 (overrides profile; not sent to gpt-5/o1/o3)
+    #[arg(
+        long = "presence-penalty",
+        value_name = "N",
+        allow_hyphen_values = true
+    )]
+    presence_penalty: Option<f32>,
+
+    /// Auto-copy the first recommended command for this run (alias: --cc, -cc)
     #[arg(
         long = "copy-command",
         alias = "cc",
@@ -87,10 +168,110 @@ struct Cli {
     )]
     no_copy_command: bool,
 
-    /// Print raw text (no formatting)
-    #[arg(short = 'r', long = "raw", action = ArgAction::SetTrue)]
+    /// After answering, offer to run the first suggested command (asks before executing)
+    #[arg(long = "run", action = ArgAction::SetTrue)]
+    run: bool,
+
+    /// Print only the first suggested command, unformatted, to stdout (errors if there is none)
+    #[arg(
+        long = "command-only",
+        action = ArgAction::SetTrue,
+        conflicts_with_all = ["run", "batch", "ping", "dry_run", "count_tokens"]
+    )]
+    command_only: bool,
+
+    /// Answer rendering: xml (colored tags, default), markdown, or plain (`default_format` in config)
+    #[arg(long = "format", value_name = "FORMAT", conflicts_with_all = ["raw", "no_raw"])]
+    format: Option<OutputFormat>,
+
+    /// Print plain text without formatting (same as --format plain)
+    #[arg(short = 'r', long = "raw", action = ArgAction::SetTrue, conflicts_with = "no_raw")]
     raw: bool,
 
+    /// Format output even if `default_raw` is set in config
+    #[arg(long = "no-raw", action = ArgAction::SetTrue, conflicts_with = "raw")]
+    no_raw: bool,
+
+    /// Send a tiny request to the resolved provider and report status and latency
+    #[arg(long = "ping", action = ArgAction::SetTrue)]
+    ping: bool,
+
+    /// Send only the user message, without qq's system prompt (for experiments)
+    #[arg(long = "no-system", action = ArgAction::SetTrue)]
+    no_system: bool,
+
+    /// List configured profiles (default marked with *) with provider, model, and key status
+    #[arg(long = "list-profiles", action = ArgAction::SetTrue)]
+    list_profiles: bool,
+
+    /// Save the current -p/--model/--temperature/--param/... overrides as a new profile and exit
+    #[arg(
+        long = "save-profile",
+        value_name = "NAME",
+        conflicts_with_all = ["batch", "ping", "replay", "api_key", "headers"]
+    )]
+    save_profile: Option<String>,
+
+    /// With --save-profile, overwrite an existing profile of that name
+    #[arg(long = "force", action = ArgAction::SetTrue, requires = "save_profile")]
+    force: bool,
+
+    /// Print the built-in default config as JSON (writes nothing) and exit
+    #[arg(long = "print-default-config", action = ArgAction::SetTrue)]
+    print_default_config: bool,
+
+    /// Re-run the last qq question with its flags under the current config; flags given here are added
+    #[arg(long = "replay", action = ArgAction::SetTrue, conflicts_with_all = ["question", "batch", "ping"])]
+    replay: bool,
+
+    /// Load provider keys from this env file (KEY=VALUE) in addition to ~/.qq/.env
+    #[arg(long = "env-file", value_name = "PATH")]
+    env_file: Option<PathBuf>,
+
+    /// Ask every non-empty line of FILE as its own question (4 at a time) and print all answers
+    #[arg(long = "batch", value_name = "FILE", conflicts_with = "ping")]
+    batch: Option<PathBuf>,
+
+    /// Few-shot example file: user text, a `---` line, then the assistant reply (repeatable)
+    #[arg(long = "example", value_name = "FILE", action = ArgAction::Append)]
+    example: Vec<PathBuf>,
+
+    /// Print the assembled prompt messages and exit without calling the provider
+    #[arg(long = "dry-run", action = ArgAction::SetTrue, conflicts_with_all = ["batch", "ping"])]
+    dry_run: bool,
+
+    /// Append a built-in persona directive to the system prompt (concise, verbose, scripts-only)
+    #[arg(long = "persona", value_name = "NAME", value_parser = persona_directive)]
+    persona: Option<&'static str>,
+
+    /// Estimate the assembled prompt's token count (system, history, stdin, question) and exit without calling the provider
+    #[arg(long = "count-tokens", action = ArgAction::SetTrue, conflicts_with_all = ["batch", "ping", "dry_run"])]
+    count_tokens: bool,
+
+    /// With --batch or --dry-run, print JSON to stdout instead of labeled text
+    #[arg(long = "json", action = ArgAction::SetTrue, requires = "json_output")]
+    json: bool,
+
+    /// Suppress warnings and hints on stderr
+    #[arg(short = 'q', long = "quiet", action = ArgAction::SetTrue)]
+    quiet: bool,
+
+    /// Extra request header "Name: Value" for this run (repeatable; overrides provider headers)
+    #[arg(long = "header", value_name = "NAME: VALUE")]
+    headers: Vec<String>,
+
+    /// Extra request body field KEY=VALUE for this run (repeatable; VALUE parsed as JSON, else a string)
+    #[arg(long = "param", value_name = "KEY=VALUE")]
+    params: Vec<String>,
+
+    /// Seconds to wait for the provider connection to open (default 10; overrides the provider's connect_timeout_secs)
+    #[arg(long = "connect-timeout", value_name = "SECS")]
+    connect_timeout: Option<u64>,
+
+    /// Force the shell flavor (posix, cmd, powershell) instead of detecting it; affects the prompt's shell hint
+    #[arg(long = "shell", value_name = "SHELL")]
+    shell: Option<ShellKind>,
+
     /// Verbose internal logs
     #[arg(short = 'd', long = "debug", action = ArgAction::SetTrue)]
     debug: bool,
@@ -102,16 +283,31 @@ struct Cli {
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let cli = Cli::parse_from(normalized_cli_args());
+    let mut argv = normalized_cli_args();
+    let mut cli = Cli::parse_from(&argv);
     configure_color_output();
 
+    if cli.replay {
+        let last = load_last_run(&config_dir()?, "qq")?;
+        eprintln!("Replaying: {}", last.describe("qq"));
+        if last.had_stdin_context {
+            eprintln!("(the original run's piped input is not replayed)");
+        }
+        argv = last.replay_args(argv[0].clone(), &argv[1..]);
+        cli = Cli::parse_from(&argv);
+    }
+    logging::init_cli(cli.debug);
+
+    if cli.print_default_config {
+        println!("{}", Config::default_config_json()?);
+        return Ok(());
+    }
+
     // Run interactive init if requested.
     if cli.init {
         match Config::init_interactive(cli.debug) {
             Ok(path) => {
-                if cli.debug {
-                    eprintln!("[debug] Initialized config at {}", path.display());
-                }
+                debug_log!(cli.debug, "Initialized config at {}", path.display());
             }
             Err(e) => match e.downcast::<InitExistsError>() {
                 Ok(init_err) => {
@@ -126,21 +322,46 @@ async fn main() -> Result<()> {
         return Ok(());
     }
 
+    qqqa::env_file::load_env_files(cli.env_file.as_deref(), cli.debug)?;
+
+    if cli.list_profiles {
+        let (cfg, _) = Config::load_or_init(cli.debug)?;
+        print!("{}", format_profile_list(&cfg.profile_summaries()));
+        return Ok(());
+    }
+
+    if let Some(name) = cli.save_profile.as_deref() {
+        return save_profile_from_flags(&cli, name);
+    }
+
     let config_flags_requested = cli.no_fun || cli.enable_auto_copy || cli.disable_auto_copy;
     if config_flags_requested {
         persist_config_flags(&cli)?;
     }
 
-    // Detect piped stdin and read it if present.
+    // Detect piped stdin and read it if present. --ping takes no input.
     let stdin_is_tty = atty::is(atty::Stream::Stdin);
-    let stdin_block = if !stdin_is_tty {
-        Some(read_all_stdin(std::io::stdin())?)
+    if cli.stdin_only && stdin_is_tty {
+        return Err(anyhow!(
+            "--stdin-only needs the question piped on stdin (e.g. `echo \"...\" | qq --stdin-only`)."
+        ));
+    }
+    let stdin_block = if !stdin_is_tty && !cli.ping {
+        Some(read_all_stdin(std::io::stdin(), cli.debug)?)
     } else {
         None
     };
 
-    let prepared = coalesce_prompt_inputs(cli.question.join(" "), stdin_block);
-    if prepared.question.trim().is_empty() {
+    let batch_questions = cli.batch.as_deref().map(read_batch_questions).transpose()?;
+    let examples = read_examples(&cli.example)?;
+
+    let prepared = prepare_prompt_inputs(
+        cli.question.join(" "),
+        stdin_block,
+        cli.no_redact,
+        cli.debug,
+    );
+    if prepared.question.trim().is_empty() && !cli.ping && batch_questions.is_none() {
         if config_flags_requested {
             return Ok(());
         }
@@ -154,16 +375,60 @@ async fn main() -> Result<()> {
     } = prepared;
 
     // Load config and resolve profile/model.
-    let (cfg, cfg_path) = Config::load_or_init(cli.debug)?;
+    let (mut cfg, cfg_path) = Config::load_or_init(cli.debug)?;
     let cfg_dir = cfg_path.parent();
-    let copy_enabled = if cli.copy_command {
-        true
-    } else if cli.no_copy_command {
-        false
-    } else {
-        cfg.copy_first_command_enabled()
+    if !cli.ping
+        && batch_questions.is_none()
+        && let Some(dir) = cfg_dir
+    {
+        let run = StoredRun::from_invocation(
+            &argv,
+            cli.question.len(),
+            &question,
+            stdin_block.as_deref().is_some_and(|b| !b.trim().is_empty()),
+        );
+        if let Err(err) = save_last_run(dir, "qq", run) {
+            debug_log!(cli.debug, "Could not save the run for --replay: {}", err);
+        }
+    }
+    // Config defaults apply only when neither the flag nor its opposite was passed.
+    let format = match cli.format {
+        Some(format) => format,
+        None if cli.raw => OutputFormat::Plain,
+        None if cli.no_raw => OutputFormat::Xml,
+        None => match cfg.default_format()? {
+            Some(format) => format,
+            None if cfg.default_raw => OutputFormat::Plain,
+            None => OutputFormat::Xml,
+        },
+    };
+    cli.no_stream = cli.no_stream || (!cli.stream && cfg.default_no_stream);
+    // Nothing is shown until the command is extracted, so there is nothing to stream.
+    cli.no_stream = cli.no_stream || cli.command_only;
+    qqqa::formatting::set_loading_elapsed_enabled(
+        qqqa::formatting::loading_elapsed_from_env().unwrap_or(cfg.show_elapsed),
+    );
+    let copy = CopySettings {
+        enabled: !cli.command_only
+            && (cli.copy_command || (!cli.no_copy_command && cfg.copy_first_command_enabled())),
+        explicit: cli.copy_command,
+        format,
+        debug: cli.debug,
+        state_dir: cfg_dir,
+    };
+    let overrides = ProfileOverrides {
+        profile: cli.profile.as_deref(),
+        model: cli.model.as_deref(),
+        api_base: cli.api_base.as_deref(),
+        api_key: cli.api_key.as_deref(),
+        temperature: cli.temperature,
+        frequency_penalty: cli.frequency_penalty,
+        presence_penalty: cli.presence_penalty,
+        headers: &cli.headers,
+        params: &cli.params,
+        connect_timeout_secs: cli.connect_timeout,
     };
-    let mut eff = match cfg.resolve_profile(cli.profile.as_deref(), cli.model.as_deref(), cfg_dir) {
+    let mut eff = match resolve_run_profile(&cfg, cfg_dir, &overrides) {
         Ok(eff) => eff,
         Err(e) => {
             let msg = e.to_string();
@@ -176,141 +441,274 @@ async fn main() -> Result<()> {
             return Err(anyhow!(out));
         }
     };
-    if let Some(base) = cli.api_base.as_deref() {
-        if let Some(http) = eff.http_mut() {
-            http.base_url = base.to_string();
-        } else {
-            return Err(anyhow!(
-                "--api-base override is only supported for HTTP providers (current: '{}').",
-                eff.provider_key
-            ));
-        }
+    if cli.model.is_none()
+        && let Some(model) = env_model_override()
+    {
+        debug_log!(cli.debug, "Using model '{}' from {}", model, MODEL_ENV);
     }
-    if let Some(temp) = cli.temperature {
-        eff.temperature = Some(temp);
-    }
-    if cli.debug {
-        match &eff.connection {
-            ProviderConnection::Http(conn) => {
-                eprintln!(
-                    "[debug] Using provider='{}' base_url='{}' model='{}'",
-                    eff.provider_key, conn.base_url, eff.model
-                );
-            }
-            ProviderConnection::Cli(conn) => {
-                eprintln!(
-                    "[debug] Using provider='{}' cli_binary='{}' model='{}'",
-                    eff.provider_key, conn.binary, eff.model
-                );
-            }
+    if !cli.quiet {
+        for notice in run_notices(&cfg, &overrides, &eff) {
+            eprintln!("{}", notice);
         }
     }
+    log_resolved_profile(&eff, cli.debug);
+
+    if cli.ping {
+        return run_ping(&eff, cli.debug).await;
+    }
 
     // Read terminal history unless disabled.
     let include_history = if cli.no_history {
-        false
-    } else if cli.history {
-        true
-    } else {
-        cfg.history_enabled()
-    };
-    let history = if include_history {
-        read_recent_history(10, cli.debug)
+        Some(false)
     } else {
-        Vec::new()
+        cli.history.then_some(true)
     };
+    let history = prompt_history(
+        &cfg,
+        include_history,
+        cli.history_since,
+        cli.no_redact,
+        cli.debug,
+    );
 
     // Build system + user messages for formatting/topic control.
-    let mut system = build_qq_system_prompt();
-    if cfg.no_emoji_enabled() {
-        system.push_str("\nHard rule: You MUST NOT use emojis anywhere in the response.\n");
-    }
     let os_details = os_info::get();
     let os_type = os_details.os_type();
-    let shell_kind = detect_shell(os_type);
-    if cli.debug {
-        eprintln!("[debug] Inferred shell: {}", shell_kind.display_name(),);
-    }
-    let shell_hint = shell_hint_for_prompt(shell_kind);
-    let user = build_qq_user_message(
-        Some(os_type),
-        Some(shell_hint),
+    let (shell_kind, shell_forced) = resolve_shell(os_type, cli.shell);
+    debug_log!(
+        cli.debug,
+        "{}",
+        environment_debug_line(os_type, shell_kind, shell_forced)
+    );
+    let cwd_listing = cli
+        .cwd_context
+        .then(qqqa::cwd_listing::current_dir_listing)
+        .transpose()?;
+    let context = context_options(
+        &cfg,
+        ContextFlags {
+            max_context_bytes: cli.max_context_bytes,
+            stdin_label: cli.stdin_label.as_deref(),
+            local_time: cli.local_time,
+            no_os_line: cli.no_os_line,
+        },
+        cwd_listing.as_deref(),
+    );
+
+    if let Some(questions) = batch_questions {
+        let client = build_http_client(&eff)?.ok_or_else(|| {
+            anyhow!(
+                "--batch needs an HTTP provider; '{}' is CLI-backed",
+                eff.provider_key
+            )
+        })?;
+        let prompts = questions
+            .into_iter()
+            .map(|question| {
+                let (mut system, user) = qq_prompts(
+                    &cfg,
+                    os_type,
+                    shell_kind,
+                    &history,
+                    stdin_block.as_deref(),
+                    &question,
+                    &context,
+                );
+                if let Some(directive) = cli.persona {
+                    system.push_str(directive);
+                }
+                if cli.no_system {
+                    system.clear();
+                }
+                BatchPrompt {
+                    question,
+                    system,
+                    user,
+                }
+            })
+            .collect();
+        let loading = (!cli.json).then(start_loading_animation);
+        let results = run_batch(&client, &eff.model, prompts, &examples, cli.debug).await;
+        drop(loading);
+        if cli.json {
+            println!("{}", serde_json::to_string_pretty(&results)?);
+        } else {
+            print_batch_results(&results, format);
+        }
+        let failed = results.iter().filter(|r| r.error.is_some()).count();
+        if failed > 0 {
+            return Err(anyhow!(
+                "{} of {} batch questions failed",
+                failed,
+                results.len()
+            ));
+        }
+        if results.iter().any(|r| r.refused) {
+            exit_refused();
+        }
+        return Ok(());
+    }
+    let (mut system, user) = qq_prompts(
+        &cfg,
+        os_type,
+        shell_kind,
         &history,
         stdin_block.as_deref(),
         &question,
+        &context,
     );
 
-    // Prepare backend-specific client.
-    let http_client = match &eff.connection {
-        ProviderConnection::Http(conn) => Some(
-            ChatClient::new(
-                conn.base_url.clone(),
-                conn.api_key.clone(),
-                conn.headers.clone(),
-                conn.tls.as_ref(),
-                conn.request_timeout_secs.map(Duration::from_secs),
-            )?
-            .with_reasoning_effort(eff.reasoning_effort.clone())
-            .with_temperature(eff.temperature, eff.temperature.is_some()),
-        ),
-        ProviderConnection::Cli(_) => None,
+    if let Some(directive) = cli.persona {
+        system.push_str(directive);
+    }
+    if cli.no_system {
+        system.clear();
+        debug_log!(cli.debug, "--no-system: omitting the system prompt");
+    }
+
+    let messages = chat_messages(&system, &examples, &user);
+
+    if cli.count_tokens {
+        let contents: Vec<&str> = messages.iter().map(|m| m.content).collect();
+        print!(
+            "{}",
+            TokenBreakdown::estimate(
+                &contents,
+                &system,
+                &history,
+                stdin_block.as_deref(),
+                &question
+            )
+        );
+        return Ok(());
+    }
+
+    if cli.dry_run {
+        if cli.json {
+            println!("{}", format_dry_run_json(&eff, &messages)?);
+        } else {
+            eprint!("{}", format_dry_run_text(&eff, &messages));
+        }
+        return Ok(());
+    }
+
+    // Provider-side failures move on to the profile's fallback_profiles, in order.
+    let mut fallbacks = cfg.fallback_profiles(cli.profile.as_deref()).iter();
+    let mut answered_by = None;
+    let answer = loop {
+        let mut printed = false;
+        let err =
+            match ask_profile(&eff, &cli, &copy, &system, &user, &examples, &mut printed).await {
+                Ok(Some(answer)) => break answer,
+                Ok(None) => return Ok(()),
+                Err(err) => err,
+            };
+        // Once part of an answer is on screen, another model would only repeat it.
+        let next = fallbacks.next();
+        let Some(name) = next.filter(|_| !printed && is_provider_failure(&err)) else {
+            return Err(err);
+        };
+        if !cli.quiet {
+            eprintln!(
+                "[warn] provider '{}' failed ({}); trying fallback profile '{}'",
+                eff.provider_key,
+                err.to_string().lines().next().unwrap_or_default(),
+                name
+            );
+        }
+        eff = resolve_fallback_profile(&cfg, cfg_dir, name)?;
+        answered_by = Some(name);
     };
+    if let Some(name) = answered_by {
+        debug_log!(
+            cli.debug,
+            "Answered by fallback profile '{}' (provider='{}' model='{}')",
+            name,
+            eff.provider_key,
+            eff.model
+        );
+    }
 
-    let messages = [
-        Msg {
-            role: "system",
-            content: &system,
-        },
-        Msg {
-            role: "user",
-            content: &user,
-        },
-    ];
+    if cli.command_only {
+        let command = extract_first_command(&answer)
+            .ok_or_else(|| anyhow!("--command-only: the answer contained no <cmd> block"))?;
+        println!("{}", command);
+        return Ok(());
+    }
+    if answer.trim().is_empty() && !cli.quiet {
+        eprintln!(
+            "{}",
+            render_xmlish_to_ansi(
+                "<warn>The model returned an empty response; try again or check the model/provider.</warn>"
+            )
+        );
+    }
+    if is_topic_refusal(&answer) {
+        exit_refused();
+    }
+    if cli.run {
+        offer_to_run_first_command(&answer, &mut cfg, &cfg_path, shell_kind, cli.debug).await?;
+    }
+    Ok(())
+}
 
-    match (&eff.connection, &http_client) {
+/// Ask `eff` for the answer and print it as it arrives. `None` when stdout closed
+/// mid-stream; `printed` reports whether any of the answer reached the terminal, even
+/// when the request then failed.
+async fn ask_profile(
+    eff: &EffectiveProfile,
+    cli: &Cli,
+    copy: &CopySettings<'_>,
+    system: &str,
+    user: &str,
+    examples: &[Example],
+    printed: &mut bool,
+) -> Result<Option<String>> {
+    let http_client = build_http_client(eff)?;
+    let format = copy.format;
+    let messages = chat_messages(system, examples, user);
+
+    let answer = match (&eff.connection, &http_client) {
         (ProviderConnection::Http(_), Some(client)) => {
             if !cli.no_stream {
-                if cli.raw {
-                    println!("");
-                    let mut raw_buffer = String::new();
-                    client
-                        .chat_stream_messages(&eff.model, &messages, cli.debug, |tok| {
-                            raw_buffer.push_str(tok);
-                            print_stream_token(tok);
-                        })
-                        .await?;
-                    println!();
-                    maybe_copy_first_command(&raw_buffer, copy_enabled, cli.raw, cli.debug);
-                } else {
-                    println!("");
-                    let mut buf = String::new();
-                    let mut formatter = StreamingFormatter::new();
-                    let mut writer = PrettyStreamWriter::new();
-                    client
-                        .chat_stream_messages(&eff.model, &messages, cli.debug, |tok| {
-                            buf.push_str(tok);
-                            if let Some(delta) = formatter.push(tok) {
-                                writer.write(&delta);
-                            }
-                        })
-                        .await?;
-                    if let Some(tail) = formatter.flush() {
-                        if !tail.is_empty() {
-                            writer.write(&tail);
+                println!();
+                let mut buf = String::new();
+                let mut formatter = StreamingFormatter::with_format(format);
+                let mut writer = PrettyStreamWriter::new();
+                let streamed = client
+                    .chat_stream_messages(&eff.model, &messages, cli.debug, |tok| {
+                        buf.push_str(tok);
+                        if let Some(delta) = formatter.push(tok) {
+                            writer.write(&delta);
                         }
-                    }
-                    println!("");
-                    maybe_copy_first_command(&buf, copy_enabled, cli.raw, cli.debug);
+                    })
+                    .await;
+                if stdout_closed() {
+                    return Ok(None);
+                }
+                if let Some(tail) = formatter.flush()
+                    && !tail.is_empty()
+                {
+                    writer.write(&tail);
                 }
+                println!();
+                // Copy from whatever arrived, even if the stream broke off.
+                *printed = !buf.is_empty();
+                maybe_copy_first_command(&buf, copy);
+                streamed?;
+                buf
             } else {
                 let loading = start_loading_animation();
                 let full = client
                     .chat_once_messages(&eff.model, &messages, cli.debug)
                     .await?;
                 drop(loading);
-                println!("");
-                print_assistant_text(&full, cli.raw);
-                maybe_copy_first_command(&full, copy_enabled, cli.raw, cli.debug);
+                if !cli.command_only {
+                    println!();
+                    print_assistant_text(&full, format);
+                    maybe_copy_first_command(&full, copy);
+                }
+                full
             }
         }
         (ProviderConnection::Cli(cli_conn), _) => {
@@ -318,11 +716,13 @@ async fn main() -> Result<()> {
                 .request_timeout_secs
                 .map(Duration::from_secs)
                 .unwrap_or_else(|| Duration::from_secs(DEFAULT_REQUEST_TIMEOUT_SECS));
+            // CLI backends take a single prompt, so examples are inlined ahead of it.
+            let user = inline_examples(examples, user);
             let make_request = || CliCompletionRequest {
                 engine: cli_conn.engine,
                 binary: &cli_conn.binary,
                 base_args: &cli_conn.base_args,
-                system_prompt: &system,
+                system_prompt: system,
                 user_prompt: &user,
                 model: &eff.model,
                 reasoning_effort: eff.reasoning_effort.as_deref(),
@@ -333,86 +733,368 @@ async fn main() -> Result<()> {
             let streaming_enabled = !cli.no_stream && cli_conn.engine.supports_streaming();
 
             if streaming_enabled {
-                println!("");
-                if cli.raw {
-                    let mut collected = String::new();
-                    let fallback = run_cli_completion_streaming(make_request(), |tok| {
-                        collected.push_str(tok);
-                        print_stream_token(tok);
-                    })
-                    .await?;
-                    if collected.is_empty() {
-                        collected = fallback;
-                    }
-                    println!("");
-                    maybe_copy_first_command(&collected, copy_enabled, cli.raw, cli.debug);
-                } else {
-                    let mut formatter = StreamingFormatter::new();
-                    let mut writer = PrettyStreamWriter::new();
-                    let mut collected = String::new();
-                    let fallback = run_cli_completion_streaming(make_request(), |tok| {
-                        collected.push_str(tok);
-                        if let Some(delta) = formatter.push(tok) {
-                            writer.write(&delta);
-                        }
-                    })
-                    .await?;
-                    if let Some(tail) = formatter.flush() {
-                        if !tail.is_empty() {
-                            writer.write(&tail);
-                        }
-                    }
-                    if collected.is_empty() {
-                        collected = fallback;
+                println!();
+                let mut formatter = StreamingFormatter::with_format(format);
+                let mut writer = PrettyStreamWriter::new();
+                let mut collected = String::new();
+                let fallback = run_cli_completion_streaming(make_request(), |tok| {
+                    collected.push_str(tok);
+                    if let Some(delta) = formatter.push(tok) {
+                        writer.write(&delta);
                     }
-                    println!("");
-                    maybe_copy_first_command(&collected, copy_enabled, cli.raw, cli.debug);
+                })
+                .await;
+                *printed = !collected.is_empty();
+                let fallback = fallback?;
+                if stdout_closed() {
+                    return Ok(None);
                 }
+                if let Some(tail) = formatter.flush()
+                    && !tail.is_empty()
+                {
+                    writer.write(&tail);
+                }
+                if collected.is_empty() {
+                    collected = fallback;
+                }
+                println!();
+                maybe_copy_first_command(&collected, copy);
+                collected
             } else {
-                if !cli.no_stream && cli.debug {
-                    eprintln!(
-                        "[debug] CLI provider '{}' does not support streaming; buffering output.",
+                if !cli.no_stream {
+                    debug_log!(
+                        cli.debug,
+                        "CLI provider '{}' does not support streaming; buffering output.",
                         eff.provider_key
                     );
                 }
                 let loading = start_loading_animation();
                 let response = run_cli_completion(make_request()).await?;
                 drop(loading);
-                println!("");
-                print_assistant_text(&response, cli.raw);
-                maybe_copy_first_command(&response, copy_enabled, cli.raw, cli.debug);
+                if !cli.command_only {
+                    println!();
+                    print_assistant_text(&response, format);
+                    maybe_copy_first_command(&response, copy);
+                }
+                response
             }
         }
         _ => unreachable!("Provider/client mismatch"),
+    };
+    Ok(Some(answer))
+}
+
+/// System, few-shot example, and user messages; an empty system prompt (`--no-system`)
+/// is left out entirely.
+fn chat_messages<'a>(system: &'a str, examples: &'a [Example], user: &'a str) -> Vec<Msg<'a>> {
+    let mut messages = Vec::with_capacity(2 + examples.len() * 2);
+    if !system.is_empty() {
+        messages.push(Msg {
+            role: "system",
+            content: system,
+        });
+    }
+    for example in examples {
+        messages.push(Msg {
+            role: "user",
+            content: &example.user,
+        });
+        messages.push(Msg {
+            role: "assistant",
+            content: &example.assistant,
+        });
+    }
+    messages.push(Msg {
+        role: "user",
+        content: user,
+    });
+    messages
+}
+
+/// `--dry-run` text view: a header per message, written to stderr.
+fn format_dry_run_text(eff: &EffectiveProfile, messages: &[Msg<'_>]) -> String {
+    let mut out = format!(
+        "[dry-run] provider: {}  model: {}\n",
+        eff.provider_key, eff.model
+    );
+    for msg in messages {
+        out.push_str(&format!("--- {} ---\n{}\n", msg.role, msg.content));
+    }
+    out
+}
+
+/// `--dry-run --json` view for bug reports and scripting.
+fn format_dry_run_json(eff: &EffectiveProfile, messages: &[Msg<'_>]) -> Result<String> {
+    Ok(serde_json::to_string_pretty(&serde_json::json!({
+        "provider": eff.provider_key,
+        "model": eff.model,
+        "messages": messages,
+    }))?)
+}
+
+/// One `--example` file: a prior user turn and the assistant reply to imitate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Example {
+    user: String,
+    assistant: String,
+}
+
+fn read_examples(paths: &[PathBuf]) -> Result<Vec<Example>> {
+    paths
+        .iter()
+        .map(|path| {
+            let contents = fs_err::read_to_string(path)
+                .with_context(|| format!("Reading example file: {}", path.display()))?;
+            parse_example(&contents)
+                .with_context(|| format!("Invalid example file {}", path.display()))
+        })
+        .collect()
+}
+
+/// Split an example on its single `---` line into user and assistant sections.
+fn parse_example(contents: &str) -> Result<Example> {
+    let mut sections = vec![String::new()];
+    for line in contents.lines() {
+        if line.trim() == "---" {
+            sections.push(String::new());
+        } else {
+            let section = sections.last_mut().expect("at least one section");
+            section.push_str(line);
+            section.push('\n');
+        }
+    }
+    let [user, assistant] = sections.as_slice() else {
+        return Err(anyhow!(
+            "expected a user section, one `---` line, then an assistant section (found {} `---` lines)",
+            sections.len() - 1
+        ));
+    };
+    let (user, assistant) = (user.trim(), assistant.trim());
+    if user.is_empty() {
+        return Err(anyhow!("the user section before `---` is empty"));
+    }
+    if assistant.is_empty() {
+        return Err(anyhow!("the assistant section after `---` is empty"));
+    }
+    Ok(Example {
+        user: user.to_string(),
+        assistant: assistant.to_string(),
+    })
+}
+
+/// Prefix the user prompt with example exchanges for single-prompt CLI backends.
+fn inline_examples(examples: &[Example], user: &str) -> String {
+    if examples.is_empty() {
+        return user.to_string();
+    }
+    let mut out = String::from("Example exchanges to imitate:\n\n");
+    for example in examples {
+        out.push_str(&format!(
+            "User:\n{}\n\nAssistant:\n{}\n\n",
+            example.user, example.assistant
+        ));
+    }
+    out.push_str(user);
+    out
+}
+
+/// Exit status when the model only refused an off-topic question, so scripts can tell a
+/// refusal from a real answer.
+const REFUSAL_EXIT_CODE: i32 = 3;
+
+/// Flush stdout and exit with [`REFUSAL_EXIT_CODE`].
+fn exit_refused() -> ! {
+    use std::io::Write;
+    let _ = std::io::stdout().flush();
+    std::process::exit(REFUSAL_EXIT_CODE)
+}
+
+/// How many `--batch` questions are in flight at once.
+const BATCH_CONCURRENCY: usize = 4;
+
+struct BatchPrompt {
+    question: String,
+    system: String,
+    user: String,
+}
+
+/// One `--batch` answer; exactly one of `answer` / `error` is set.
+#[derive(Debug, Serialize)]
+struct BatchResult {
+    question: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    answer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// The answer was only the off-topic refusal.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    refused: bool,
+}
+
+/// Non-empty, trimmed lines of the batch file, in order.
+fn read_batch_questions(path: &Path) -> Result<Vec<String>> {
+    let contents = fs_err::read_to_string(path)
+        .with_context(|| format!("Reading batch file: {}", path.display()))?;
+    let questions = parse_batch_questions(&contents);
+    if questions.is_empty() {
+        return Err(anyhow!("Batch file {} has no questions", path.display()));
     }
+    Ok(questions)
+}
 
+fn parse_batch_questions(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Ask every prompt with bounded concurrency; results keep the input order.
+async fn run_batch(
+    client: &ChatClient,
+    model: &str,
+    prompts: Vec<BatchPrompt>,
+    examples: &[Example],
+    debug: bool,
+) -> Vec<BatchResult> {
+    stream::iter(prompts)
+        .map(|prompt| async move {
+            let messages = chat_messages(&prompt.system, examples, &prompt.user);
+            let reply = client.chat_once_messages(model, &messages, debug).await;
+            match reply {
+                Ok(answer) => BatchResult {
+                    question: prompt.question,
+                    refused: is_topic_refusal(&answer),
+                    answer: Some(answer),
+                    error: None,
+                },
+                Err(err) => BatchResult {
+                    question: prompt.question,
+                    answer: None,
+                    error: Some(format!("{:#}", err)),
+                    refused: false,
+                },
+            }
+        })
+        .buffered(BATCH_CONCURRENCY)
+        .collect()
+        .await
+}
+
+fn print_batch_results(results: &[BatchResult], format: OutputFormat) {
+    let total = results.len();
+    for (idx, result) in results.iter().enumerate() {
+        println!();
+        println!("[{}/{}] {}", idx + 1, total, result.question);
+        match (&result.answer, &result.error) {
+            (Some(answer), _) => print_assistant_text(answer, format),
+            (None, Some(err)) => eprintln!("Error: {}", err),
+            (None, None) => {}
+        }
+    }
+}
+
+/// Render `--list-profiles` as aligned columns; `*` marks the default profile.
+fn format_profile_list(rows: &[ProfileSummary]) -> String {
+    let name_w = rows.iter().map(|r| r.name.len()).max().unwrap_or(0);
+    let provider_w = rows.iter().map(|r| r.provider.len()).max().unwrap_or(0);
+    let model_w = rows.iter().map(|r| r.model.len()).max().unwrap_or(0);
+    let mut out = String::new();
+    for row in rows {
+        out.push_str(&format!(
+            "{} {:<name_w$}  {:<provider_w$}  {:<model_w$}  key: {}\n",
+            if row.is_default { '*' } else { ' ' },
+            row.name,
+            row.provider,
+            row.model,
+            row.key,
+        ));
+    }
+    out
+}
+
+/// Health check for `--ping`: one tiny request (HTTP) or trivial prompt (CLI), then a report.
+async fn run_ping(eff: &EffectiveProfile, debug: bool) -> Result<()> {
+    println!("provider: {}", eff.provider_key);
+    println!("model: {}", eff.model);
+    match &eff.connection {
+        ProviderConnection::Http(conn) => {
+            println!("base_url: {}", conn.base_url);
+            let client = build_http_client(eff)?.context("HTTP profile without a client")?;
+            let report = client
+                .ping(&eff.model, debug)
+                .await
+                .with_context(|| format!("Ping to {} failed", conn.base_url))?;
+            println!("status: {}", report.status);
+            println!("round_trip: {} ms", report.elapsed.as_millis());
+            if !report.status.is_success() {
+                return Err(anyhow!(
+                    "Ping failed: API error ({}): {}",
+                    report.status,
+                    report.body.trim()
+                ));
+            }
+        }
+        ProviderConnection::Cli(conn) => {
+            println!("binary: {}", conn.binary);
+            let started = Instant::now();
+            let result =
+                cli_completion(eff, "Reply with the single word: pong", "ping", debug).await;
+            let elapsed = started.elapsed();
+            println!("status: {}", if result.is_ok() { "ok" } else { "failed" });
+            println!("round_trip: {} ms", elapsed.as_millis());
+            result.context("Ping failed")?;
+        }
+    }
     Ok(())
 }
 
 /// Read the entire stdin into a string. We do this synchronously before async work
 /// begins to keep things simple and robust.
-fn read_all_stdin(mut stdin: Stdin) -> Result<String> {
-    let mut buf = String::new();
-    stdin.read_to_string(&mut buf)?;
-    Ok(buf)
+fn read_all_stdin(mut stdin: Stdin, debug: bool) -> Result<String> {
+    let mut buf = Vec::new();
+    stdin.read_to_end(&mut buf)?;
+    let (text, lossy) = decode_stdin_lossy(buf);
+    if lossy {
+        debug_log!(
+            debug,
+            "stdin was not valid UTF-8; invalid bytes were replaced with U+FFFD"
+        );
+    }
+    Ok(text)
 }
 
+/// Single-dash multi-letter aliases that clap would otherwise split into short flags
+/// (`-cc` as `-c -c`), mapped to their long spelling.
+const SHORT_ALIASES: [(&str, &str); 2] = [("-cc", "--cc"), ("-ncc", "--ncc")];
+
 fn normalized_cli_args() -> Vec<OsString> {
-    normalize_ncc(std::env::args_os())
+    normalize_short_aliases(std::env::args_os())
 }
 
-fn normalize_ncc<I>(args: I) -> Vec<OsString>
+/// Rewrite [`SHORT_ALIASES`] tokens; the program name and anything after `--` are left alone.
+fn normalize_short_aliases<I>(args: I) -> Vec<OsString>
 where
     I: IntoIterator<Item = OsString>,
 {
+    let mut passthrough = false;
     args.into_iter()
         .enumerate()
         .map(|(idx, arg)| {
-            if idx > 0 && arg == OsString::from("-ncc") {
-                OsString::from("--ncc")
-            } else {
-                arg
+            if idx == 0 || passthrough {
+                return arg;
+            }
+            if arg == "--" {
+                passthrough = true;
+                return arg;
             }
+            SHORT_ALIASES
+                .iter()
+                .find(|(short, _)| arg == *short)
+                .map(|(_, long)| OsString::from(long))
+                .unwrap_or(arg)
         })
         .collect()
 }
@@ -431,8 +1113,7 @@ impl PrettyStreamWriter {
         if filtered.is_empty() {
             return;
         }
-        print!("{}", filtered);
-        let _ = std::io::stdout().flush();
+        write_stdout(&filtered);
     }
 
     fn filter(&mut self, text: &str) -> String {
@@ -458,30 +1139,108 @@ impl PrettyStreamWriter {
     }
 }
 
-fn maybe_copy_first_command(text: &str, enabled: bool, raw_output: bool, debug: bool) {
-    if !enabled {
+/// `--run`: hand the answer's first `<cmd>` to the allowlist + confirmation flow `qa`
+/// uses for execute_command.
+async fn offer_to_run_first_command(
+    answer: &str,
+    cfg: &mut Config,
+    cfg_path: &Path,
+    shell: ShellKind,
+    debug: bool,
+) -> Result<()> {
+    let Some(command) = extract_first_command(answer) else {
+        eprintln!("No <cmd> block found in the answer; nothing to run.");
+        return Ok(());
+    };
+    let command_timeout = command_timeout(None, cfg)?;
+    apply_command_policy(cfg)?;
+    let opts = InteractiveOptions {
+        auto_yes: false,
+        debug,
+        shell,
+        command_timeout,
+        color_stderr: false,
+    };
+    println!();
+    let args = execute_command::Args { command, cwd: None };
+    let result = run_with_allowlist_prompt(args, opts, cfg, cfg_path).await?;
+    if !result.streamed_live {
+        print!("{}", result.summary);
+    }
+    Ok(())
+}
+
+/// How (and whether) qq copies the first `<cmd>` block for this run.
+struct CopySettings<'a> {
+    enabled: bool,
+    /// `--copy-command` was passed, so failures are always reported.
+    explicit: bool,
+    format: OutputFormat,
+    debug: bool,
+    /// Config directory holding the "missing helper already reported" marker.
+    state_dir: Option<&'a Path>,
+}
+
+fn maybe_copy_first_command(text: &str, copy: &CopySettings<'_>) {
+    if !copy.enabled {
         return;
     }
     let Some(command) = extract_first_command(text) else {
-        if debug {
-            eprintln!("[debug] No <cmd> block found to copy.");
-        }
+        debug_log!(copy.debug, "No <cmd> block found to copy.");
         return;
     };
     match clipboard::copy_to_clipboard(&command) {
-        Ok(()) => print_copy_notice(raw_output),
+        Ok(()) => {
+            if let Some(dir) = copy.state_dir {
+                clipboard::set_missing_helper_reported(dir, false);
+            }
+            print_copy_notice(copy.format);
+        }
+        Err(err) if !copy.explicit && err.is::<clipboard::NoClipboardHelper>() => {
+            // Headless machines with auto-copy on would otherwise fail loudly on every run.
+            let reported = copy
+                .state_dir
+                .is_some_and(clipboard::missing_helper_reported);
+            if reported {
+                debug_log!(
+                    copy.debug,
+                    "Auto-copy skipped: {} (already reported once)",
+                    err
+                );
+            } else {
+                eprintln!(
+                    "Auto-copy skipped: {}. Install one, or run `qq --disable-auto-copy`; this notice is shown once.",
+                    err
+                );
+                if let Some(dir) = copy.state_dir {
+                    clipboard::set_missing_helper_reported(dir, true);
+                }
+            }
+        }
         Err(err) => {
             eprintln!("Failed to copy first command to clipboard: {}", err);
         }
     }
 }
 
+/// First `<cmd>` body. An unterminated `<cmd>` (e.g. a stream cut off mid-answer)
+/// runs to the end of the text, minus any partial closing tag.
 fn extract_first_command(text: &str) -> Option<String> {
+    const CLOSE: &str = "</cmd>";
     let lower = text.to_ascii_lowercase();
     let start = lower.find("<cmd>")?;
     let after_start = start + 5;
-    let closing_rel = lower[after_start..].find("</cmd>")?;
-    let end = after_start + closing_rel;
+    let end = match lower[after_start..].find(CLOSE) {
+        Some(closing_rel) => after_start + closing_rel,
+        None => {
+            let rest = &lower[after_start..];
+            let partial = (1..CLOSE.len())
+                .rev()
+                .find(|&n| rest.ends_with(&CLOSE[..n]))
+                .unwrap_or(0);
+            lower.len() - partial
+        }
+    };
     let raw = &text[after_start..end];
     let normalized = raw
         .replace("<br/>", "\n")
@@ -503,16 +1262,43 @@ fn unescape_entities(input: &str) -> String {
         .replace("&amp;", "&")
 }
 
-fn print_copy_notice(raw_output: bool) {
-    println!("");
-    if raw_output {
-        println!("<info>Copied first command to clipboard</info>");
-    } else {
-        println!(
-            "{}",
-            render_xmlish_to_ansi("<info>Copied first command to clipboard</info>")
-        );
+fn print_copy_notice(format: OutputFormat) {
+    println!();
+    println!(
+        "{}",
+        render_answer("<info>Copied first command to clipboard</info>", format)
+    );
+}
+
+/// `--save-profile`: store the override flags as profile `name` on top of `-p` (or the
+/// default profile). API keys and headers are never written this way.
+fn save_profile_from_flags(cli: &Cli, name: &str) -> Result<()> {
+    let (mut cfg, path) = Config::load_or_init(cli.debug)?;
+    let mut extra_body = serde_json::Map::new();
+    for raw in &cli.params {
+        let (key, value) = parse_param_flag(raw)?;
+        extra_body.insert(key, value);
     }
+    let draft = ProfileDraft {
+        base: cli.profile.as_deref(),
+        model: cli.model.as_deref(),
+        temperature: cli.temperature,
+        frequency_penalty: cli.frequency_penalty,
+        presence_penalty: cli.presence_penalty,
+        extra_body,
+        api_base: cli.api_base.as_deref(),
+        connect_timeout_secs: cli.connect_timeout,
+    };
+    let provider = cfg.save_profile(name, &draft, cli.force)?;
+    cfg.save(&path, cli.debug)?;
+    println!(
+        "Saved profile '{}' (provider '{}') to {}. Use it with `qq -p {}`.",
+        name.trim(),
+        provider,
+        path.display(),
+        name.trim()
+    );
+    Ok(())
 }
 
 fn persist_config_flags(cli: &Cli) -> Result<()> {
@@ -525,12 +1311,11 @@ fn persist_config_flags(cli: &Cli) -> Result<()> {
             cfg.no_emoji = desired;
             changed = true;
         }
-        if cli.debug {
-            eprintln!(
-                "[debug] Disabled emojis in system prompt (persisted at {}).",
-                path.display()
-            );
-        }
+        debug_log!(
+            cli.debug,
+            "Disabled emojis in system prompt (persisted at {}).",
+            path.display()
+        );
     }
 
     if cli.enable_auto_copy {
@@ -557,36 +1342,7 @@ fn persist_config_flags(cli: &Cli) -> Result<()> {
 }
 
 fn configure_color_output() {
-    let stdout_is_tty = atty::is(atty::Stream::Stdout);
-    let enable_color = if env_var_truthy("CLICOLOR_FORCE") {
-        true
-    } else if std::env::var_os("NO_COLOR").is_some() || env_var_falsey("CLICOLOR") {
-        false
-    } else {
-        stdout_is_tty
-    };
-    qqqa::formatting::set_color_output_enabled(enable_color);
-}
-
-fn env_var_truthy(name: &str) -> bool {
-    matches!(env_var_bool(name), Some(true))
-}
-
-fn env_var_falsey(name: &str) -> bool {
-    matches!(env_var_bool(name), Some(false))
-}
-
-fn env_var_bool(name: &str) -> Option<bool> {
-    let raw = std::env::var(name).ok()?;
-    let normalized = raw.trim().to_ascii_lowercase();
-    if normalized.is_empty() {
-        return None;
-    }
-    match normalized.as_str() {
-        "1" | "true" | "yes" | "on" => Some(true),
-        "0" | "false" | "no" | "off" => Some(false),
-        _ => None,
-    }
+    qqqa::formatting::set_color_output_enabled(qqqa::formatting::color_output_from_env());
 }
 
 #[cfg(test)]
@@ -600,22 +1356,93 @@ mod tests {
         assert_eq!(extracted, "echo foo&<bar>\nls");
     }
 
+    #[test]
+    fn parse_batch_questions_skips_blank_lines() {
+        let questions = parse_batch_questions("how do I list ports?\n\n   \n  what is rg  \n");
+        assert_eq!(questions, vec!["how do I list ports?", "what is rg"]);
+    }
+
+    #[test]
+    fn parse_example_splits_on_separator_and_validates() {
+        let example = parse_example("list files\n---\n<cmd>ls -la</cmd>\n").unwrap();
+        assert_eq!(
+            example,
+            Example {
+                user: "list files".into(),
+                assistant: "<cmd>ls -la</cmd>".into(),
+            }
+        );
+        let messages = chat_messages("sys", std::slice::from_ref(&example), "hi");
+        let roles: Vec<&str> = messages.iter().map(|m| m.role).collect();
+        assert_eq!(roles, vec!["system", "user", "assistant", "user"]);
+
+        assert!(parse_example("no separator").is_err());
+        assert!(parse_example("a\n---\nb\n---\nc").is_err());
+        let err = parse_example("   \n---\nanswer").unwrap_err();
+        assert!(err.to_string().contains("user section"), "{}", err);
+    }
+
+    #[test]
+    fn chat_messages_omits_empty_system_prompt() {
+        let with_system = chat_messages("sys", &[], "hi");
+        assert_eq!(with_system.len(), 2);
+        assert_eq!(with_system[0].role, "system");
+
+        let without = chat_messages("", &[], "hi");
+        assert_eq!(without.len(), 1);
+        assert_eq!(without[0].role, "user");
+        assert_eq!(without[0].content, "hi");
+    }
+
+    #[test]
+    fn extract_first_command_accepts_unterminated_cmd() {
+        let extracted = extract_first_command("<bold>Try</bold><br/><cmd>ls -la &amp;&amp; pwd")
+            .expect("should treat end of text as closing tag");
+        assert_eq!(extracted, "ls -la && pwd");
+
+        let partial_close = extract_first_command("<cmd>git status</cm").expect("partial close");
+        assert_eq!(partial_close, "git status");
+    }
+
     #[test]
     fn extract_first_command_returns_none_when_missing() {
         assert!(extract_first_command("<info>No commands here</info>").is_none());
     }
 
     #[test]
-    fn normalize_ncc_rewrites_short_flag() {
+    fn normalize_short_aliases_rewrites_ncc() {
         let args = vec![
             OsString::from("qq"),
             OsString::from("-ncc"),
             OsString::from("status"),
         ];
-        let normalized = normalize_ncc(args);
+        let normalized = normalize_short_aliases(args);
         assert_eq!(normalized[1], OsString::from("--ncc"));
     }
 
+    #[test]
+    fn normalize_short_aliases_rewrites_cc_and_parses() {
+        let args: Vec<OsString> = ["qq", "-cc", "list", "files"]
+            .into_iter()
+            .map(OsString::from)
+            .collect();
+        let normalized = normalize_short_aliases(args);
+        assert_eq!(normalized[1], OsString::from("--cc"));
+        let cli = Cli::try_parse_from(normalized).expect("-cc parses");
+        assert!(cli.copy_command);
+        assert!(!cli.no_copy_command);
+    }
+
+    #[test]
+    fn normalize_short_aliases_leaves_args_after_double_dash() {
+        let args: Vec<OsString> = ["qq", "--", "-cc"]
+            .into_iter()
+            .map(OsString::from)
+            .collect();
+        let normalized = normalize_short_aliases(args);
+        assert_eq!(normalized[2], OsString::from("-cc"));
+    }
+
     #[test]
     fn pretty_stream_writer_limits_blank_lines() {
         let mut writer = PrettyStreamWriter::new();
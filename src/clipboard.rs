@@ -1,5 +1,7 @@
 use anyhow::{Context, Result, anyhow};
-use std::io::Write;
+use std::fmt;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 
 type CommandSpec = &'static [&'static str];
@@ -21,19 +23,88 @@ const CLIPBOARD_COMMANDS: &[CommandSpec] = &[
     &["clip"],
 ];
 
+/// Windows clipboard helper reachable from WSL through interop.
+#[cfg(target_os = "linux")]
+const WSL_CLIPBOARD_COMMAND: CommandSpec = &["clip.exe"];
+
+fn clipboard_commands() -> Vec<CommandSpec> {
+    #[allow(unused_mut)]
+    let mut commands = CLIPBOARD_COMMANDS.to_vec();
+    #[cfg(target_os = "linux")]
+    if is_wsl() {
+        commands.push(WSL_CLIPBOARD_COMMAND);
+    }
+    commands
+}
+
+#[cfg(target_os = "linux")]
+fn is_wsl() -> bool {
+    std::fs::read_to_string("/proc/version")
+        .map(|version| kernel_version_is_wsl(&version))
+        .unwrap_or(false)
+}
+
+/// WSL kernels report e.g. "Linux version 5.15.90.1-microsoft-standard-WSL2".
+#[cfg(any(target_os = "linux", test))]
+fn kernel_version_is_wsl(version: &str) -> bool {
+    version.to_ascii_lowercase().contains("microsoft")
+}
+
+/// Error returned by [`copy_to_clipboard`] when none of the helpers is installed, as
+/// opposed to a helper that ran and failed.
+#[derive(Debug)]
+pub struct NoClipboardHelper;
+
+impl fmt::Display for NoClipboardHelper {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "No supported clipboard utility found (looked for pbcopy, wl-copy, xclip, clip, clip.exe)"
+        )
+    }
+}
+
+impl std::error::Error for NoClipboardHelper {}
+
+/// File in the config directory recording that auto-copy already reported a missing helper.
+const MISSING_HELPER_MARKER: &str = "clipboard-missing";
+
+fn missing_helper_marker(dir: &Path) -> PathBuf {
+    dir.join(MISSING_HELPER_MARKER)
+}
+
+/// Whether a missing clipboard helper was already reported from `dir`.
+pub fn missing_helper_reported(dir: &Path) -> bool {
+    missing_helper_marker(dir).exists()
+}
+
+/// Remember (best-effort) that a missing helper was reported, or forget it once copying works.
+pub fn set_missing_helper_reported(dir: &Path, reported: bool) {
+    let marker = missing_helper_marker(dir);
+    if reported {
+        let _ = std::fs::write(marker, b"");
+    } else if marker.exists() {
+        let _ = std::fs::remove_file(marker);
+    }
+}
+
 /// Copy text into the user's clipboard using best-effort native utilities.
 pub fn copy_to_clipboard(text: &str) -> Result<()> {
     let mut last_error = None;
-    for spec in CLIPBOARD_COMMANDS {
+    for spec in clipboard_commands() {
         match try_copy_with(spec, text) {
             Ok(()) => return Ok(()),
+            Err(err) if helper_not_installed(&err) => {}
             Err(err) => last_error = Some(err),
         }
     }
 
-    Err(last_error.unwrap_or_else(|| {
-        anyhow!("No supported clipboard utility found (looked for pbcopy, wl-copy, xclip, clip)")
-    }))
+    Err(last_error.unwrap_or_else(|| anyhow!(NoClipboardHelper)))
+}
+
+fn helper_not_installed(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<std::io::Error>()
+        .is_some_and(|io| io.kind() == ErrorKind::NotFound)
 }
 
 fn try_copy_with(spec: CommandSpec, text: &str) -> Result<()> {
@@ -67,3 +138,19 @@ fn try_copy_with(spec: CommandSpec, text: &str) -> Result<()> {
         ))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_wsl_kernel_versions() {
+        assert!(kernel_version_is_wsl(
+            "Linux version 5.15.90.1-microsoft-standard-WSL2 (gcc 11.2.0)"
+        ));
+        assert!(kernel_version_is_wsl("Linux version 4.4.0-19041-Microsoft"));
+        assert!(!kernel_version_is_wsl(
+            "Linux version 6.8.0-45-generic (buildd@lcy02-amd64-075)"
+        ));
+    }
+}
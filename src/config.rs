@@ -1,3 +1,6 @@
+use crate::formatting::OutputFormat;
+use crate::logging;
+use crate::tools::output_encoding::OutputEncoding;
 use anyhow::{Context, Result, anyhow};
 use fs_err as fs;
 use serde::{Deserialize, Serialize};
@@ -9,6 +12,17 @@ const CONFIG_DIR_NAME: &str = ".qq";
 const CONFIG_FILE_NAME: &str = "config.json";
 const LOCAL_PROVIDER_PLACEHOLDER_API_KEY: &str = "qqqa-local-placeholder";
 
+/// Env var naming a model for every profile when `--model` is not passed.
+pub const MODEL_ENV: &str = "QQQA_MODEL";
+
+/// The trimmed `QQQA_MODEL` value, if set and non-blank.
+pub fn env_model_override() -> Option<String> {
+    std::env::var(MODEL_ENV)
+        .ok()
+        .map(|m| m.trim().to_string())
+        .filter(|m| !m.is_empty())
+}
+
 #[derive(Debug)]
 pub struct InitExistsError {
     pub path: PathBuf,
@@ -24,17 +38,13 @@ impl std::error::Error for InitExistsError {}
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
+#[derive(Default)]
 pub enum ProviderMode {
+    #[default]
     Http,
     Cli,
 }
 
-impl Default for ProviderMode {
-    fn default() -> Self {
-        ProviderMode::Http
-    }
-}
-
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
 pub enum CliEngine {
@@ -43,6 +53,14 @@ pub enum CliEngine {
 }
 
 impl CliEngine {
+    /// Config name of the engine (`codex` / `claude`).
+    pub fn name(self) -> &'static str {
+        match self {
+            CliEngine::Codex => "codex",
+            CliEngine::Claude => "claude",
+        }
+    }
+
     pub fn supports_streaming(self) -> bool {
         matches!(self, CliEngine::Claude)
     }
@@ -68,6 +86,10 @@ pub struct ModelProvider {
     /// Optional inline api key in config. Env var takes precedence only if this is absent.
     #[serde(default)]
     pub api_key: Option<String>,
+    /// Optional OS keychain entry (service name) holding the key; wins over `api_key`.
+    /// Requires the `keychain` cargo feature.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key_keychain: Option<String>,
     /// True when the provider targets a local runtime (no API key required).
     #[serde(default)]
     pub local: bool,
@@ -80,6 +102,131 @@ pub struct ModelProvider {
     /// Optional CLI-specific parameters (required when mode = cli).
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub cli: Option<CliProviderConfig>,
+    /// Optional OpenRouter upstream routing preferences (ignored for other providers).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub routing: Option<OpenRouterRouting>,
+    /// How reasoning effort is sent: flat `reasoning_effort` (default) or `reasoning: {effort}`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning_style: Option<ReasoningStyle>,
+    /// Optional connection pooling and keep-alive tuning for HTTP providers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub network: Option<NetworkConfig>,
+    /// Ollama runtime options sent as the request's `options` object (e.g. `num_ctx`).
+    /// A `keep_alive` entry is sent top-level, where Ollama expects it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub options: Option<serde_json::Value>,
+    /// Accept a 2xx answer that is not JSON (e.g. `text/plain`) as the assistant text, for
+    /// minimal gateways. Off by default so malformed JSON still surfaces as an error.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub plain_text_responses: bool,
+    /// Request qa's tool calls as a stream and rebuild them from the deltas, for providers
+    /// that only return tool calls when streaming.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub stream_tool_calls: bool,
+    /// gzip the JSON request body and send `Content-Encoding: gzip`. Only for servers
+    /// that accept compressed request bodies; most answer 400 or 415 otherwise.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub compress_request: bool,
+}
+
+/// Connection reuse and request pacing; unset fields keep reqwest's defaults.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+pub struct NetworkConfig {
+    /// Seconds an idle pooled connection is kept open (reqwest default: 90).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pool_idle_timeout_secs: Option<u64>,
+    /// Maximum idle connections kept per host.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pool_max_idle_per_host: Option<usize>,
+    /// TCP keep-alive interval in seconds (disabled by default).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tcp_keepalive_secs: Option<u64>,
+    /// Speak HTTP/2 from the first byte (h2c on `http://`), for servers known to accept it.
+    /// Off by default: plain `http://` stays on HTTP/1.1 and `https://` negotiates via ALPN.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http2_prior_knowledge: Option<bool>,
+    /// Minimum gap in milliseconds between request starts from one process (default 0:
+    /// no limiting). Spaces out batch and library calls so local models are not overloaded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_request_interval_ms: Option<u64>,
+    /// Retries for transient failures (default 0). For now this covers a stream that ends
+    /// without any content, which is retried once without streaming.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_retries: Option<u32>,
+    /// Seconds to wait for the TCP/TLS connection to open (default 10), separate from the
+    /// overall request timeout.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connect_timeout_secs: Option<u64>,
+}
+
+/// Request shape for the reasoning effort parameter.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ReasoningStyle {
+    /// `"reasoning_effort": "minimal"` (Chat Completions).
+    #[default]
+    Flat,
+    /// `"reasoning": {"effort": "minimal"}` (Responses-style; some OpenRouter models).
+    Object,
+}
+
+/// How much of the XML-tag formatting guide the qq system prompt carries.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum FormattingInstructions {
+    /// Every tag (`<cmd>`, `<bold>`, `<info>`, `<file>`, `<warn>`, `<br/>`) plus spacing rules.
+    #[default]
+    Full,
+    /// Only `<cmd>` around commands; everything else is plain text.
+    Minimal,
+    /// No tags at all; pairs with `--format plain`.
+    None,
+}
+
+impl FormattingInstructions {
+    fn is_full(&self) -> bool {
+        *self == FormattingInstructions::Full
+    }
+}
+
+/// Typed subset of OpenRouter's `provider` request object.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct OpenRouterRouting {
+    /// Upstream provider slugs to try in order (sent as `provider.order`).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub route_order: Vec<String>,
+    /// Whether OpenRouter may fall back to upstreams outside `route_order`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow_fallbacks: Option<bool>,
+    /// Upstream sort strategy, e.g. "throughput", "price", or "latency".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sort: Option<String>,
+}
+
+impl OpenRouterRouting {
+    /// Build the `provider` object OpenRouter expects, or None when nothing is set.
+    pub fn to_provider_object(&self) -> Option<serde_json::Value> {
+        let mut obj = serde_json::Map::new();
+        if !self.route_order.is_empty() {
+            obj.insert("order".into(), serde_json::json!(self.route_order));
+        }
+        if let Some(allow) = self.allow_fallbacks {
+            obj.insert("allow_fallbacks".into(), serde_json::json!(allow));
+        }
+        if let Some(sort) = self
+            .sort
+            .as_deref()
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+        {
+            obj.insert("sort".into(), serde_json::json!(sort));
+        }
+        if obj.is_empty() {
+            None
+        } else {
+            Some(serde_json::Value::Object(obj))
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -87,14 +234,33 @@ pub struct ProviderTlsConfig {
     /// Path to a PEM/DER CA bundle. Relative paths are resolved against ~/.qq/.
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub ca_bundle_path: Option<PathBuf>,
+    /// Inline PEM bundle (one or more `-----BEGIN CERTIFICATE-----` blocks).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca_bundle_pem: Option<String>,
     /// Optional env var name whose value points to the bundle path.
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub ca_bundle_env: Option<String>,
+    /// Optional env var name whose value is the PEM bundle itself.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca_bundle_pem_env: Option<String>,
+    /// INSECURE: skip certificate verification entirely. Only for local dev gateways.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub danger_accept_invalid_certs: bool,
 }
 
 #[derive(Debug, Clone)]
 pub struct ResolvedTlsConfig {
-    pub ca_bundle_path: PathBuf,
+    pub ca_bundle: Option<CaBundle>,
+    pub danger_accept_invalid_certs: bool,
+}
+
+/// Where the extra CA certificates come from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CaBundle {
+    /// PEM or DER file on disk.
+    Path(PathBuf),
+    /// PEM content given inline or through an env var.
+    Pem(String),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -105,9 +271,40 @@ pub struct Profile {
     pub reasoning_effort: Option<String>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub temperature: Option<f32>,
+    /// Penalize tokens by how often they already appeared (-2.0 to 2.0).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f32>,
+    /// Penalize tokens that appeared at all, nudging toward new topics (-2.0 to 2.0).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f32>,
     /// Optional per-profile request timeout override (seconds as string)
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub timeout: Option<String>,
+    /// Extra fields merged into HTTP request bodies (must be a JSON object).
+    /// Keys qqqa already sends (model, messages, stream, ...) are never overwritten.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extra_body: Option<serde_json::Value>,
+    /// Profiles qq tries, in order, when a request to this one fails on the provider's side
+    /// (auth, timeout, rate limit, or server error).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fallback_profiles: Vec<String>,
+}
+
+/// Overrides `qq --save-profile` writes into a new profile on top of its base profile.
+#[derive(Debug, Clone, Default)]
+pub struct ProfileDraft<'a> {
+    /// Profile to copy; `None` uses `default_profile`.
+    pub base: Option<&'a str>,
+    pub model: Option<&'a str>,
+    pub temperature: Option<f32>,
+    pub frequency_penalty: Option<f32>,
+    pub presence_penalty: Option<f32>,
+    /// Merged into the profile's `extra_body`, replacing keys it already has.
+    pub extra_body: serde_json::Map<String, serde_json::Value>,
+    /// A provider base URL or connect timeout different from the base provider's puts the
+    /// profile on a copy of that provider named after the profile.
+    pub api_base: Option<&'a str>,
+    pub connect_timeout_secs: Option<u64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -120,13 +317,75 @@ pub struct Config {
     /// Automatically copy the first recommended <cmd> block from qq answers.
     #[serde(default)]
     pub copy_first_command: bool,
+    /// qq prints raw text unless `--no-raw` is passed.
+    #[serde(default)]
+    pub default_raw: bool,
+    /// qq's answer format (`xml`, `markdown`, or `plain`) when `--format`/`--raw` are not
+    /// passed; takes precedence over `default_raw`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_format: Option<String>,
+    /// How much tag-formatting guidance qq's system prompt includes (`full`, `minimal`,
+    /// `none`); smaller local models often do better with less.
+    #[serde(default, skip_serializing_if = "FormattingInstructions::is_full")]
+    pub formatting_instructions: FormattingInstructions,
+    /// qq waits for the full response unless `--stream` is passed.
+    #[serde(default)]
+    pub default_no_stream: bool,
+    /// Show elapsed seconds next to qq's loading dots (`QQQA_SHOW_ELAPSED` overrides).
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub show_elapsed: bool,
     /// Optional flag to control emoji usage in prompts.
     /// If present and not equal to "0" or "false" (case-insensitive), emojis are disabled.
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub no_emoji: Option<String>,
+    /// Run qa commands that pass the safety checks without asking (like `qa --auto-safe`);
+    /// commands that need confirmation still prompt.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub auto_confirm_safe: bool,
+    /// Run qa commands in a working directory outside home without the extra "Proceed
+    /// anyway?" prompt (like `qa --allow-outside-home`).
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub allow_cwd_outside_home: bool,
     /// Optional list of additional commands allowed for qa execute_command.
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub command_allowlist: Option<Vec<String>>,
+    /// Optional pipeline shapes (program names joined by `|`, e.g. `"git | head"`) that
+    /// skip qa's confirmation prompt.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub safe_pipelines: Option<Vec<String>>,
+    /// Optional extra substrings (case-insensitive) that block a qa command outright,
+    /// e.g. `"git push --force"`; the built-in blocks always apply.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dangerous_patterns: Option<Vec<String>>,
+    /// Optional cap (bytes) on qa write_file content; default 1 MiB.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub write_file_max_bytes: Option<usize>,
+    /// Optional wall-clock limit (seconds) for qa execute_command runs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command_timeout_secs: Option<u64>,
+    /// Optional encoding for cmd.exe/PowerShell command output without a BOM
+    /// (utf-8, utf-16le, utf-16be, cp437, cp850, windows-1252); default utf-8.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub windows_output_encoding: Option<String>,
+    /// Optional shell for qa execute_command on Unix (default `sh`), invoked as `<shell> -lc`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub posix_shell: Option<String>,
+    /// Optional byte budget for the combined history + stdin context in prompts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_context_bytes: Option<usize>,
+    /// Stamp prompts with local time and UTC offset instead of UTC (`--local-time`).
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub prompt_local_time: bool,
+    /// Send the OS and date/time lines in prompts (default on); `false` works like
+    /// `--no-os-line`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub include_os_info: Option<bool>,
+    /// Optional directories outside cwd where qa tools may read/write and run commands.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_exec_roots: Option<Vec<PathBuf>>,
+    /// Short model names expanded to full model IDs, e.g. `"sonnet": "claude-3-5-sonnet-20241022"`.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub model_aliases: HashMap<String, String>,
 }
 
 impl Default for Config {
@@ -143,6 +402,14 @@ impl Default for Config {
                 local: false,
                 tls: None,
                 mode: ProviderMode::Http,
+                routing: None,
+                reasoning_style: None,
+                network: None,
+                api_key_keychain: None,
+                options: None,
+                plain_text_responses: false,
+                stream_tool_calls: false,
+                compress_request: false,
                 cli: None,
             },
         );
@@ -156,6 +423,14 @@ impl Default for Config {
                 local: false,
                 tls: None,
                 mode: ProviderMode::Http,
+                routing: None,
+                reasoning_style: None,
+                network: None,
+                api_key_keychain: None,
+                options: None,
+                plain_text_responses: false,
+                stream_tool_calls: false,
+                compress_request: false,
                 cli: None,
             },
         );
@@ -169,6 +444,14 @@ impl Default for Config {
                 local: false,
                 tls: None,
                 mode: ProviderMode::Http,
+                routing: None,
+                reasoning_style: None,
+                network: None,
+                api_key_keychain: None,
+                options: None,
+                plain_text_responses: false,
+                stream_tool_calls: false,
+                compress_request: false,
                 cli: None,
             },
         );
@@ -182,6 +465,14 @@ impl Default for Config {
                 local: false,
                 tls: None,
                 mode: ProviderMode::Http,
+                routing: None,
+                reasoning_style: None,
+                network: None,
+                api_key_keychain: None,
+                options: None,
+                plain_text_responses: false,
+                stream_tool_calls: false,
+                compress_request: false,
                 cli: None,
             },
         );
@@ -195,6 +486,14 @@ impl Default for Config {
                 local: true,
                 tls: None,
                 mode: ProviderMode::Http,
+                routing: None,
+                reasoning_style: None,
+                network: None,
+                api_key_keychain: None,
+                options: None,
+                plain_text_responses: false,
+                stream_tool_calls: false,
+                compress_request: false,
                 cli: None,
             },
         );
@@ -208,6 +507,14 @@ impl Default for Config {
                 local: true,
                 tls: None,
                 mode: ProviderMode::Cli,
+                routing: None,
+                reasoning_style: None,
+                network: None,
+                api_key_keychain: None,
+                options: None,
+                plain_text_responses: false,
+                stream_tool_calls: false,
+                compress_request: false,
                 cli: Some(CliProviderConfig {
                     engine: CliEngine::Codex,
                     binary: "codex".to_string(),
@@ -226,6 +533,14 @@ impl Default for Config {
                 local: true,
                 tls: None,
                 mode: ProviderMode::Cli,
+                routing: None,
+                reasoning_style: None,
+                network: None,
+                api_key_keychain: None,
+                options: None,
+                plain_text_responses: false,
+                stream_tool_calls: false,
+                compress_request: false,
                 cli: Some(CliProviderConfig {
                     engine: CliEngine::Claude,
                     binary: "claude".to_string(),
@@ -243,7 +558,11 @@ impl Default for Config {
                 model: "openai/gpt-4.1-nano".to_string(),
                 reasoning_effort: None,
                 temperature: None,
+                frequency_penalty: None,
+                presence_penalty: None,
                 timeout: None,
+                extra_body: None,
+                fallback_profiles: Vec::new(),
             },
         );
         profiles.insert(
@@ -253,7 +572,11 @@ impl Default for Config {
                 model: "gpt-5-mini".to_string(),
                 reasoning_effort: None,
                 temperature: None,
+                frequency_penalty: None,
+                presence_penalty: None,
                 timeout: None,
+                extra_body: None,
+                fallback_profiles: Vec::new(),
             },
         );
         profiles.insert(
@@ -263,7 +586,11 @@ impl Default for Config {
                 model: "openai/gpt-oss-20b".to_string(),
                 reasoning_effort: None,
                 temperature: None,
+                frequency_penalty: None,
+                presence_penalty: None,
                 timeout: None,
+                extra_body: None,
+                fallback_profiles: Vec::new(),
             },
         );
         profiles.insert(
@@ -273,7 +600,11 @@ impl Default for Config {
                 model: "claude-3-5-sonnet-20241022".to_string(),
                 reasoning_effort: None,
                 temperature: None,
+                frequency_penalty: None,
+                presence_penalty: None,
                 timeout: None,
+                extra_body: None,
+                fallback_profiles: Vec::new(),
             },
         );
         profiles.insert(
@@ -283,7 +614,11 @@ impl Default for Config {
                 model: "llama3.1".to_string(),
                 reasoning_effort: None,
                 temperature: None,
+                frequency_penalty: None,
+                presence_penalty: None,
                 timeout: None,
+                extra_body: None,
+                fallback_profiles: Vec::new(),
             },
         );
         profiles.insert(
@@ -293,7 +628,11 @@ impl Default for Config {
                 model: "gpt-5".to_string(),
                 reasoning_effort: Some("minimal".to_string()),
                 temperature: None,
+                frequency_penalty: None,
+                presence_penalty: None,
                 timeout: None,
+                extra_body: None,
+                fallback_profiles: Vec::new(),
             },
         );
         profiles.insert(
@@ -303,7 +642,11 @@ impl Default for Config {
                 model: "claude-haiku-4-5".to_string(),
                 reasoning_effort: None,
                 temperature: None,
+                frequency_penalty: None,
+                presence_penalty: None,
                 timeout: None,
+                extra_body: None,
+                fallback_profiles: Vec::new(),
             },
         );
 
@@ -313,12 +656,73 @@ impl Default for Config {
             profiles,
             include_history: false,
             copy_first_command: false,
+            default_raw: false,
+            default_no_stream: false,
+            default_format: None,
+            formatting_instructions: FormattingInstructions::Full,
+            show_elapsed: false,
             no_emoji: None,
+            auto_confirm_safe: false,
+            allow_cwd_outside_home: false,
             command_allowlist: None,
+            safe_pipelines: None,
+            dangerous_patterns: None,
+            write_file_max_bytes: None,
+            command_timeout_secs: None,
+            posix_shell: None,
+            windows_output_encoding: None,
+            max_context_bytes: None,
+            prompt_local_time: false,
+            include_os_info: None,
+            allowed_exec_roots: None,
+            model_aliases: HashMap::new(),
         }
     }
 }
 
+/// Where a profile's API key would come from, without making any request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyStatus {
+    /// `api_key_keychain` names an OS keychain entry (not read until a request is made).
+    Keychain(String),
+    /// `api_key` is set inline in the config.
+    Inline,
+    /// The provider's `env_key` variable is set.
+    Env(String),
+    /// Local provider without a key; a placeholder is sent.
+    LocalPlaceholder,
+    /// CLI-backed provider; the CLI handles its own auth.
+    NotRequired,
+    /// No key found; holds the env var that would need to be set.
+    Missing(String),
+    /// The profile references a provider that is not configured.
+    UnknownProvider,
+}
+
+impl std::fmt::Display for KeyStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeyStatus::Keychain(entry) => write!(f, "keychain {}", entry),
+            KeyStatus::Inline => write!(f, "inline api_key"),
+            KeyStatus::Env(var) => write!(f, "env {}", var),
+            KeyStatus::LocalPlaceholder => write!(f, "local (no key needed)"),
+            KeyStatus::NotRequired => write!(f, "cli (no key needed)"),
+            KeyStatus::Missing(var) => write!(f, "missing (set {})", var),
+            KeyStatus::UnknownProvider => write!(f, "unknown provider"),
+        }
+    }
+}
+
+/// One row of `qq --list-profiles`.
+#[derive(Debug, Clone)]
+pub struct ProfileSummary {
+    pub name: String,
+    pub provider: String,
+    pub model: String,
+    pub key: KeyStatus,
+    pub is_default: bool,
+}
+
 #[derive(Debug, Clone)]
 pub struct HttpConnection {
     pub base_url: String,
@@ -327,6 +731,13 @@ pub struct HttpConnection {
     pub is_local: bool,
     pub headers: HashMap<String, String>,
     pub tls: Option<ResolvedTlsConfig>,
+    pub network: NetworkConfig,
+    /// Provider's `plain_text_responses`.
+    pub plain_text_responses: bool,
+    /// Provider's `stream_tool_calls`.
+    pub stream_tool_calls: bool,
+    /// Provider's `compress_request`.
+    pub compress_request: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -350,7 +761,13 @@ pub struct EffectiveProfile {
     pub model: String,
     pub connection: ProviderConnection,
     pub reasoning_effort: Option<String>,
+    pub reasoning_style: ReasoningStyle,
     pub temperature: Option<f32>,
+    pub frequency_penalty: Option<f32>,
+    pub presence_penalty: Option<f32>,
+    pub extra_body: Option<serde_json::Map<String, serde_json::Value>>,
+    /// Per-run `--param` body fields (empty unless given on the command line).
+    pub request_params: serde_json::Map<String, serde_json::Value>,
 }
 
 impl EffectiveProfile {
@@ -376,11 +793,16 @@ impl EffectiveProfile {
     }
 }
 
+/// `~/.qq`, where the config and qq's other state files live (it may not exist yet).
+pub fn config_dir() -> Result<PathBuf> {
+    let home = dirs::home_dir().ok_or_else(|| anyhow!("Could not determine home directory"))?;
+    Ok(home.join(CONFIG_DIR_NAME))
+}
+
 impl Config {
     /// Load config from disk or create a default one on first run.
     pub fn load_or_init(debug: bool) -> Result<(Self, PathBuf)> {
-        let home = dirs::home_dir().ok_or_else(|| anyhow!("Could not determine home directory"))?;
-        let dir = home.join(CONFIG_DIR_NAME);
+        let dir = config_dir()?;
         let path = dir.join(CONFIG_FILE_NAME);
 
         if !dir.exists() {
@@ -414,6 +836,14 @@ impl Config {
         Ok(())
     }
 
+    /// Built-in defaults (every provider and profile) as pretty JSON with sorted keys, for
+    /// `qq --print-default-config`. Fields skipped when unset do not appear.
+    pub fn default_config_json() -> Result<String> {
+        let value =
+            serde_json::to_value(Self::default()).with_context(|| "Serializing config JSON")?;
+        serde_json::to_string_pretty(&value).with_context(|| "Serializing config JSON")
+    }
+
     /// Whether the no-emoji flag is effectively enabled.
     pub fn no_emoji_enabled(&self) -> bool {
         match &self.no_emoji {
@@ -430,6 +860,84 @@ impl Config {
         self.command_allowlist.clone().unwrap_or_default()
     }
 
+    /// Pipeline shapes from `safe_pipelines` that qa runs without confirmation.
+    pub fn safe_pipelines(&self) -> Vec<String> {
+        self.safe_pipelines.clone().unwrap_or_default()
+    }
+
+    /// Extra command patterns from `dangerous_patterns` that qa always blocks.
+    pub fn dangerous_patterns(&self) -> Vec<String> {
+        self.dangerous_patterns.clone().unwrap_or_default()
+    }
+
+    /// Parsed `default_format`, if set.
+    pub fn default_format(&self) -> Result<Option<OutputFormat>> {
+        self.default_format.as_deref().map(str::parse).transpose()
+    }
+
+    /// Parsed `windows_output_encoding`; unset means UTF-8.
+    pub fn windows_output_encoding(&self) -> Result<OutputEncoding> {
+        self.windows_output_encoding
+            .as_deref()
+            .map(str::parse)
+            .transpose()
+            .map(Option::unwrap_or_default)
+    }
+
+    /// Configured `allowed_exec_roots` with a leading `~` expanded; relative entries are dropped.
+    pub fn allowed_exec_roots(&self) -> Vec<PathBuf> {
+        let home = dirs::home_dir();
+        self.allowed_exec_roots
+            .iter()
+            .flatten()
+            .filter_map(|root| match root.strip_prefix("~") {
+                Ok(rest) => home.as_ref().map(|h| h.join(rest)),
+                Err(_) => Some(root.clone()),
+            })
+            .filter(|root| root.is_absolute())
+            .collect()
+    }
+
+    /// Read-only overview of every profile, sorted by name. Makes no network calls.
+    pub fn profile_summaries(&self) -> Vec<ProfileSummary> {
+        let mut names: Vec<&String> = self.profiles.keys().collect();
+        names.sort();
+        names
+            .into_iter()
+            .map(|name| {
+                let profile = &self.profiles[name];
+                let provider = self.model_providers.get(&profile.model_provider);
+                let key = match provider {
+                    None => KeyStatus::UnknownProvider,
+                    Some(p) if p.mode == ProviderMode::Cli || p.cli.is_some() => {
+                        KeyStatus::NotRequired
+                    }
+                    Some(p) if p.api_key_keychain.is_some() => {
+                        KeyStatus::Keychain(p.api_key_keychain.clone().unwrap_or_default())
+                    }
+                    Some(p) if p.api_key.is_some() => KeyStatus::Inline,
+                    Some(p) if std::env::var_os(&p.env_key).is_some() => {
+                        KeyStatus::Env(p.env_key.clone())
+                    }
+                    Some(p) if p.local => KeyStatus::LocalPlaceholder,
+                    Some(p) => KeyStatus::Missing(p.env_key.clone()),
+                };
+                ProfileSummary {
+                    name: name.clone(),
+                    provider: profile.model_provider.clone(),
+                    model: profile.model.clone(),
+                    key,
+                    is_default: *name == self.default_profile,
+                }
+            })
+            .collect()
+    }
+
+    /// Whether prompts carry the OS and time line by default (on unless `include_os_info` is false).
+    pub fn os_info_enabled(&self) -> bool {
+        self.include_os_info.unwrap_or(true)
+    }
+
     /// Whether terminal history should be included in prompts by default.
     pub fn history_enabled(&self) -> bool {
         self.include_history
@@ -463,12 +971,202 @@ impl Config {
         }
     }
 
+    /// Add profile `name` built from `draft`, returning the provider it uses. Existing
+    /// profiles (and a differing provider of the same name) are only replaced with `force`.
+    pub fn save_profile(
+        &mut self,
+        name: &str,
+        draft: &ProfileDraft,
+        force: bool,
+    ) -> Result<String> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(anyhow!("Profile name must not be empty"));
+        }
+        if self.profiles.contains_key(name) && !force {
+            return Err(anyhow!(
+                "Profile '{}' already exists; pass --force to overwrite it",
+                name
+            ));
+        }
+        let base_name = draft.base.unwrap_or(&self.default_profile);
+        let mut profile = self
+            .profiles
+            .get(base_name)
+            .cloned()
+            .ok_or_else(|| anyhow!("Profile '{}' not found in config", base_name))?;
+        let base_provider = self
+            .model_providers
+            .get(&profile.model_provider)
+            .ok_or_else(|| {
+                anyhow!(
+                    "Profile '{}' references unknown provider '{}'",
+                    base_name,
+                    profile.model_provider
+                )
+            })?;
+
+        let mut provider = base_provider.clone();
+        if let Some(base_url) = draft.api_base {
+            provider.base_url = base_url.to_string();
+        }
+        if let Some(secs) = draft.connect_timeout_secs {
+            provider
+                .network
+                .get_or_insert_with(Default::default)
+                .connect_timeout_secs = Some(secs);
+        }
+        if (draft.api_base.is_some() || draft.connect_timeout_secs.is_some())
+            && (provider.mode == ProviderMode::Cli || provider.cli.is_some())
+        {
+            return Err(anyhow!(
+                "--api-base and --connect-timeout only apply to HTTP providers (current: '{}')",
+                profile.model_provider
+            ));
+        }
+        let same_provider =
+            serde_json::to_value(&provider).ok() == serde_json::to_value(base_provider).ok();
+        if !same_provider {
+            if let Some(existing) = self.model_providers.get(name)
+                && serde_json::to_value(existing).ok() != serde_json::to_value(&provider).ok()
+                && !force
+            {
+                return Err(anyhow!(
+                    "Provider '{}' already exists with different settings; pass --force to overwrite it",
+                    name
+                ));
+            }
+            self.model_providers.insert(name.to_string(), provider);
+            profile.model_provider = name.to_string();
+        }
+
+        if let Some(model) = draft.model {
+            profile.model = model.to_string();
+        }
+        profile.temperature = draft.temperature.or(profile.temperature);
+        profile.frequency_penalty = draft.frequency_penalty.or(profile.frequency_penalty);
+        profile.presence_penalty = draft.presence_penalty.or(profile.presence_penalty);
+        if !draft.extra_body.is_empty() {
+            let mut body = match profile.extra_body.take() {
+                Some(serde_json::Value::Object(map)) => map,
+                Some(_) => {
+                    return Err(anyhow!(
+                        "Invalid extra_body for profile '{}': must be a JSON object",
+                        base_name
+                    ));
+                }
+                None => serde_json::Map::new(),
+            };
+            body.extend(draft.extra_body.clone());
+            profile.extra_body = Some(serde_json::Value::Object(body));
+        }
+        let provider_key = profile.model_provider.clone();
+        self.profiles.insert(name.to_string(), profile);
+        Ok(provider_key)
+    }
+
+    /// Advisory hint when `--model` is given without `-p` and the model name looks like it
+    /// belongs to a different provider than the default profile. Never changes resolution.
+    pub fn model_profile_hint(
+        &self,
+        profile_opt: Option<&str>,
+        model_override: Option<&str>,
+    ) -> Option<String> {
+        if profile_opt.is_some() {
+            return None;
+        }
+        let model = model_override?.trim();
+        let (family, providers) = guess_model_family(model)?;
+        let current = self
+            .profiles
+            .get(&self.default_profile)
+            .map(|p| p.model_provider.as_str())?;
+        if providers.contains(&current) {
+            return None;
+        }
+        // Prefer profiles in the same order as the family's provider list, then by name.
+        let mut candidates: Vec<(&String, &Profile)> = self
+            .profiles
+            .iter()
+            .filter(|(_, p)| providers.contains(&p.model_provider.as_str()))
+            .collect();
+        candidates.sort_by_key(|(name, p)| {
+            let rank = providers
+                .iter()
+                .position(|k| *k == p.model_provider)
+                .unwrap_or(usize::MAX);
+            (rank, name.as_str() != p.model_provider, (*name).clone())
+        });
+        let (name, _) = candidates.first()?;
+        Some(format!(
+            "model '{}' looks like {}; try -p {}",
+            model, family, name
+        ))
+    }
+
     /// Resolve the effective profile based on optional CLI overrides for profile name and model.
+    /// Without a model override, `QQQA_MODEL` replaces the profile's model.
     pub fn resolve_profile(
         &self,
         profile_opt: Option<&str>,
         model_override: Option<&str>,
         config_dir: Option<&Path>,
+    ) -> Result<EffectiveProfile> {
+        self.resolve_profile_with_api_key(profile_opt, model_override, config_dir, None)
+    }
+
+    /// `fallback_profiles` of the named profile (or `default_profile`); empty when unset
+    /// or the profile does not exist.
+    pub fn fallback_profiles(&self, profile_opt: Option<&str>) -> &[String] {
+        self.profiles
+            .get(profile_opt.unwrap_or(&self.default_profile))
+            .map(|p| p.fallback_profiles.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Resolve `profile_name` exactly as configured: unlike [`Config::resolve_profile`],
+    /// `QQQA_MODEL` does not replace its model. Used for fallback profiles, which usually
+    /// sit on a different provider than the model override was meant for.
+    pub fn resolve_profile_as_configured(
+        &self,
+        profile_name: &str,
+        config_dir: Option<&Path>,
+        api_key: Option<&str>,
+    ) -> Result<EffectiveProfile> {
+        logging::traced_sync("resolve_profile", profile_name, || {
+            self.resolve_profile_inner(Some(profile_name), None, false, config_dir, api_key)
+        })
+    }
+
+    /// Like [`Config::resolve_profile`], but `api_key` (when set) replaces the inline/env key
+    /// of HTTP providers, so a missing provider key is not an error.
+    pub fn resolve_profile_with_api_key(
+        &self,
+        profile_opt: Option<&str>,
+        model_override: Option<&str>,
+        config_dir: Option<&Path>,
+        api_key: Option<&str>,
+    ) -> Result<EffectiveProfile> {
+        let profile_name = profile_opt.unwrap_or(&self.default_profile);
+        logging::traced_sync("resolve_profile", profile_name, || {
+            self.resolve_profile_inner(profile_opt, model_override, true, config_dir, api_key)
+        })
+        .map_err(|err| {
+            let env_model = env_model_override();
+            match self.model_profile_hint(profile_opt, model_override.or(env_model.as_deref())) {
+                Some(hint) => anyhow!("{}\nHint: {}", err, hint),
+                None => err,
+            }
+        })
+    }
+
+    fn resolve_profile_inner(
+        &self,
+        profile_opt: Option<&str>,
+        model_override: Option<&str>,
+        use_env_model: bool,
+        config_dir: Option<&Path>,
+        api_key_override: Option<&str>,
     ) -> Result<EffectiveProfile> {
         let profile_name = profile_opt.unwrap_or(&self.default_profile);
         let profile = self
@@ -481,7 +1179,9 @@ impl Config {
             .get(provider_key)
             .ok_or_else(|| anyhow!("Model provider '{}' not found in config", provider_key))?;
 
-        let user_model_override = model_override.map(|m| m.to_string());
+        let user_model_override = model_override
+            .map(|m| m.to_string())
+            .or_else(|| use_env_model.then(env_model_override).flatten());
 
         let request_timeout_secs = if let Some(raw) = profile.timeout.as_deref() {
             let trimmed = raw.trim();
@@ -506,6 +1206,45 @@ impl Config {
         } else {
             None
         };
+        let mut extra_body = match &profile.extra_body {
+            None => None,
+            Some(serde_json::Value::Object(map)) => Some(map.clone()),
+            Some(_) => {
+                return Err(anyhow!(
+                    "Invalid extra_body for profile '{}': must be a JSON object",
+                    profile_name
+                ));
+            }
+        };
+        // An explicit extra_body.provider wins over the typed routing fields.
+        if provider_key == "openrouter"
+            && let Some(routing) = provider
+                .routing
+                .as_ref()
+                .and_then(OpenRouterRouting::to_provider_object)
+        {
+            extra_body
+                .get_or_insert_with(serde_json::Map::new)
+                .entry("provider")
+                .or_insert(routing);
+        }
+        if let Some(options) = &provider.options {
+            let serde_json::Value::Object(options) = options else {
+                return Err(anyhow!(
+                    "Invalid options for provider '{}': must be a JSON object",
+                    provider_key
+                ));
+            };
+            let mut options = options.clone();
+            let body = extra_body.get_or_insert_with(serde_json::Map::new);
+            if let Some(keep_alive) = options.remove("keep_alive") {
+                body.entry("keep_alive").or_insert(keep_alive);
+            }
+            if !options.is_empty() {
+                body.entry("options")
+                    .or_insert(serde_json::Value::Object(options));
+            }
+        }
         let effective_mode = if provider.mode == ProviderMode::Cli {
             ProviderMode::Cli
         } else if provider.cli.is_some() {
@@ -518,17 +1257,19 @@ impl Config {
         let mut model = user_model_override
             .clone()
             .unwrap_or_else(|| profile.model.clone());
-        if user_model_override.is_none() && matches!(effective_mode, ProviderMode::Cli) {
-            if let Some(cli) = provider.cli.as_ref() {
-                if let Some(override_model) = cli
-                    .model_override
-                    .as_deref()
-                    .map(str::trim)
-                    .filter(|m| !m.is_empty())
-                {
-                    model = override_model.to_string();
-                }
-            }
+        if user_model_override.is_none()
+            && matches!(effective_mode, ProviderMode::Cli)
+            && let Some(cli) = provider.cli.as_ref()
+            && let Some(override_model) = cli
+                .model_override
+                .as_deref()
+                .map(str::trim)
+                .filter(|m| !m.is_empty())
+        {
+            model = override_model.to_string();
+        }
+        if let Some(full) = self.model_aliases.get(model.trim()) {
+            model = full.clone();
         }
 
         let connection = match effective_mode {
@@ -542,7 +1283,13 @@ impl Config {
                 let headers = provider_default_headers(provider_key);
                 // Prefer inline api_key; else env var per env_key. Local providers fall back to a
                 // placeholder key so callers can continue to send an Authorization header.
-                let api_key = if let Some(k) = provider.api_key.clone() {
+                let api_key = if let Some(k) = api_key_override {
+                    k.to_string()
+                } else if let Some(entry) = provider.api_key_keychain.as_deref() {
+                    crate::keychain::read_secret(entry).with_context(|| {
+                        format!("Reading API key for provider '{}'", provider_key)
+                    })?
+                } else if let Some(k) = provider.api_key.clone() {
                     k
                 } else if let Ok(value) = std::env::var(&provider.env_key) {
                     value
@@ -569,6 +1316,10 @@ impl Config {
                     is_local: provider.local,
                     headers,
                     tls,
+                    network: provider.network.unwrap_or_default(),
+                    plain_text_responses: provider.plain_text_responses,
+                    stream_tool_calls: provider.stream_tool_calls,
+                    compress_request: provider.compress_request,
                 })
             }
             ProviderMode::Cli => {
@@ -577,7 +1328,7 @@ impl Config {
                     .as_ref()
                     .ok_or_else(|| anyhow!("Provider '{}' missing cli settings", provider_key))?;
                 ProviderConnection::Cli(CliConnection {
-                    engine: cli.engine.clone(),
+                    engine: cli.engine,
                     binary: cli.binary.clone(),
                     base_args: cli.base_args.clone(),
                     request_timeout_secs,
@@ -590,7 +1341,12 @@ impl Config {
             model,
             connection,
             reasoning_effort: profile.reasoning_effort.clone(),
+            reasoning_style: provider.reasoning_style.unwrap_or_default(),
             temperature: profile.temperature,
+            frequency_penalty: profile.frequency_penalty,
+            presence_penalty: profile.presence_penalty,
+            extra_body,
+            request_params: serde_json::Map::new(),
         })
     }
 
@@ -688,6 +1444,12 @@ impl Config {
                 }
             }
 
+            if provider_key == "ollama" {
+                println!(
+                    "Tip: add \"options\": {{\"num_ctx\": 8192, \"keep_alive\": \"10m\"}} under model_providers.ollama in config.json to set the context window and how long the model stays loaded."
+                );
+            }
+
             if provider.local {
                 let default_base = provider.base_url.clone();
                 println!(
@@ -699,10 +1461,10 @@ impl Config {
                 let mut base_in = String::new();
                 io::stdin().read_line(&mut base_in).ok();
                 let base_in = base_in.trim();
-                if !base_in.is_empty() {
-                    if let Some(mp) = cfg.model_providers.get_mut(&provider_key) {
-                        mp.base_url = base_in.to_string();
-                    }
+                if !base_in.is_empty()
+                    && let Some(mp) = cfg.model_providers.get_mut(&provider_key)
+                {
+                    mp.base_url = base_in.to_string();
                 }
             }
         } else {
@@ -792,50 +1554,84 @@ impl Config {
 }
 
 impl ProviderTlsConfig {
+    /// Pick the CA bundle source. Precedence: `ca_bundle_path` > `ca_bundle_pem` >
+    /// `ca_bundle_env` (path) > `ca_bundle_pem_env` (PEM content).
     pub fn resolve(&self, config_dir: Option<&Path>) -> Result<Option<ResolvedTlsConfig>> {
-        let env_path = match self.ca_bundle_env.as_deref() {
-            Some(key) if key.trim().is_empty() => {
-                return Err(anyhow!("TLS config ca_bundle_env cannot be empty"));
+        let bundle = if let Some(path) = self.ca_bundle_path.clone() {
+            Some(CaBundle::Path(path))
+        } else if let Some(pem) = self.ca_bundle_pem.as_deref() {
+            if pem.trim().is_empty() {
+                return Err(anyhow!("TLS config ca_bundle_pem cannot be empty"));
             }
-            Some(key) => match std::env::var(key) {
-                Ok(value) => {
-                    if value.trim().is_empty() {
-                        return Err(anyhow!(
-                            "Environment variable '{}' for TLS CA bundle is empty",
-                            key
-                        ));
-                    }
-                    Some(PathBuf::from(value))
-                }
-                Err(std::env::VarError::NotPresent) => None,
-                Err(err) => {
-                    return Err(anyhow!(
-                        "Failed to read environment variable '{}': {}",
-                        key,
-                        err
-                    ));
-                }
-            },
-            None => None,
-        };
-
-        let chosen = env_path.or_else(|| self.ca_bundle_path.clone());
-        let Some(path) = chosen else {
-            return Ok(None);
-        };
-
-        let resolved = if path.is_absolute() || config_dir.is_none() {
-            path
+            Some(CaBundle::Pem(pem.to_string()))
+        } else if let Some(path) = tls_env_value(self.ca_bundle_env.as_deref(), "ca_bundle_env")? {
+            Some(CaBundle::Path(PathBuf::from(path)))
         } else {
-            config_dir.unwrap().join(path)
+            tls_env_value(self.ca_bundle_pem_env.as_deref(), "ca_bundle_pem_env")?
+                .map(CaBundle::Pem)
         };
 
+        let bundle = bundle.map(|bundle| match bundle {
+            CaBundle::Path(path) => CaBundle::Path(match config_dir {
+                Some(dir) if !path.is_absolute() => dir.join(path),
+                _ => path,
+            }),
+            pem => pem,
+        });
+        if bundle.is_none() && !self.danger_accept_invalid_certs {
+            return Ok(None);
+        }
         Ok(Some(ResolvedTlsConfig {
-            ca_bundle_path: resolved,
+            ca_bundle: bundle,
+            danger_accept_invalid_certs: self.danger_accept_invalid_certs,
         }))
     }
 }
 
+/// Read the env var named by a TLS setting; unset vars are skipped, empty ones are errors.
+fn tls_env_value(key: Option<&str>, field: &str) -> Result<Option<String>> {
+    let Some(key) = key else {
+        return Ok(None);
+    };
+    if key.trim().is_empty() {
+        return Err(anyhow!("TLS config {} cannot be empty", field));
+    }
+    match std::env::var(key) {
+        Ok(value) if value.trim().is_empty() => Err(anyhow!(
+            "Environment variable '{}' for TLS CA bundle is empty",
+            key
+        )),
+        Ok(value) => Ok(Some(value)),
+        Err(std::env::VarError::NotPresent) => Ok(None),
+        Err(err) => Err(anyhow!(
+            "Failed to read environment variable '{}': {}",
+            key,
+            err
+        )),
+    }
+}
+
+/// Rough provider family for a bare model name, with the provider keys that serve it.
+/// Vendor-prefixed slugs (e.g. `anthropic/claude-3.5-sonnet`) are gateway-style and skipped.
+fn guess_model_family(model: &str) -> Option<(&'static str, &'static [&'static str])> {
+    let lower = model.to_ascii_lowercase();
+    if lower.is_empty() || lower.contains('/') {
+        return None;
+    }
+    if lower.starts_with("claude") {
+        return Some(("Anthropic", &["anthropic", "claude"]));
+    }
+    const OPENAI_PREFIXES: [&str; 5] = ["gpt-", "o1", "o3", "o4", "chatgpt"];
+    if OPENAI_PREFIXES.iter().any(|p| lower.starts_with(p)) {
+        return Some(("OpenAI", &["openai", "codex"]));
+    }
+    const OLLAMA_PREFIXES: [&str; 6] = ["llama", "qwen", "mistral", "gemma", "phi", "deepseek"];
+    if OLLAMA_PREFIXES.iter().any(|p| lower.starts_with(p)) {
+        return Some(("a local Ollama model", &["ollama"]));
+    }
+    None
+}
+
 fn provider_default_headers(provider_key: &str) -> HashMap<String, String> {
     match provider_key {
         "openrouter" => {
@@ -851,6 +1647,28 @@ fn provider_default_headers(provider_key: &str) -> HashMap<String, String> {
     }
 }
 
+fn set_permissions_dir(path: &Path, _debug: bool) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mut perms = fs::metadata(path)?.permissions();
+        perms.set_mode(0o700);
+        fs::set_permissions(path, perms)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn set_permissions_file(path: &Path, _debug: bool) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mut perms = fs::metadata(path)?.permissions();
+        perms.set_mode(0o600);
+        fs::set_permissions(path, perms)?;
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -861,20 +1679,20 @@ mod tests {
         let dir = tempdir().unwrap();
         let tls = ProviderTlsConfig {
             ca_bundle_path: Some(PathBuf::from("certs/litellm-ca.pem")),
-            ca_bundle_env: None,
+            ..Default::default()
         };
         let resolved = tls
             .resolve(Some(dir.path()))
             .expect("resolution succeeds")
             .expect("path present");
         assert_eq!(
-            resolved.ca_bundle_path,
-            dir.path().join("certs/litellm-ca.pem")
+            resolved.ca_bundle,
+            Some(CaBundle::Path(dir.path().join("certs/litellm-ca.pem")))
         );
     }
 
     #[test]
-    fn tls_config_prefers_env_value_when_present() {
+    fn tls_config_uses_env_path_when_no_explicit_bundle() {
         let key = "QQQA_TEST_TLS_ENV";
         let dir = tempdir().unwrap();
         let env_path = dir.path().join("env-ca.pem");
@@ -882,38 +1700,74 @@ mod tests {
             std::env::set_var(key, env_path.to_string_lossy().to_string());
         }
         let tls = ProviderTlsConfig {
-            ca_bundle_path: Some(PathBuf::from("ignored.pem")),
             ca_bundle_env: Some(key.to_string()),
+            ca_bundle_pem_env: Some("QQQA_TEST_TLS_UNUSED".to_string()),
+            ..Default::default()
         };
         let resolved = tls
             .resolve(None)
             .expect("resolution succeeds")
             .expect("env value present");
-        assert_eq!(resolved.ca_bundle_path, env_path);
+        assert_eq!(resolved.ca_bundle, Some(CaBundle::Path(env_path)));
+
+        let tls = ProviderTlsConfig {
+            ca_bundle_path: Some(PathBuf::from("/etc/explicit.pem")),
+            ..tls
+        };
+        let resolved = tls.resolve(None).unwrap().unwrap();
+        assert_eq!(
+            resolved.ca_bundle,
+            Some(CaBundle::Path(PathBuf::from("/etc/explicit.pem")))
+        );
         unsafe {
             std::env::remove_var(key);
         }
     }
-}
 
-fn set_permissions_dir(path: &Path, _debug: bool) -> Result<()> {
-    #[cfg(unix)]
-    {
-        use std::os::unix::fs::PermissionsExt;
-        let mut perms = fs::metadata(path)?.permissions();
-        perms.set_mode(0o700);
-        fs::set_permissions(path, perms)?;
+    #[test]
+    fn tls_config_danger_flag_resolves_without_bundle() {
+        assert!(
+            ProviderTlsConfig::default()
+                .resolve(None)
+                .unwrap()
+                .is_none()
+        );
+        let tls = ProviderTlsConfig {
+            danger_accept_invalid_certs: true,
+            ..Default::default()
+        };
+        let resolved = tls.resolve(None).unwrap().expect("danger flag kept");
+        assert!(resolved.danger_accept_invalid_certs);
+        assert_eq!(resolved.ca_bundle, None);
     }
-    Ok(())
-}
 
-fn set_permissions_file(path: &Path, _debug: bool) -> Result<()> {
-    #[cfg(unix)]
-    {
-        use std::os::unix::fs::PermissionsExt;
-        let mut perms = fs::metadata(path)?.permissions();
-        perms.set_mode(0o600);
-        fs::set_permissions(path, perms)?;
+    #[test]
+    fn tls_config_inline_pem_beats_env_sources() {
+        let key = "QQQA_TEST_TLS_PEM_ENV";
+        unsafe {
+            std::env::set_var(key, "-----BEGIN CERTIFICATE-----\nenv\n");
+        }
+        let tls = ProviderTlsConfig {
+            ca_bundle_pem_env: Some(key.to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            tls.resolve(None).unwrap().unwrap().ca_bundle,
+            Some(CaBundle::Pem("-----BEGIN CERTIFICATE-----\nenv\n".into()))
+        );
+
+        let tls = ProviderTlsConfig {
+            ca_bundle_pem: Some("-----BEGIN CERTIFICATE-----\ninline\n".into()),
+            ..tls
+        };
+        assert_eq!(
+            tls.resolve(None).unwrap().unwrap().ca_bundle,
+            Some(CaBundle::Pem(
+                "-----BEGIN CERTIFICATE-----\ninline\n".into()
+            ))
+        );
+        unsafe {
+            std::env::remove_var(key);
+        }
     }
-    Ok(())
 }
@@ -0,0 +1,82 @@
+//! Top-level listing of the working directory for `--cwd-context`.
+//!
+//! The scan reads one directory level with `read_dir` (no shell command). It looks at
+//! symlinks without following them and keeps at most [`CWD_LISTING_MAX_ENTRIES`] names,
+//! so a huge directory cannot crowd out the rest of the prompt.
+
+use anyhow::{Context, Result};
+use fs_err as fs;
+use std::path::Path;
+
+/// Most entries listed; the rest are summarized as "... and N more".
+pub const CWD_LISTING_MAX_ENTRIES: usize = 50;
+
+/// Longer names are cut to this many characters.
+const MAX_NAME_CHARS: usize = 120;
+
+/// List the current directory (see the module docs for the bounds).
+pub fn current_dir_listing() -> Result<String> {
+    let cwd = std::env::current_dir().context("Failed to read current directory")?;
+    dir_listing(&cwd, CWD_LISTING_MAX_ENTRIES)
+}
+
+/// `dir` as a path line followed by one `- name (type)` line per entry, directories
+/// first, then by name.
+pub fn dir_listing(dir: &Path, max_entries: usize) -> Result<String> {
+    let mut entries: Vec<(bool, String, &'static str)> = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let kind = match entry.file_type() {
+            Ok(t) if t.is_symlink() => "symlink",
+            Ok(t) if t.is_dir() => "dir",
+            Ok(t) if t.is_file() => "file",
+            _ => "other",
+        };
+        let mut name = entry.file_name().to_string_lossy().into_owned();
+        if name.chars().count() > MAX_NAME_CHARS {
+            name = name.chars().take(MAX_NAME_CHARS).collect::<String>() + "...";
+        }
+        entries.push((kind != "dir", name, kind));
+    }
+    entries.sort();
+
+    let mut out = format!("{}\n", dir.display());
+    if entries.is_empty() {
+        out.push_str("(empty)\n");
+    }
+    for (_, name, kind) in entries.iter().take(max_entries) {
+        out.push_str(&format!("- {} ({})\n", name, kind));
+    }
+    if entries.len() > max_entries {
+        out.push_str(&format!("... and {} more\n", entries.len() - max_entries));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_directories_first_and_caps_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("src")).unwrap();
+        for name in ["b.txt", "a.txt", "c.txt"] {
+            std::fs::write(dir.path().join(name), "x").unwrap();
+        }
+        let listing = dir_listing(dir.path(), 3).unwrap();
+        let lines: Vec<&str> = listing.lines().skip(1).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "- src (dir)",
+                "- a.txt (file)",
+                "- b.txt (file)",
+                "... and 1 more"
+            ]
+        );
+
+        let empty = tempfile::tempdir().unwrap();
+        assert!(dir_listing(empty.path(), 3).unwrap().ends_with("(empty)\n"));
+    }
+}
@@ -0,0 +1,122 @@
+use crate::debug_log;
+use anyhow::{Context, Result};
+use fs_err as fs;
+use std::path::{Path, PathBuf};
+
+const DEFAULT_ENV_FILE: &str = ".qq/.env";
+
+/// Parse `KEY=VALUE` lines. Blank lines and `#` comments are skipped, a leading
+/// `export ` is accepted, and matching single/double quotes around values are stripped.
+pub fn parse_env_file(contents: &str) -> Vec<(String, String)> {
+    let mut vars = Vec::new();
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let key = key.trim();
+        if key.is_empty() || key.contains(char::is_whitespace) {
+            continue;
+        }
+        vars.push((key.to_string(), unquote(value.trim()).to_string()));
+    }
+    vars
+}
+
+fn unquote(value: &str) -> &str {
+    for quote in ['"', '\''] {
+        if value.len() >= 2 && value.starts_with(quote) && value.ends_with(quote) {
+            return &value[1..value.len() - 1];
+        }
+    }
+    value
+}
+
+/// Export variables from `path` that are not already set. Returns how many were applied.
+pub fn load_env_file(path: &Path, debug: bool) -> Result<usize> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Reading env file: {}", path.display()))?;
+    let mut applied = 0;
+    for (key, value) in parse_env_file(&contents) {
+        if std::env::var_os(&key).is_some() {
+            debug_log!(debug, "env file: keeping existing {}", key);
+            continue;
+        }
+        // SAFETY: called during startup, before any threads read the environment.
+        unsafe {
+            std::env::set_var(&key, value);
+        }
+        applied += 1;
+    }
+    debug_log!(
+        debug,
+        "Loaded {} variable(s) from {}",
+        applied,
+        path.display()
+    );
+    Ok(applied)
+}
+
+/// Load `explicit` (which must exist) and then `~/.qq/.env` when present.
+/// Earlier files win because set variables are never overridden.
+pub fn load_env_files(explicit: Option<&Path>, debug: bool) -> Result<()> {
+    if let Some(path) = explicit {
+        load_env_file(path, debug)?;
+    }
+    if let Some(path) = default_env_file()
+        && path.is_file()
+    {
+        load_env_file(&path, debug)?;
+    }
+    Ok(())
+}
+
+fn default_env_file() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(DEFAULT_ENV_FILE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_env_file_handles_comments_quotes_and_export() {
+        let vars = parse_env_file(
+            "# keys\n\nOPENAI_API_KEY=sk-plain\nexport GROQ_API_KEY=\"gsk quoted\"\nX='single'\nnot a pair\n",
+        );
+        assert_eq!(
+            vars,
+            vec![
+                ("OPENAI_API_KEY".to_string(), "sk-plain".to_string()),
+                ("GROQ_API_KEY".to_string(), "gsk quoted".to_string()),
+                ("X".to_string(), "single".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn load_env_file_does_not_override_existing_vars() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".env");
+        std::fs::write(
+            &path,
+            "QQQA_ENV_FILE_TEST_SET=from-file\nQQQA_ENV_FILE_TEST_NEW=from-file\n",
+        )
+        .unwrap();
+        unsafe {
+            std::env::set_var("QQQA_ENV_FILE_TEST_SET", "from-env");
+            std::env::remove_var("QQQA_ENV_FILE_TEST_NEW");
+        }
+
+        assert_eq!(load_env_file(&path, false).unwrap(), 1);
+        assert_eq!(std::env::var("QQQA_ENV_FILE_TEST_SET").unwrap(), "from-env");
+        assert_eq!(
+            std::env::var("QQQA_ENV_FILE_TEST_NEW").unwrap(),
+            "from-file"
+        );
+    }
+}
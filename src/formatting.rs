@@ -1,3 +1,4 @@
+use anyhow::{Result, anyhow};
 use nu_ansi_term::{Color, Style};
 use std::io::Write as _;
 use std::sync::{
@@ -8,6 +9,36 @@ use std::thread;
 use std::time::Duration;
 
 static COLOR_OUTPUT_ENABLED: AtomicBool = AtomicBool::new(true);
+static LOADING_ELAPSED_ENABLED: AtomicBool = AtomicBool::new(false);
+static STDOUT_CLOSED: AtomicBool = AtomicBool::new(false);
+
+/// How qq renders answers (`--format`, config `default_format`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// qq's pseudo-XML tags rendered as ANSI colors.
+    #[default]
+    Xml,
+    /// Tags turned into Markdown: `**bold**`, `` `cmd` ``.
+    Markdown,
+    /// Tags stripped, no colors (what `--raw` selects).
+    Plain,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "xml" => Ok(Self::Xml),
+            "markdown" | "md" => Ok(Self::Markdown),
+            "plain" | "text" => Ok(Self::Plain),
+            _ => Err(anyhow!(
+                "Unsupported format '{}' (expected xml, markdown, or plain)",
+                s.trim()
+            )),
+        }
+    }
+}
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum MarkupTag {
@@ -29,6 +60,49 @@ fn color_output_enabled() -> bool {
     COLOR_OUTPUT_ENABLED.load(Ordering::Relaxed)
 }
 
+/// Decide on color from `CLICOLOR_FORCE`, `NO_COLOR`, `CLICOLOR`, and whether stdout is a TTY.
+pub fn color_output_from_env() -> bool {
+    color_from_env(atty::Stream::Stdout)
+}
+
+/// Same as [`color_output_from_env`], but for prompts and notices written to stderr.
+pub fn color_stderr_from_env() -> bool {
+    color_from_env(atty::Stream::Stderr)
+}
+
+fn color_from_env(stream: atty::Stream) -> bool {
+    if env_var_bool("CLICOLOR_FORCE") == Some(true) {
+        true
+    } else if std::env::var_os("NO_COLOR").is_some() || env_var_bool("CLICOLOR") == Some(false) {
+        false
+    } else {
+        atty::is(stream)
+    }
+}
+
+/// Show elapsed seconds next to the loading dots (`.. 4s`). Off by default.
+pub fn set_loading_elapsed_enabled(enabled: bool) {
+    LOADING_ELAPSED_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// `QQQA_SHOW_ELAPSED` as a boolean, or `None` when unset or unrecognized.
+pub fn loading_elapsed_from_env() -> Option<bool> {
+    env_var_bool("QQQA_SHOW_ELAPSED")
+}
+
+fn env_var_bool(name: &str) -> Option<bool> {
+    let raw = std::env::var(name).ok()?;
+    let normalized = raw.trim().to_ascii_lowercase();
+    if normalized.is_empty() {
+        return None;
+    }
+    match normalized.as_str() {
+        "1" | "true" | "yes" | "on" => Some(true),
+        "0" | "false" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
+
 fn style_for_stack(stack: &[MarkupTag]) -> Style {
     let mut st = Style::new();
     for t in stack {
@@ -63,7 +137,16 @@ fn tag_from_name(name: &str) -> Option<MarkupTag> {
     }
 }
 
-fn push_with_style(out: &mut String, text: &str, stack: &[MarkupTag]) {
+/// Markdown delimiter written around a tag's text in [`OutputFormat::Markdown`].
+fn markdown_marker(tag: MarkupTag) -> &'static str {
+    match tag {
+        MarkupTag::Bold | MarkupTag::Warn => "**",
+        MarkupTag::Cmd | MarkupTag::Code | MarkupTag::File => "`",
+        MarkupTag::Info => "",
+    }
+}
+
+fn push_with_style(out: &mut String, text: &str, stack: &[MarkupTag], format: OutputFormat) {
     if text.is_empty() {
         return;
     }
@@ -71,7 +154,7 @@ fn push_with_style(out: &mut String, text: &str, stack: &[MarkupTag]) {
     if text.is_empty() {
         return;
     }
-    if stack.is_empty() || !color_output_enabled() {
+    if stack.is_empty() || format != OutputFormat::Xml || !color_output_enabled() {
         out.push_str(&text);
     } else {
         out.push_str(&style_for_stack(stack).paint(text).to_string());
@@ -92,13 +175,15 @@ fn split_emit_tail(text: &str) -> (&str, usize) {
 struct XmlishStreamingParser {
     stack: Vec<MarkupTag>,
     pending: String,
+    format: OutputFormat,
 }
 
 impl XmlishStreamingParser {
-    fn new() -> Self {
+    fn new(format: OutputFormat) -> Self {
         Self {
             stack: Vec::new(),
             pending: String::new(),
+            format,
         }
     }
 
@@ -117,7 +202,7 @@ impl XmlishStreamingParser {
                 let start = processed + rel;
                 if start > processed {
                     let text = &self.pending[processed..start];
-                    push_with_style(out, text, &self.stack);
+                    push_with_style(out, text, &self.stack, self.format);
                 }
                 let after = &self.pending[start..];
                 if let Some(end_rel) = after.find('>') {
@@ -134,7 +219,7 @@ impl XmlishStreamingParser {
                 // No more tags, emit safe portion
                 let tail = &self.pending[processed..];
                 let (emit, tail_len) = split_emit_tail(tail);
-                push_with_style(out, emit, &self.stack);
+                push_with_style(out, emit, &self.stack, self.format);
                 processed = self.pending.len() - tail_len;
                 break;
             }
@@ -144,7 +229,7 @@ impl XmlishStreamingParser {
 
     fn finish(&mut self, out: &mut String) {
         if !self.pending.is_empty() {
-            push_with_style(out, &self.pending, &self.stack);
+            push_with_style(out, &self.pending, &self.stack, self.format);
             self.pending.clear();
         }
     }
@@ -160,17 +245,25 @@ impl XmlishStreamingParser {
             out.push('\n');
             return;
         }
-        if raw_trim.starts_with('/') {
-            let name = raw_trim[1..].trim().to_ascii_lowercase();
-            if let Some(tag) = tag_from_name(&name) {
-                if let Some(pos) = self.stack.iter().rposition(|t| *t == tag) {
-                    self.stack.remove(pos);
-                }
+        if let Some(closing) = raw_trim.strip_prefix('/') {
+            let name = closing.trim().to_ascii_lowercase();
+            if let Some(tag) = tag_from_name(&name)
+                && let Some(pos) = self.stack.iter().rposition(|t| *t == tag)
+            {
+                self.stack.remove(pos);
+                self.push_markdown_marker(tag, out);
             }
             return;
         }
         if let Some(tag) = tag_from_name(&lowered) {
             self.stack.push(tag);
+            self.push_markdown_marker(tag, out);
+        }
+    }
+
+    fn push_markdown_marker(&self, tag: MarkupTag, out: &mut String) {
+        if self.format == OutputFormat::Markdown {
+            out.push_str(markdown_marker(tag));
         }
     }
 }
@@ -178,14 +271,19 @@ impl XmlishStreamingParser {
 /// Render our pseudo-XML to ANSI-colored text.
 /// Supported tags: <bold>, <cmd>, <info>, <file>, <warn>, <code>, <br/>
 pub fn render_xmlish_to_ansi(input: &str) -> String {
-    let mut parser = XmlishStreamingParser::new();
+    render_answer(input, OutputFormat::Xml)
+}
+
+/// Render our pseudo-XML in `format`; [`OutputFormat::Xml`] is [`render_xmlish_to_ansi`].
+pub fn render_answer(input: &str, format: OutputFormat) -> String {
+    let mut parser = XmlishStreamingParser::new(format);
     let mut out = String::new();
     parser.process(input, &mut out);
     parser.finish(&mut out);
     out
 }
 
-/// Incremental formatter that mirrors `render_xmlish_to_ansi` but streams output.
+/// Incremental formatter that mirrors `render_answer` but streams output.
 pub struct StreamingFormatter {
     parser: XmlishStreamingParser,
     raw: String,
@@ -194,11 +292,7 @@ pub struct StreamingFormatter {
 
 impl Default for StreamingFormatter {
     fn default() -> Self {
-        Self {
-            parser: XmlishStreamingParser::new(),
-            raw: String::new(),
-            rendered: String::new(),
-        }
+        Self::with_format(OutputFormat::Xml)
     }
 }
 
@@ -207,6 +301,14 @@ impl StreamingFormatter {
         Self::default()
     }
 
+    pub fn with_format(format: OutputFormat) -> Self {
+        Self {
+            parser: XmlishStreamingParser::new(format),
+            raw: String::new(),
+            rendered: String::new(),
+        }
+    }
+
     /// Push a chunk of model output; returns the formatted delta (if any) to print immediately.
     pub fn push(&mut self, chunk: &str) -> Option<String> {
         if chunk.is_empty() {
@@ -240,7 +342,7 @@ impl StreamingFormatter {
 
     /// Retrieve the full formatted text seen so far.
     pub fn rendered(&self) -> String {
-        render_xmlish_to_ansi(&self.raw)
+        render_answer(&self.raw, self.parser.format)
     }
 
     fn ingest_suffix(&mut self, suffix: &str) -> Option<String> {
@@ -269,21 +371,39 @@ pub fn status_thinking() {
 
 /// Print streamed token to stdout. We avoid buffering to keep latency low.
 pub fn print_stream_token(token: &str) {
-    print!("{}", token);
-    let _ = std::io::Write::flush(&mut std::io::stdout());
+    write_stdout(token);
 }
 
-/// Print a full, non-streamed assistant message.
-pub fn print_assistant_text(text: &str, raw: bool) {
-    if raw {
-        println!("{}", text);
-    } else {
-        let rendered = render_xmlish_to_ansi(text);
-        let compacted = compact_blank_lines(&rendered);
-        println!("{}", compacted.trim_end());
+/// Write and flush `text` to stdout. A broken pipe (the reader of `qq ... | head -1`
+/// exited) is remembered via [`stdout_closed`] and later writes are dropped.
+pub fn write_stdout(text: &str) {
+    if stdout_closed() {
+        return;
+    }
+    let mut handle = std::io::stdout().lock();
+    let result = handle
+        .write_all(text.as_bytes())
+        .and_then(|()| handle.flush());
+    if let Err(err) = result
+        && err.kind() == std::io::ErrorKind::BrokenPipe
+    {
+        STDOUT_CLOSED.store(true, Ordering::Relaxed);
     }
 }
 
+/// Whether a [`write_stdout`] hit a broken pipe; streaming stops early once it has.
+pub fn stdout_closed() -> bool {
+    STDOUT_CLOSED.load(Ordering::Relaxed)
+}
+
+/// Print a full, non-streamed assistant message.
+pub fn print_assistant_text(text: &str, format: OutputFormat) {
+    // Compact before rendering, while the <cmd>/<code> tags still mark what to keep.
+    let compacted = compact_blank_lines(text);
+    let rendered = render_answer(&compacted, format);
+    println!("{}", rendered.trim_end());
+}
+
 /// Handle to a simple loading animation (cyclic dots) printed to stderr.
 pub struct LoadingAnimation {
     stop: Arc<AtomicBool>,
@@ -297,7 +417,7 @@ impl LoadingAnimation {
             let _ = h.join();
         }
         // Clear the line
-        eprint!("\r        \r");
+        eprint!("\r{}\r", " ".repeat(LOADING_CLEAR_WIDTH));
         let _ = std::io::stderr().flush();
     }
 }
@@ -308,24 +428,38 @@ impl Drop for LoadingAnimation {
         if let Some(h) = self.handle.take() {
             let _ = h.join();
         }
-        eprint!("\r        \r");
+        eprint!("\r{}\r", " ".repeat(LOADING_CLEAR_WIDTH));
         let _ = std::io::stderr().flush();
     }
 }
 
+/// Wide enough to blank a frame like `... 12345s`.
+const LOADING_CLEAR_WIDTH: usize = 16;
+
+/// One animation frame: 1–3 dots, plus elapsed seconds when enabled.
+fn loading_frame(n: u8, elapsed: Option<Duration>) -> String {
+    let dots = match n {
+        1 => ".",
+        2 => "..",
+        _ => "...",
+    };
+    match elapsed {
+        Some(elapsed) => format!("{:<3} {}s", dots, elapsed.as_secs()),
+        None => dots.to_string(),
+    }
+}
+
 /// Start the cyclic dot animation on stderr. Returns a handle that stops it when dropped.
 pub fn start_loading_animation() -> LoadingAnimation {
     let stop = Arc::new(AtomicBool::new(false));
     let stop_cl = stop.clone();
+    let show_elapsed = LOADING_ELAPSED_ENABLED.load(Ordering::Relaxed);
+    let started = std::time::Instant::now();
     let handle = thread::spawn(move || {
         let mut n: u8 = 1;
         while !stop_cl.load(Ordering::SeqCst) {
-            let dots = match n {
-                1 => ".",
-                2 => "..",
-                _ => "...",
-            };
-            eprint!("\r{}", dots);
+            let frame = loading_frame(n, show_elapsed.then(|| started.elapsed()));
+            eprint!("\r{}", frame);
             let _ = std::io::stderr().flush();
             n = if n >= 3 { 1 } else { n + 1 };
             thread::sleep(Duration::from_millis(300));
@@ -337,13 +471,21 @@ pub fn start_loading_animation() -> LoadingAnimation {
     }
 }
 
-/// Reduce runs of blank lines to a single blank line and normalize newlines.
+/// Reduce runs of blank lines to a single blank line and normalize newlines. Lines inside
+/// `<cmd>`/`<code>` blocks are kept as-is, so scripts keep their intentional spacing.
 pub fn compact_blank_lines(input: &str) -> String {
     let mut out = String::with_capacity(input.len());
     let mut last_blank = false;
+    let mut code_depth = 0usize;
     for line in input.replace('\r', "").split('\n') {
+        let in_code = code_depth > 0;
+        code_depth = code_depth_after(code_depth, line);
         let is_blank = line.trim().is_empty();
-        if is_blank {
+        if in_code && is_blank {
+            out.push_str(line);
+            out.push('\n');
+            last_blank = false;
+        } else if is_blank {
             if !last_blank {
                 out.push('\n');
                 last_blank = true;
@@ -360,13 +502,23 @@ pub fn compact_blank_lines(input: &str) -> String {
     out
 }
 
+/// Open `<cmd>`/`<code>` blocks after `line`, starting from `depth`.
+fn code_depth_after(depth: usize, line: &str) -> usize {
+    let lowered = line.to_ascii_lowercase();
+    let opens = lowered.matches("<cmd>").count() + lowered.matches("<code>").count();
+    let closes = lowered.matches("</cmd>").count() + lowered.matches("</code>").count();
+    (depth + opens).saturating_sub(closes)
+}
+
 #[cfg(test)]
 mod tests {
     use super::{
-        COLOR_OUTPUT_ENABLED, StreamingFormatter, render_xmlish_to_ansi, set_color_output_enabled,
+        COLOR_OUTPUT_ENABLED, OutputFormat, StreamingFormatter, compact_blank_lines, loading_frame,
+        render_answer, render_xmlish_to_ansi, set_color_output_enabled,
     };
     use std::sync::atomic::Ordering;
     use std::sync::{Mutex, OnceLock};
+    use std::time::Duration;
 
     fn stream_chunks(input: &str, chunk_size: usize) -> String {
         let mut fmt = StreamingFormatter::new();
@@ -441,6 +593,37 @@ mod tests {
         assert_eq!(rendered, "ls");
     }
 
+    #[test]
+    fn plain_and_markdown_formats_rewrite_tags() {
+        let input = "Run <cmd>ls -la</cmd> in <file>src</file>, <bold>carefully</bold> &amp; <info>done</info>";
+        let plain = with_color_setting(true, || render_answer(input, OutputFormat::Plain));
+        assert_eq!(plain, "Run ls -la in src, carefully & done");
+        let markdown = with_color_setting(true, || render_answer(input, OutputFormat::Markdown));
+        assert_eq!(markdown, "Run `ls -la` in `src`, **carefully** & done");
+
+        let mut fmt = StreamingFormatter::with_format(OutputFormat::Markdown);
+        for chunk in ["Run <c", "md>ls", " -la</c", "md>"] {
+            let _ = fmt.push(chunk);
+        }
+        let _ = fmt.flush();
+        assert_eq!(fmt.rendered(), "Run `ls -la`");
+        assert_eq!(
+            "md".parse::<OutputFormat>().unwrap(),
+            OutputFormat::Markdown
+        );
+        assert!("html".parse::<OutputFormat>().is_err());
+    }
+
+    #[test]
+    fn compact_blank_lines_keeps_spacing_inside_code_blocks() {
+        let input =
+            "Intro\n\n\n\nRun:\n<cmd>cat <<EOF > notes.txt\nfirst\n\n\nsecond\nEOF</cmd>\n\n\nDone";
+        assert_eq!(
+            compact_blank_lines(input),
+            "Intro\n\nRun:\n<cmd>cat <<EOF > notes.txt\nfirst\n\n\nsecond\nEOF</cmd>\n\nDone\n"
+        );
+    }
+
     fn with_color_setting<F, R>(enabled: bool, func: F) -> R
     where
         F: FnOnce() -> R,
@@ -454,4 +637,15 @@ mod tests {
         drop(guard);
         result
     }
+
+    #[test]
+    fn loading_frame_appends_elapsed_seconds_only_when_enabled() {
+        assert_eq!(loading_frame(1, None), ".");
+        assert_eq!(loading_frame(3, None), "...");
+        assert_eq!(
+            loading_frame(2, Some(Duration::from_millis(4_900))),
+            "..  4s"
+        );
+        assert_eq!(loading_frame(3, Some(Duration::from_secs(61))), "... 61s");
+    }
 }
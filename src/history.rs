@@ -1,9 +1,25 @@
+use crate::debug_log;
 use fs_err as fs;
 use std::cmp::min;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 /// Try to read the last `limit` commands from common shell history files.
 /// This function is best-effort and will gracefully return an empty list if nothing is found.
 pub fn read_recent_history(limit: usize, debug: bool) -> Vec<String> {
+    read_recent_history_since(limit, None, debug)
+}
+
+/// Like [`read_recent_history`], but drops timestamped entries (zsh extended history,
+/// fish `when:`) older than `since`. Entries without a parseable timestamp are kept.
+pub fn read_recent_history_since(
+    limit: usize,
+    since: Option<Duration>,
+    debug: bool,
+) -> Vec<String> {
+    let cutoff = since.and_then(|window| {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?;
+        Some(now.saturating_sub(window).as_secs())
+    });
     let home = match dirs::home_dir() {
         Some(p) => p,
         None => return Vec::new(),
@@ -20,44 +36,42 @@ pub fn read_recent_history(limit: usize, debug: bool) -> Vec<String> {
         if !path.exists() {
             continue;
         }
-        if debug {
-            eprintln!("[debug] Reading history from {}", path.display());
-        }
+        debug_log!(debug, "Reading history from {}", path.display());
         let content = match fs::read_to_string(path) {
             Ok(c) => c,
             Err(_) => continue,
         };
-        let mut lines: Vec<String> = Vec::new();
+        let mut lines: Vec<(Option<u64>, String)> = Vec::new();
         if path.ends_with("fish_history") {
-            // fish format: lines like "- cmd: echo hi"
-            for line in content.lines() {
-                if let Some(rest) = line.strip_prefix("- cmd: ") {
-                    lines.push(rest.trim().to_string());
-                }
-            }
+            lines = parse_fish_history(&content);
         } else if path.ends_with(".zsh_history") {
             for line in content.lines() {
                 // zsh extended history format: ": 1696971992:0;command here"
                 if let Some(semi_idx) = line.rfind(';') {
                     let cmd = &line[semi_idx + 1..];
                     if !cmd.trim().is_empty() {
-                        lines.push(cmd.trim().to_string());
+                        lines.push((zsh_timestamp(line), cmd.trim().to_string()));
                     }
                 } else if !line.trim().is_empty() {
-                    lines.push(line.trim().to_string());
+                    lines.push((None, line.trim().to_string()));
                 }
             }
         } else {
             // bash or plain text
             for line in content.lines() {
                 if !line.trim().is_empty() {
-                    lines.push(line.trim().to_string());
+                    lines.push((None, line.trim().to_string()));
                 }
             }
         }
 
         let filtered: Vec<String> = lines
             .into_iter()
+            .filter(|(ts, _)| match (ts, cutoff) {
+                (Some(ts), Some(cutoff)) => *ts >= cutoff,
+                _ => true,
+            })
+            .map(|(_, line)| line)
             .filter(|line| is_supported_history_command(line))
             .collect();
 
@@ -72,11 +86,64 @@ pub fn read_recent_history(limit: usize, debug: bool) -> Vec<String> {
     Vec::new()
 }
 
+/// Turn a `--history-since <minutes>` value into a window for [`read_recent_history_since`].
+pub fn history_window(minutes: Option<u64>) -> Option<Duration> {
+    minutes.map(|m| Duration::from_secs(m.saturating_mul(60)))
+}
+
+/// Parse fish's YAML-like history: each entry starts with `- cmd: <escaped command>` and
+/// may be followed by indented `when: <epoch>` and `paths:` lines, which belong to it.
+fn parse_fish_history(content: &str) -> Vec<(Option<u64>, String)> {
+    let mut entries: Vec<(Option<u64>, String)> = Vec::new();
+    let mut in_entry = false;
+    for line in content.lines() {
+        if let Some(rest) = line.strip_prefix("- cmd:") {
+            let cmd = unescape_fish(rest.trim());
+            in_entry = !cmd.trim().is_empty();
+            if in_entry {
+                entries.push((None, cmd.trim().to_string()));
+            }
+        } else if in_entry
+            && line.starts_with(' ')
+            && let Some(when) = line.trim_start().strip_prefix("when:")
+            && let Some(last) = entries.last_mut()
+        {
+            last.0 = when.trim().parse().ok();
+        }
+    }
+    entries
+}
+
+/// Undo fish's history escaping: `\n` is a newline in the command and `\\` a backslash.
+fn unescape_fish(raw: &str) -> String {
+    let mut out = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('\\') => out.push('\\'),
+            Some(other) => {
+                out.push('\\');
+                out.push(other);
+            }
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// Epoch seconds from a zsh extended history line (`: 1696971992:0;cmd`).
+fn zsh_timestamp(line: &str) -> Option<u64> {
+    let rest = line.strip_prefix(": ")?;
+    let (secs, _) = rest.split_once(':')?;
+    secs.trim().parse().ok()
+}
+
 fn is_supported_history_command(line: &str) -> bool {
-    let first = line
-        .trim_start()
-        .split_whitespace()
-        .next()
-        .unwrap_or_default();
+    let first = line.split_whitespace().next().unwrap_or_default();
     matches!(first, "qq" | "qa")
 }
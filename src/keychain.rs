@@ -0,0 +1,44 @@
+//! Read provider API keys from the OS keychain (`keychain` cargo feature).
+//!
+//! Lookups go through the `keyring` crate: the login keychain on macOS, the Secret Service
+//! (GNOME Keyring, KWallet) on Linux, and the Credential Manager on Windows. An entry is
+//! found by its service name (`api_key_keychain`) and the account [`KEYCHAIN_ACCOUNT`].
+
+use anyhow::{Result, anyhow};
+
+/// Account name qqqa looks up under each `api_key_keychain` service.
+pub const KEYCHAIN_ACCOUNT: &str = "qqqa";
+
+/// Fetch the secret stored under `entry` (the service name).
+#[cfg(feature = "keychain")]
+pub fn read_secret(entry: &str) -> Result<String> {
+    let entry = entry.trim();
+    if entry.is_empty() {
+        return Err(anyhow!("api_key_keychain cannot be empty"));
+    }
+    let secret = keyring::Entry::new(entry, KEYCHAIN_ACCOUNT)
+        .and_then(|item| item.get_password())
+        .map_err(|err| match err {
+            keyring::Error::NoEntry => anyhow!(
+                "Keychain entry '{}' (account '{}') not found",
+                entry,
+                KEYCHAIN_ACCOUNT
+            ),
+            other => anyhow!("Reading keychain entry '{}': {}", entry, other),
+        })?;
+    let secret = secret.trim_end_matches(['\r', '\n']).to_string();
+    if secret.is_empty() {
+        return Err(anyhow!("Keychain entry '{}' is empty", entry));
+    }
+    Ok(secret)
+}
+
+/// Without the `keychain` feature, configured keychain entries are an error rather than
+/// silently falling back to other key sources.
+#[cfg(not(feature = "keychain"))]
+pub fn read_secret(entry: &str) -> Result<String> {
+    Err(anyhow!(
+        "api_key_keychain '{}' is set, but this build lacks the `keychain` feature (rebuild with `cargo install qqqa --features keychain`)",
+        entry
+    ))
+}
@@ -0,0 +1,293 @@
+//! The previous qq/qa invocation, kept in `~/.qq/last_session.json` for `--replay`.
+//!
+//! Only the flags and the final question/task are stored. Piped stdin that was sent as
+//! extra context is not written to disk, so a replay runs without it. Flags that can carry
+//! credentials ([`SECRET_FLAGS`]) are dropped with their values; a replay uses the
+//! configured key and headers unless they are passed again.
+
+use crate::config::set_permissions_file;
+use anyhow::{Context, Result, anyhow};
+use fs_err as fs;
+use serde::{Deserialize, Serialize};
+use std::ffi::OsString;
+use std::path::Path;
+
+/// File name inside the config directory.
+pub const LAST_SESSION_FILE: &str = "last_session.json";
+
+/// Flag that replays the stored run; never stored itself.
+pub const REPLAY_FLAG: &str = "--replay";
+
+/// Flags whose values may be secrets (`--api-key`, `--header "Authorization: ..."`).
+pub const SECRET_FLAGS: [&str; 3] = ["--api-key", "--header", "-H"];
+
+/// One stored invocation.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoredRun {
+    /// Flags as passed (after alias normalization), without the question/task words.
+    pub args: Vec<String>,
+    /// The question (qq) or task (qa) after merging piped input.
+    pub input: String,
+    /// Piped stdin was sent alongside `input`; it is not stored or replayed.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub had_stdin_context: bool,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct LastSession {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    qq: Option<StoredRun>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    qa: Option<StoredRun>,
+}
+
+impl StoredRun {
+    /// Capture a run from its argv (program name first) whose last `positional` words were
+    /// the question/task.
+    pub fn from_invocation(
+        argv: &[OsString],
+        positional: usize,
+        input: &str,
+        had_stdin_context: bool,
+    ) -> Self {
+        let flags = argv.get(1..).unwrap_or_default();
+        let flags = &flags[..flags.len().saturating_sub(positional)];
+        let mut args = without_secret_flags(
+            flags
+                .iter()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .filter(|arg| arg != REPLAY_FLAG),
+        );
+        if args.last().is_some_and(|arg| arg == "--") {
+            args.pop();
+        }
+        Self {
+            args,
+            input: input.to_string(),
+            had_stdin_context,
+        }
+    }
+
+    /// Argv for re-running: stored flags, then `extra` (later flags win), then the input.
+    pub fn replay_args(&self, program: OsString, extra: &[OsString]) -> Vec<OsString> {
+        let mut argv = vec![program];
+        argv.extend(self.args.iter().map(OsString::from));
+        argv.extend(
+            extra
+                .iter()
+                .filter(|arg| arg.as_os_str() != REPLAY_FLAG)
+                .cloned(),
+        );
+        argv.push("--".into());
+        argv.push(self.input.clone().into());
+        argv
+    }
+
+    /// Shell-like rendering for the "Replaying: ..." line.
+    pub fn describe(&self, tool: &str) -> String {
+        let mut parts = vec![tool.to_string()];
+        let args = without_secret_flags(self.args.iter().cloned());
+        parts.extend(args.iter().map(|arg| shell_quote(arg)));
+        parts.push(shell_quote(&self.input));
+        parts.join(" ")
+    }
+}
+
+/// Drop [`SECRET_FLAGS`] together with their value, whether passed as `--flag value`,
+/// `--flag=value`, or attached to the short flag (`-HName: value`).
+fn without_secret_flags(args: impl Iterator<Item = String>) -> Vec<String> {
+    let mut kept = Vec::new();
+    let mut skip_value = false;
+    for arg in args {
+        if std::mem::take(&mut skip_value) {
+            continue;
+        }
+        let name = arg.split_once('=').map_or(arg.as_str(), |(name, _)| name);
+        if SECRET_FLAGS.contains(&name) {
+            skip_value = name == arg;
+            continue;
+        }
+        if arg.starts_with("-H") {
+            continue;
+        }
+        kept.push(arg);
+    }
+    kept
+}
+
+fn shell_quote(word: &str) -> String {
+    if !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c))
+    {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', r"'\''"))
+    }
+}
+
+fn read_session(dir: &Path) -> Result<LastSession> {
+    let path = dir.join(LAST_SESSION_FILE);
+    if !path.exists() {
+        return Ok(LastSession::default());
+    }
+    let bytes = fs::read(&path)?;
+    serde_json::from_slice(&bytes).with_context(|| format!("Parsing {}", path.display()))
+}
+
+/// Store `run` as the last `tool` ("qq" or "qa") invocation, keeping the other tool's entry.
+pub fn save_last_run(dir: &Path, tool: &str, run: StoredRun) -> Result<()> {
+    // An unreadable file is replaced rather than blocking the current run.
+    let mut session = read_session(dir).unwrap_or_default();
+    match tool {
+        "qa" => session.qa = Some(run),
+        _ => session.qq = Some(run),
+    }
+    let path = dir.join(LAST_SESSION_FILE);
+    let json = serde_json::to_vec_pretty(&session).with_context(|| "Serializing last session")?;
+    fs::write(&path, json)?;
+    set_permissions_file(&path, false).ok();
+    Ok(())
+}
+
+/// The last stored `tool` invocation, or an error when there is none.
+pub fn load_last_run(dir: &Path, tool: &str) -> Result<StoredRun> {
+    let session = read_session(dir)?;
+    let run = match tool {
+        "qa" => session.qa,
+        _ => session.qq,
+    };
+    run.ok_or_else(|| {
+        anyhow!(
+            "No previous {} run to replay (looked in {})",
+            tool,
+            dir.join(LAST_SESSION_FILE).display()
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn argv(words: &[&str]) -> Vec<OsString> {
+        words.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn invocation_round_trips_through_replay_args() {
+        let run = StoredRun::from_invocation(
+            &argv(&[
+                "qq",
+                "-m",
+                "gpt-5",
+                "--no-stream",
+                "--",
+                "how",
+                "are",
+                "you",
+            ]),
+            3,
+            "how are you",
+            false,
+        );
+        assert_eq!(run.args, vec!["-m", "gpt-5", "--no-stream"]);
+        assert_eq!(
+            run.replay_args("qq".into(), &argv(&["--replay", "--raw"])),
+            argv(&[
+                "qq",
+                "-m",
+                "gpt-5",
+                "--no-stream",
+                "--raw",
+                "--",
+                "how are you"
+            ])
+        );
+        assert_eq!(run.describe("qq"), "qq -m gpt-5 --no-stream 'how are you'");
+    }
+
+    #[test]
+    fn secret_flags_never_reach_the_session_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let run = StoredRun::from_invocation(
+            &argv(&[
+                "qq",
+                "--api-key",
+                "sk-SECRETSECRET123",
+                "--no-stream",
+                "--header",
+                "Authorization: Bearer tok-SECRET",
+                "--api-key=sk-INLINE",
+                "--header=X-Api-Key: hdr-SECRET",
+                "-m",
+                "gpt-5",
+                "what is ls",
+            ]),
+            1,
+            "what is ls",
+            false,
+        );
+        assert_eq!(run.args, vec!["--no-stream", "-m", "gpt-5"]);
+        save_last_run(dir.path(), "qq", run).unwrap();
+        let saved = std::fs::read_to_string(dir.path().join(LAST_SESSION_FILE)).unwrap();
+        assert!(
+            !saved.contains("SECRET") && !saved.contains("INLINE"),
+            "{saved}"
+        );
+
+        // Files written before secrets were filtered still describe safely.
+        let old = StoredRun {
+            args: vec!["--api-key".into(), "sk-SECRET".into(), "--raw".into()],
+            input: "hi".into(),
+            had_stdin_context: false,
+        };
+        assert_eq!(old.describe("qq"), "qq --raw hi");
+    }
+
+    #[test]
+    fn attached_short_header_values_are_dropped() {
+        let run = StoredRun::from_invocation(
+            &argv(&[
+                "qa",
+                "-HAuthorization: Bearer short-SECRET",
+                "-H=X-Token: eq-SECRET",
+                "-H",
+                "X-Token: spaced-SECRET",
+                "--yes",
+                "list files",
+            ]),
+            1,
+            "list files",
+            false,
+        );
+        assert_eq!(run.args, vec!["--yes"]);
+        let attached = StoredRun {
+            args: vec!["-HAuthorization: Bearer old-SECRET".into(), "--raw".into()],
+            input: "hi".into(),
+            had_stdin_context: false,
+        };
+        assert_eq!(attached.describe("qa"), "qa --raw hi");
+    }
+
+    #[test]
+    fn sessions_keep_qq_and_qa_entries_apart() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(load_last_run(dir.path(), "qq").is_err());
+        let qq = StoredRun {
+            input: "what's up".into(),
+            ..Default::default()
+        };
+        save_last_run(dir.path(), "qq", qq.clone()).unwrap();
+        let qa = StoredRun {
+            args: vec!["-y".into()],
+            input: "run tests".into(),
+            had_stdin_context: true,
+        };
+        save_last_run(dir.path(), "qa", qa.clone()).unwrap();
+        assert_eq!(load_last_run(dir.path(), "qq").unwrap(), qq);
+        assert_eq!(load_last_run(dir.path(), "qa").unwrap(), qa);
+        assert_eq!(qq.describe("qq"), r"qq 'what'\''s up'");
+    }
+}
@@ -1,9 +1,20 @@
 pub mod ai;
 pub mod clipboard;
 pub mod config;
+pub mod cwd_listing;
+pub mod env_file;
 pub mod formatting;
 pub mod history;
+pub mod keychain;
+pub mod last_session;
+pub mod logging;
 pub mod perms;
 pub mod prompt;
+pub mod redact;
+pub mod run;
 pub mod shell;
+pub mod tokens;
 pub mod tools;
+pub mod version;
+
+pub use run::{QaOptions, QaOutcome, QqOptions, run_qa, run_qq};
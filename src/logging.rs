@@ -0,0 +1,144 @@
+//! Debug output for `--debug`.
+//!
+//! By default messages are printed to stderr as `[debug] ...` when the caller's `debug`
+//! flag is set. With the `tracing` cargo feature they become `tracing` debug events
+//! (target `qqqa`) instead, HTTP calls, tool runs, and profile resolution get spans, and
+//! embedders decide what to keep with their own subscriber. The binaries install a small
+//! stderr subscriber under `--debug`, so the CLI output looks the same either way.
+
+use std::fmt;
+use std::future::Future;
+
+/// Report a debug message (see the module docs for where it goes).
+pub fn debug(enabled: bool, message: fmt::Arguments<'_>) {
+    #[cfg(feature = "tracing")]
+    {
+        let _ = enabled;
+        tracing::debug!(target: "qqqa", "{}", message);
+    }
+    #[cfg(not(feature = "tracing"))]
+    if enabled {
+        eprintln!("[debug] {}", message);
+    }
+}
+
+/// `format!`-style shorthand for [`debug`]: `debug_log!(cli.debug, "POST {}", url)`.
+#[macro_export]
+macro_rules! debug_log {
+    ($enabled:expr, $($arg:tt)+) => {
+        $crate::logging::debug($enabled, format_args!($($arg)+))
+    };
+}
+
+/// Run `fut` inside a `qqqa` debug span tagged with `op` and `detail`; a plain `.await`
+/// without the `tracing` feature.
+pub async fn traced<F: Future>(op: &'static str, detail: &str, fut: F) -> F::Output {
+    #[cfg(feature = "tracing")]
+    {
+        use tracing::Instrument;
+        fut.instrument(tracing::debug_span!(target: "qqqa", "qqqa", op, detail))
+            .await
+    }
+    #[cfg(not(feature = "tracing"))]
+    {
+        let _ = (op, detail);
+        fut.await
+    }
+}
+
+/// Synchronous counterpart of [`traced`].
+pub fn traced_sync<T>(op: &'static str, detail: &str, f: impl FnOnce() -> T) -> T {
+    #[cfg(feature = "tracing")]
+    {
+        let _span = tracing::debug_span!(target: "qqqa", "qqqa", op, detail).entered();
+        f()
+    }
+    #[cfg(not(feature = "tracing"))]
+    {
+        let _ = (op, detail);
+        f()
+    }
+}
+
+/// Called by the binaries after parsing flags: with the `tracing` feature and `--debug`,
+/// install a global subscriber that prints qqqa's events to stderr as `[debug] ...`.
+/// Does nothing otherwise, or when the process already has a global subscriber.
+pub fn init_cli(debug: bool) {
+    #[cfg(feature = "tracing")]
+    if debug {
+        let _ = tracing::subscriber::set_global_default(stderr_subscriber::StderrSubscriber::new());
+    }
+    #[cfg(not(feature = "tracing"))]
+    let _ = debug;
+}
+
+#[cfg(feature = "tracing")]
+mod stderr_subscriber {
+    use std::fmt::Write as _;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Level, Metadata, Subscriber};
+
+    /// Prints `qqqa` events at debug level and above; spans are accepted but not shown.
+    pub(super) struct StderrSubscriber {
+        next_id: AtomicU64,
+    }
+
+    impl StderrSubscriber {
+        pub(super) fn new() -> Self {
+            Self {
+                next_id: AtomicU64::new(1),
+            }
+        }
+    }
+
+    /// The `message` field followed by any other fields as `name=value`.
+    #[derive(Default)]
+    struct Line {
+        message: String,
+        fields: String,
+    }
+
+    impl Visit for Line {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            if field.name() == "message" {
+                let _ = write!(self.message, "{:?}", value);
+            } else {
+                let _ = write!(self.fields, " {}={:?}", field.name(), value);
+            }
+        }
+
+        fn record_str(&mut self, field: &Field, value: &str) {
+            if field.name() == "message" {
+                self.message.push_str(value);
+            } else {
+                let _ = write!(self.fields, " {}={}", field.name(), value);
+            }
+        }
+    }
+
+    impl Subscriber for StderrSubscriber {
+        fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+            metadata.target().starts_with("qqqa") && *metadata.level() <= Level::DEBUG
+        }
+
+        fn new_span(&self, _span: &Attributes<'_>) -> Id {
+            Id::from_u64(self.next_id.fetch_add(1, Ordering::Relaxed))
+        }
+
+        fn record(&self, _span: &Id, _values: &Record<'_>) {}
+
+        fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            let mut line = Line::default();
+            event.record(&mut line);
+            eprintln!("[debug] {}{}", line.message, line.fields);
+        }
+
+        fn enter(&self, _span: &Id) {}
+
+        fn exit(&self, _span: &Id) {}
+    }
+}
@@ -2,16 +2,26 @@ use anyhow::{Result, anyhow};
 use fs_err as fs;
 use std::collections::HashSet;
 use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Mutex, OnceLock};
 
 /// Cap read_file size to 1 MiB
-pub const READ_FILE_MAX_BYTES: usize = 1 * 1024 * 1024;
+pub const READ_FILE_MAX_BYTES: usize = 1024 * 1024;
+
+/// Default cap on write_file content, mirroring the read limit.
+pub const WRITE_FILE_MAX_BYTES: usize = 1024 * 1024;
+
+static WRITE_FILE_LIMIT: AtomicUsize = AtomicUsize::new(WRITE_FILE_MAX_BYTES);
 
 const SAFE_COMMANDS: &[&str] = &[
     "awk", "cat", "cut", "df", "du", "env", "echo", "find", "grep", "head", "ls", "pwd", "rg",
     "sed", "sort", "stat", "tail", "tree", "uniq", "wc",
 ];
 
+/// Read-only filters that may follow an allowlisted command in a pipeline without
+/// triggering confirmation, e.g. `ls | grep foo | wc -l`.
+const PIPELINE_FILTERS: &[&str] = &["grep", "sort", "head", "tail", "wc", "uniq"];
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum CommandDisposition {
     Allowed,
@@ -52,7 +62,92 @@ pub fn set_custom_allowlist(commands: Vec<String>) {
     }
 }
 
-/// Ensure a path is within HOME or current working directory.
+/// Add commands to the custom allowlist without dropping existing entries.
+pub fn extend_custom_allowlist(commands: impl IntoIterator<Item = String>) {
+    custom_allowlist().lock().unwrap().extend(commands);
+}
+
+fn safe_pipeline_shapes() -> &'static Mutex<Vec<Vec<String>>> {
+    static SHAPES: OnceLock<Mutex<Vec<Vec<String>>>> = OnceLock::new();
+    SHAPES.get_or_init(|| Mutex::new(Vec::new()))
+}
+
+/// Replace the configured pipeline shapes that skip confirmation. Each shape lists program
+/// names separated by `|`, e.g. `"git | head"`; blank stages make a shape invalid and it is ignored.
+pub fn set_safe_pipeline_shapes(shapes: Vec<String>) {
+    let parsed = shapes
+        .iter()
+        .filter_map(|shape| {
+            let stages: Vec<String> = shape.split('|').map(|s| s.trim().to_string()).collect();
+            (stages.len() > 1 && stages.iter().all(|s| !s.is_empty())).then_some(stages)
+        })
+        .collect();
+    *safe_pipeline_shapes().lock().unwrap() = parsed;
+}
+
+/// Set the write_file size cap from `write_file_max_bytes`; `None` restores the default.
+pub fn set_write_file_max_bytes(limit: Option<usize>) {
+    WRITE_FILE_LIMIT.store(limit.unwrap_or(WRITE_FILE_MAX_BYTES), Ordering::Relaxed);
+}
+
+/// Current write_file size cap in bytes.
+pub fn write_file_max_bytes() -> usize {
+    WRITE_FILE_LIMIT.load(Ordering::Relaxed)
+}
+
+fn extra_roots() -> &'static Mutex<Vec<PathBuf>> {
+    static ROOTS: OnceLock<Mutex<Vec<PathBuf>>> = OnceLock::new();
+    ROOTS.get_or_init(|| Mutex::new(Vec::new()))
+}
+
+/// Directories (besides HOME and cwd) that qa tools may touch, from `allowed_exec_roots`.
+pub fn set_allowed_exec_roots(roots: Vec<PathBuf>) {
+    let mut extra = extra_roots().lock().unwrap();
+    extra.clear();
+    let mut seen: HashSet<PathBuf> = HashSet::new();
+    for root in roots {
+        if !root.is_absolute() {
+            continue;
+        }
+        let normalized = normalize_path(&root);
+        if let Ok(canon) = fs::canonicalize(&normalized) {
+            push_unique(&mut extra, &mut seen, canon);
+        }
+        push_unique(&mut extra, &mut seen, normalized);
+    }
+}
+
+fn dangerous_patterns() -> &'static Mutex<Vec<String>> {
+    static PATTERNS: OnceLock<Mutex<Vec<String>>> = OnceLock::new();
+    PATTERNS.get_or_init(|| Mutex::new(Vec::new()))
+}
+
+/// Replace the user-configured `dangerous_patterns`; a command containing any of them
+/// (case-insensitive substring) is blocked on top of the built-in checks. Blank entries are ignored.
+pub fn set_dangerous_patterns(patterns: Vec<String>) {
+    *dangerous_patterns().lock().unwrap() = patterns
+        .into_iter()
+        .map(|p| p.trim().to_string())
+        .filter(|p| !p.is_empty())
+        .collect();
+}
+
+fn matched_dangerous_pattern(cmd: &str) -> Option<String> {
+    let lower = cmd.to_lowercase();
+    dangerous_patterns()
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|pattern| lower.contains(&pattern.to_lowercase()))
+        .cloned()
+}
+
+/// Whether `path` lies under one of the configured `allowed_exec_roots`.
+pub fn is_within_exec_roots(path: &Path) -> bool {
+    is_within_allowed(path, &extra_roots().lock().unwrap())
+}
+
+/// Ensure a path is within HOME, the current working directory, or an `allowed_exec_roots` entry.
 pub fn ensure_safe_path(p: &Path) -> Result<()> {
     let resolved = resolve_path(p)?;
     let allowed_roots = gather_allowed_roots()?;
@@ -79,6 +174,13 @@ pub fn ensure_safe_path(p: &Path) -> Result<()> {
 
 /// Very conservative safety checks for shell commands.
 pub fn ensure_safe_command(cmd: &str) -> Result<CommandDisposition> {
+    // User-configured patterns hold even under QQQA_ALLOW_UNSAFE_COMMANDS.
+    if let Some(pattern) = matched_dangerous_pattern(cmd) {
+        return Err(anyhow!(
+            "Blocked command matching dangerous pattern '{}' (from dangerous_patterns)",
+            pattern
+        ));
+    }
     if allow_unsafe_commands() {
         return Ok(CommandDisposition::Allowed);
     }
@@ -135,7 +237,7 @@ pub fn ensure_safe_command(cmd: &str) -> Result<CommandDisposition> {
     if lower.contains("mkfs") || lower.contains("dd if=") {
         return Err(anyhow!("Blocked potentially destructive disk operation"));
     }
-    if needs_confirmation {
+    if needs_confirmation && !is_safe_pipeline(trimmed) {
         Ok(CommandDisposition::NeedsConfirmation {
             reason: "Command uses shell control operators (pipelines, redirection, chaining).",
         })
@@ -146,7 +248,7 @@ pub fn ensure_safe_command(cmd: &str) -> Result<CommandDisposition> {
 
 fn allow_unsafe_commands() -> bool {
     std::env::var("QQQA_ALLOW_UNSAFE_COMMANDS")
-        .map(|v| !v.trim().is_empty() && v != "0" && v.to_ascii_lowercase() != "false")
+        .map(|v| !v.trim().is_empty() && v != "0" && !v.eq_ignore_ascii_case("false"))
         .unwrap_or(false)
 }
 
@@ -163,30 +265,20 @@ fn contains_control_operators(cmd: &str) -> bool {
         }
 
         match c {
-            '\\' => {
-                if !in_single {
-                    escape_next = true;
-                }
+            '\\' if !in_single => {
+                escape_next = true;
             }
-            '\'' => {
-                if !in_double {
-                    in_single = !in_single;
-                }
+            '\'' if !in_double => {
+                in_single = !in_single;
             }
-            '"' => {
-                if !in_single {
-                    in_double = !in_double;
-                }
+            '"' if !in_single => {
+                in_double = !in_double;
             }
-            '\n' => {
-                if !in_single && !in_double {
-                    return true;
-                }
+            '\n' if !in_single && !in_double => {
+                return true;
             }
-            '`' => {
-                if !in_single && !in_double {
-                    return true;
-                }
+            '`' if !in_single && !in_double => {
+                return true;
             }
             '$' if !in_single => {
                 if let Some('(') = chars.peek().copied() {
@@ -212,6 +304,94 @@ fn contains_control_operators(cmd: &str) -> bool {
     false
 }
 
+/// Plain `a | b | c` pipelines whose stages are all allowlisted read-only programs, or that
+/// match a configured shape, can skip confirmation. Anything else (redirection, chaining,
+/// subshells, command substitution, background jobs) still asks.
+fn is_safe_pipeline(cmd: &str) -> bool {
+    let Some(stages) = pipeline_stages(cmd) else {
+        return false;
+    };
+    if stages.len() < 2 {
+        return false;
+    }
+    let programs: Vec<&str> = stages.iter().map(|tokens| tokens[0].as_str()).collect();
+    if safe_pipeline_shapes().lock().unwrap().iter().any(|shape| {
+        shape
+            .iter()
+            .map(String::as_str)
+            .eq(programs.iter().copied())
+    }) {
+        return true;
+    }
+    SAFE_COMMANDS.contains(&programs[0])
+        && stages[1..].iter().all(|tokens| {
+            PIPELINE_FILTERS.contains(&tokens[0].as_str()) && !filter_writes_files(tokens)
+        })
+}
+
+/// `sort -o FILE` and `uniq IN OUT` write files despite being filters.
+fn filter_writes_files(tokens: &[String]) -> bool {
+    match tokens[0].as_str() {
+        "sort" => tokens[1..].iter().any(|t| {
+            t == "--output"
+                || t.starts_with("--output=")
+                || (t.starts_with('-') && !t.starts_with("--") && t.contains('o'))
+        }),
+        "uniq" => {
+            tokens[1..]
+                .iter()
+                .filter(|t| !t.starts_with('-') || t.as_str() == "-")
+                .count()
+                > 1
+        }
+        _ => false,
+    }
+}
+
+/// Split a command into pipeline stages, returning `None` when it uses any shell operator
+/// other than a single unquoted `|`.
+fn pipeline_stages(cmd: &str) -> Option<Vec<Vec<String>>> {
+    let mut stages = Vec::new();
+    let mut start = 0usize;
+    let mut chars = cmd.char_indices().peekable();
+    let mut in_single = false;
+    let mut in_double = false;
+    let mut escape_next = false;
+
+    while let Some((idx, ch)) = chars.next() {
+        if escape_next {
+            escape_next = false;
+            continue;
+        }
+        match ch {
+            '\\' if !in_single => escape_next = true,
+            '\'' if !in_double => in_single = !in_single,
+            '"' if !in_single => in_double = !in_double,
+            '`' if !in_single => return None,
+            '$' if !in_single && chars.peek().is_some_and(|&(_, next)| next == '(') => return None,
+            '\n' | '&' | ';' | '>' | '<' | '(' | ')' if !in_single && !in_double => return None,
+            '|' if !in_single && !in_double => {
+                if chars.peek().is_some_and(|&(_, next)| next == '|') {
+                    return None;
+                }
+                stages.push(&cmd[start..idx]);
+                start = idx + 1;
+            }
+            _ => {}
+        }
+    }
+    stages.push(&cmd[start..]);
+
+    stages
+        .into_iter()
+        .map(|stage| {
+            shell_words::split(stage.trim())
+                .ok()
+                .filter(|tokens| !tokens.is_empty())
+        })
+        .collect()
+}
+
 fn split_command_segments(cmd: &str) -> Vec<String> {
     let mut segments = Vec::new();
     let mut start = 0usize;
@@ -227,20 +407,14 @@ fn split_command_segments(cmd: &str) -> Vec<String> {
         }
 
         match ch {
-            '\\' => {
-                if !in_single {
-                    escape_next = true;
-                }
+            '\\' if !in_single => {
+                escape_next = true;
             }
-            '\'' => {
-                if !in_double {
-                    in_single = !in_single;
-                }
+            '\'' if !in_double => {
+                in_single = !in_single;
             }
-            '"' => {
-                if !in_single {
-                    in_double = !in_double;
-                }
+            '"' if !in_single => {
+                in_double = !in_double;
             }
             '\n' if !in_single && !in_double => {
                 if idx >= start {
@@ -251,11 +425,11 @@ fn split_command_segments(cmd: &str) -> Vec<String> {
             '|' | '&' | ';' if !in_single && !in_double => {
                 let op_char = ch;
                 let mut op_end = idx + ch.len_utf8();
-                if let Some(&(_, next_ch)) = chars.peek() {
-                    if next_ch == op_char {
-                        chars.next();
-                        op_end += next_ch.len_utf8();
-                    }
+                if let Some(&(_, next_ch)) = chars.peek()
+                    && next_ch == op_char
+                {
+                    chars.next();
+                    op_end += next_ch.len_utf8();
                 }
                 if idx >= start {
                     segments.push(cmd[start..idx].to_string());
@@ -279,20 +453,18 @@ fn is_custom_allowed(program: &str) -> bool {
 
 fn enforce_program_specific_rules(program: &str, tokens: &[String]) -> Result<()> {
     match program {
-        "find" => {
+        "find"
             if tokens.iter().any(|t| {
                 matches!(
                     t.as_str(),
                     "-delete" | "-exec" | "-execdir" | "-ok" | "-okdir"
                 )
-            }) {
-                return Err(anyhow!("Blocked find command with delete/exec actions"));
-            }
+            }) =>
+        {
+            return Err(anyhow!("Blocked find command with delete/exec actions"));
         }
-        "sed" => {
-            if tokens.iter().any(|t| t == "-i" || t.starts_with("-i")) {
-                return Err(anyhow!("Blocked sed -i edits from qa for safety"));
-            }
+        "sed" if tokens.iter().any(|t| t == "-i" || t.starts_with("-i")) => {
+            return Err(anyhow!("Blocked sed -i edits from qa for safety"));
         }
         "awk" | "cat" | "cut" | "df" | "du" | "env" | "grep" | "head" | "ls" | "pwd" | "rg"
         | "sort" | "stat" | "tail" | "tree" | "uniq" | "wc" => {}
@@ -343,6 +515,10 @@ fn gather_allowed_roots() -> Result<Vec<PathBuf>> {
         push_unique(&mut roots, &mut seen, canon);
     }
 
+    for root in extra_roots().lock().unwrap().iter() {
+        push_unique(&mut roots, &mut seen, root.clone());
+    }
+
     Ok(roots)
 }
 
@@ -1,4 +1,5 @@
-use chrono::Utc;
+use crate::config::FormattingInstructions;
+use chrono::{Local, SecondsFormat, Utc};
 use os_info::Type as OsType;
 
 /// Normalized inputs for building prompts.
@@ -7,6 +8,15 @@ pub struct PromptInputs {
     pub stdin_block: Option<String>,
 }
 
+/// Decode piped bytes as UTF-8, replacing invalid sequences instead of failing.
+/// Returns the text and whether any replacement happened.
+pub fn decode_stdin_lossy(bytes: Vec<u8>) -> (String, bool) {
+    match String::from_utf8(bytes) {
+        Ok(text) => (text, false),
+        Err(err) => (String::from_utf8_lossy(err.as_bytes()).into_owned(), true),
+    }
+}
+
 /// Merge CLI question/task text with optional piped stdin.
 ///
 /// - If CLI arguments are present (non-empty after trimming), they win and any
@@ -14,20 +24,20 @@ pub struct PromptInputs {
 /// - Otherwise, a non-empty piped block becomes the question/task and is NOT
 ///   echoed as separate context to avoid duplication.
 pub fn coalesce_prompt_inputs(args_question: String, stdin_block: Option<String>) -> PromptInputs {
-    if args_question.trim().is_empty() {
-        if let Some(block) = stdin_block {
-            let trimmed = block.trim();
-            if !trimmed.is_empty() {
-                return PromptInputs {
-                    question: trimmed.to_string(),
-                    stdin_block: None,
-                };
-            }
+    if args_question.trim().is_empty()
+        && let Some(block) = stdin_block
+    {
+        let trimmed = block.trim();
+        if !trimmed.is_empty() {
             return PromptInputs {
-                question: args_question,
-                stdin_block: Some(block),
+                question: trimmed.to_string(),
+                stdin_block: None,
             };
         }
+        return PromptInputs {
+            question: args_question,
+            stdin_block: Some(block),
+        };
     }
 
     PromptInputs {
@@ -49,21 +59,153 @@ pub fn build_qq_prompt(
     stdin_block: Option<&str>,
     question: &str,
 ) -> String {
-    let now = Utc::now();
+    render_qq_prompt(
+        os,
+        shell_hint,
+        fit_context_budget(history, stdin_block, None),
+        &ContextOptions::default(),
+        question,
+    )
+}
+
+/// Section label used for piped input unless `stdin_label` overrides it.
+pub const DEFAULT_STDIN_LABEL: &str = "Input from pipe";
+
+/// Per-run knobs for how history and piped input are framed in the user message.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ContextOptions<'a> {
+    /// Byte budget for history + stdin (see [`CONTEXT_TRUNCATED_MARKER`]).
+    pub max_context_bytes: Option<usize>,
+    /// Replaces [`DEFAULT_STDIN_LABEL`], e.g. "JSON payload".
+    pub stdin_label: Option<&'a str>,
+    /// Stamp the date/time line in local time with its UTC offset instead of UTC.
+    pub local_time: bool,
+    /// Top-level listing of the working directory (`--cwd-context`), shown ahead of
+    /// the piped input; not counted against `max_context_bytes`.
+    pub cwd_listing: Option<&'a str>,
+    /// Leave out the OS and date/time lines (`--no-os-line`).
+    pub omit_os_info: bool,
+}
+
+impl ContextOptions<'_> {
+    fn stdin_label(&self) -> &str {
+        self.stdin_label
+            .map(|label| label.trim().trim_end_matches(':').trim_end())
+            .filter(|label| !label.is_empty())
+            .unwrap_or(DEFAULT_STDIN_LABEL)
+    }
+}
+
+/// Marker placed ahead of the context sections when `max_context_bytes` dropped content.
+pub const CONTEXT_TRUNCATED_MARKER: &str = "[context truncated]";
+
+/// History and stdin context after applying the byte budget.
+struct ContextBudget<'a> {
+    history: &'a [String],
+    stdin_block: Option<&'a str>,
+    truncated: bool,
+}
+
+/// Trim history + stdin to at most `max_bytes`, keeping the most recent content:
+/// the tail of the piped input first, then the newest history entries.
+fn fit_context_budget<'a>(
+    history: &'a [String],
+    stdin_block: Option<&'a str>,
+    max_bytes: Option<usize>,
+) -> ContextBudget<'a> {
+    let Some(max) = max_bytes else {
+        return ContextBudget {
+            history,
+            stdin_block,
+            truncated: false,
+        };
+    };
+
+    let mut truncated = false;
+    let stdin_block = stdin_block.map(|block| {
+        if block.len() <= max {
+            return block;
+        }
+        truncated = true;
+        let mut start = block.len() - max;
+        while !block.is_char_boundary(start) {
+            start += 1;
+        }
+        let tail = &block[start..];
+        // Drop the partial first line when a later line boundary exists.
+        match tail.find('\n') {
+            Some(idx) if idx + 1 < tail.len() => &tail[idx + 1..],
+            _ => tail,
+        }
+    });
+
+    let mut remaining = max.saturating_sub(stdin_block.map_or(0, str::len));
+    let mut keep = 0;
+    for line in history.iter().rev() {
+        let cost = line.len() + 1;
+        if cost > remaining {
+            break;
+        }
+        remaining -= cost;
+        keep += 1;
+    }
+    if keep < history.len() {
+        truncated = true;
+    }
+
+    ContextBudget {
+        history: &history[history.len() - keep..],
+        stdin_block,
+        truncated,
+    }
+}
+
+/// RFC 3339 timestamp to the second: UTC as `...Z`, local time with its offset.
+fn prompt_timestamp(local_time: bool) -> String {
+    if local_time {
+        Local::now().to_rfc3339_opts(SecondsFormat::Secs, false)
+    } else {
+        Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)
+    }
+}
+
+fn render_qq_prompt(
+    os: Option<OsType>,
+    shell_hint: Option<&str>,
+    context: ContextBudget<'_>,
+    options: &ContextOptions<'_>,
+    question: &str,
+) -> String {
+    let local_time = options.local_time;
+    let ContextBudget {
+        history,
+        stdin_block,
+        truncated,
+    } = context;
     let os_name = os
         .map(|t| t.to_string())
         .unwrap_or_else(|| os_info::get().os_type().to_string());
 
     let mut out = String::new();
-    out.push_str(&format!(
-        "Timestamp (UTC): {}\nOS: {}\n",
-        now.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
-        os_name
-    ));
+    if !options.omit_os_info {
+        out.push_str(&format!(
+            "Timestamp ({}): {}\nOS: {}\n",
+            if local_time { "local" } else { "UTC" },
+            prompt_timestamp(local_time),
+            os_name
+        ));
+    }
     if let Some(shell) = shell_hint {
         out.push_str(&format!("Default Shell: {}\n", shell));
     }
-    out.push('\n');
+    if !out.is_empty() {
+        out.push('\n');
+    }
+
+    if truncated {
+        out.push_str(CONTEXT_TRUNCATED_MARKER);
+        out.push_str("\n\n");
+    }
 
     if !history.is_empty() {
         out.push_str("Terminal History (last commands):\n");
@@ -74,15 +216,21 @@ pub fn build_qq_prompt(
         out.push('\n');
     }
 
-    if let Some(block) = stdin_block {
-        if !block.trim().is_empty() {
-            out.push_str("Input from pipe:\n");
-            out.push_str(block);
-            if !block.ends_with('\n') {
-                out.push('\n');
-            }
+    if let Some(listing) = options.cwd_listing {
+        out.push_str("Current directory:\n");
+        out.push_str(listing);
+        out.push('\n');
+    }
+
+    if let Some(block) = stdin_block
+        && !block.trim().is_empty()
+    {
+        out.push_str(&format!("{}:\n", options.stdin_label()));
+        out.push_str(block);
+        if !block.ends_with('\n') {
             out.push('\n');
         }
+        out.push('\n');
     }
 
     out.push_str("Question: ");
@@ -92,74 +240,193 @@ pub fn build_qq_prompt(
 
 /// System prompt for `qq`: restrict topics to terminal/dev and enforce XML-tag formatting.
 pub fn build_qq_system_prompt() -> String {
+    build_qq_system_prompt_with(FormattingInstructions::Full)
+}
+
+/// [`build_qq_system_prompt`] with only as much tag-formatting guidance as `formatting`
+/// asks for (config `formatting_instructions`).
+pub fn build_qq_system_prompt_with(formatting: FormattingInstructions) -> String {
+    let full = formatting == FormattingInstructions::Full;
+    let tags = formatting != FormattingInstructions::None;
+    let cmd = |command: &str| {
+        if tags {
+            format!("<cmd>{}</cmd>", command)
+        } else {
+            command.to_string()
+        }
+    };
     let mut s = String::new();
     s.push_str("Balance quick answer with just enough thinking.\n");
     s.push_str("You are a terminal assistant. Help users ONLY with command-line, programming, system administration, and technical computing tasks.\n\n");
     s.push_str("TOPIC RESTRICTIONS:\n");
     s.push_str("- ONLY answer questions about: terminal commands, shell scripting, file operations, system administration, programming, development tools, git, network tools, text processing, etc.\n");
     s.push_str("- REFUSE to answer: philosophical questions, general knowledge, personal advice, non-technical topics, creative writing, etc.\n");
-    s.push_str("- For off-topic questions, kindly refuse and then provide fun computer/Unix/Linux trivia with wit and humor that's somehow related to their question if possible. ALWAYS use this EXACT format with XML tags:\n");
-    s.push_str("  <warn>I can only help with terminal and technical tasks, but here's some fun tech trivia...</warn><br/>\n");
-    s.push_str("  <info>Your witty, reality-based computer/Unix/Linux fact goes here</info>\n\n");
-    s.push_str(
-        "CRITICAL: ALWAYS wrap trivia text in <info></info> tags for proper cyan formatting!\n\n",
-    );
+    if full {
+        s.push_str("- For off-topic questions, kindly refuse and then provide fun computer/Unix/Linux trivia with wit and humor that's somehow related to their question if possible. ALWAYS use this EXACT format with XML tags:\n");
+        s.push_str("  <warn>I can only help with terminal and technical tasks, but here's some fun tech trivia...</warn><br/>\n");
+        s.push_str(
+            "  <info>Your witty, reality-based computer/Unix/Linux fact goes here</info>\n\n",
+        );
+        s.push_str(
+            "CRITICAL: ALWAYS wrap trivia text in <info></info> tags for proper cyan formatting!\n\n",
+        );
+    } else {
+        s.push_str("- For off-topic questions, kindly refuse with \"I can only help with terminal and technical tasks, but here's some fun tech trivia...\" and then share one witty, reality-based computer/Unix/Linux fact that's somehow related to their question if possible.\n\n");
+    }
     s.push_str("ANSWER THE USER'S QUESTION DIRECTLY. For \"convert mp4 to mp3\", provide ffmpeg commands. For \"list files\", provide ls commands. Give the actual commands they need to run.\n\n");
     s.push_str("- Start with 1-3 commands (1-3 commands should generally be enough to solve the user's problem)\n");
-    s.push_str("FORMAT using XML tags:\n");
-    s.push_str("<cmd>command to run</cmd> = terminal commands (green)\n");
-    s.push_str("<bold>heading</bold> = headings and emphasis\n");
-    s.push_str("<info>tip</info> = tips and notes (cyan)\n");
-    s.push_str("<file>path</file> = file/directory paths (magenta)\n");
-    s.push_str("<warn>warning</warn> = warnings (yellow)\n");
-    s.push_str("<br/> = line break (use for spacing between sections)\n\n");
-    s.push_str("CRITICAL SPACING RULES:\n");
-    s.push_str("- Use <br/> tags for line breaks between different sections\n");
-    s.push_str("- Add <br/> after each command block\n");
-    s.push_str("- Add <br/> before and after explanatory text\n");
-    s.push_str("- Example format:\n");
-    s.push_str("  <bold>Heading</bold><br/>\n");
-    s.push_str("  <cmd>command1</cmd><br/>\n");
-    s.push_str("  <cmd>command2</cmd><br/>\n");
-    s.push_str("  <br/>\n");
-    s.push_str("  <info>Explanation or tip here</info>\n\n");
+    match formatting {
+        FormattingInstructions::Full => {
+            s.push_str("FORMAT using XML tags:\n");
+            s.push_str("<cmd>command to run</cmd> = terminal commands (green)\n");
+            s.push_str("<bold>heading</bold> = headings and emphasis\n");
+            s.push_str("<info>tip</info> = tips and notes (cyan)\n");
+            s.push_str("<file>path</file> = file/directory paths (magenta)\n");
+            s.push_str("<warn>warning</warn> = warnings (yellow)\n");
+            s.push_str("<br/> = line break (use for spacing between sections)\n\n");
+            s.push_str("CRITICAL SPACING RULES:\n");
+            s.push_str("- Use <br/> tags for line breaks between different sections\n");
+            s.push_str("- Add <br/> after each command block\n");
+            s.push_str("- Add <br/> before and after explanatory text\n");
+            s.push_str("- Example format:\n");
+            s.push_str("  <bold>Heading</bold><br/>\n");
+            s.push_str("  <cmd>command1</cmd><br/>\n");
+            s.push_str("  <cmd>command2</cmd><br/>\n");
+            s.push_str("  <br/>\n");
+            s.push_str("  <info>Explanation or tip here</info>\n\n");
+        }
+        FormattingInstructions::Minimal => {
+            s.push_str("FORMAT: wrap each terminal command in <cmd></cmd> tags, one command per tag, on its own line. Write everything else as plain text without any other tags or Markdown.\n\n");
+        }
+        FormattingInstructions::None => {
+            s.push_str("FORMAT: plain text only. Put each command on its own line, without tags, Markdown, or code fences.\n\n");
+        }
+    }
     s.push_str("RULES:\n");
     s.push_str("- Provide REAL WORKING COMMANDS the user can copy and run\n");
-    s.push_str("- Example: User asks \"convert mp4 to mp3\" → Provide: <cmd>ffmpeg -i input.mp4 output.mp3</cmd>\n");
-    s.push_str("- NOT: <cmd>qq something</cmd> (don't tell them to run qq again!)\n");
-    s.push_str("- Escape XML chars: & -> &amp; < -> &lt; > -> &gt;\n");
+    s.push_str(&format!(
+        "- Example: User asks \"convert mp4 to mp3\" → Provide: {}\n",
+        cmd("ffmpeg -i input.mp4 output.mp3")
+    ));
+    s.push_str(&format!(
+        "- NOT: {} (don't tell them to run qq again!)\n",
+        cmd("qq something")
+    ));
+    if tags {
+        s.push_str("- Escape XML chars: & -> &amp; < -> &lt; > -> &gt;\n");
+    }
     // Line repeated on purpose - it works for some smaller models to reiterate this rule - DO NOT REMOVE
     s.push_str("- Start with 1-3 commands (1-3 commands should generally be enough to solve the user's problem)\n");
     s.push_str("- Be concise and practical\n");
     s.push_str("- Prefer tools that are typically pre-installed on macOS/Linux (POSIX utilities like ls, find, grep, awk, sed, xargs, tar, curl, ssh). Optimize for portability.\n");
-    s.push_str("- You MAY suggest non-default tools (e.g., ripgrep, fd, bat, tree) only if they are notably better; when you do, include an install hint for the detected OS (e.g., <cmd>brew install ripgrep</cmd> on macOS, <cmd>sudo apt-get install ripgrep</cmd> on Debian/Ubuntu) and ALSO provide a built-in alternative.\n");
+    s.push_str(&format!(
+        "- You MAY suggest non-default tools (e.g., ripgrep, fd, bat, tree) only if they are notably better; when you do, include an install hint for the detected OS (e.g., {} on macOS, {} on Debian/Ubuntu) and ALSO provide a built-in alternative.\n",
+        cmd("brew install ripgrep"),
+        cmd("sudo apt-get install ripgrep")
+    ));
     s.push_str("- Tailor flags/commands to the provided Operating System AND Default Shell in the user message (POSIX sh vs Windows cmd.exe vs Windows PowerShell behave differently).\n");
     s
 }
 
+/// Whether a qq answer is only the off-topic refusal the system prompt asks for: a
+/// `<warn>` saying it can only help with technical tasks, optionally followed by
+/// `<info>` trivia, and nothing else.
+pub fn is_topic_refusal(answer: &str) -> bool {
+    let Some(rest) = answer.trim().strip_prefix("<warn>") else {
+        return false;
+    };
+    let Some((warning, mut rest)) = rest.split_once("</warn>") else {
+        return false;
+    };
+    if !warning.to_lowercase().contains("can only help with") {
+        return false;
+    }
+    loop {
+        rest = rest.trim_start();
+        if let Some(after) = rest.strip_prefix("<br/>") {
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("<info>") {
+            match after.split_once("</info>") {
+                Some((_, after)) => rest = after,
+                None => return false,
+            }
+        } else {
+            return rest.is_empty();
+        }
+    }
+}
+
+/// Built-in `qq --persona` presets: name and the directive appended to the system prompt.
+pub const PERSONAS: &[(&str, &str)] = &[
+    (
+        "concise",
+        "\nPersona: concise. Give the single best command with at most one short sentence of explanation; skip tips and alternatives unless they are essential.\n",
+    ),
+    (
+        "verbose",
+        "\nPersona: verbose. Explain what each command and its important flags do, mention common pitfalls, and offer an alternative where it helps.\n",
+    ),
+    (
+        "scripts-only",
+        "\nPersona: scripts-only. Reply with commands only, each in its own <cmd></cmd> block; no headings, explanations, tips, or trivia.\n",
+    ),
+];
+
+/// Look up a `--persona` name (case-insensitive), returning its system-prompt directive.
+pub fn persona_directive(name: &str) -> Result<&'static str, String> {
+    let wanted = name.trim();
+    PERSONAS
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(wanted))
+        .map(|(_, directive)| *directive)
+        .ok_or_else(|| {
+            let names: Vec<&str> = PERSONAS.iter().map(|(key, _)| *key).collect();
+            format!(
+                "unknown persona '{}' (available: {})",
+                wanted,
+                names.join(", ")
+            )
+        })
+}
+
 /// User prompt for `qq` per the documented template (timestamp/OS/history/stdin/question).
+/// `context` caps and labels the history + stdin sections (see [`ContextOptions`]).
 pub fn build_qq_user_message(
     os: Option<OsType>,
     shell_hint: Option<&str>,
     history: &[String],
     stdin_block: Option<&str>,
     question: &str,
+    context: &ContextOptions<'_>,
 ) -> String {
-    let now = Utc::now();
+    let ContextBudget {
+        history,
+        stdin_block,
+        truncated,
+    } = fit_context_budget(history, stdin_block, context.max_context_bytes);
     let os_name = os
         .map(|t| t.to_string())
         .unwrap_or_else(|| os_info::get().os_type().to_string());
 
     let mut out = String::new();
-    out.push_str(&format!(
-        "Current date/time: {}\n",
-        now.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
-    ));
-    out.push_str(&format!("Operating System: {}\n", os_name));
+    if !context.omit_os_info {
+        out.push_str(&format!(
+            "Current date/time: {}\n",
+            prompt_timestamp(context.local_time)
+        ));
+        out.push_str(&format!("Operating System: {}\n", os_name));
+    }
     if let Some(shell) = shell_hint {
         out.push_str(&format!("Default Shell: {}\n", shell));
     }
-    out.push('\n');
+    if !out.is_empty() {
+        out.push('\n');
+    }
+
+    if truncated {
+        out.push_str(CONTEXT_TRUNCATED_MARKER);
+        out.push_str("\n\n");
+    }
 
     if !history.is_empty() {
         out.push_str("[Recent terminal commands for context (sanitized for privacy):\n");
@@ -169,15 +436,21 @@ pub fn build_qq_user_message(
         out.push_str("]\n\n");
     }
 
-    if let Some(block) = stdin_block {
-        if !block.trim().is_empty() {
-            out.push_str("[Input from pipe:\n");
-            out.push_str(block);
-            if !block.ends_with('\n') {
-                out.push('\n');
-            }
-            out.push_str("]\n\n");
+    if let Some(listing) = context.cwd_listing {
+        out.push_str("[Current directory:\n");
+        out.push_str(listing);
+        out.push_str("]\n\n");
+    }
+
+    if let Some(block) = stdin_block
+        && !block.trim().is_empty()
+    {
+        out.push_str(&format!("[{}:\n", context.stdin_label()));
+        out.push_str(block);
+        if !block.ends_with('\n') {
+            out.push('\n');
         }
+        out.push_str("]\n\n");
     }
 
     out.push_str("Question: ");
@@ -189,7 +462,8 @@ pub fn build_qq_user_message(
 /// The system prompt instructs the assistant to either:
 /// - Return a normal helpful answer as plain text, or
 /// - Return ONLY a JSON object of the form:
-///   { "tool": "read_file"|"write_file"|"execute_command", "arguments": { ... } }
+///   { "tool": "read_file"|"read_files"|"write_file"|"append_file"|"execute_command", "arguments": { ... } }
+///
 /// The CLI enforces a single tool call and will not loop.
 pub fn build_qa_system_prompt() -> String {
     let mut s = String::new();
@@ -198,8 +472,10 @@ pub fn build_qa_system_prompt() -> String {
     s.push_str("You must satisfy every user request by issuing exactly one tool call, returned as a JSON object with this shape:\n");
     s.push_str("{ \"tool\": string, \"arguments\": object }\n\n");
     s.push_str("Available tools and JSON argument schemas:\n");
-    s.push_str("- read_file: { \"path\": string }\n");
+    s.push_str("- read_file: { \"path\": string, \"with_metadata\"?: bool }\n");
+    s.push_str("- read_files: { \"paths\": [string] } (several files at once, each under a `=== path ===` header)\n");
     s.push_str("- write_file: { \"path\": string, \"content\": string }\n");
+    s.push_str("- append_file: { \"path\": string, \"content\": string } (appends; creates the file if missing)\n");
     s.push_str("- execute_command: { \"command\": string, \"cwd?\": string }\n\n");
     s.push_str("Rules:\n");
     s.push_str("- Single step: at most one tool call.\n");
@@ -209,13 +485,69 @@ pub fn build_qa_system_prompt() -> String {
     s
 }
 
+/// System-prompt rule appended when `qa --tools` narrows the enabled tools.
+pub fn enabled_tools_rule(tools: &[String]) -> String {
+    format!(
+        "\nOnly these tools are enabled for this run: {}. Do not call any other tool.\n",
+        tools.join(", ")
+    )
+}
+
+/// System prompt for the follow-up request of `qa --summarize`.
+pub const SUMMARIZE_SYSTEM_PROMPT: &str = "You summarize command output for a terminal user. Reply in plain text with a few short sentences or bullet points: what the output shows, anything that failed or needs attention, and how it answers the user's task. Do not call tools and do not repeat the output verbatim.\n";
+
+/// Command output beyond this many bytes is cut from the middle before `--summarize`
+/// sends it back to the model.
+pub const SUMMARY_OUTPUT_MAX_BYTES: usize = 16 * 1024;
+
+/// User message for `qa --summarize`: the task plus the command output, keeping its first
+/// and last `max_bytes / 2` bytes (exit code and stdout head, stderr tail) when it is longer.
+pub fn build_output_summary_message(task: &str, output: &str, max_bytes: usize) -> String {
+    let output = output.trim_end();
+    let mut out = format!("Task: {}\n\nCommand output:\n", task.trim());
+    if output.len() <= max_bytes {
+        out.push_str(output);
+    } else {
+        let mut head = max_bytes / 2;
+        while !output.is_char_boundary(head) {
+            head -= 1;
+        }
+        let mut tail = output.len() - max_bytes / 2;
+        while !output.is_char_boundary(tail) {
+            tail += 1;
+        }
+        out.push_str(&output[..head]);
+        out.push_str(&format!(
+            "\n[... {} bytes of output omitted ...]\n",
+            tail - head
+        ));
+        out.push_str(&output[tail..]);
+    }
+    out.push_str("\n\nSummarize this output for the task above.");
+    out
+}
+
+/// System-prompt rule appended for `qa --explain`: tool calls carry a one-line `reason`.
+pub const EXPLAIN_RULE: &str = "\nWhen you call a tool, also include a short \"reason\" string (one sentence) saying what the call does and why, e.g. {\"tool\": \"read_file\", \"arguments\": {\"path\": \"README.md\"}, \"reason\": \"Check the install steps before editing them.\"}. For native tool calls, put \"reason\" inside the arguments.\n";
+
+/// System-prompt rule appended for `qa --plan`: describe the steps instead of calling a tool.
+pub const PLAN_RULE: &str = "\nPlan mode overrides the single-tool-call rule: do not call any tool and do not run anything. Reply with ONLY a JSON array listing, in order, the tool calls you would make to finish the task, each shaped like {\"step\": \"what this step does\", \"tool\": \"read_file\", \"arguments\": {\"path\": \"README.md\"}}. Use the tool names and argument schemas above; when a step depends on earlier output, give your best guess for its arguments.\n";
+
 /// Build the user message for `qa`: includes timestamp, OS, optional history and stdin context, plus the task.
+/// `context` caps and labels the history + stdin sections, as for `qq`.
 pub fn build_qa_user_message(
     os: Option<OsType>,
     shell_hint: Option<&str>,
     history: &[String],
     stdin_block: Option<&str>,
     task: &str,
+    context: &ContextOptions<'_>,
 ) -> String {
-    build_qq_prompt(os, shell_hint, history, stdin_block, task)
+    render_qq_prompt(
+        os,
+        shell_hint,
+        fit_context_budget(history, stdin_block, context.max_context_bytes),
+        context,
+        task,
+    )
 }
@@ -0,0 +1,1173 @@
+//! Embeddable entry points mirroring the `qq` and `qa` binaries, plus the setup steps
+//! they share.
+//!
+//! [`run_qq`] and [`run_qa`] return results instead of printing them. The binaries do the
+//! same setup through the helpers below (profile resolution, tool permissions, history,
+//! prompt context). They then print their own output: streaming, formatting, clipboard,
+//! and interactive allowlist prompts. `qa` runs its step through [`run_qa_with_hooks`],
+//! printing and prompting from its [`QaHooks`].
+
+use crate::ai::{
+    AssistantReply, ChatClient, CliCompletionRequest, DEFAULT_REQUEST_TIMEOUT_SECS,
+    MOCK_RESPONSE_ENV, Msg, PROTECTED_BODY_KEYS, Usage, run_cli_completion,
+};
+use crate::config::{Config, EffectiveProfile, MODEL_ENV, ProviderConnection, env_model_override};
+use crate::debug_log;
+use crate::env_file;
+use crate::history::{history_window, read_recent_history_since};
+use crate::logging;
+use crate::perms;
+use crate::prompt::{
+    ContextOptions, EXPLAIN_RULE, PLAN_RULE, PromptInputs, SUMMARIZE_SYSTEM_PROMPT,
+    SUMMARY_OUTPUT_MAX_BYTES, build_output_summary_message, build_qa_system_prompt,
+    build_qa_user_message, build_qq_system_prompt_with, build_qq_user_message,
+    coalesce_prompt_inputs, enabled_tools_rule, persona_directive,
+};
+use crate::redact::{redact_history, redact_stdin};
+use crate::shell::{ShellKind, environment_debug_line, resolve_shell, shell_hint_for_prompt};
+use crate::tools::execute_command::{
+    self, DEFAULT_COMMAND_TIMEOUT_SECS, ExecuteCommandResult, InteractiveOptions,
+};
+use crate::tools::{
+    self, ToolCall, append_file, read_file, read_files, tool_call_reason, tools_spec_with_reason,
+    write_file,
+};
+use anyhow::{Context, Result, anyhow};
+use os_info::Type as OsType;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const NO_EMOJI_RULE: &str = "\nHard rule: You MUST NOT use emojis anywhere in the response.\n";
+
+/// Generic env var that overrides the provider API key (below `--api-key`).
+pub const API_KEY_ENV: &str = "QQQA_API_KEY";
+
+/// Per-run overrides applied on top of the configured profile.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProfileOverrides<'a> {
+    pub profile: Option<&'a str>,
+    pub model: Option<&'a str>,
+    pub api_base: Option<&'a str>,
+    /// Replaces the provider key; falls back to `QQQA_API_KEY`. Never logged.
+    pub api_key: Option<&'a str>,
+    pub temperature: Option<f32>,
+    pub frequency_penalty: Option<f32>,
+    pub presence_penalty: Option<f32>,
+    /// Raw `--header "Name: Value"` flags, overriding provider headers on conflict.
+    pub headers: &'a [String],
+    /// Raw `--param key=value` flags merged into the request body.
+    pub params: &'a [String],
+    /// `--connect-timeout` seconds, replacing the provider's `connect_timeout_secs`.
+    pub connect_timeout_secs: Option<u64>,
+}
+
+/// Per-run tool permission flags for [`apply_qa_permissions`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PermissionFlags<'a> {
+    /// Extra programs allowed for this run only (`qa --allow`).
+    pub allow: &'a [String],
+    /// `qa --auto-safe`; also on when config sets `auto_confirm_safe`.
+    pub auto_safe: bool,
+    /// `qa --allow-outside-home`; also on when config sets `allow_cwd_outside_home`.
+    pub allow_outside_home: bool,
+    /// `Some(true)` for `--pty`, `Some(false)` for `--no-pty`.
+    pub pty: Option<bool>,
+}
+
+/// Prompt-context flags shared by `qq` and `qa`; see [`context_options`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ContextFlags<'a> {
+    pub max_context_bytes: Option<usize>,
+    pub stdin_label: Option<&'a str>,
+    pub local_time: bool,
+    pub no_os_line: bool,
+}
+
+/// Options for [`run_qq`], mirroring the `qq` CLI flags.
+#[derive(Debug, Clone, Default)]
+pub struct QqOptions {
+    pub question: String,
+    /// Extra context, as if piped on stdin.
+    pub stdin: Option<String>,
+    pub profile: Option<String>,
+    pub model: Option<String>,
+    pub api_base: Option<String>,
+    /// API key override (same as `--api-key`).
+    pub api_key: Option<String>,
+    pub temperature: Option<f32>,
+    pub frequency_penalty: Option<f32>,
+    pub presence_penalty: Option<f32>,
+    /// Extra request headers as `Name: Value` (same as `--header`).
+    pub headers: Vec<String>,
+    /// Extra request body fields as `key=value` (same as `--param`).
+    pub params: Vec<String>,
+    /// Connect timeout in seconds (same as `--connect-timeout`).
+    pub connect_timeout_secs: Option<u64>,
+    /// Force terminal history on or off; `None` follows the config.
+    pub history: Option<bool>,
+    /// Only include history from the last N minutes (same as `--history-since`).
+    pub history_since_minutes: Option<u64>,
+    /// Skip masking likely secrets in history and stdin (same as `--no-redact`).
+    pub no_redact: bool,
+    /// Byte budget for history + stdin context; falls back to config.
+    pub max_context_bytes: Option<usize>,
+    /// Label for the piped-input section (default "Input from pipe").
+    pub stdin_label: Option<String>,
+    /// Stamp prompts with local time instead of UTC; also on when config sets `prompt_local_time`.
+    pub local_time: bool,
+    /// Add a listing of the current directory to the prompt (same as `--cwd-context`).
+    pub cwd_context: bool,
+    /// Leave the OS and date/time lines out of the prompt (same as `--no-os-line`).
+    pub no_os_line: bool,
+    /// Built-in persona name (same as `--persona`), e.g. "concise".
+    pub persona: Option<String>,
+    /// Use this config instead of loading `~/.qq/config.json`.
+    pub config: Option<Config>,
+    /// Extra env file to load before `~/.qq/.env` (same as `--env-file`).
+    pub env_file: Option<PathBuf>,
+    /// Shell flavor for prompts (and qa commands); `None` detects it.
+    pub shell: Option<ShellKind>,
+    pub debug: bool,
+}
+
+/// Options for [`run_qa`], mirroring the `qa` CLI flags.
+#[derive(Debug, Clone, Default)]
+pub struct QaOptions {
+    pub task: String,
+    /// Extra context, as if piped on stdin.
+    pub stdin: Option<String>,
+    pub profile: Option<String>,
+    pub model: Option<String>,
+    pub api_base: Option<String>,
+    /// API key override (same as `--api-key`).
+    pub api_key: Option<String>,
+    pub temperature: Option<f32>,
+    pub frequency_penalty: Option<f32>,
+    pub presence_penalty: Option<f32>,
+    /// Extra request headers as `Name: Value` (same as `--header`).
+    pub headers: Vec<String>,
+    /// Extra request body fields as `key=value` (same as `--param`).
+    pub params: Vec<String>,
+    /// Connect timeout in seconds (same as `--connect-timeout`).
+    pub connect_timeout_secs: Option<u64>,
+    /// Force terminal history on or off; `None` follows the config.
+    pub history: Option<bool>,
+    /// Only include history from the last N minutes (same as `--history-since`).
+    pub history_since_minutes: Option<u64>,
+    /// Skip masking likely secrets in history and stdin (same as `--no-redact`).
+    pub no_redact: bool,
+    /// Byte budget for history + stdin context; falls back to config.
+    pub max_context_bytes: Option<usize>,
+    /// Label for the piped-input section (default "Input from pipe").
+    pub stdin_label: Option<String>,
+    /// Stamp prompts with local time instead of UTC; also on when config sets `prompt_local_time`.
+    pub local_time: bool,
+    /// Add a listing of the current directory to the prompt (same as `--cwd-context`).
+    pub cwd_context: bool,
+    /// Leave the OS and date/time lines out of the prompt (same as `--no-os-line`).
+    pub no_os_line: bool,
+    /// Auto-approve command execution (same as `qa --yes`).
+    pub yes: bool,
+    /// Run commands that pass the safety checks without asking (same as `qa --auto-safe`);
+    /// also on when config sets `auto_confirm_safe`.
+    pub auto_safe: bool,
+    /// Skip the prompt for a working directory outside home (same as
+    /// `qa --allow-outside-home`); also on when config sets `allow_cwd_outside_home`.
+    pub allow_outside_home: bool,
+    /// Force (`Some(true)`) or disable (`Some(false)`) a PTY for execute_command, like
+    /// `qa --pty` / `--no-pty`; `None` follows `QQQA_FORCE_PTY`/`QQQA_DISABLE_PTY`.
+    pub pty: Option<bool>,
+    /// Extra programs allowed for this run only (same as `qa --allow`).
+    pub allow: Vec<String>,
+    /// Restrict the tools qa may use (same as `qa --tools`); `None` allows all.
+    pub tools: Option<Vec<String>>,
+    /// execute_command timeout; falls back to config, then the default.
+    pub command_timeout_secs: Option<u64>,
+    /// After execute_command succeeds, ask the model to summarize its output for the task
+    /// (same as `qa --summarize`).
+    pub summarize: bool,
+    /// Ask for a `reason` with each tool call and pass it to [`QaHooks::tool_requested`]
+    /// (same as `qa --explain`).
+    pub explain: bool,
+    /// Ask for a multi-step plan instead of a tool call; nothing runs (same as `qa --plan`).
+    pub plan: bool,
+    /// Tool calls allowed before dispatch is refused (same as `qa --max-steps`).
+    pub max_steps: Option<u32>,
+    /// Refuse further dispatch once provider-reported tokens pass this total (same as
+    /// `qa --max-tokens-total`); not enforced when the provider reports no usage.
+    pub max_tokens_total: Option<u64>,
+    /// Use this config instead of loading `~/.qq/config.json`.
+    pub config: Option<Config>,
+    /// Extra env file to load before `~/.qq/.env` (same as `--env-file`).
+    pub env_file: Option<PathBuf>,
+    /// Shell flavor for prompts (and qa commands); `None` detects it.
+    pub shell: Option<ShellKind>,
+    pub debug: bool,
+}
+
+/// What a single `qa` step produced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QaOutcome {
+    /// Plain-text answer; no tool was called.
+    Answer(String),
+    /// A tool ran; `output` is its content or summary.
+    ToolOutput { tool: String, output: String },
+    /// execute_command ran under `summarize`; `summary` is the model's take on `output`.
+    CommandSummary { output: String, summary: String },
+    /// A tool was requested but was blocked, canceled, or failed.
+    ToolError { tool: String, error: String },
+    /// The model asked for a tool qa does not provide.
+    UnknownTool(String),
+    /// `plan` was set: the model's plan reply, unparsed, or `None` when it called a tool
+    /// anyway (nothing ran).
+    Plan(Option<String>),
+    /// `max_steps` or `max_tokens_total` refused the next dispatch. `output` holds
+    /// execute_command's output when the stop came after it ran (before the `summarize`
+    /// request).
+    BudgetExceeded {
+        reason: String,
+        output: Option<String>,
+    },
+}
+
+/// `qa --max-steps` / `--max-tokens-total` limits and what the run has used so far.
+/// Both are checked before a tool call or the `--summarize` request is dispatched.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StepBudget {
+    max_steps: Option<u32>,
+    max_tokens_total: Option<u64>,
+    requests: u32,
+    tool_calls: u32,
+    tokens: u64,
+    /// Some request came back without usage, so `tokens` undercounts.
+    usage_missing: bool,
+}
+
+impl StepBudget {
+    pub fn new(max_steps: Option<u32>, max_tokens_total: Option<u64>) -> Self {
+        Self {
+            max_steps,
+            max_tokens_total,
+            ..Default::default()
+        }
+    }
+
+    /// Count one model request and the tokens it reported (`None` for CLI providers and
+    /// responses without `usage`).
+    pub fn record_request(&mut self, usage: Option<&Usage>) {
+        self.requests += 1;
+        match usage.and_then(Usage::total) {
+            Some(tokens) => self.tokens += tokens,
+            None => self.usage_missing = true,
+        }
+    }
+
+    pub fn record_tool_call(&mut self) {
+        self.tool_calls += 1;
+    }
+
+    /// `--max-tokens-total` is set but the provider has not reported usage for every
+    /// request, so only `--max-steps` is reliable.
+    pub fn tokens_unreported(&self) -> bool {
+        self.max_tokens_total.is_some() && self.usage_missing
+    }
+
+    /// Stop message when dispatching the next tool call would go over either limit.
+    pub fn check_tool_call(&self) -> Option<String> {
+        let reason = match self.max_steps {
+            Some(max) if self.tool_calls >= max => format!("--max-steps {} reached", max),
+            _ => self.token_limit_reason()?,
+        };
+        Some(self.stop_message(&reason, "the tool call"))
+    }
+
+    /// Stop message when the token limit rules out another model request.
+    pub fn check_request(&self, next: &str) -> Option<String> {
+        let reason = self.token_limit_reason()?;
+        Some(self.stop_message(&reason, next))
+    }
+
+    /// What the run used, e.g. "1 request, 1523 tokens, 0 tool calls".
+    pub fn summary(&self) -> String {
+        let tokens = match (self.usage_missing, self.tokens) {
+            (true, 0) => "tokens not reported".to_string(),
+            (true, n) => format!("at least {} tokens", n),
+            (false, n) => format!("{} tokens", n),
+        };
+        format!(
+            "{} request{}, {}, {} tool call{}",
+            self.requests,
+            if self.requests == 1 { "" } else { "s" },
+            tokens,
+            self.tool_calls,
+            if self.tool_calls == 1 { "" } else { "s" }
+        )
+    }
+
+    fn token_limit_reason(&self) -> Option<String> {
+        let max = self.max_tokens_total?;
+        (self.tokens > max).then(|| format!("--max-tokens-total {} exceeded", max))
+    }
+
+    fn stop_message(&self, reason: &str, next: &str) -> String {
+        format!(
+            "qa stopped before {}: {} (used {}).",
+            next,
+            reason,
+            self.summary()
+        )
+    }
+}
+
+/// Ask a question the way `qq` does and return the full (unformatted) answer.
+pub async fn run_qq(opts: QqOptions) -> Result<String> {
+    env_file::load_env_files(opts.env_file.as_deref(), opts.debug)?;
+    let (cfg, cfg_dir) = load_config(opts.config, opts.debug)?;
+    let eff = resolve_run_profile(
+        &cfg,
+        cfg_dir.as_deref(),
+        &ProfileOverrides {
+            profile: opts.profile.as_deref(),
+            model: opts.model.as_deref(),
+            api_base: opts.api_base.as_deref(),
+            api_key: opts.api_key.as_deref(),
+            temperature: opts.temperature,
+            frequency_penalty: opts.frequency_penalty,
+            presence_penalty: opts.presence_penalty,
+            headers: &opts.headers,
+            params: &opts.params,
+            connect_timeout_secs: opts.connect_timeout_secs,
+        },
+    )?;
+    let prepared = prepare_prompt_inputs(opts.question, opts.stdin, opts.no_redact, opts.debug);
+    if prepared.question.trim().is_empty() {
+        return Err(anyhow!("No input provided"));
+    }
+    let history = prompt_history(
+        &cfg,
+        opts.history,
+        opts.history_since_minutes,
+        opts.no_redact,
+        opts.debug,
+    );
+    let os_type = os_info::get().os_type();
+    let persona = opts
+        .persona
+        .as_deref()
+        .map(persona_directive)
+        .transpose()
+        .map_err(|e| anyhow!(e))?;
+    let cwd_listing = opts
+        .cwd_context
+        .then(crate::cwd_listing::current_dir_listing)
+        .transpose()?;
+    let (mut system, user) = qq_prompts(
+        &cfg,
+        os_type,
+        resolve_shell(os_type, opts.shell).0,
+        &history,
+        prepared.stdin_block.as_deref(),
+        &prepared.question,
+        &context_options(
+            &cfg,
+            ContextFlags {
+                max_context_bytes: opts.max_context_bytes,
+                stdin_label: opts.stdin_label.as_deref(),
+                local_time: opts.local_time,
+                no_os_line: opts.no_os_line,
+            },
+            cwd_listing.as_deref(),
+        ),
+    );
+    if let Some(directive) = persona {
+        system.push_str(directive);
+    }
+
+    match build_http_client(&eff)? {
+        Some(client) => {
+            let messages = [
+                Msg {
+                    role: "system",
+                    content: &system,
+                },
+                Msg {
+                    role: "user",
+                    content: &user,
+                },
+            ];
+            client
+                .chat_once_messages(&eff.model, &messages, opts.debug)
+                .await
+        }
+        None => cli_completion(&eff, &system, &user, opts.debug).await,
+    }
+}
+
+/// The interactive parts of a `qa` step. [`run_qa`] takes the defaults (nothing printed,
+/// nothing asked); the `qa` binary prints and prompts through its own implementation, so
+/// both go through the same request and tool dispatch in [`run_qa_with_hooks`].
+pub trait QaHooks {
+    /// One-line warning for the run: the [`run_notices`], and a provider that reports no
+    /// usage under `max_tokens_total`.
+    fn notice(&mut self, _message: &str) {}
+
+    /// The model asked for tool `name`, with any text it sent alongside and (under
+    /// `explain`) its reason. Called before the `tools` filter and the step budget.
+    fn tool_requested(&mut self, _name: &str, _preamble: Option<&str>, _reason: Option<&str>) {}
+
+    /// write_file content is over `write_file_max_bytes`; `true` writes it anyway.
+    fn confirm_large_write(&mut self, _path: &str, _size: usize, _limit: usize) -> Result<bool> {
+        Ok(false)
+    }
+
+    /// Run an execute_command call. The default runs it from the current directory,
+    /// without live output or the allowlist prompt.
+    fn execute_command(
+        &mut self,
+        args: execute_command::Args,
+        opts: InteractiveOptions,
+    ) -> impl Future<Output = Result<ExecuteCommandResult>> {
+        async move {
+            let base_dir = std::env::current_dir().context("Failed to read current directory")?;
+            let base_dir = base_dir.canonicalize().unwrap_or(base_dir);
+            let args = execute_command::sanitize_execute_args(args, &base_dir, opts.debug);
+            let summary = execute_command::run(
+                args,
+                opts.auto_yes,
+                opts.debug,
+                opts.shell,
+                opts.command_timeout,
+                None,
+            )
+            .await?;
+            Ok(ExecuteCommandResult {
+                summary,
+                streamed_live: false,
+            })
+        }
+    }
+
+    /// A tool ran and produced `output` (already on screen when `streamed_live`). Called
+    /// before any `summarize` request.
+    fn tool_output(&mut self, _tool: &str, _output: &str, _streamed_live: bool) {}
+
+    /// A tool call was refused by the `tools` filter, or was blocked, canceled, or failed.
+    fn tool_error(&mut self, _tool: &str, _error: &str) {}
+}
+
+impl QaHooks for () {}
+
+/// Run a single `qa` step: one request, then at most one tool call.
+pub async fn run_qa(opts: QaOptions) -> Result<QaOutcome> {
+    run_qa_with_hooks(opts, &mut ()).await
+}
+
+/// [`run_qa`] with warnings, confirmations, and tool results going through `hooks`.
+pub async fn run_qa_with_hooks<H: QaHooks>(opts: QaOptions, hooks: &mut H) -> Result<QaOutcome> {
+    env_file::load_env_files(opts.env_file.as_deref(), opts.debug)?;
+    let (cfg, cfg_dir) = load_config(opts.config, opts.debug)?;
+    apply_qa_permissions(
+        &cfg,
+        &PermissionFlags {
+            allow: &opts.allow,
+            auto_safe: opts.auto_safe,
+            allow_outside_home: opts.allow_outside_home,
+            pty: opts.pty,
+        },
+    )?;
+    let overrides = ProfileOverrides {
+        profile: opts.profile.as_deref(),
+        model: opts.model.as_deref(),
+        api_base: opts.api_base.as_deref(),
+        api_key: opts.api_key.as_deref(),
+        temperature: opts.temperature,
+        frequency_penalty: opts.frequency_penalty,
+        presence_penalty: opts.presence_penalty,
+        headers: &opts.headers,
+        params: &opts.params,
+        connect_timeout_secs: opts.connect_timeout_secs,
+    };
+    let eff = resolve_run_profile(&cfg, cfg_dir.as_deref(), &overrides)?;
+    if opts.model.is_none()
+        && let Some(model) = env_model_override()
+    {
+        debug_log!(opts.debug, "Using model '{}' from {}", model, MODEL_ENV);
+    }
+    for notice in run_notices(&cfg, &overrides, &eff) {
+        hooks.notice(&notice);
+    }
+    log_resolved_profile(&eff, opts.debug);
+
+    let prepared = prepare_prompt_inputs(opts.task, opts.stdin, opts.no_redact, opts.debug);
+    if prepared.question.trim().is_empty() {
+        return Err(anyhow!("No input provided"));
+    }
+    let history = prompt_history(
+        &cfg,
+        opts.history,
+        opts.history_since_minutes,
+        opts.no_redact,
+        opts.debug,
+    );
+    let allowed_tools = opts
+        .tools
+        .as_ref()
+        .map(|tools| tools::parse_tool_filter(&tools.join(",")))
+        .transpose()?;
+    let os_type = os_info::get().os_type();
+    let (shell, shell_forced) = resolve_shell(os_type, opts.shell);
+    debug_log!(
+        opts.debug,
+        "{}",
+        environment_debug_line(os_type, shell, shell_forced)
+    );
+    let cwd_listing = opts
+        .cwd_context
+        .then(crate::cwd_listing::current_dir_listing)
+        .transpose()?;
+    let (mut system, user) = qa_prompts(
+        &cfg,
+        os_type,
+        shell,
+        &history,
+        prepared.stdin_block.as_deref(),
+        &prepared.question,
+        &context_options(
+            &cfg,
+            ContextFlags {
+                max_context_bytes: opts.max_context_bytes,
+                stdin_label: opts.stdin_label.as_deref(),
+                local_time: opts.local_time,
+                no_os_line: opts.no_os_line,
+            },
+            cwd_listing.as_deref(),
+        ),
+    );
+    if let Some(tools) = &allowed_tools {
+        system.push_str(&enabled_tools_rule(tools));
+    }
+    let mut tools_spec = tools::tools_spec_filtered(allowed_tools.as_deref());
+    if opts.explain {
+        system.push_str(EXPLAIN_RULE);
+        tools_spec = tools_spec_with_reason(tools_spec);
+    }
+    if opts.plan {
+        system.push_str(PLAN_RULE);
+    }
+    let command = InteractiveOptions {
+        auto_yes: opts.yes,
+        debug: opts.debug,
+        shell,
+        command_timeout: command_timeout(opts.command_timeout_secs, &cfg)?,
+        color_stderr: false,
+    };
+    let mut budget = StepBudget::new(opts.max_steps, opts.max_tokens_total);
+
+    let messages = [
+        Msg {
+            role: "system",
+            content: &system,
+        },
+        Msg {
+            role: "user",
+            content: &user,
+        },
+    ];
+    let (reply, usage) = match build_http_client(&eff)? {
+        // No tool specs in plan mode, so the model can only describe its steps.
+        Some(client) if opts.plan => {
+            let (text, usage) = client
+                .chat_once_messages_with_usage(&eff.model, &messages, opts.debug)
+                .await?;
+            (AssistantReply::Content(text), usage)
+        }
+        Some(client) => {
+            client
+                .chat_once_with_tools_and_usage(&eff.model, &messages, tools_spec, opts.debug)
+                .await?
+        }
+        None => (
+            AssistantReply::Content(cli_completion(&eff, &system, &user, opts.debug).await?),
+            None,
+        ),
+    };
+    budget.record_request(usage.as_ref());
+    if budget.tokens_unreported() {
+        hooks.notice(
+            "[warn] The provider reported no token usage; only --max-steps limits this run.",
+        );
+    }
+
+    if opts.plan {
+        return Ok(QaOutcome::Plan(match reply {
+            AssistantReply::Content(text) => {
+                debug_log!(opts.debug, "assistant raw: {}", text);
+                Some(text)
+            }
+            AssistantReply::ToolCall { .. } => None,
+        }));
+    }
+
+    let call = match reply {
+        AssistantReply::ToolCall {
+            name,
+            arguments_json,
+            content,
+        } => {
+            debug_log!(opts.debug, "tool call '{}' args: {}", name, arguments_json);
+            let reason = opts
+                .explain
+                .then(|| tool_call_reason(&arguments_json))
+                .flatten();
+            hooks.tool_requested(&name, content.as_deref(), reason.as_deref());
+            match tools::resolve_function_call(&name, &arguments_json)? {
+                Some(call) => call,
+                None => return Ok(QaOutcome::UnknownTool(name)),
+            }
+        }
+        AssistantReply::Content(text) => {
+            debug_log!(opts.debug, "assistant raw: {}", text);
+            // Try to parse as a tool call per our plain-JSON protocol; else it is the answer.
+            match tools::parse_tool_call(text.trim()) {
+                Ok(call) => {
+                    let reason = opts.explain.then(|| tool_call_reason(&text)).flatten();
+                    hooks.tool_requested(call.name(), None, reason.as_deref());
+                    call
+                }
+                Err(_) => return Ok(QaOutcome::Answer(text.trim_end().to_string())),
+            }
+        }
+    };
+
+    let tool = call.name();
+    if !tools::tool_enabled(allowed_tools.as_deref(), tool) {
+        let error = format!("Tool '{}' is not enabled for this run (--tools)", tool);
+        hooks.tool_error(tool, &error);
+        return Ok(QaOutcome::ToolError {
+            tool: tool.to_string(),
+            error,
+        });
+    }
+    if let Some(reason) = budget.check_tool_call() {
+        return Ok(QaOutcome::BudgetExceeded {
+            reason,
+            output: None,
+        });
+    }
+    budget.record_tool_call();
+
+    let result = logging::traced("tool", tool, dispatch_tool_call(call, command, hooks)).await;
+    let output = match result {
+        Ok((output, streamed_live)) => {
+            hooks.tool_output(tool, &output, streamed_live);
+            output
+        }
+        Err(err) => {
+            let error = err.to_string();
+            hooks.tool_error(tool, &error);
+            return Ok(QaOutcome::ToolError {
+                tool: tool.to_string(),
+                error,
+            });
+        }
+    };
+    if !opts.summarize || tool != execute_command::NAME {
+        if opts.summarize {
+            debug_log!(
+                opts.debug,
+                "--summarize: no execute_command output to summarize"
+            );
+        }
+        return Ok(QaOutcome::ToolOutput {
+            tool: tool.to_string(),
+            output,
+        });
+    }
+    if let Some(reason) = budget.check_request("the summary request") {
+        return Ok(QaOutcome::BudgetExceeded {
+            reason,
+            output: Some(output),
+        });
+    }
+    let (summary, _) =
+        summarize_command_output_with_usage(&eff, &prepared.question, &output, opts.debug).await?;
+    Ok(QaOutcome::CommandSummary { output, summary })
+}
+
+/// Run one tool call; returns its output and whether that was already streamed live.
+async fn dispatch_tool_call<H: QaHooks>(
+    call: ToolCall,
+    command: InteractiveOptions,
+    hooks: &mut H,
+) -> Result<(String, bool)> {
+    let output = match call {
+        ToolCall::ReadFile(args) => read_file::run(args)?,
+        ToolCall::ReadFiles(args) => read_files::run(args)?,
+        ToolCall::WriteFile(args) => {
+            let limit = perms::write_file_max_bytes();
+            let size = args.content.len();
+            if size > limit && hooks.confirm_large_write(&args.path, size, limit)? {
+                write_file::run_without_size_limit(args)?
+            } else {
+                write_file::run(args)?
+            }
+        }
+        ToolCall::AppendFile(args) => append_file::run(args)?,
+        ToolCall::ExecuteCommand(args) => {
+            let result = hooks.execute_command(args, command).await?;
+            return Ok((result.summary, result.streamed_live));
+        }
+    };
+    Ok((output, false))
+}
+
+/// Apply the config's command policy to the process-wide tool settings: allowlist, safe
+/// pipeline shapes, dangerous patterns, exec roots, write_file limit, shell, and output
+/// encoding. `qq --run` needs only this; `qa` goes through [`apply_qa_permissions`].
+pub fn apply_command_policy(cfg: &Config) -> Result<()> {
+    perms::set_custom_allowlist(cfg.command_allowlist());
+    perms::set_safe_pipeline_shapes(cfg.safe_pipelines());
+    perms::set_dangerous_patterns(cfg.dangerous_patterns());
+    perms::set_allowed_exec_roots(cfg.allowed_exec_roots());
+    perms::set_write_file_max_bytes(cfg.write_file_max_bytes);
+    execute_command::set_posix_shell(cfg.posix_shell.clone());
+    execute_command::set_windows_output_encoding(cfg.windows_output_encoding()?);
+    Ok(())
+}
+
+/// [`apply_command_policy`] plus qa's per-run flags (`--allow`, `--auto-safe`,
+/// `--allow-outside-home`, `--pty`/`--no-pty`).
+pub fn apply_qa_permissions(cfg: &Config, flags: &PermissionFlags<'_>) -> Result<()> {
+    apply_command_policy(cfg)?;
+    perms::extend_custom_allowlist(flags.allow.iter().cloned());
+    execute_command::set_auto_confirm_safe(flags.auto_safe || cfg.auto_confirm_safe);
+    execute_command::set_allow_cwd_outside_home(
+        flags.allow_outside_home || cfg.allow_cwd_outside_home,
+    );
+    execute_command::set_pty_mode(execute_command::PtyMode::from_flag(flags.pty));
+    Ok(())
+}
+
+/// execute_command timeout: the flag, then config, then the default. Zero is rejected.
+pub fn command_timeout(flag_secs: Option<u64>, cfg: &Config) -> Result<Duration> {
+    let secs = flag_secs
+        .or(cfg.command_timeout_secs)
+        .unwrap_or(DEFAULT_COMMAND_TIMEOUT_SECS);
+    if secs == 0 {
+        return Err(anyhow!("Command timeout must be greater than zero"));
+    }
+    Ok(Duration::from_secs(secs))
+}
+
+/// Mask likely secrets in piped stdin (unless `no_redact`), then merge it with the
+/// typed question.
+pub fn prepare_prompt_inputs(
+    question: String,
+    mut stdin: Option<String>,
+    no_redact: bool,
+    debug: bool,
+) -> PromptInputs {
+    if let Some(block) = stdin.as_mut()
+        && !no_redact
+    {
+        redact_stdin(block, debug);
+    }
+    coalesce_prompt_inputs(question, stdin)
+}
+
+/// Recent terminal history for the prompt. `include` forces it on or off; otherwise
+/// `--history-since` or the config decides. Likely secrets are masked unless `no_redact`.
+pub fn prompt_history(
+    cfg: &Config,
+    include: Option<bool>,
+    since_minutes: Option<u64>,
+    no_redact: bool,
+    debug: bool,
+) -> Vec<String> {
+    let include = include.unwrap_or_else(|| since_minutes.is_some() || cfg.history_enabled());
+    if !include {
+        return Vec::new();
+    }
+    let mut history = read_recent_history_since(10, history_window(since_minutes), debug);
+    if !no_redact {
+        redact_history(&mut history, debug);
+    }
+    history
+}
+
+/// Prompt context for `flags`, with config fallbacks (`max_context_bytes`,
+/// `prompt_local_time`, `include_os_info`).
+pub fn context_options<'a>(
+    cfg: &Config,
+    flags: ContextFlags<'a>,
+    cwd_listing: Option<&'a str>,
+) -> ContextOptions<'a> {
+    ContextOptions {
+        max_context_bytes: flags.max_context_bytes.or(cfg.max_context_bytes),
+        stdin_label: flags.stdin_label,
+        local_time: flags.local_time || cfg.prompt_local_time,
+        cwd_listing,
+        omit_os_info: flags.no_os_line || !cfg.os_info_enabled(),
+    }
+}
+
+/// One-line stderr notices for a resolved run (a `[hint]` when `--model` looks like
+/// another provider's, ignored `--temperature`, an Authorization `--header`). The binaries
+/// print them unless `--quiet`.
+pub fn run_notices(
+    cfg: &Config,
+    overrides: &ProfileOverrides<'_>,
+    eff: &EffectiveProfile,
+) -> Vec<String> {
+    let mut notices = Vec::new();
+    if let Some(hint) = cfg.model_profile_hint(overrides.profile, overrides.model) {
+        notices.push(format!("[hint] {}", hint));
+    }
+    notices.extend(ignored_temperature_warning(
+        &eff.model,
+        overrides.temperature,
+    ));
+    notices.extend(authorization_override_warning(overrides.headers));
+    notices
+}
+
+/// Debug line naming the provider, endpoint (or CLI binary), and model in use.
+pub fn log_resolved_profile(eff: &EffectiveProfile, debug: bool) {
+    match &eff.connection {
+        ProviderConnection::Http(conn) => debug_log!(
+            debug,
+            "Using provider='{}' base_url='{}' model='{}'",
+            eff.provider_key,
+            conn.base_url,
+            eff.model
+        ),
+        ProviderConnection::Cli(conn) => debug_log!(
+            debug,
+            "Using provider='{}' cli_binary='{}' model='{}'",
+            eff.provider_key,
+            conn.binary,
+            eff.model
+        ),
+    }
+}
+
+/// Second, tool-free request of `qa --summarize`: the task and the command output (cut to
+/// [`SUMMARY_OUTPUT_MAX_BYTES`]) go back to the model, which explains the output.
+pub async fn summarize_command_output(
+    eff: &EffectiveProfile,
+    task: &str,
+    output: &str,
+    debug: bool,
+) -> Result<String> {
+    Ok(
+        summarize_command_output_with_usage(eff, task, output, debug)
+            .await?
+            .0,
+    )
+}
+
+/// [`summarize_command_output`] plus the provider-reported usage of the summary request.
+pub async fn summarize_command_output_with_usage(
+    eff: &EffectiveProfile,
+    task: &str,
+    output: &str,
+    debug: bool,
+) -> Result<(String, Option<Usage>)> {
+    let user = build_output_summary_message(task, output, SUMMARY_OUTPUT_MAX_BYTES);
+    debug_log!(
+        debug,
+        "--summarize: sending {} bytes of command output back",
+        output.len().min(SUMMARY_OUTPUT_MAX_BYTES)
+    );
+    let (summary, usage) = match build_http_client(eff)? {
+        Some(client) => {
+            let messages = [
+                Msg {
+                    role: "system",
+                    content: SUMMARIZE_SYSTEM_PROMPT,
+                },
+                Msg {
+                    role: "user",
+                    content: &user,
+                },
+            ];
+            client
+                .chat_once_messages_with_usage(&eff.model, &messages, debug)
+                .await?
+        }
+        None => (
+            cli_completion(eff, SUMMARIZE_SYSTEM_PROMPT, &user, debug).await?,
+            None,
+        ),
+    };
+    Ok((summary.trim_end().to_string(), usage))
+}
+
+/// Resolve the profile and apply every [`ProfileOverrides`] field on top of it: the
+/// profile/model choice, `--api-base`, `--api-key`, `--temperature`, the penalties,
+/// `--header`, `--param`, and `--connect-timeout`.
+pub fn resolve_run_profile(
+    cfg: &Config,
+    cfg_dir: Option<&Path>,
+    overrides: &ProfileOverrides<'_>,
+) -> Result<EffectiveProfile> {
+    let env_api_key = std::env::var(API_KEY_ENV)
+        .ok()
+        .filter(|key| !key.trim().is_empty());
+    let api_key = overrides
+        .api_key
+        .or(env_api_key.as_deref())
+        .or(mock_api_key());
+    let mut eff =
+        cfg.resolve_profile_with_api_key(overrides.profile, overrides.model, cfg_dir, api_key)?;
+    if overrides.api_key.is_some() && eff.http().is_none() {
+        return Err(anyhow!(
+            "--api-key override is only supported for HTTP providers (current: '{}').",
+            eff.provider_key
+        ));
+    }
+    if let Some(base) = overrides.api_base {
+        if let Some(http) = eff.http_mut() {
+            http.base_url = base.to_string();
+        } else {
+            return Err(anyhow!(
+                "--api-base override is only supported for HTTP providers (current: '{}').",
+                eff.provider_key
+            ));
+        }
+    }
+    if !overrides.headers.is_empty() {
+        let Some(http) = eff.http_mut() else {
+            return Err(anyhow!(
+                "--header is only supported for HTTP providers (current: '{}').",
+                eff.provider_key
+            ));
+        };
+        for raw in overrides.headers {
+            let (name, value) = parse_header_flag(raw)?;
+            http.headers
+                .retain(|existing, _| !existing.eq_ignore_ascii_case(&name));
+            http.headers.insert(name, value);
+        }
+    }
+    if let Some(secs) = overrides.connect_timeout_secs {
+        let Some(http) = eff.http_mut() else {
+            return Err(anyhow!(
+                "--connect-timeout is only supported for HTTP providers (current: '{}').",
+                eff.provider_key
+            ));
+        };
+        http.network.connect_timeout_secs = Some(secs);
+    }
+    if !overrides.params.is_empty() {
+        if eff.http().is_none() {
+            return Err(anyhow!(
+                "--param is only supported for HTTP providers (current: '{}').",
+                eff.provider_key
+            ));
+        }
+        for raw in overrides.params {
+            let (key, value) = parse_param_flag(raw)?;
+            eff.request_params.insert(key, value);
+        }
+    }
+    if let Some(temp) = overrides.temperature {
+        eff.temperature = Some(temp);
+    }
+    if let Some(penalty) = overrides.frequency_penalty {
+        eff.frequency_penalty = Some(penalty);
+    }
+    if let Some(penalty) = overrides.presence_penalty {
+        eff.presence_penalty = Some(penalty);
+    }
+    Ok(eff)
+}
+
+/// Resolve a `fallback_profiles` entry as configured: per-run overrides (`-m`, `--api-key`,
+/// `QQQA_MODEL`, ...) target the primary profile and are not carried over.
+pub fn resolve_fallback_profile(
+    cfg: &Config,
+    cfg_dir: Option<&Path>,
+    name: &str,
+) -> Result<EffectiveProfile> {
+    cfg.resolve_profile_as_configured(name, cfg_dir, mock_api_key())
+}
+
+/// Canned responses never reach a provider, so any placeholder key will do.
+fn mock_api_key() -> Option<&'static str> {
+    std::env::var_os(MOCK_RESPONSE_ENV)
+        .is_some_and(|v| !v.is_empty())
+        .then_some("mock")
+}
+
+/// Parse a `--header "Name: Value"` flag, validating it the way `ChatClient` does.
+pub fn parse_header_flag(raw: &str) -> Result<(String, String)> {
+    let (name, value) = raw
+        .split_once(':')
+        .ok_or_else(|| anyhow!("Invalid --header '{}': expected \"Name: Value\"", raw))?;
+    let (name, value) = (name.trim(), value.trim());
+    reqwest::header::HeaderName::from_bytes(name.as_bytes())
+        .map_err(|_| anyhow!("Invalid --header name '{}'", name))?;
+    reqwest::header::HeaderValue::from_str(value)
+        .map_err(|_| anyhow!("Invalid --header value for '{}'", name))?;
+    Ok((name.to_string(), value.to_string()))
+}
+
+/// Parse a `--param key=value` flag. The value is read as JSON when it parses (numbers,
+/// booleans, arrays, objects, null) and kept as a plain string otherwise.
+pub fn parse_param_flag(raw: &str) -> Result<(String, serde_json::Value)> {
+    let (key, value) = raw
+        .split_once('=')
+        .ok_or_else(|| anyhow!("Invalid --param '{}': expected key=value", raw))?;
+    let key = key.trim();
+    if key.is_empty() {
+        return Err(anyhow!("Invalid --param '{}': empty key", raw));
+    }
+    if PROTECTED_BODY_KEYS.contains(&key) {
+        return Err(anyhow!("--param cannot override '{}'", key));
+    }
+    let value = serde_json::from_str(value.trim())
+        .unwrap_or_else(|_| serde_json::Value::String(value.to_string()));
+    Ok((key.to_string(), value))
+}
+
+/// One-line warning when `--temperature` was requested for a GPT-5 model, which only accepts 1.0.
+pub fn ignored_temperature_warning(model: &str, requested: Option<f32>) -> Option<String> {
+    let requested = requested?;
+    ChatClient::is_gpt5_model(model).then(|| {
+        format!(
+            "[warn] GPT-5 model '{}' ignores --temperature {}; sending 1.0 instead.",
+            model, requested
+        )
+    })
+}
+
+/// One-line warning when a `--header` flag sets Authorization, which replaces the bearer key.
+pub fn authorization_override_warning(headers: &[String]) -> Option<String> {
+    headers
+        .iter()
+        .filter_map(|raw| raw.split_once(':'))
+        .any(|(name, _)| name.trim().eq_ignore_ascii_case("authorization"))
+        .then(|| {
+            "[warn] --header replaces the Authorization header; the provider API key is not sent."
+                .to_string()
+        })
+}
+
+/// Build the HTTP client for an HTTP profile; `None` for CLI-backed profiles.
+pub fn build_http_client(eff: &EffectiveProfile) -> Result<Option<ChatClient>> {
+    let Some(conn) = eff.http() else {
+        return Ok(None);
+    };
+    let client = ChatClient::with_network(
+        conn.base_url.clone(),
+        conn.api_key.clone(),
+        conn.headers.clone(),
+        conn.tls.as_ref(),
+        conn.request_timeout_secs.map(Duration::from_secs),
+        &conn.network,
+    )?
+    .with_reasoning_effort(eff.reasoning_effort.clone())
+    .with_reasoning_style(eff.reasoning_style)
+    // The binaries report an ignored --temperature via `run_notices`, so the client's
+    // debug-only warning stays off here.
+    .with_temperature(eff.temperature, false)
+    .with_penalties(eff.frequency_penalty, eff.presence_penalty)
+    .with_extra_body(eff.extra_body.clone())
+    .with_request_params(eff.request_params.clone())
+    .with_plain_text_responses(conn.plain_text_responses)
+    .with_stream_tool_calls(conn.stream_tool_calls)
+    .with_compress_request(conn.compress_request);
+    Ok(Some(client))
+}
+
+/// System and user messages for `qq`.
+pub fn qq_prompts(
+    cfg: &Config,
+    os_type: OsType,
+    shell: ShellKind,
+    history: &[String],
+    stdin_block: Option<&str>,
+    question: &str,
+    context: &ContextOptions<'_>,
+) -> (String, String) {
+    let mut system = build_qq_system_prompt_with(cfg.formatting_instructions);
+    if cfg.no_emoji_enabled() {
+        system.push_str(NO_EMOJI_RULE);
+    }
+    let user = build_qq_user_message(
+        Some(os_type),
+        Some(shell_hint_for_prompt(shell)),
+        history,
+        stdin_block,
+        question,
+        context,
+    );
+    (system, user)
+}
+
+/// System and user messages for `qa`.
+pub fn qa_prompts(
+    cfg: &Config,
+    os_type: OsType,
+    shell: ShellKind,
+    history: &[String],
+    stdin_block: Option<&str>,
+    task: &str,
+    context: &ContextOptions<'_>,
+) -> (String, String) {
+    let mut system = build_qa_system_prompt();
+    if cfg.no_emoji_enabled() {
+        system.push_str(NO_EMOJI_RULE);
+    }
+    let user = build_qa_user_message(
+        Some(os_type),
+        Some(shell_hint_for_prompt(shell)),
+        history,
+        stdin_block,
+        task,
+        context,
+    );
+    (system, user)
+}
+
+fn load_config(config: Option<Config>, debug: bool) -> Result<(Config, Option<PathBuf>)> {
+    match config {
+        Some(cfg) => Ok((cfg, None)),
+        None => {
+            let (cfg, path) = Config::load_or_init(debug)?;
+            Ok((cfg, path.parent().map(Path::to_path_buf)))
+        }
+    }
+}
+
+/// One non-streaming completion from a CLI-backed profile.
+pub async fn cli_completion(
+    eff: &EffectiveProfile,
+    system: &str,
+    user: &str,
+    debug: bool,
+) -> Result<String> {
+    let ProviderConnection::Cli(conn) = &eff.connection else {
+        return Err(anyhow!(
+            "Profile '{}' is not a CLI provider",
+            eff.provider_key
+        ));
+    };
+    let timeout = Duration::from_secs(
+        conn.request_timeout_secs
+            .unwrap_or(DEFAULT_REQUEST_TIMEOUT_SECS),
+    );
+    run_cli_completion(CliCompletionRequest {
+        engine: conn.engine,
+        binary: &conn.binary,
+        base_args: &conn.base_args,
+        system_prompt: system,
+        user_prompt: user,
+        model: &eff.model,
+        reasoning_effort: eff.reasoning_effort.as_deref(),
+        debug,
+        timeout,
+    })
+    .await
+}
@@ -19,6 +19,31 @@ impl ShellKind {
             ShellKind::PowerShell => "Windows PowerShell",
         }
     }
+
+    /// Name accepted by `--shell`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ShellKind::Posix => "posix",
+            ShellKind::CmdExe => "cmd",
+            ShellKind::PowerShell => "powershell",
+        }
+    }
+}
+
+impl std::str::FromStr for ShellKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "posix" | "sh" => Ok(ShellKind::Posix),
+            "cmd" | "cmd.exe" => Ok(ShellKind::CmdExe),
+            "powershell" | "pwsh" => Ok(ShellKind::PowerShell),
+            other => Err(format!(
+                "unknown shell '{}' (expected posix, cmd, or powershell)",
+                other
+            )),
+        }
+    }
 }
 
 /// Infer the shell flavor based on the current OS and environment.
@@ -43,10 +68,10 @@ fn classify_windows_shell(
     has_prompt: bool,
     has_pwsh_markers: bool,
 ) -> ShellKind {
-    if let Some(lower) = shell_env_lower {
-        if lower.contains("bash") || lower.contains("sh") {
-            return ShellKind::Posix;
-        }
+    if let Some(lower) = shell_env_lower
+        && (lower.contains("bash") || lower.contains("sh"))
+    {
+        return ShellKind::Posix;
     }
 
     if !has_prompt && has_pwsh_markers {
@@ -56,6 +81,27 @@ fn classify_windows_shell(
     }
 }
 
+/// Use `forced` (from `--shell`) when given, otherwise infer from the environment.
+/// Returns the shell and whether it was forced.
+pub fn resolve_shell(os_type: OsType, forced: Option<ShellKind>) -> (ShellKind, bool) {
+    match forced {
+        Some(kind) => (kind, true),
+        None => (detect_shell(os_type), false),
+    }
+}
+
+/// One-line `--debug` summary of the detected environment, e.g.
+/// `Environment: os=Linux shell=posix (POSIX sh, detected)`.
+pub fn environment_debug_line(os_type: OsType, shell: ShellKind, forced: bool) -> String {
+    format!(
+        "Environment: os={} shell={} ({}, {})",
+        os_type,
+        shell.as_str(),
+        shell.display_name(),
+        if forced { "from --shell" } else { "detected" }
+    )
+}
+
 pub fn shell_hint_for_prompt(kind: ShellKind) -> &'static str {
     match kind {
         ShellKind::Posix => "POSIX-compatible sh",
@@ -68,6 +114,32 @@ pub fn shell_hint_for_prompt(kind: ShellKind) -> &'static str {
 mod tests {
     use super::*;
 
+    #[test]
+    fn shell_kind_parses_flag_values() {
+        assert_eq!("posix".parse::<ShellKind>(), Ok(ShellKind::Posix));
+        assert_eq!("CMD".parse::<ShellKind>(), Ok(ShellKind::CmdExe));
+        assert_eq!("pwsh".parse::<ShellKind>(), Ok(ShellKind::PowerShell));
+        assert!("fish".parse::<ShellKind>().is_err());
+        for kind in [ShellKind::Posix, ShellKind::CmdExe, ShellKind::PowerShell] {
+            assert_eq!(kind.as_str().parse::<ShellKind>(), Ok(kind));
+        }
+    }
+
+    #[test]
+    fn resolve_shell_prefers_forced_kind() {
+        assert_eq!(
+            resolve_shell(OsType::Linux, Some(ShellKind::PowerShell)),
+            (ShellKind::PowerShell, true)
+        );
+        assert_eq!(
+            resolve_shell(OsType::Linux, None),
+            (ShellKind::Posix, false)
+        );
+        let line = environment_debug_line(OsType::Linux, ShellKind::CmdExe, true);
+        assert!(line.contains("shell=cmd"));
+        assert!(line.contains("from --shell"));
+    }
+
     #[test]
     fn non_windows_defaults_to_posix_even_when_prompt_true() {
         assert_eq!(detect_shell(OsType::Linux), ShellKind::Posix);
@@ -0,0 +1,92 @@
+//! Rough, offline token estimates for `qq --count-tokens`.
+
+use std::fmt;
+
+/// Approximate token count using the ~4 characters per token rule of thumb.
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+/// Estimated tokens per prompt part. `other` covers what the parts don't: the date/OS
+/// header, section labels, and few-shot examples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenBreakdown {
+    pub system: usize,
+    pub history: usize,
+    pub stdin: usize,
+    pub question: usize,
+    pub other: usize,
+    pub total: usize,
+}
+
+impl TokenBreakdown {
+    /// Estimate each part, with `total` taken from the assembled message contents.
+    pub fn estimate(
+        messages: &[&str],
+        system: &str,
+        history: &[String],
+        stdin_block: Option<&str>,
+        question: &str,
+    ) -> Self {
+        let system = estimate_tokens(system);
+        let history: usize = history.iter().map(|entry| estimate_tokens(entry)).sum();
+        let stdin = stdin_block.map(estimate_tokens).unwrap_or(0);
+        let question = estimate_tokens(question);
+        let total: usize = messages.iter().map(|m| estimate_tokens(m)).sum();
+        // Parts trimmed by max_context_bytes can exceed what was actually assembled.
+        let other = total.saturating_sub(system + history + stdin + question);
+        Self {
+            system,
+            history,
+            stdin,
+            question,
+            other,
+            total,
+        }
+    }
+}
+
+impl fmt::Display for TokenBreakdown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Estimated prompt tokens (~4 chars/token, nothing sent):")?;
+        for (label, value) in [
+            ("system", self.system),
+            ("history", self.history),
+            ("stdin", self.stdin),
+            ("question", self.question),
+            ("other", self.other),
+        ] {
+            writeln!(f, "  {:<9}{:>8}", label, value)?;
+        }
+        writeln!(f, "  {:<9}{:>8}", "total", self.total)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimate_rounds_up_per_four_chars() {
+        assert_eq!(estimate_tokens(""), 0);
+        assert_eq!(estimate_tokens("abc"), 1);
+        assert_eq!(estimate_tokens("abcdefgh"), 2);
+        assert_eq!(estimate_tokens("ééééé"), 2);
+    }
+
+    #[test]
+    fn breakdown_attributes_leftovers_to_other() {
+        let history = vec!["qq hello".to_string()];
+        let user = "Header line\nqq hello\npiped\nQuestion: why";
+        let b = TokenBreakdown::estimate(&["sys!", user], "sys!", &history, Some("piped"), "why");
+        assert_eq!((b.system, b.history, b.stdin, b.question), (1, 2, 2, 1));
+        assert_eq!(b.total, 1 + estimate_tokens(user));
+        assert_eq!(b.other, b.total - 6);
+        let text = b.to_string();
+        assert!(text.contains("  question        1\n"), "{text}");
+        assert!(
+            text.ends_with(&format!("  total    {:>8}\n", b.total)),
+            "{text}"
+        );
+    }
+}
@@ -0,0 +1,54 @@
+use crate::perms::{ensure_safe_path, resolve_path};
+use anyhow::{Context, Result};
+use fs_err as fs;
+use serde::Deserialize;
+use std::io::Write;
+use std::path::PathBuf;
+
+/// Tool name the model calls.
+pub const NAME: &str = "append_file";
+
+/// Function description and JSON schema for [`Args`], as sent in the tools spec.
+pub fn spec() -> (&'static str, serde_json::Value) {
+    (
+        "Append UTF-8 text to a file, creating it if missing.",
+        serde_json::json!({
+            "type": "object",
+            "properties": {"path": {"type": "string"}, "content": {"type": "string"}},
+            "required": ["path", "content"]
+        }),
+    )
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Args {
+    pub path: String,
+    pub content: String,
+}
+
+pub fn run(args: Args) -> Result<String> {
+    let path = PathBuf::from(&args.path);
+    ensure_safe_path(&path)?;
+    let resolved = resolve_path(&path)?;
+    if let Some(parent) = resolved.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Creating parent directories for {}", resolved.display()))?;
+    }
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&resolved)
+        .with_context(|| format!("Opening file for append: {}", resolved.display()))?;
+    file.write_all(args.content.as_bytes())
+        .with_context(|| format!("Appending to file: {}", resolved.display()))?;
+    let total = file
+        .metadata()
+        .with_context(|| format!("Reading metadata for {}", resolved.display()))?
+        .len();
+    Ok(format!(
+        "Appended {} bytes to {} (now {} bytes)",
+        args.content.len(),
+        path.display(),
+        total
+    ))
+}
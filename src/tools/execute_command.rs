@@ -1,11 +1,17 @@
-use crate::perms::{CommandDisposition, ensure_safe_command};
+use crate::config::Config;
+use crate::perms::{self, CommandDisposition, ensure_safe_command};
 use crate::shell::ShellKind;
+use crate::tools::output_encoding::{OutputEncoding, decode_output};
+use crate::{debug_log, logging};
 use anyhow::{Context, Result, anyhow};
 use atty::Stream;
+use nu_ansi_term::Color;
 use serde::Deserialize;
 use std::io::{self, Read, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
 use tokio::io::{AsyncRead, AsyncReadExt, BufReader};
 use tokio::process::Command;
 use tokio::sync::mpsc;
@@ -16,6 +22,129 @@ use portable_pty::Child as PtyChild;
 #[cfg(unix)]
 use portable_pty::{CommandBuilder, PtySize, native_pty_system};
 
+/// Default wall-clock limit for a single command, independent of the HTTP request timeout.
+pub const DEFAULT_COMMAND_TIMEOUT_SECS: u64 = 120;
+
+/// Shell used for `ShellKind::Posix` commands unless configured otherwise.
+pub const DEFAULT_POSIX_SHELL: &str = "sh";
+
+fn posix_shell_override() -> &'static Mutex<Option<String>> {
+    static SHELL: OnceLock<Mutex<Option<String>>> = OnceLock::new();
+    SHELL.get_or_init(|| Mutex::new(None))
+}
+
+/// Set the shell binary (e.g. `bash`, `/bin/zsh`) that POSIX commands run under via `-lc`.
+/// `None` or a blank value restores the default `sh`.
+pub fn set_posix_shell(shell: Option<String>) {
+    let shell = shell
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty());
+    *posix_shell_override().lock().unwrap() = shell;
+}
+
+/// The shell POSIX commands currently run under.
+pub fn posix_shell() -> String {
+    posix_shell_override()
+        .lock()
+        .unwrap()
+        .clone()
+        .unwrap_or_else(|| DEFAULT_POSIX_SHELL.to_string())
+}
+
+static AUTO_CONFIRM_SAFE: AtomicBool = AtomicBool::new(false);
+
+/// Skip the "Execute?" prompt for commands whose disposition is `Allowed`. Commands that
+/// need confirmation still prompt, and so does a working directory outside home.
+pub fn set_auto_confirm_safe(enabled: bool) {
+    AUTO_CONFIRM_SAFE.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether [`set_auto_confirm_safe`] is on.
+pub fn auto_confirm_safe() -> bool {
+    AUTO_CONFIRM_SAFE.load(Ordering::Relaxed)
+}
+
+static ALLOW_CWD_OUTSIDE_HOME: AtomicBool = AtomicBool::new(false);
+
+/// Skip the "Proceed anyway?" prompt for a working directory outside home. The warning
+/// still prints, and the command safety checks and "Execute?" prompt are unchanged.
+pub fn set_allow_cwd_outside_home(enabled: bool) {
+    ALLOW_CWD_OUTSIDE_HOME.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether [`set_allow_cwd_outside_home`] is on.
+pub fn allow_cwd_outside_home() -> bool {
+    ALLOW_CWD_OUTSIDE_HOME.load(Ordering::Relaxed)
+}
+
+/// Per-run PTY choice (`qa --pty` / `--no-pty`), ahead of `QQQA_FORCE_PTY` and
+/// `QQQA_DISABLE_PTY`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PtyMode {
+    /// Follow the env vars, then use a PTY when stdout is a terminal.
+    #[default]
+    Auto,
+    ForcePty,
+    DisablePty,
+}
+
+impl PtyMode {
+    /// `Some(true)` for `--pty`, `Some(false)` for `--no-pty`, `None` for neither.
+    pub fn from_flag(pty: Option<bool>) -> Self {
+        match pty {
+            Some(true) => PtyMode::ForcePty,
+            Some(false) => PtyMode::DisablePty,
+            None => PtyMode::Auto,
+        }
+    }
+}
+
+fn pty_mode_override() -> &'static Mutex<PtyMode> {
+    static MODE: OnceLock<Mutex<PtyMode>> = OnceLock::new();
+    MODE.get_or_init(|| Mutex::new(PtyMode::Auto))
+}
+
+/// Set whether POSIX commands run in a PTY for the rest of this process.
+pub fn set_pty_mode(mode: PtyMode) {
+    *pty_mode_override().lock().unwrap() = mode;
+}
+
+fn windows_output_encoding_override() -> &'static Mutex<OutputEncoding> {
+    static ENCODING: OnceLock<Mutex<OutputEncoding>> = OnceLock::new();
+    ENCODING.get_or_init(|| Mutex::new(OutputEncoding::default()))
+}
+
+/// Set how cmd.exe/PowerShell output is decoded when it carries no BOM (default UTF-8).
+pub fn set_windows_output_encoding(encoding: OutputEncoding) {
+    *windows_output_encoding_override().lock().unwrap() = encoding;
+}
+
+/// Decode captured output: POSIX shells are read as UTF-8, Windows shells honor a BOM and
+/// then `windows_output_encoding`.
+fn decode_command_output(bytes: &[u8], shell: ShellKind) -> String {
+    match shell {
+        ShellKind::Posix => String::from_utf8_lossy(bytes).into_owned(),
+        ShellKind::CmdExe | ShellKind::PowerShell => {
+            decode_output(bytes, *windows_output_encoding_override().lock().unwrap())
+        }
+    }
+}
+
+/// Tool name the model calls.
+pub const NAME: &str = "execute_command";
+
+/// Function description and JSON schema for [`Args`], as sent in the tools spec.
+pub fn spec() -> (&'static str, serde_json::Value) {
+    (
+        "Run a safe shell command in the workspace/home.",
+        serde_json::json!({
+            "type": "object",
+            "properties": {"command": {"type": "string"}, "cwd": {"type": "string"}},
+            "required": ["command"]
+        }),
+    )
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct Args {
     pub command: String,
@@ -40,10 +169,28 @@ pub async fn run(
     auto_yes: bool,
     debug: bool,
     shell: ShellKind,
+    command_timeout: Duration,
+    on_chunk: Option<&mut dyn for<'chunk> FnMut(StreamChunk<'chunk>)>,
+) -> Result<String> {
+    let command = args.command.clone();
+    let run = confirm_and_run(args, auto_yes, debug, shell, command_timeout, on_chunk);
+    logging::traced("command", &command, run).await
+}
+
+async fn confirm_and_run(
+    args: Args,
+    auto_yes: bool,
+    debug: bool,
+    shell: ShellKind,
+    command_timeout: Duration,
     mut on_chunk: Option<&mut dyn for<'chunk> FnMut(StreamChunk<'chunk>)>,
 ) -> Result<String> {
     let cwd = args.cwd.as_deref().unwrap_or(".");
-    eprintln!("Proposed command: {}", &args.command);
+    let color = crate::formatting::color_stderr_from_env();
+    eprintln!(
+        "Proposed command: {}",
+        paint(&args.command, Color::Green, color)
+    );
     eprintln!("Working directory: {}", cwd);
 
     let disposition = match ensure_safe_command(&args.command) {
@@ -56,8 +203,11 @@ pub async fn run(
     let mut requires_manual_confirmation = false;
     if let CommandDisposition::NeedsConfirmation { reason } = &disposition {
         requires_manual_confirmation = true;
-        eprintln!("[warn] {}", reason);
+        eprintln!("[warn] {}", paint(reason, Color::Yellow, color));
     }
+    // sanitize_cwd_path keeps in-bounds relative dirs even when they are missing; catch that
+    // here instead of surfacing an opaque spawn error after the confirmation prompt.
+    ensure_working_dir(Path::new(cwd))?;
     // Additional confirmation if cwd is outside the user's home directory
     if let Some(home) = dirs::home_dir() {
         use std::path::Path;
@@ -65,23 +215,28 @@ pub async fn run(
         // Best effort: canonicalize if possible, else use provided path
         let resolved = std::fs::canonicalize(cwd_path).unwrap_or_else(|_| cwd_path.to_path_buf());
         if !resolved.starts_with(&home) {
-            eprintln!(
+            let warning = format!(
                 "Warning: working directory is outside your home: {}",
                 resolved.display()
             );
-            if !auto_yes {
-                if !prompt_yes_no("Proceed anyway? [y/N]: ")? {
-                    return Err(anyhow!("Execution canceled by user (cwd outside home)"));
-                }
-            } else if debug {
-                eprintln!("[debug] Proceeding due to --yes flag");
+            eprintln!("{}", paint(&warning, Color::Yellow, color));
+            if auto_yes {
+                debug_log!(debug, "Proceeding due to --yes flag");
+            } else if allow_cwd_outside_home() {
+                debug_log!(debug, "Proceeding: cwd outside home is allowed");
+            } else if !prompt_yes_no("Proceed anyway? [y/N]: ")? {
+                return Err(anyhow!("Execution canceled by user (cwd outside home)"));
             }
         }
     }
-    if requires_manual_confirmation || !auto_yes {
-        if !prompt_yes_no("Execute? [y/N]: ")? {
-            return Err(anyhow!("Execution canceled by user"));
-        }
+    let execute_prompt = if requires_manual_confirmation {
+        format!("{} [y/N]: ", paint("Execute?", Color::Yellow, color))
+    } else {
+        "Execute? [y/N]: ".to_string()
+    };
+    let skip_prompt = auto_yes || (auto_confirm_safe() && !requires_manual_confirmation);
+    if (requires_manual_confirmation || !skip_prompt) && !prompt_yes_no(&execute_prompt)? {
+        return Err(anyhow!("Execution canceled by user"));
     }
 
     let child = spawn_child(&args.command, cwd, shell, debug)?;
@@ -120,27 +275,29 @@ pub async fn run(
         Ok::<(), anyhow::Error>(())
     };
 
-    match timeout(Duration::from_secs(120), collect_future).await {
+    match timeout(command_timeout, collect_future).await {
         Ok(res) => res?,
         Err(_) => {
             killswitch.terminate().await?;
             let _ = killswitch.wait().await;
-            return Err(anyhow!("Command timed out after 120s"));
+            return Err(anyhow!(
+                "Command timed out after {}s",
+                command_timeout.as_secs()
+            ));
         }
     }
 
     let code = killswitch.wait().await?;
-    let stdout = String::from_utf8_lossy(&stdout_buf).to_string();
-    let stderr = String::from_utf8_lossy(&stderr_buf).to_string();
+    let stdout = decode_command_output(&stdout_buf, shell);
+    let stderr = decode_command_output(&stderr_buf, shell);
 
-    if debug {
-        eprintln!(
-            "[debug] exit code: {} (stdout {} bytes, stderr {} bytes)",
-            code,
-            stdout.len(),
-            stderr.len()
-        );
-    }
+    debug_log!(
+        debug,
+        "exit code: {} (stdout {} bytes, stderr {} bytes)",
+        code,
+        stdout.len(),
+        stderr.len()
+    );
 
     let mut summary = String::new();
     summary.push_str(&format!("Exit code: {}\n", code));
@@ -157,6 +314,101 @@ pub async fn run(
     Ok(summary)
 }
 
+/// Pin an execute_command request to `base_dir`: relative cwds must stay under it and
+/// absolute cwds must canonicalize inside it (or inside an `allowed_exec_roots` entry);
+/// anything else falls back to `base_dir`.
+pub fn sanitize_execute_args(args: Args, base_dir: &Path, debug: bool) -> Args {
+    let (sanitized_path, fell_back) = sanitize_cwd_path(args.cwd.as_deref(), base_dir);
+    let sanitized_str = sanitized_path.to_string_lossy().to_string();
+
+    if fell_back {
+        if let Some(original) = args.cwd.as_deref() {
+            debug_log!(
+                debug,
+                "Ignoring requested working directory '{}' and using {} instead",
+                original,
+                sanitized_str
+            );
+        } else {
+            debug_log!(
+                debug,
+                "Using current working directory {} for execute_command",
+                sanitized_str
+            );
+        }
+    }
+
+    Args {
+        command: args.command,
+        cwd: Some(sanitized_str),
+    }
+}
+
+/// Fail with a readable message when `cwd` is missing or is not a directory.
+fn ensure_working_dir(cwd: &Path) -> Result<()> {
+    match std::fs::metadata(cwd) {
+        Ok(meta) if meta.is_dir() => Ok(()),
+        Ok(_) => Err(anyhow!(
+            "Working directory is not a directory: {}",
+            cwd.display()
+        )),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Err(anyhow!(
+            "Working directory does not exist: {}",
+            cwd.display()
+        )),
+        Err(err) => Err(anyhow!(
+            "Cannot use working directory {}: {}",
+            cwd.display(),
+            err
+        )),
+    }
+}
+
+/// Returns the resolved cwd and whether the requested value was rejected.
+/// Directories under a configured `allowed_exec_roots` entry are accepted as well.
+pub fn sanitize_cwd_path(requested: Option<&str>, base_dir: &Path) -> (PathBuf, bool) {
+    let base = base_dir.to_path_buf();
+    let Some(raw) = requested else {
+        return (base, false);
+    };
+    let trimmed = raw.trim();
+    if trimmed.is_empty() {
+        return (base, false);
+    }
+    let path = Path::new(trimmed);
+    if path.is_absolute() {
+        if let Ok(canon) = std::fs::canonicalize(path)
+            && (canon.starts_with(base_dir) || perms::is_within_exec_roots(&canon))
+        {
+            return (canon, false);
+        }
+        return (base, true);
+    }
+    if let Ok(canon) = std::fs::canonicalize(base_dir.join(path))
+        && !canon.starts_with(base_dir)
+        && perms::is_within_exec_roots(&canon)
+    {
+        return (canon, false);
+    }
+
+    let mut relative = PathBuf::new();
+    for component in path.components() {
+        match component {
+            std::path::Component::CurDir => {}
+            std::path::Component::Normal(seg) => relative.push(seg),
+            std::path::Component::ParentDir
+            | std::path::Component::RootDir
+            | std::path::Component::Prefix(_) => {
+                return (base, true);
+            }
+        }
+    }
+    if relative.as_os_str().is_empty() {
+        return (base, false);
+    }
+    (base_dir.join(relative), false)
+}
+
 enum StreamMessage {
     Stdout(Vec<u8>),
     Stderr(Vec<u8>),
@@ -200,22 +452,46 @@ impl KillSwitch {
         Ok(())
     }
 
-    async fn wait(self) -> Result<i32> {
-        match self.inner {
-            KillSwitchInner::Plain(Some(mut child)) => {
-                let status = child.wait().await?;
-                Ok(status.code().unwrap_or(-1))
-            }
-            KillSwitchInner::Plain(None) => Ok(-1),
+    async fn wait(mut self) -> Result<i32> {
+        match &mut self.inner {
+            KillSwitchInner::Plain(child_opt) => match child_opt.as_mut() {
+                Some(child) => {
+                    let status = child.wait().await?;
+                    child_opt.take();
+                    Ok(status.code().unwrap_or(-1))
+                }
+                None => Ok(-1),
+            },
             #[cfg(unix)]
-            KillSwitchInner::Pty(Some(mut child)) => {
-                let status = tokio::task::spawn_blocking(move || child.wait())
-                    .await
-                    .context("Joining PTY wait task failed")??;
-                Ok(convert_pty_exit_status(status))
+            KillSwitchInner::Pty(child_opt) => match child_opt.take() {
+                Some(mut child) => {
+                    let status = tokio::task::spawn_blocking(move || child.wait())
+                        .await
+                        .context("Joining PTY wait task failed")??;
+                    Ok(convert_pty_exit_status(status))
+                }
+                None => Ok(-1),
+            },
+        }
+    }
+}
+
+impl Drop for KillSwitch {
+    /// Kill a still-running child when the collector is abandoned (timeout, Ctrl-C, or a
+    /// dropped future) so commands never outlive the qa invocation that started them.
+    fn drop(&mut self) {
+        match &mut self.inner {
+            KillSwitchInner::Plain(child_opt) => {
+                if let Some(child) = child_opt.as_mut() {
+                    let _ = child.start_kill();
+                }
             }
             #[cfg(unix)]
-            KillSwitchInner::Pty(None) => Ok(-1),
+            KillSwitchInner::Pty(child_opt) => {
+                if let Some(child) = child_opt.as_mut() {
+                    let _ = child.kill();
+                }
+            }
         }
     }
 }
@@ -231,22 +507,23 @@ fn spawn_child(command: &str, cwd: &str, shell: ShellKind, debug: bool) -> Resul
 fn spawn_posix_child(command: &str, cwd: &str, debug: bool) -> Result<ChildProcess> {
     #[cfg(unix)]
     {
-        if should_use_pty() {
+        if should_use_pty(*pty_mode_override().lock().unwrap()) {
             match spawn_child_with_pty(command, cwd) {
                 Ok(proc) => return Ok(proc),
                 Err(err) => {
-                    if debug {
-                        eprintln!("[debug] PTY spawn failed ({}); falling back to pipes", err);
-                    }
+                    debug_log!(debug, "PTY spawn failed ({}); falling back to pipes", err);
                 }
             }
         }
     }
 
-    let mut cmd = Command::new("sh");
+    let shell = posix_shell();
+    let mut cmd = Command::new(&shell);
     cmd.arg("-lc").arg(command);
     configure_stdio(&mut cmd, cwd);
-    let child = cmd.spawn().context("Failed to spawn command via sh")?;
+    let child = cmd
+        .spawn()
+        .with_context(|| format!("Failed to spawn command via {}", shell))?;
     Ok(ChildProcess::Plain(child))
 }
 
@@ -390,22 +667,69 @@ fn convert_pty_exit_status(status: portable_pty::ExitStatus) -> i32 {
     status.exit_code() as i32
 }
 
+/// PTY rows and columns when neither the terminal nor `QQQA_PTY_ROWS`/`QQQA_PTY_COLS` give one.
+#[cfg(unix)]
+const DEFAULT_PTY_SIZE: (u16, u16) = (24, 80);
+
+/// `TERM` for PTY commands when the parent environment has none.
+#[cfg(unix)]
+const DEFAULT_PTY_TERM: &str = "xterm-256color";
+
+/// Size of the terminal qa runs in, falling back to [`DEFAULT_PTY_SIZE`]; positive
+/// `QQQA_PTY_ROWS`/`QQQA_PTY_COLS` values override either dimension.
+#[cfg(unix)]
+fn pty_size() -> PtySize {
+    let (rows, cols) = terminal_size().unwrap_or(DEFAULT_PTY_SIZE);
+    PtySize {
+        rows: env_dimension("QQQA_PTY_ROWS").unwrap_or(rows),
+        cols: env_dimension("QQQA_PTY_COLS").unwrap_or(cols),
+        pixel_width: 0,
+        pixel_height: 0,
+    }
+}
+
+/// Rows and columns of the first of stdout, stderr, or stdin that is a terminal.
+#[cfg(unix)]
+fn terminal_size() -> Option<(u16, u16)> {
+    [libc::STDOUT_FILENO, libc::STDERR_FILENO, libc::STDIN_FILENO]
+        .into_iter()
+        .find_map(|fd| {
+            let mut size = libc::winsize {
+                ws_row: 0,
+                ws_col: 0,
+                ws_xpixel: 0,
+                ws_ypixel: 0,
+            };
+            // SAFETY: TIOCGWINSZ only writes a `winsize` through the pointer we pass.
+            let rc = unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut size) };
+            (rc == 0 && size.ws_row > 0 && size.ws_col > 0).then_some((size.ws_row, size.ws_col))
+        })
+}
+
+#[cfg(unix)]
+fn env_dimension(name: &str) -> Option<u16> {
+    std::env::var(name)
+        .ok()?
+        .trim()
+        .parse::<u16>()
+        .ok()
+        .filter(|n| *n > 0)
+}
+
 #[cfg(unix)]
 fn spawn_child_with_pty(command: &str, cwd: &str) -> Result<ChildProcess> {
     let pty_system = native_pty_system();
     let pair = pty_system
-        .openpty(PtySize {
-            rows: 24,
-            cols: 80,
-            pixel_width: 0,
-            pixel_height: 0,
-        })
+        .openpty(pty_size())
         .context("failed to open PTY")?;
 
-    let mut builder = CommandBuilder::new("sh");
+    let mut builder = CommandBuilder::new(posix_shell());
     builder.arg("-lc");
     builder.arg(command);
     builder.cwd(PathBuf::from(cwd));
+    if builder.get_env("TERM").is_none_or(|term| term.is_empty()) {
+        builder.env("TERM", DEFAULT_PTY_TERM);
+    }
 
     let child = pair
         .slave
@@ -422,7 +746,12 @@ fn spawn_child_with_pty(command: &str, cwd: &str) -> Result<ChildProcess> {
 }
 
 #[cfg(unix)]
-fn should_use_pty() -> bool {
+fn should_use_pty(mode: PtyMode) -> bool {
+    match mode {
+        PtyMode::ForcePty => return true,
+        PtyMode::DisablePty => return false,
+        PtyMode::Auto => {}
+    }
     let force = matches!(
         std::env::var("QQQA_FORCE_PTY")
             .ok()
@@ -439,13 +768,17 @@ fn should_use_pty() -> bool {
 }
 
 #[cfg(not(unix))]
-fn should_use_pty() -> bool {
-    let force = matches!(
-        std::env::var("QQQA_FORCE_PTY")
-            .ok()
-            .map(|v| v.to_lowercase()),
-        Some(ref v) if v == "1" || v == "true" || v == "yes"
-    );
+fn should_use_pty(mode: PtyMode) -> bool {
+    let force = match mode {
+        PtyMode::ForcePty => true,
+        PtyMode::DisablePty => false,
+        PtyMode::Auto => matches!(
+            std::env::var("QQQA_FORCE_PTY")
+                .ok()
+                .map(|v| v.to_lowercase()),
+            Some(ref v) if v == "1" || v == "true" || v == "yes"
+        ),
+    };
     force && atty::is(Stream::Stdout)
 }
 
@@ -502,3 +835,192 @@ fn prompt_yes_no(prompt: &str) -> Result<bool> {
 fn is_yes(input: &str) -> bool {
     matches!(input.trim().to_lowercase().as_str(), "y" | "yes")
 }
+
+/// How [`run_with_allowlist_prompt`] runs a command and shows its output.
+#[derive(Debug, Clone, Copy)]
+pub struct InteractiveOptions {
+    pub auto_yes: bool,
+    pub debug: bool,
+    pub shell: ShellKind,
+    pub command_timeout: Duration,
+    /// Paint live stderr chunks so they stand apart from stdout.
+    pub color_stderr: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct ExecuteCommandResult {
+    pub summary: String,
+    /// Output was already printed while the command ran.
+    pub streamed_live: bool,
+}
+
+/// Run a command from the current directory, streaming its output. When the program is
+/// not allowlisted and a person is at the terminal, offer to allow it (saved to the config's
+/// allowlist, or for this process only) and retry.
+pub async fn run_with_allowlist_prompt(
+    args: Args,
+    opts: InteractiveOptions,
+    cfg: &mut Config,
+    cfg_path: &Path,
+) -> Result<ExecuteCommandResult> {
+    let debug = opts.debug;
+    let mut base_dir = std::env::current_dir().context("Failed to read current directory")?;
+    if let Ok(canon) = base_dir.canonicalize() {
+        base_dir = canon;
+    }
+
+    let original_args = args;
+    loop {
+        let mut stream_printer = |chunk: StreamChunk| match chunk.kind {
+            StreamKind::Stdout => {
+                let mut handle = io::stdout();
+                let _ = handle.write_all(chunk.data);
+                let _ = handle.flush();
+            }
+            // PTY runs merge both streams into Stdout, so this only applies to piped runs.
+            StreamKind::Stderr => {
+                let mut handle = io::stderr();
+                if opts.color_stderr {
+                    let _ = handle.write_all(&paint_stderr_chunk(chunk.data));
+                } else {
+                    let _ = handle.write_all(chunk.data);
+                }
+                let _ = handle.flush();
+            }
+        };
+        let on_chunk: Option<&mut dyn for<'chunk> FnMut(StreamChunk<'chunk>)> =
+            Some(&mut stream_printer);
+        let streaming_enabled = on_chunk.is_some();
+        let exec_args = sanitize_execute_args(original_args.clone(), &base_dir, debug);
+        match run(
+            exec_args,
+            opts.auto_yes,
+            debug,
+            opts.shell,
+            opts.command_timeout,
+            on_chunk,
+        )
+        .await
+        {
+            Ok(summary) => {
+                return Ok(ExecuteCommandResult {
+                    summary,
+                    streamed_live: streaming_enabled,
+                });
+            }
+            Err(err) => {
+                let Some(program) = err
+                    .downcast_ref::<perms::CommandNotAllowedError>()
+                    .map(|e| e.program.clone())
+                else {
+                    return Err(err);
+                };
+                if !atty::is(Stream::Stdin) {
+                    return Err(err);
+                }
+                match prompt_add_command_to_allowlist(&program)? {
+                    AllowlistChoice::Deny => return Err(err),
+                    AllowlistChoice::Once => {
+                        perms::extend_custom_allowlist([program.clone()]);
+                        debug_log!(debug, "Allowed '{}' for this run only", program);
+                        continue;
+                    }
+                    AllowlistChoice::Persist => {}
+                }
+                let inserted = cfg.add_command_to_allowlist(&program);
+                if inserted {
+                    cfg.save(cfg_path, debug)?;
+                }
+                perms::extend_custom_allowlist([program.clone()]);
+                if inserted {
+                    if debug {
+                        debug_log!(debug, "Added '{}' to qa command allowlist", program);
+                    } else {
+                        eprintln!("Added '{}' to qa command allowlist.", program);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// `text` in `color` when `enabled`, unchanged otherwise.
+fn paint(text: &str, color: Color, enabled: bool) -> String {
+    if enabled {
+        color.paint(text).to_string()
+    } else {
+        text.to_string()
+    }
+}
+
+/// Wrap a raw stderr chunk in dim red ANSI codes for the live view.
+fn paint_stderr_chunk(data: &[u8]) -> Vec<u8> {
+    let style = Color::Red.dimmed();
+    let mut out = style.prefix().to_string().into_bytes();
+    out.extend_from_slice(data);
+    out.extend_from_slice(style.suffix().to_string().as_bytes());
+    out
+}
+
+/// Answer to the "not in the allowlist" prompt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AllowlistChoice {
+    /// Save to the config's `command_allowlist` and retry.
+    Persist,
+    /// Allow for the rest of this process only; nothing is written to disk.
+    Once,
+    Deny,
+}
+
+fn parse_allowlist_choice(answer: &str) -> AllowlistChoice {
+    match answer.trim().to_ascii_lowercase().as_str() {
+        "y" | "yes" => AllowlistChoice::Persist,
+        "o" | "once" => AllowlistChoice::Once,
+        _ => AllowlistChoice::Deny,
+    }
+}
+
+fn prompt_add_command_to_allowlist(program: &str) -> Result<AllowlistChoice> {
+    eprint!(
+        "Command '{}' is not in the qa allowlist. Allow it and retry? [y]es, save to config / [o]nce, this run only / [N]o: ",
+        program
+    );
+    io::stderr().flush().context("Failed to flush prompt")?;
+    let mut line = String::new();
+    io::stdin()
+        .read_line(&mut line)
+        .context("Failed to read response")?;
+    Ok(parse_allowlist_choice(&line))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paint_adds_ansi_codes_only_when_enabled() {
+        assert_eq!(paint("ls -la", Color::Green, false), "ls -la");
+        let colored = paint("ls -la", Color::Green, true);
+        assert!(colored.starts_with("\u{1b}["), "{:?}", colored);
+        assert!(colored.contains("ls -la"));
+    }
+
+    #[test]
+    fn allowlist_prompt_answers_map_to_scopes() {
+        assert_eq!(parse_allowlist_choice("y\n"), AllowlistChoice::Persist);
+        assert_eq!(parse_allowlist_choice(" YES "), AllowlistChoice::Persist);
+        assert_eq!(parse_allowlist_choice("o"), AllowlistChoice::Once);
+        assert_eq!(parse_allowlist_choice("once\n"), AllowlistChoice::Once);
+        assert_eq!(parse_allowlist_choice(""), AllowlistChoice::Deny);
+        assert_eq!(parse_allowlist_choice("nope"), AllowlistChoice::Deny);
+    }
+
+    #[test]
+    fn paint_stderr_chunk_wraps_bytes_in_dim_red() {
+        let painted = paint_stderr_chunk(b"oops\n");
+        let text = String::from_utf8(painted).unwrap();
+        assert!(text.starts_with("\x1b["));
+        assert!(text.contains("oops\n"));
+        assert!(text.ends_with("\x1b[0m"));
+    }
+}
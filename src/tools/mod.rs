@@ -1,8 +1,11 @@
 use anyhow::{Context, Result, anyhow};
 use serde::Deserialize;
 
+pub mod append_file;
 pub mod execute_command;
+pub mod output_encoding;
 pub mod read_file;
+pub mod read_files;
 pub mod write_file;
 
 #[derive(Debug, Deserialize)]
@@ -14,27 +17,317 @@ pub struct ToolEnvelope {
 #[derive(Debug)]
 pub enum ToolCall {
     ReadFile(read_file::Args),
+    ReadFiles(read_files::Args),
     WriteFile(write_file::Args),
+    AppendFile(append_file::Args),
     ExecuteCommand(execute_command::Args),
 }
 
+/// One qa tool. [`TOOLS`] is the single list the tools spec, `--tools` validation, and
+/// both call parsers read from, so a new tool only needs an entry here and a dispatch arm.
+pub struct ToolDef {
+    pub name: &'static str,
+    /// Description and parameter schema sent to the model.
+    pub spec: fn() -> (&'static str, serde_json::Value),
+    parse_args: fn(serde_json::Value) -> serde_json::Result<ToolCall>,
+}
+
+/// Every tool qa can run, in spec order.
+pub const TOOLS: &[ToolDef] = &[
+    ToolDef {
+        name: read_file::NAME,
+        spec: read_file::spec,
+        parse_args: |v| serde_json::from_value(v).map(ToolCall::ReadFile),
+    },
+    ToolDef {
+        name: read_files::NAME,
+        spec: read_files::spec,
+        parse_args: |v| serde_json::from_value(v).map(ToolCall::ReadFiles),
+    },
+    ToolDef {
+        name: write_file::NAME,
+        spec: write_file::spec,
+        parse_args: |v| serde_json::from_value(v).map(ToolCall::WriteFile),
+    },
+    ToolDef {
+        name: append_file::NAME,
+        spec: append_file::spec,
+        parse_args: |v| serde_json::from_value(v).map(ToolCall::AppendFile),
+    },
+    ToolDef {
+        name: execute_command::NAME,
+        spec: execute_command::spec,
+        parse_args: |v| serde_json::from_value(v).map(ToolCall::ExecuteCommand),
+    },
+];
+
+/// Names of [`TOOLS`], in spec order.
+pub const TOOL_NAMES: [&str; TOOLS.len()] = {
+    let mut names = [""; TOOLS.len()];
+    let mut i = 0;
+    while i < TOOLS.len() {
+        names[i] = TOOLS[i].name;
+        i += 1;
+    }
+    names
+};
+
+/// Name of the wrapper function some models use to nest a call as `{tool, arguments}`.
+const JSON_WRAPPER: &str = "json";
+
+fn find_tool(name: &str) -> Option<&'static ToolDef> {
+    TOOLS.iter().find(|tool| tool.name == name)
+}
+
+impl ToolCall {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ToolCall::ReadFile(_) => read_file::NAME,
+            ToolCall::ReadFiles(_) => read_files::NAME,
+            ToolCall::WriteFile(_) => write_file::NAME,
+            ToolCall::AppendFile(_) => append_file::NAME,
+            ToolCall::ExecuteCommand(_) => execute_command::NAME,
+        }
+    }
+}
+
+/// Whether `name` passes a `--tools` filter (`None` allows every tool).
+pub fn tool_enabled(filter: Option<&[String]>, name: &str) -> bool {
+    filter.is_none_or(|tools| tools.iter().any(|t| t == name))
+}
+
+/// Parse a comma-separated `--tools` list, rejecting names qa does not know.
+pub fn parse_tool_filter(list: &str) -> Result<Vec<String>> {
+    let mut tools = Vec::new();
+    for name in list.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+        if !TOOL_NAMES.contains(&name) {
+            return Err(anyhow!(
+                "Unknown tool '{}' in --tools (available: {})",
+                name,
+                TOOL_NAMES.join(", ")
+            ));
+        }
+        if !tools.iter().any(|t| t == name) {
+            tools.push(name.to_string());
+        }
+    }
+    if tools.is_empty() {
+        return Err(anyhow!("--tools needs at least one tool name"));
+    }
+    Ok(tools)
+}
+
 /// Try to parse a tool call JSON from assistant content.
 pub fn parse_tool_call(json_text: &str) -> Result<ToolCall> {
     let env: ToolEnvelope = serde_json::from_str(json_text)
         .with_context(|| "Assistant response was not a tool JSON object")?;
-    match env.tool.as_str() {
-        "read_file" => {
-            let args: read_file::Args = serde_json::from_value(env.arguments)?;
-            Ok(ToolCall::ReadFile(args))
+    let tool = find_tool(&env.tool).ok_or_else(|| anyhow!("Unknown tool: {}", env.tool))?;
+    Ok((tool.parse_args)(env.arguments)?)
+}
+
+/// OpenAI-style function specs for every qa tool, so APIs can emit structured tool_calls.
+pub fn tools_spec() -> serde_json::Value {
+    let mut specs: Vec<serde_json::Value> = TOOLS
+        .iter()
+        .map(|tool| {
+            let (description, parameters) = (tool.spec)();
+            function_spec(tool.name, description, parameters)
+        })
+        .collect();
+    specs.push(function_spec(
+        JSON_WRAPPER,
+        r#"Wrap another tool call as { "tool": string, "arguments": object }."#,
+        serde_json::json!({
+            "type": "object",
+            "properties": {
+                "tool": {"type": "string"},
+                "arguments": {"type": "object"}
+            },
+            "required": ["tool", "arguments"]
+        }),
+    ));
+    serde_json::Value::Array(specs)
+}
+
+fn function_spec(
+    name: &str,
+    description: &str,
+    parameters: serde_json::Value,
+) -> serde_json::Value {
+    serde_json::json!({
+        "type": "function",
+        "function": {
+            "name": name,
+            "description": description,
+            "parameters": parameters
         }
-        "write_file" => {
-            let args: write_file::Args = serde_json::from_value(env.arguments)?;
-            Ok(ToolCall::WriteFile(args))
+    })
+}
+
+/// `tools_spec` limited to `allowed` (plus the `json` wrapper); `None` keeps every tool.
+pub fn tools_spec_filtered(allowed: Option<&[String]>) -> serde_json::Value {
+    let spec = tools_spec();
+    let Some(allowed) = allowed else {
+        return spec;
+    };
+    let serde_json::Value::Array(entries) = spec else {
+        return spec;
+    };
+    let kept = entries
+        .into_iter()
+        .filter(|entry| {
+            entry
+                .pointer("/function/name")
+                .and_then(|v| v.as_str())
+                .is_some_and(|name| name == JSON_WRAPPER || allowed.iter().any(|a| a == name))
+        })
+        .collect();
+    serde_json::Value::Array(kept)
+}
+
+/// Add an optional `reason` string to every function's parameters, for `qa --explain`.
+/// The `json` wrapper is left alone; its inner arguments carry the reason instead.
+pub fn tools_spec_with_reason(mut spec: serde_json::Value) -> serde_json::Value {
+    if let serde_json::Value::Array(entries) = &mut spec {
+        for entry in entries {
+            if entry.pointer("/function/name").and_then(|v| v.as_str()) == Some(JSON_WRAPPER) {
+                continue;
+            }
+            if let Some(props) = entry
+                .pointer_mut("/function/parameters/properties")
+                .and_then(|v| v.as_object_mut())
+            {
+                props.insert(
+                    "reason".into(),
+                    serde_json::json!({
+                        "type": "string",
+                        "description": "One short sentence: what this call does and why."
+                    }),
+                );
+            }
+        }
+    }
+    spec
+}
+
+/// The optional `reason` the model attached to a tool call, either at the top level of a
+/// plain-JSON envelope or inside its arguments. Blank reasons count as absent.
+pub fn tool_call_reason(json_text: &str) -> Option<String> {
+    let value: serde_json::Value = serde_json::from_str(json_text.trim()).ok()?;
+    let reason = value
+        .get("reason")
+        .or_else(|| value.pointer("/arguments/reason"))?
+        .as_str()?
+        .trim();
+    (!reason.is_empty()).then(|| reason.to_string())
+}
+
+/// One step of a `qa --plan` preview.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlanStep {
+    /// What the step does, in the model's words (may be empty).
+    pub description: String,
+    pub tool: String,
+    pub arguments: serde_json::Value,
+}
+
+/// Parse a `qa --plan` reply: a JSON array of `{step, tool, arguments}` objects, optionally
+/// inside a Markdown code fence or a `{"plan": [...]}` / `{"steps": [...]}` object.
+pub fn parse_plan(text: &str) -> Result<Vec<PlanStep>> {
+    let mut body = text.trim();
+    if let Some(rest) = body.strip_prefix("```") {
+        let rest = rest.split_once('\n').map_or("", |(_, tail)| tail);
+        body = rest.trim_end().strip_suffix("```").unwrap_or(rest).trim();
+    }
+    let value: serde_json::Value =
+        serde_json::from_str(body).context("Plan reply was not valid JSON")?;
+    let steps = match &value {
+        serde_json::Value::Array(items) => items,
+        serde_json::Value::Object(obj) => obj
+            .get("plan")
+            .or_else(|| obj.get("steps"))
+            .and_then(|v| v.as_array())
+            .ok_or_else(|| anyhow!("Plan reply was a JSON object without a plan array"))?,
+        _ => return Err(anyhow!("Plan reply was not a JSON array of steps")),
+    };
+    steps
+        .iter()
+        .enumerate()
+        .map(|(i, item)| {
+            let tool = item
+                .get("tool")
+                .and_then(|v| v.as_str())
+                .ok_or_else(|| anyhow!("Plan step {} is missing a 'tool' name", i + 1))?;
+            let description = item
+                .get("step")
+                .or_else(|| item.get("description"))
+                .and_then(|v| v.as_str())
+                .unwrap_or_default();
+            Ok(PlanStep {
+                description: description.trim().to_string(),
+                tool: tool.to_string(),
+                arguments: item
+                    .get("arguments")
+                    .cloned()
+                    .unwrap_or_else(|| serde_json::json!({})),
+            })
+        })
+        .collect()
+}
+
+/// Resolve a structured function call (name + JSON arguments) into a `ToolCall`.
+/// Unwraps the `json` wrapper tool, possibly nested. Returns `Ok(None)` for unknown tools.
+pub fn resolve_function_call(name: &str, arguments_json: &str) -> Result<Option<ToolCall>> {
+    let mut current_name = name.to_string();
+    let mut current_args = arguments_json.to_string();
+
+    loop {
+        if let Some(tool) = find_tool(&current_name) {
+            let normalized = normalize_tool_arguments(&current_args)?;
+            let args: serde_json::Value = serde_json::from_str(&normalized)
+                .map_err(|e| anyhow!("Failed to parse {} args: {}", tool.name, e))?;
+            let call = (tool.parse_args)(args)
+                .map_err(|e| anyhow!("Failed to parse {} args: {}", tool.name, e))?;
+            return Ok(Some(call));
         }
-        "execute_command" => {
-            let args: execute_command::Args = serde_json::from_value(env.arguments)?;
-            Ok(ToolCall::ExecuteCommand(args))
+        match current_name.as_str() {
+            JSON_WRAPPER => {
+                let inner: serde_json::Value = serde_json::from_str(&current_args)
+                    .map_err(|e| anyhow!("Failed to parse json wrapper: {}", e))?;
+                let tool_name = inner
+                    .get("tool")
+                    .and_then(|v| v.as_str())
+                    .ok_or_else(|| anyhow!("Wrapped json tool missing 'tool' field"))?;
+                let arguments = inner
+                    .get("arguments")
+                    .cloned()
+                    .unwrap_or_else(|| serde_json::json!({}));
+                current_name = tool_name.to_string();
+                current_args = serde_json::to_string(&arguments)?;
+                continue;
+            }
+            _ => return Ok(None),
         }
-        other => Err(anyhow!("Unknown tool: {}", other)),
+    }
+}
+
+pub fn normalize_tool_arguments(raw: &str) -> Result<String> {
+    // If the raw blob already matches our expected schema, leave it alone.
+    // Otherwise, detect legacy wrappers of the shape {"tool": ..., "arguments": {...}}
+    // and unwrap them so serde can deserialize into the tool arg structs.
+    #[derive(serde::Deserialize)]
+    struct LegacyWrapper {
+        tool: Option<String>,
+        arguments: serde_json::Value,
+    }
+
+    let trimmed = raw.trim_start();
+    if !trimmed.starts_with('{') {
+        return Ok(raw.to_string());
+    }
+
+    match serde_json::from_str::<LegacyWrapper>(trimmed) {
+        Ok(wrapper) if wrapper.tool.is_some() => Ok(wrapper.arguments.to_string()),
+        _ => Ok(raw.to_string()),
     }
 }
@@ -0,0 +1,183 @@
+//! Decode command output that is not UTF-8, for Windows shells (`windows_output_encoding`).
+//!
+//! `cmd.exe` and PowerShell often write in the console code page (CP437/CP850) or UTF-16LE.
+//! A byte-order mark always wins; otherwise the configured encoding applies, and UTF-8 is the
+//! default.
+
+use anyhow::{Result, anyhow};
+
+/// Encodings accepted by `windows_output_encoding`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputEncoding {
+    #[default]
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    /// OEM United States console code page.
+    Cp437,
+    /// OEM Western European console code page.
+    Cp850,
+    /// ANSI Western European code page.
+    Windows1252,
+}
+
+impl std::str::FromStr for OutputEncoding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let normalized = s.trim().to_ascii_lowercase().replace('_', "-");
+        match normalized.as_str() {
+            "utf-8" | "utf8" | "cp65001" => Ok(Self::Utf8),
+            "utf-16le" | "utf-16" | "utf16le" | "unicode" => Ok(Self::Utf16Le),
+            "utf-16be" | "utf16be" => Ok(Self::Utf16Be),
+            "cp437" | "ibm437" | "437" => Ok(Self::Cp437),
+            "cp850" | "ibm850" | "850" => Ok(Self::Cp850),
+            "windows-1252" | "cp1252" | "1252" => Ok(Self::Windows1252),
+            _ => Err(anyhow!(
+                "Unsupported windows_output_encoding '{}' (expected utf-8, utf-16le, utf-16be, cp437, cp850, or windows-1252)",
+                s.trim()
+            )),
+        }
+    }
+}
+
+/// Decode `bytes`, honoring a leading BOM before falling back to `encoding`.
+/// Invalid sequences become U+FFFD rather than failing.
+pub fn decode_output(bytes: &[u8], encoding: OutputEncoding) -> String {
+    if let Some(rest) = bytes.strip_prefix(&[0xEF, 0xBB, 0xBF]) {
+        return String::from_utf8_lossy(rest).into_owned();
+    }
+    if let Some(rest) = bytes.strip_prefix(&[0xFF, 0xFE]) {
+        return decode_utf16(rest, u16::from_le_bytes);
+    }
+    if let Some(rest) = bytes.strip_prefix(&[0xFE, 0xFF]) {
+        return decode_utf16(rest, u16::from_be_bytes);
+    }
+    match encoding {
+        OutputEncoding::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+        OutputEncoding::Utf16Le => decode_utf16(bytes, u16::from_le_bytes),
+        OutputEncoding::Utf16Be => decode_utf16(bytes, u16::from_be_bytes),
+        OutputEncoding::Cp437 => decode_single_byte(bytes, &CP437_HIGH),
+        OutputEncoding::Cp850 => decode_single_byte(bytes, &CP850_HIGH),
+        OutputEncoding::Windows1252 => decode_single_byte(bytes, &WINDOWS_1252_HIGH),
+    }
+}
+
+fn decode_utf16(bytes: &[u8], unit: fn([u8; 2]) -> u16) -> String {
+    let units = bytes.chunks(2).map(|pair| match pair {
+        [a, b] => unit([*a, *b]),
+        // A dangling odd byte cannot form a code unit.
+        _ => 0xFFFD,
+    });
+    char::decode_utf16(units)
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect()
+}
+
+fn decode_single_byte(bytes: &[u8], high: &[char; 128]) -> String {
+    bytes
+        .iter()
+        .map(|&b| {
+            if b < 0x80 {
+                b as char
+            } else {
+                high[(b - 0x80) as usize]
+            }
+        })
+        .collect()
+}
+
+const CP437_HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', 'É', 'æ', 'Æ',
+    'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ', 'á', 'í', 'ó', 'ú', 'ñ', 'Ñ',
+    'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»', '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕',
+    '╣', '║', '╗', '╝', '╜', '╛', '┐', '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦',
+    '╠', '═', '╬', '╧', '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐',
+    '▀', 'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩', '≡', '±',
+    '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+const CP850_HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', 'É', 'æ', 'Æ',
+    'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', 'ø', '£', 'Ø', '×', 'ƒ', 'á', 'í', 'ó', 'ú', 'ñ', 'Ñ',
+    'ª', 'º', '¿', '®', '¬', '½', '¼', '¡', '«', '»', '░', '▒', '▓', '│', '┤', 'Á', 'Â', 'À', '©',
+    '╣', '║', '╗', '╝', '¢', '¥', '┐', '└', '┴', '┬', '├', '─', '┼', 'ã', 'Ã', '╚', '╔', '╩', '╦',
+    '╠', '═', '╬', '¤', 'ð', 'Ð', 'Ê', 'Ë', 'È', 'ı', 'Í', 'Î', 'Ï', '┘', '┌', '█', '▄', '¦', 'Ì',
+    '▀', 'Ó', 'ß', 'Ô', 'Ò', 'õ', 'Õ', 'µ', 'þ', 'Þ', 'Ú', 'Û', 'Ù', 'ý', 'Ý', '¯', '´', '\u{ad}',
+    '±', '‗', '¾', '¶', '§', '÷', '¸', '°', '¨', '·', '¹', '³', '²', '■', '\u{a0}',
+];
+
+/// Bytes Windows-1252 leaves undefined map to U+FFFD.
+const WINDOWS_1252_HIGH: [char; 128] = [
+    '€', '\u{fffd}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{fffd}', 'Ž',
+    '\u{fffd}', '\u{fffd}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{fffd}',
+    'ž', 'Ÿ', '\u{a0}', '¡', '¢', '£', '¤', '¥', '¦', '§', '¨', '©', 'ª', '«', '¬', '\u{ad}', '®',
+    '¯', '°', '±', '²', '³', '´', 'µ', '¶', '·', '¸', '¹', 'º', '»', '¼', '½', '¾', '¿', 'À', 'Á',
+    'Â', 'Ã', 'Ä', 'Å', 'Æ', 'Ç', 'È', 'É', 'Ê', 'Ë', 'Ì', 'Í', 'Î', 'Ï', 'Ð', 'Ñ', 'Ò', 'Ó', 'Ô',
+    'Õ', 'Ö', '×', 'Ø', 'Ù', 'Ú', 'Û', 'Ü', 'Ý', 'Þ', 'ß', 'à', 'á', 'â', 'ã', 'ä', 'å', 'æ', 'ç',
+    'è', 'é', 'ê', 'ë', 'ì', 'í', 'î', 'ï', 'ð', 'ñ', 'ò', 'ó', 'ô', 'õ', 'ö', '÷', 'ø', 'ù', 'ú',
+    'û', 'ü', 'ý', 'þ', 'ÿ',
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn utf16_boms_override_configured_encoding() {
+        let mut le = vec![0xFF, 0xFE];
+        le.extend("héllo\r\n".encode_utf16().flat_map(u16::to_le_bytes));
+        assert_eq!(decode_output(&le, OutputEncoding::Cp850), "héllo\r\n");
+
+        let mut be = vec![0xFE, 0xFF];
+        be.extend("dir ✓".encode_utf16().flat_map(u16::to_be_bytes));
+        assert_eq!(decode_output(&be, OutputEncoding::Utf8), "dir ✓");
+    }
+
+    #[test]
+    fn utf8_bom_is_stripped() {
+        assert_eq!(decode_output(b"\xEF\xBB\xBFok", OutputEncoding::Utf8), "ok");
+    }
+
+    #[test]
+    fn bomless_utf16_needs_configured_encoding() {
+        let bytes: Vec<u8> = "ok".encode_utf16().flat_map(u16::to_le_bytes).collect();
+        assert_eq!(decode_output(&bytes, OutputEncoding::Utf16Le), "ok");
+        // Odd trailing byte is replaced, not dropped silently.
+        let mut odd = bytes.clone();
+        odd.push(b'x');
+        assert_eq!(decode_output(&odd, OutputEncoding::Utf16Le), "ok\u{FFFD}");
+    }
+
+    #[test]
+    fn oem_code_pages_decode_high_bytes() {
+        // "Größe" in CP850 / CP437: ö = 0x94, ß = 0xE1.
+        let bytes = b"Gr\x94\xE1e";
+        assert_eq!(decode_output(bytes, OutputEncoding::Cp850), "Größe");
+        assert_eq!(decode_output(bytes, OutputEncoding::Cp437), "Größe");
+        assert_eq!(decode_output(b"\xB5", OutputEncoding::Cp850), "Á");
+        assert_eq!(decode_output(b"\xB5", OutputEncoding::Cp437), "╡");
+        assert_eq!(decode_output(b"\x80", OutputEncoding::Windows1252), "€");
+        assert_eq!(
+            decode_output(b"caf\xE9", OutputEncoding::Utf8),
+            "caf\u{FFFD}"
+        );
+    }
+
+    #[test]
+    fn parses_common_encoding_names() {
+        assert_eq!(
+            "UTF-16LE".parse::<OutputEncoding>().unwrap(),
+            OutputEncoding::Utf16Le
+        );
+        assert_eq!(
+            "cp850".parse::<OutputEncoding>().unwrap(),
+            OutputEncoding::Cp850
+        );
+        assert_eq!(
+            "windows_1252".parse::<OutputEncoding>().unwrap(),
+            OutputEncoding::Windows1252
+        );
+        assert!("ebcdic".parse::<OutputEncoding>().is_err());
+    }
+}
@@ -1,12 +1,34 @@
 use crate::perms::{READ_FILE_MAX_BYTES, ensure_safe_path, resolve_path};
 use anyhow::{Context, Result, anyhow};
+use chrono::{DateTime, Local, SecondsFormat};
 use fs_err as fs;
 use serde::Deserialize;
 use std::path::PathBuf;
 
+/// Tool name the model calls.
+pub const NAME: &str = "read_file";
+
+/// Function description and JSON schema for [`Args`], as sent in the tools spec.
+pub fn spec() -> (&'static str, serde_json::Value) {
+    (
+        "Read a UTF-8 text file up to a safe size. Set with_metadata to get path, size, modified time, encoding, and line endings before the content.",
+        serde_json::json!({
+            "type": "object",
+            "properties": {
+                "path": {"type": "string"},
+                "with_metadata": {"type": "boolean"}
+            },
+            "required": ["path"]
+        }),
+    )
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Args {
     pub path: String,
+    /// Prepend a header with path, size, modification time, encoding, and line endings.
+    #[serde(default)]
+    pub with_metadata: bool,
 }
 
 pub fn run(args: Args) -> Result<String> {
@@ -22,7 +44,58 @@ pub fn run(args: Args) -> Result<String> {
             path.display()
         ));
     }
-    let content = fs::read_to_string(&resolved)
-        .with_context(|| format!("Reading file: {}", resolved.display()))?;
-    Ok(content)
+    let bytes =
+        fs::read(&resolved).with_context(|| format!("Reading file: {}", resolved.display()))?;
+    let content = decode_text(bytes).ok_or_else(|| {
+        anyhow!(
+            "Refusing to read binary file: {}. Use execute_command with a hexdump tool (e.g. `xxd {}` or `od -c {}`) to inspect it.",
+            path.display(),
+            args.path,
+            args.path
+        )
+    })?;
+    if !args.with_metadata {
+        return Ok(content);
+    }
+    let modified = meta
+        .modified()
+        .map(|t| DateTime::<Local>::from(t).to_rfc3339_opts(SecondsFormat::Secs, false))
+        .unwrap_or_else(|_| "unknown".to_string());
+    let encoding = if content.starts_with('\u{feff}') {
+        "utf-8 (with BOM)"
+    } else {
+        "utf-8"
+    };
+    Ok(format!(
+        "path: {}\nsize: {} bytes\nmodified: {}\nencoding: {}\nline_endings: {}\n---\n{}",
+        resolved.display(),
+        meta.len(),
+        modified,
+        encoding,
+        line_endings(&content),
+        content
+    ))
+}
+
+/// How much of the file is sniffed for NUL bytes before decoding.
+const BINARY_SNIFF_BYTES: usize = 8 * 1024;
+
+/// Decode UTF-8 text, returning `None` for content that looks binary.
+fn decode_text(bytes: Vec<u8>) -> Option<String> {
+    let head = &bytes[..bytes.len().min(BINARY_SNIFF_BYTES)];
+    if head.contains(&0) {
+        return None;
+    }
+    String::from_utf8(bytes).ok()
+}
+
+fn line_endings(content: &str) -> &'static str {
+    let crlf = content.matches("\r\n").count();
+    let lf = content.matches('\n').count() - crlf;
+    match (lf, crlf) {
+        (0, 0) => "none",
+        (_, 0) => "lf",
+        (0, _) => "crlf",
+        _ => "mixed",
+    }
 }
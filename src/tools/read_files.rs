@@ -0,0 +1,71 @@
+use crate::perms::READ_FILE_MAX_BYTES;
+use crate::tools::read_file;
+use anyhow::{Result, anyhow};
+use serde::Deserialize;
+
+/// Tool name the model calls.
+pub const NAME: &str = "read_files";
+
+/// Most paths one call may request.
+pub const MAX_PATHS: usize = 20;
+
+/// Function description and JSON schema for [`Args`], as sent in the tools spec.
+pub fn spec() -> (&'static str, serde_json::Value) {
+    (
+        "Read several UTF-8 text files in one step. Each file gets a `=== path ===` header; unreadable files are skipped with a note.",
+        serde_json::json!({
+            "type": "object",
+            "properties": {
+                "paths": {"type": "array", "items": {"type": "string"}}
+            },
+            "required": ["paths"]
+        }),
+    )
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Args {
+    pub paths: Vec<String>,
+}
+
+/// Read each path with [`read_file::run`] (same path checks and per-file cap). Files that
+/// fail, or that would push the combined output past `READ_FILE_MAX_BYTES`, are listed
+/// as skipped instead of failing the whole call.
+pub fn run(args: Args) -> Result<String> {
+    if args.paths.is_empty() {
+        return Err(anyhow!("read_files needs at least one path"));
+    }
+    if args.paths.len() > MAX_PATHS {
+        return Err(anyhow!(
+            "read_files accepts at most {} paths (got {})",
+            MAX_PATHS,
+            args.paths.len()
+        ));
+    }
+    let mut out = String::new();
+    let mut used = 0usize;
+    for path in args.paths {
+        out.push_str(&format!("=== {} ===\n", path));
+        let result = read_file::run(read_file::Args {
+            path: path.clone(),
+            with_metadata: false,
+        });
+        match result {
+            Ok(content) if used + content.len() > READ_FILE_MAX_BYTES => {
+                out.push_str(&format!(
+                    "[skipped: combined output would exceed {} bytes]\n",
+                    READ_FILE_MAX_BYTES
+                ));
+            }
+            Ok(content) => {
+                used += content.len();
+                out.push_str(&content);
+                if !content.ends_with('\n') {
+                    out.push('\n');
+                }
+            }
+            Err(err) => out.push_str(&format!("[skipped: {}]\n", err)),
+        }
+    }
+    Ok(out)
+}
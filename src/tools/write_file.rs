@@ -1,16 +1,66 @@
-use crate::perms::{ensure_safe_path, resolve_path};
+use crate::perms::{ensure_safe_path, resolve_path, write_file_max_bytes};
 use anyhow::{Context, Result};
 use fs_err as fs;
 use serde::Deserialize;
 use std::path::PathBuf;
 
+/// Tool name the model calls.
+pub const NAME: &str = "write_file";
+
+/// Function description and JSON schema for [`Args`], as sent in the tools spec.
+pub fn spec() -> (&'static str, serde_json::Value) {
+    (
+        "Create or overwrite a UTF-8 text file.",
+        serde_json::json!({
+            "type": "object",
+            "properties": {"path": {"type": "string"}, "content": {"type": "string"}},
+            "required": ["path", "content"]
+        }),
+    )
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Args {
     pub path: String,
     pub content: String,
 }
 
+/// Content larger than `write_file_max_bytes`; callers may confirm and retry with
+/// [`run_without_size_limit`].
+#[derive(Debug)]
+pub struct WriteTooLargeError {
+    pub path: String,
+    pub size: usize,
+    pub limit: usize,
+}
+
+impl std::fmt::Display for WriteTooLargeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Refusing to write {} bytes to {}: exceeds write_file_max_bytes ({} bytes)",
+            self.size, self.path, self.limit
+        )
+    }
+}
+
+impl std::error::Error for WriteTooLargeError {}
+
 pub fn run(args: Args) -> Result<String> {
+    let limit = write_file_max_bytes();
+    if args.content.len() > limit {
+        return Err(WriteTooLargeError {
+            path: args.path,
+            size: args.content.len(),
+            limit,
+        }
+        .into());
+    }
+    run_without_size_limit(args)
+}
+
+/// Write without the size cap, for callers that already confirmed a large write.
+pub fn run_without_size_limit(args: Args) -> Result<String> {
     let path = PathBuf::from(&args.path);
     ensure_safe_path(&path)?;
     let resolved = resolve_path(&path)?;
@@ -0,0 +1,17 @@
+//! Build metadata embedded by `build.rs`, shared by both binaries.
+
+/// Short git commit hash of the build, or "unknown" outside a git checkout.
+pub const GIT_HASH: &str = env!("QQQA_GIT_HASH");
+
+/// Build date (UTC, `YYYY-MM-DD`).
+pub const BUILD_DATE: &str = env!("QQQA_BUILD_DATE");
+
+/// Version string for `--version`, e.g. `1.0.0 (abc1234, 2024-06-01)`.
+pub const LONG_VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    " (",
+    env!("QQQA_GIT_HASH"),
+    ", ",
+    env!("QQQA_BUILD_DATE"),
+    ")"
+);
@@ -1,9 +1,12 @@
+use flate2::read::GzDecoder;
 use httpmock::Method::POST;
 use httpmock::MockServer;
 use httpmock::prelude::HttpMockRequest;
-use qqqa::ai::{AssistantReply, ChatClient, Msg};
+use qqqa::ai::{AssistantReply, ChatClient, Msg, StreamEvent, Usage};
+use qqqa::config::{NetworkConfig, ReasoningStyle};
 use serde_json::{Value, json};
 use std::collections::HashMap;
+use std::io::Read;
 use std::net::TcpListener;
 use std::time::{Duration, Instant};
 
@@ -62,6 +65,129 @@ async fn chat_stream_streams_tokens() {
     mock.assert();
 }
 
+#[tokio::test]
+async fn chat_stream_messages_skips_keepalive_comments() {
+    if sandbox_blocks_binding() {
+        eprintln!("[skip] sandbox blocks binding to 127.0.0.1; skipping httpmock test");
+        return;
+    }
+    let server = MockServer::start();
+    let sse_body = concat!(
+        ": keep-alive\n\n",
+        "data: {\"choices\":[{\"delta\":{\"content\":\"He\"}}]}\n\n",
+        ":OPENROUTER PROCESSING\n",
+        "data: {\"choices\":[{\"delta\":{\"content\":\"llo\"}}]}\n\n",
+        "data: [DONE]\n\n"
+    );
+    let mock = server.mock(|when, then| {
+        when.method(POST)
+            .path("/chat/completions")
+            .header("accept", "text/event-stream");
+        then.status(200)
+            .header("content-type", "text/event-stream")
+            .body(sse_body);
+    });
+
+    let client =
+        ChatClient::new(server.base_url(), "test".into(), HashMap::new(), None, None).unwrap();
+    let messages = [Msg {
+        role: "user",
+        content: "Hi",
+    }];
+    let mut acc = String::new();
+    client
+        .chat_stream_messages("model-x", &messages, false, |tok| acc.push_str(tok))
+        .await
+        .unwrap();
+    assert_eq!(acc, "Hello");
+    mock.assert();
+}
+
+#[tokio::test]
+async fn chat_stream_events_reports_reasoning_usage_and_finish_reason() {
+    if sandbox_blocks_binding() {
+        eprintln!("[skip] sandbox blocks binding to 127.0.0.1; skipping httpmock test");
+        return;
+    }
+    let server = MockServer::start();
+    let sse_body = concat!(
+        "data: {\"choices\":[{\"delta\":{\"reasoning\":\"think\"}}]}\n\n",
+        "data: {\"choices\":[{\"delta\":{\"content\":\"Hi\"}}]}\n\n",
+        "data: {\"choices\":[{\"delta\":{},\"finish_reason\":\"stop\"}]}\n\n",
+        "data: {\"choices\":[],\"usage\":{\"prompt_tokens\":5,\"completion_tokens\":2,\"total_tokens\":7}}\n\n",
+        "data: [DONE]\n\n"
+    );
+    server.mock(|when, then| {
+        when.method(POST).path("/chat/completions");
+        then.status(200)
+            .header("content-type", "text/event-stream")
+            .body(sse_body);
+    });
+
+    let client =
+        ChatClient::new(server.base_url(), "test".into(), HashMap::new(), None, None).unwrap();
+    let messages = [Msg {
+        role: "user",
+        content: "Hi",
+    }];
+    let mut events = Vec::new();
+    client
+        .chat_stream_events("model-x", &messages, false, |event| events.push(event))
+        .await
+        .unwrap();
+    assert_eq!(
+        events,
+        vec![
+            StreamEvent::Reasoning("think".into()),
+            StreamEvent::Token("Hi".into()),
+            StreamEvent::Usage(Usage {
+                prompt_tokens: Some(5),
+                completion_tokens: Some(2),
+                total_tokens: Some(7),
+            }),
+            StreamEvent::Done {
+                finish_reason: Some("stop".into()),
+            },
+        ]
+    );
+}
+
+#[tokio::test]
+async fn chat_stream_messages_handles_compact_and_packed_data_lines() {
+    if sandbox_blocks_binding() {
+        eprintln!("[skip] sandbox blocks binding to 127.0.0.1; skipping httpmock test");
+        return;
+    }
+    let server = MockServer::start();
+    let sse_body = concat!(
+        "data:{\"choices\":[{\"delta\":{\"content\":\"data: \"}}]}\n\n",
+        "data: {\"choices\":[{\"delta\":{\"content\":\"one\"}}]}",
+        "data:{\"choices\":[{\"delta\":{\"content\":\" two\"}}]}\n\n",
+        "data:[DONE]\n\n",
+        "data: {\"choices\":[{\"delta\":{\"content\":\"ignored\"}}]}\n\n"
+    );
+    let mock = server.mock(|when, then| {
+        when.method(POST).path("/chat/completions");
+        then.status(200)
+            .header("content-type", "text/event-stream")
+            .body(sse_body);
+    });
+
+    let client =
+        ChatClient::new(server.base_url(), "test".into(), HashMap::new(), None, None).unwrap();
+    let messages = [Msg {
+        role: "user",
+        content: "Hi",
+    }];
+    let mut acc = String::new();
+    client
+        .chat_stream_messages("model-x", &messages, false, |tok| acc.push_str(tok))
+        .await
+        .unwrap();
+    assert_eq!(acc, "data: one two");
+    mock.assert();
+}
+
 #[tokio::test]
 async fn chat_once_uses_new_parameters_for_new_models() {
     if sandbox_blocks_binding() {
@@ -142,6 +268,62 @@ async fn chat_once_uses_legacy_parameters_for_old_models() {
     mock.assert();
 }
 
+#[tokio::test]
+async fn penalties_are_sent_to_legacy_models_and_omitted_for_gpt5() {
+    if sandbox_blocks_binding() {
+        eprintln!("[skip] sandbox blocks binding to 127.0.0.1; skipping httpmock test");
+        return;
+    }
+    fn penalties(req: &HttpMockRequest) -> (String, Option<f64>, Option<f64>) {
+        let payload: Value =
+            serde_json::from_slice(req.body.as_ref().expect("request body")).expect("JSON body");
+        (
+            payload["model"].as_str().unwrap_or_default().to_string(),
+            payload.get("frequency_penalty").and_then(Value::as_f64),
+            payload.get("presence_penalty").and_then(Value::as_f64),
+        )
+    }
+    let server = MockServer::start();
+    let legacy = server.mock(|when, then| {
+        when.method(POST)
+            .path("/chat/completions")
+            .matches(|req: &HttpMockRequest| {
+                let (model, frequency, presence) = penalties(req);
+                model == "llama3.1"
+                    && frequency.is_some_and(|v| (v - 0.5).abs() < 1e-6)
+                    && presence.is_some_and(|v| (v + 0.25).abs() < 1e-6)
+            });
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(r#"{"choices":[{"message":{"content":"legacy"}}]}"#);
+    });
+    let gpt5 = server.mock(|when, then| {
+        when.method(POST)
+            .path("/chat/completions")
+            .matches(|req: &HttpMockRequest| {
+                let (model, frequency, presence) = penalties(req);
+                model == "gpt-5-mini" && frequency.is_none() && presence.is_none()
+            });
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(r#"{"choices":[{"message":{"content":"gpt5"}}]}"#);
+    });
+
+    let client = ChatClient::new(server.base_url(), "test".into(), HashMap::new(), None, None)
+        .unwrap()
+        .with_penalties(Some(0.5), Some(-0.25));
+    assert_eq!(
+        client.chat_once("llama3.1", "Hi", false).await.unwrap(),
+        "legacy"
+    );
+    assert_eq!(
+        client.chat_once("gpt-5-mini", "Hi", false).await.unwrap(),
+        "gpt5"
+    );
+    legacy.assert();
+    gpt5.assert();
+}
+
 #[tokio::test]
 async fn chat_once_defaults_temperature_for_o1_models() {
     if sandbox_blocks_binding() {
@@ -251,6 +433,122 @@ async fn chat_once_respects_reasoning_override_when_configured() {
     mock.assert();
 }
 
+#[tokio::test]
+async fn chat_once_sends_reasoning_object_when_configured() {
+    if sandbox_blocks_binding() {
+        eprintln!("[skip] sandbox blocks binding to 127.0.0.1; skipping httpmock test");
+        return;
+    }
+    let server = MockServer::start();
+    let mock = server.mock(|when, then| {
+        when.method(POST)
+            .path("/chat/completions")
+            .matches(|req: &HttpMockRequest| {
+                let body = req.body.as_ref().expect("expected request body");
+                let payload: Value =
+                    serde_json::from_slice(body).expect("request body should be valid JSON");
+                assert!(payload.get("reasoning_effort").is_none());
+                assert_eq!(payload.get("reasoning"), Some(&json!({"effort": "high"})));
+                true
+            });
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(r#"{"choices":[{"message":{"content":"ok"}}]}"#);
+    });
+
+    let client = ChatClient::new(server.base_url(), "test".into(), HashMap::new(), None, None)
+        .unwrap()
+        .with_reasoning_effort(Some("high".to_string()))
+        .with_reasoning_style(ReasoningStyle::Object);
+    let got = client.chat_once("gpt-5-mini", "Hi", false).await.unwrap();
+    assert_eq!(got, "ok");
+    mock.assert();
+}
+
+#[tokio::test]
+async fn chat_client_with_network_reuses_client_across_calls() {
+    if sandbox_blocks_binding() {
+        eprintln!("[skip] sandbox blocks binding to 127.0.0.1; skipping httpmock test");
+        return;
+    }
+    let server = MockServer::start();
+    let mock = server.mock(|when, then| {
+        when.method(POST).path("/chat/completions");
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(r#"{"choices":[{"message":{"content":"ok"}}]}"#);
+    });
+
+    let network = NetworkConfig {
+        pool_idle_timeout_secs: Some(30),
+        pool_max_idle_per_host: Some(2),
+        tcp_keepalive_secs: Some(15),
+        http2_prior_knowledge: None,
+        min_request_interval_ms: None,
+        max_retries: None,
+        connect_timeout_secs: Some(5),
+    };
+    let client = ChatClient::with_network(
+        server.base_url(),
+        "test".into(),
+        HashMap::new(),
+        None,
+        None,
+        &network,
+    )
+    .unwrap();
+    for _ in 0..2 {
+        let got = client.chat_once("gpt-4.1-mini", "Hi", false).await.unwrap();
+        assert_eq!(got, "ok");
+    }
+    mock.assert_hits(2);
+}
+
+#[tokio::test]
+async fn min_request_interval_spaces_out_consecutive_requests() {
+    if sandbox_blocks_binding() {
+        eprintln!("[skip] sandbox blocks binding to 127.0.0.1; skipping httpmock test");
+        return;
+    }
+    let server = MockServer::start();
+    let mock = server.mock(|when, then| {
+        when.method(POST).path("/chat/completions");
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(r#"{"choices":[{"message":{"content":"ok"}}]}"#);
+    });
+
+    let network = NetworkConfig {
+        min_request_interval_ms: Some(150),
+        ..Default::default()
+    };
+    let client = ChatClient::with_network(
+        server.base_url(),
+        "test".into(),
+        HashMap::new(),
+        None,
+        None,
+        &network,
+    )
+    .unwrap();
+    let started = Instant::now();
+    let (a, b, c) = tokio::join!(
+        client.chat_once("llama3.1", "one", false),
+        client.chat_once("llama3.1", "two", false),
+        client.chat_once("llama3.1", "three", false),
+    );
+    for reply in [a, b, c] {
+        assert_eq!(reply.unwrap(), "ok");
+    }
+    // Three starts spaced 150ms apart: the last one waits at least 300ms.
+    assert!(
+        started.elapsed() >= Duration::from_millis(300),
+        "requests were not spaced: {:?}",
+        started.elapsed()
+    );
+    mock.assert_hits(3);
+}
+
 #[tokio::test]
 async fn chat_client_sends_custom_headers() {
     if sandbox_blocks_binding() {
@@ -361,8 +659,10 @@ async fn chat_once_messages_with_tools_handles_legacy_function_call() {
         AssistantReply::ToolCall {
             name,
             arguments_json,
+            content,
         } => {
             assert_eq!(name, "read_file");
+            assert_eq!(content, None);
             assert!(arguments_json.contains("/tmp/file.txt"));
         }
         AssistantReply::Content(other) => {
@@ -372,3 +672,358 @@ async fn chat_once_messages_with_tools_handles_legacy_function_call() {
 
     mock.assert();
 }
+
+#[tokio::test]
+async fn chat_once_messages_with_tools_keeps_content_alongside_tool_calls() {
+    if sandbox_blocks_binding() {
+        eprintln!("[skip] sandbox blocks binding to 127.0.0.1; skipping httpmock test");
+        return;
+    }
+    let server = MockServer::start();
+    server.mock(|when, then| {
+        when.method(POST).path("/chat/completions");
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(
+                r#"{"choices":[{"message":{"content":"Let me look at the file first.","tool_calls":[{"id":"call_1","type":"function","function":{"name":"read_file","arguments":"{\"path\":\"a.txt\"}"}}]}}]}"#,
+            );
+    });
+
+    let client =
+        ChatClient::new(server.base_url(), "test".into(), HashMap::new(), None, None).unwrap();
+    let reply = client
+        .chat_once_messages_with_tools(
+            "model-x",
+            &[Msg {
+                role: "user",
+                content: "Read a.txt",
+            }],
+            json!([]),
+            false,
+        )
+        .await
+        .unwrap();
+
+    match reply {
+        AssistantReply::ToolCall { name, content, .. } => {
+            assert_eq!(name, "read_file");
+            assert_eq!(content.as_deref(), Some("Let me look at the file first."));
+        }
+        AssistantReply::Content(other) => panic!("expected tool call, got content: {}", other),
+    }
+}
+
+#[tokio::test]
+async fn stream_tool_calls_reassembles_argument_fragments_by_index() {
+    if sandbox_blocks_binding() {
+        eprintln!("[skip] sandbox blocks binding to 127.0.0.1; skipping httpmock test");
+        return;
+    }
+    let server = MockServer::start();
+    let sse_body = concat!(
+        "data: {\"choices\":[{\"delta\":{\"content\":\"Checking.\"}}]}\n\n",
+        "data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"id\":\"call_1\",\"type\":\"function\",\"function\":{\"name\":\"read_file\",\"arguments\":\"\"}}]}}]}\n\n",
+        "data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"{\\\"pa\"}}]}}]}\n\n",
+        "data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":1,\"function\":{\"name\":\"write_file\",\"arguments\":\"{}\"}}]}}]}\n\n",
+        "data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"th\\\":\\\"a.txt\\\"}\"}}]}}]}\n\n",
+        "data: {\"choices\":[{\"delta\":{},\"finish_reason\":\"tool_calls\"}]}\n\n",
+        "data: [DONE]\n\n"
+    );
+    let mock = server.mock(|when, then| {
+        when.method(POST)
+            .path("/chat/completions")
+            .body_contains("\"stream\":true")
+            .body_contains("\"tools\"");
+        then.status(200)
+            .header("content-type", "text/event-stream")
+            .body(sse_body);
+    });
+
+    let client = ChatClient::new(server.base_url(), "test".into(), HashMap::new(), None, None)
+        .unwrap()
+        .with_stream_tool_calls(true);
+    let reply = client
+        .chat_once_messages_with_tools(
+            "model-x",
+            &[Msg {
+                role: "user",
+                content: "Read a.txt",
+            }],
+            json!([]),
+            false,
+        )
+        .await
+        .unwrap();
+
+    match reply {
+        AssistantReply::ToolCall {
+            name,
+            arguments_json,
+            content,
+        } => {
+            assert_eq!(name, "read_file");
+            assert_eq!(arguments_json, r#"{"path":"a.txt"}"#);
+            assert_eq!(content.as_deref(), Some("Checking."));
+        }
+        AssistantReply::Content(other) => panic!("expected tool call, got content: {}", other),
+    }
+    mock.assert();
+}
+
+#[tokio::test]
+async fn ping_sends_single_token_request_and_reports_status() {
+    if sandbox_blocks_binding() {
+        eprintln!("[skip] sandbox blocks binding to 127.0.0.1; skipping httpmock test");
+        return;
+    }
+    let server = MockServer::start();
+    let mock = server.mock(|when, then| {
+        when.method(POST)
+            .path("/chat/completions")
+            .matches(|req: &HttpMockRequest| {
+                let payload: Value =
+                    serde_json::from_slice(req.body.as_ref().expect("ping body")).unwrap();
+                assert_eq!(payload.get("max_tokens").and_then(|v| v.as_u64()), Some(1));
+                assert_eq!(payload["messages"][0]["content"], "ping");
+                true
+            });
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(r#"{"choices":[{"message":{"content":"p"}}]}"#);
+    });
+
+    let client =
+        ChatClient::new(server.base_url(), "test".into(), HashMap::new(), None, None).unwrap();
+    let report = client.ping("model-x", false).await.unwrap();
+    assert!(report.status.is_success());
+    mock.assert();
+}
+
+#[tokio::test]
+async fn ping_returns_report_for_error_status() {
+    if sandbox_blocks_binding() {
+        eprintln!("[skip] sandbox blocks binding to 127.0.0.1; skipping httpmock test");
+        return;
+    }
+    let server = MockServer::start();
+    let mock = server.mock(|when, then| {
+        when.method(POST).path("/chat/completions");
+        then.status(401).body(r#"{"error":"bad key"}"#);
+    });
+
+    let client =
+        ChatClient::new(server.base_url(), "test".into(), HashMap::new(), None, None).unwrap();
+    let report = client.ping("model-x", false).await.unwrap();
+    assert_eq!(report.status.as_u16(), 401);
+    assert!(report.body.contains("bad key"));
+    mock.assert();
+}
+
+#[tokio::test]
+async fn chat_once_merges_extra_body_without_overriding_core_fields() {
+    if sandbox_blocks_binding() {
+        eprintln!("[skip] sandbox blocks binding to 127.0.0.1; skipping httpmock test");
+        return;
+    }
+    let server = MockServer::start();
+    let mock = server.mock(|when, then| {
+        when.method(POST)
+            .path("/chat/completions")
+            .matches(|req: &HttpMockRequest| {
+                let body = req.body.as_ref().expect("expected request body");
+                let payload: Value =
+                    serde_json::from_slice(body).expect("request body should be valid JSON");
+                assert_eq!(
+                    payload.get("frequency_penalty").and_then(|v| v.as_f64()),
+                    Some(0.5)
+                );
+                assert_eq!(payload["provider"]["order"][0], "groq");
+                assert_eq!(payload.get("model").and_then(|v| v.as_str()), Some("m"));
+                true
+            });
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(r#"{"choices":[{"message":{"content":"ok"}}]}"#);
+    });
+
+    let extra = serde_json::json!({
+        "frequency_penalty": 0.5,
+        "provider": {"order": ["groq"]},
+        "model": "should-not-win"
+    });
+    let client = ChatClient::new(server.base_url(), "test".into(), HashMap::new(), None, None)
+        .unwrap()
+        .with_extra_body(extra.as_object().cloned());
+    let got = client.chat_once("m", "Hi", false).await.unwrap();
+    assert_eq!(got, "ok");
+    mock.assert();
+}
+
+#[tokio::test]
+async fn empty_stream_is_retried_once_without_streaming_when_max_retries_set() {
+    if sandbox_blocks_binding() {
+        eprintln!("[skip] sandbox blocks binding to 127.0.0.1; skipping httpmock test");
+        return;
+    }
+    let server = MockServer::start();
+    let stream = server.mock(|when, then| {
+        when.method(POST)
+            .path("/chat/completions")
+            .body_contains(r#""stream":true"#);
+        then.status(200)
+            .header("content-type", "text/event-stream")
+            .body("data: [DONE]\n\n");
+    });
+    let retry = server.mock(|when, then| {
+        when.method(POST)
+            .path("/chat/completions")
+            .body_contains(r#""stream":false"#);
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(r#"{"choices":[{"message":{"content":"second try"}}]}"#);
+    });
+
+    for (max_retries, expected) in [(None, ""), (Some(1), "second try")] {
+        let network = NetworkConfig {
+            max_retries,
+            ..Default::default()
+        };
+        let client = ChatClient::with_network(
+            server.base_url(),
+            "test".into(),
+            HashMap::new(),
+            None,
+            None,
+            &network,
+        )
+        .unwrap();
+        let messages = [Msg {
+            role: "user",
+            content: "hi",
+        }];
+        let mut out = String::new();
+        client
+            .chat_stream_messages("llama3.1", &messages, false, |tok| out.push_str(tok))
+            .await
+            .unwrap();
+        assert_eq!(out, expected, "max_retries {max_retries:?}");
+    }
+    stream.assert_hits(2);
+    retry.assert_hits(1);
+}
+
+#[tokio::test]
+async fn plain_text_responses_become_the_answer_when_enabled() {
+    if sandbox_blocks_binding() {
+        eprintln!("[skip] sandbox blocks binding to 127.0.0.1; skipping httpmock test");
+        return;
+    }
+    let server = MockServer::start();
+    server.mock(|when, then| {
+        when.method(POST).path("/chat/completions");
+        then.status(200)
+            .header("content-type", "text/plain; charset=utf-8")
+            .body("Use `ls -la`.\n");
+    });
+
+    let strict =
+        ChatClient::new(server.base_url(), "test".into(), HashMap::new(), None, None).unwrap();
+    assert!(strict.chat_once("model-x", "Hi", false).await.is_err());
+
+    let client = strict.with_plain_text_responses(true);
+    let got = client.chat_once("model-x", "Hi", false).await.unwrap();
+    assert_eq!(got, "Use `ls -la`.");
+    let mut acc = String::new();
+    client
+        .chat_stream("model-x", "Hi", false, |tok| acc.push_str(tok))
+        .await
+        .unwrap();
+    assert_eq!(acc.trim_end(), "Use `ls -la`.");
+}
+
+#[tokio::test]
+async fn compress_request_gzips_the_json_body() {
+    if sandbox_blocks_binding() {
+        eprintln!("[skip] sandbox blocks binding to 127.0.0.1; skipping httpmock test");
+        return;
+    }
+    const LINE: &str = "error: connection reset by peer\n";
+    const RAW_LEN: usize = LINE.len() * 400;
+    let prompt = LINE.repeat(400);
+    let server = MockServer::start();
+    let gzipped = server.mock(|when, then| {
+        when.method(POST)
+            .path("/chat/completions")
+            .header("content-encoding", "gzip")
+            .header("content-type", "application/json")
+            .matches(|req: &HttpMockRequest| {
+                let body = req.body.as_deref().unwrap_or_default();
+                let mut json = String::new();
+                if GzDecoder::new(body).read_to_string(&mut json).is_err() {
+                    return false;
+                }
+                let Ok(payload) = serde_json::from_str::<Value>(&json) else {
+                    return false;
+                };
+                body.len() < RAW_LEN / 10
+                    && payload["model"] == "model-x"
+                    && payload["messages"][0]["role"] == "user"
+                    && payload["messages"][0]["content"] == LINE.repeat(400)
+            });
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(r#"{"choices":[{"message":{"content":"ok"}}]}"#);
+    });
+
+    let client = ChatClient::new(server.base_url(), "test".into(), HashMap::new(), None, None)
+        .unwrap()
+        .with_compress_request(true);
+    let got = client.chat_once("model-x", &prompt, false).await.unwrap();
+    assert_eq!(got, "ok");
+    gzipped.assert();
+}
+
+/// First bytes a client sends on a fresh connection to 127.0.0.1.
+async fn first_bytes_sent(network: NetworkConfig) -> Vec<u8> {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    let reader = std::thread::spawn(move || {
+        let (mut conn, _) = listener.accept().unwrap();
+        let mut buf = [0u8; 24];
+        conn.read_exact(&mut buf).unwrap();
+        buf.to_vec()
+    });
+    let client = ChatClient::with_network(
+        base_url,
+        "test".into(),
+        HashMap::new(),
+        None,
+        None,
+        &network,
+    )
+    .unwrap();
+    // The listener hangs up after reading, so the request itself fails.
+    assert!(client.chat_once("llama3.1", "Hi", false).await.is_err());
+    reader.join().unwrap()
+}
+
+#[tokio::test]
+async fn http2_prior_knowledge_opens_with_the_h2_preface() {
+    if sandbox_blocks_binding() {
+        eprintln!("[skip] sandbox blocks binding to 127.0.0.1; skipping raw socket test");
+        return;
+    }
+    let h2 = first_bytes_sent(NetworkConfig {
+        http2_prior_knowledge: Some(true),
+        ..Default::default()
+    })
+    .await;
+    assert_eq!(h2, b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n");
+
+    let default = first_bytes_sent(NetworkConfig::default()).await;
+    assert!(
+        default.starts_with(b"POST /chat/completions "),
+        "{:?}",
+        default
+    );
+}
@@ -100,6 +100,31 @@ printf '%s\n' '{"type":"item.completed","item":{"type":"agent_message","text":"h
     assert_eq!(text, "hello");
 }
 
+#[tokio::test]
+async fn run_cli_completion_kills_stalled_binary_after_timeout() {
+    let dir = tempdir().unwrap();
+    let script_path = dir.path().join("fake_codex");
+    write_executable_script(&script_path, "#!/bin/sh\nsleep 30\n");
+
+    let started = std::time::Instant::now();
+    let err = run_cli_completion_with_retry(|| CliCompletionRequest {
+        engine: CliEngine::Codex,
+        binary: script_path.to_str().unwrap(),
+        base_args: &[],
+        system_prompt: "SYSTEM",
+        user_prompt: "USER",
+        model: "gpt-5",
+        reasoning_effort: None,
+        debug: false,
+        timeout: Duration::from_secs(1),
+    })
+    .await
+    .expect_err("stalled binary should time out");
+
+    assert!(err.to_string().contains("timed out after 1s"), "{}", err);
+    assert!(started.elapsed() < StdDuration::from_secs(10));
+}
+
 #[tokio::test]
 async fn run_cli_completion_writes_tagged_prompts_to_stdin() {
     let dir = tempdir().unwrap();
@@ -210,3 +235,28 @@ printf '%s' '{{"type":"result","subtype":"success","result":"<cmd>echo hi</cmd>"
     .collect();
     assert_eq!(args, expected);
 }
+
+#[tokio::test]
+async fn run_cli_completion_reports_missing_binary_with_config_hint() {
+    let dir = tempdir().unwrap();
+    let missing = dir.path().join("no-such-codex");
+    let err = run_cli_completion(CliCompletionRequest {
+        engine: CliEngine::Codex,
+        binary: missing.to_str().unwrap(),
+        base_args: &[],
+        system_prompt: "SYSTEM",
+        user_prompt: "USER",
+        model: "gpt-5",
+        reasoning_effort: None,
+        debug: false,
+        timeout: Duration::from_secs(5),
+    })
+    .await
+    .expect_err("missing binary should fail");
+
+    let message = err.to_string();
+    assert!(message.contains("no-such-codex"), "{}", message);
+    assert!(message.contains("codex engine"), "{}", message);
+    assert!(message.contains("not found"), "{}", message);
+    assert!(message.contains("cli.binary"), "{}", message);
+}
@@ -2,8 +2,9 @@ use assert_cmd::cargo::cargo_bin_cmd;
 use fs_err as fs;
 use qqqa::config::{Config, ProviderConnection};
 use serial_test::serial;
+use std::net::TcpListener;
 use std::path::Path;
-use tempfile::tempdir;
+use tempfile::{TempDir, tempdir};
 
 fn run_init_with_bin(bin: &str, input: &str) -> Config {
     let home = tempdir().expect("temp dir");
@@ -43,6 +44,72 @@ fn read_config_from_home(home: &Path) -> Config {
     serde_json::from_slice(&bytes).expect("parse config")
 }
 
+fn sandbox_blocks_binding() -> bool {
+    TcpListener::bind("127.0.0.1:0").is_err()
+}
+
+/// Default config with the local ollama profile pointed at `base_url`.
+fn ollama_config(base_url: &str) -> Config {
+    let mut cfg = Config {
+        default_profile: "ollama".into(),
+        ..Default::default()
+    };
+    cfg.model_providers.get_mut("ollama").unwrap().base_url = base_url.into();
+    cfg
+}
+
+/// Temp HOME whose `~/.qq/config.json` is `cfg`.
+fn home_with(cfg: &Config) -> TempDir {
+    let home = tempdir().expect("temp dir");
+    let dir = home.path().join(".qq");
+    fs::create_dir_all(&dir).unwrap();
+    cfg.save(&dir.join("config.json"), false).unwrap();
+    home
+}
+
+/// Temp HOME whose config sends the default ollama profile to `base_url`.
+fn home_with_config(base_url: &str) -> TempDir {
+    home_with(&ollama_config(base_url))
+}
+
+#[test]
+#[serial]
+fn qa_max_steps_stops_before_the_tool_call() {
+    if sandbox_blocks_binding() {
+        eprintln!("[skip] sandbox blocks binding to 127.0.0.1; skipping httpmock test");
+        return;
+    }
+    let server = httpmock::MockServer::start();
+    server.mock(|when, then| {
+        when.method(httpmock::Method::POST).path("/chat/completions");
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(
+                r#"{"choices":[{"message":{"tool_calls":[{"type":"function","function":{"name":"execute_command","arguments":"{\"command\":\"echo should-not-run\"}"}}]}}],"usage":{"prompt_tokens":40,"completion_tokens":2}}"#,
+            );
+    });
+
+    let home = home_with_config(&server.base_url());
+
+    let mut cmd = cargo_bin_cmd!("qa");
+    let output = cmd
+        .args(["--no-history", "--yes", "--max-steps", "0", "say hi"])
+        .env("HOME", home.path())
+        .output()
+        .expect("run qa");
+    assert!(!output.status.success(), "{:?}", output);
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("should-not-run"));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(
+            "qa stopped before the tool call: --max-steps 0 reached \
+             (used 1 request, 42 tokens, 0 tool calls)."
+        ),
+        "{}",
+        stderr
+    );
+}
+
 #[test]
 #[serial]
 fn local_provider_uses_placeholder_api_key_when_env_missing() {
@@ -97,10 +164,10 @@ fn claude_cli_profile_resolves_to_cli_backend() {
 #[test]
 fn cli_model_override_forces_model_when_set() {
     let mut cfg = Config::default();
-    if let Some(provider) = cfg.model_providers.get_mut("claude_cli") {
-        if let Some(cli) = provider.cli.as_mut() {
-            cli.model_override = Some("claude-custom".to_string());
-        }
+    if let Some(provider) = cfg.model_providers.get_mut("claude_cli")
+        && let Some(cli) = provider.cli.as_mut()
+    {
+        cli.model_override = Some("claude-custom".to_string());
     }
 
     let eff = cfg
@@ -217,6 +284,186 @@ fn cli_mode_does_not_require_base_url() {
     }
 }
 
+#[test]
+#[serial]
+fn extra_body_must_be_json_object() {
+    unsafe {
+        std::env::remove_var("OLLAMA_API_KEY");
+    }
+    let mut cfg = Config::default();
+    let profile = cfg.profiles.get_mut("ollama").expect("ollama profile");
+    profile.extra_body = Some(serde_json::json!({"frequency_penalty": 0.2}));
+    let eff = cfg
+        .resolve_profile(Some("ollama"), None, None)
+        .expect("object extra_body resolves");
+    assert!(eff.extra_body.unwrap().contains_key("frequency_penalty"));
+
+    let profile = cfg.profiles.get_mut("ollama").expect("ollama profile");
+    profile.extra_body = Some(serde_json::json!(["not", "an", "object"]));
+    let err = cfg
+        .resolve_profile(Some("ollama"), None, None)
+        .expect_err("array extra_body should be rejected");
+    assert!(err.to_string().contains("extra_body"));
+}
+
+#[test]
+#[serial]
+fn openrouter_routing_becomes_provider_object() {
+    use qqqa::config::OpenRouterRouting;
+    let mut cfg = Config::default();
+    let routing = OpenRouterRouting {
+        route_order: vec!["groq".into(), "cerebras".into()],
+        allow_fallbacks: Some(false),
+        sort: Some("throughput".into()),
+    };
+    for provider in cfg.model_providers.values_mut() {
+        provider.api_key = Some("test".into());
+        provider.routing = Some(routing.clone());
+    }
+    let eff = cfg
+        .resolve_profile(Some("openrouter"), None, None)
+        .expect("openrouter profile resolves");
+    let extra = eff.extra_body.expect("routing populates extra_body");
+    assert_eq!(
+        extra["provider"],
+        serde_json::json!({
+            "order": ["groq", "cerebras"],
+            "allow_fallbacks": false,
+            "sort": "throughput"
+        })
+    );
+
+    // Other providers ignore the routing block.
+    let eff = cfg
+        .resolve_profile(Some("openai"), None, None)
+        .expect("openai profile resolves");
+    assert!(eff.extra_body.is_none());
+}
+
+#[test]
+#[serial]
+fn reasoning_style_defaults_to_flat_and_parses_object() {
+    use qqqa::config::ReasoningStyle;
+    let mut cfg = Config::default();
+    for provider in cfg.model_providers.values_mut() {
+        provider.api_key = Some("test".into());
+    }
+    let eff = cfg
+        .resolve_profile(Some("openrouter"), None, None)
+        .expect("openrouter profile resolves");
+    assert_eq!(eff.reasoning_style, ReasoningStyle::Flat);
+
+    let style: ReasoningStyle = serde_json::from_str("\"object\"").unwrap();
+    assert_eq!(style, ReasoningStyle::Object);
+    cfg.model_providers
+        .get_mut("openrouter")
+        .unwrap()
+        .reasoning_style = Some(style);
+    let eff = cfg
+        .resolve_profile(Some("openrouter"), None, None)
+        .expect("openrouter profile resolves");
+    assert_eq!(eff.reasoning_style, ReasoningStyle::Object);
+}
+
+#[test]
+fn model_aliases_expand_cli_and_profile_models() {
+    let mut cfg = Config::default();
+    for provider in cfg.model_providers.values_mut() {
+        provider.api_key = Some("test".into());
+    }
+    cfg.model_aliases
+        .insert("sonnet".into(), "claude-3-5-sonnet-20241022".into());
+
+    let eff = cfg
+        .resolve_profile(Some("openrouter"), Some("sonnet"), None)
+        .expect("alias resolves");
+    assert_eq!(eff.model, "claude-3-5-sonnet-20241022");
+
+    cfg.profiles.get_mut("openrouter").unwrap().model = "sonnet".into();
+    let eff = cfg
+        .resolve_profile(Some("openrouter"), None, None)
+        .expect("profile alias resolves");
+    assert_eq!(eff.model, "claude-3-5-sonnet-20241022");
+
+    let eff = cfg
+        .resolve_profile(Some("openrouter"), Some("gpt-4.1-mini"), None)
+        .expect("non-alias passes through");
+    assert_eq!(eff.model, "gpt-4.1-mini");
+}
+
+#[test]
+fn keychain_entry_takes_precedence_over_inline_key() {
+    let mut cfg = Config::default();
+    let provider = cfg.model_providers.get_mut("openai").unwrap();
+    provider.api_key = Some("inline".into());
+    provider.api_key_keychain = Some("qqqa-test-missing-entry".into());
+
+    let summary = cfg
+        .profile_summaries()
+        .into_iter()
+        .find(|row| row.name == "openai")
+        .unwrap();
+    assert_eq!(summary.key.to_string(), "keychain qqqa-test-missing-entry");
+
+    // The inline key must not be used as a silent fallback.
+    let err = cfg
+        .resolve_profile(Some("openai"), None, None)
+        .expect_err("missing keychain entry should fail");
+    let msg = format!("{:#}", err);
+    assert!(msg.contains("qqqa-test-missing-entry"), "{}", msg);
+    #[cfg(not(feature = "keychain"))]
+    assert!(msg.contains("`keychain` feature"), "{}", msg);
+}
+
+#[test]
+fn ollama_options_serialize_into_request_body() {
+    let mut cfg = Config::default();
+    let provider: qqqa::config::ModelProvider = serde_json::from_value(serde_json::json!({
+        "name": "Ollama",
+        "base_url": "http://127.0.0.1:11434/v1",
+        "env_key": "OLLAMA_API_KEY",
+        "local": true,
+        "options": {"num_ctx": 8192, "keep_alive": "10m"}
+    }))
+    .expect("provider parses");
+    cfg.model_providers.insert("ollama".into(), provider);
+
+    let eff = cfg
+        .resolve_profile(Some("ollama"), None, None)
+        .expect("ollama resolves");
+    let body = eff.extra_body.expect("options reach the body");
+    assert_eq!(body["options"], serde_json::json!({"num_ctx": 8192}));
+    assert_eq!(body["keep_alive"], "10m");
+
+    cfg.model_providers.get_mut("ollama").unwrap().options = Some(serde_json::json!([1]));
+    assert!(cfg.resolve_profile(Some("ollama"), None, None).is_err());
+}
+
+#[test]
+fn provider_network_settings_reach_http_connection() {
+    use qqqa::config::NetworkConfig;
+    let mut cfg = Config::default();
+    for provider in cfg.model_providers.values_mut() {
+        provider.api_key = Some("test".into());
+    }
+    let eff = cfg
+        .resolve_profile(Some("openrouter"), None, None)
+        .expect("openrouter profile resolves");
+    assert_eq!(eff.http().unwrap().network, NetworkConfig::default());
+
+    let network: NetworkConfig =
+        serde_json::from_str(r#"{"pool_idle_timeout_secs": 300, "tcp_keepalive_secs": 30}"#)
+            .unwrap();
+    cfg.model_providers.get_mut("openrouter").unwrap().network = Some(network);
+    let eff = cfg
+        .resolve_profile(Some("openrouter"), None, None)
+        .expect("openrouter profile resolves");
+    let got = eff.http().unwrap().network;
+    assert_eq!(got.pool_idle_timeout_secs, Some(300));
+    assert_eq!(got.tcp_keepalive_secs, Some(30));
+    assert_eq!(got.pool_max_idle_per_host, None);
+}
+
 #[test]
 #[serial]
 fn init_defaults_to_openrouter_profile_and_keeps_history_off() {
@@ -355,3 +602,1206 @@ fn qq_disable_auto_copy_flag_persists_without_question() {
     let cfg = read_config_from_home(&home_path);
     assert!(!cfg.copy_first_command_enabled());
 }
+
+#[test]
+fn model_profile_hint_suggests_matching_profile() {
+    let cfg = Config::default();
+    let hint = cfg
+        .model_profile_hint(None, Some("claude-3-5-sonnet"))
+        .expect("claude model on default profile should produce a hint");
+    assert!(hint.contains("Anthropic"), "{}", hint);
+    assert!(hint.contains("-p anthropic"), "{}", hint);
+
+    let hint = cfg
+        .model_profile_hint(None, Some("gpt-5-mini"))
+        .expect("gpt model on openrouter default should produce a hint");
+    assert!(hint.contains("-p openai"), "{}", hint);
+
+    // Explicit profile, vendor-prefixed slugs, and unknown names stay quiet.
+    assert!(
+        cfg.model_profile_hint(Some("openrouter"), Some("claude-3-5-sonnet"))
+            .is_none()
+    );
+    assert!(
+        cfg.model_profile_hint(None, Some("anthropic/claude-3.5-sonnet"))
+            .is_none()
+    );
+    assert!(
+        cfg.model_profile_hint(None, Some("my-custom-model"))
+            .is_none()
+    );
+    assert!(cfg.model_profile_hint(None, None).is_none());
+}
+
+#[test]
+#[serial]
+fn resolve_profile_error_includes_model_hint() {
+    unsafe {
+        std::env::remove_var("OPENROUTER_API_KEY");
+    }
+    let cfg = Config::default();
+    let err = cfg
+        .resolve_profile(None, Some("claude-3-5-sonnet"), None)
+        .expect_err("missing openrouter key should fail");
+    let msg = err.to_string();
+    assert!(msg.contains("Missing API key"), "{}", msg);
+    assert!(
+        msg.contains("Hint: model 'claude-3-5-sonnet' looks like Anthropic"),
+        "{}",
+        msg
+    );
+}
+
+#[test]
+fn version_flag_includes_build_metadata() {
+    for (bin, mut cmd) in [("qq", cargo_bin_cmd!("qq")), ("qa", cargo_bin_cmd!("qa"))] {
+        let output = cmd.arg("--version").output().expect("run --version");
+        assert!(output.status.success());
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert_eq!(
+            stdout.trim(),
+            format!("{} {}", bin, qqqa::version::LONG_VERSION)
+        );
+        assert!(stdout.contains(qqqa::version::GIT_HASH));
+    }
+}
+
+#[test]
+#[serial]
+fn profile_summaries_report_key_sources_and_default() {
+    use qqqa::config::KeyStatus;
+    let mut cfg = Config::default();
+    cfg.model_providers.get_mut("groq").unwrap().api_key = Some("inline".into());
+    unsafe {
+        std::env::set_var("OPENAI_API_KEY", "sk-test");
+        std::env::remove_var("OPENROUTER_API_KEY");
+    }
+    let rows = cfg.profile_summaries();
+    unsafe {
+        std::env::remove_var("OPENAI_API_KEY");
+    }
+    let find = |name: &str| rows.iter().find(|r| r.name == name).expect("profile row");
+
+    let names: Vec<&str> = rows.iter().map(|r| r.name.as_str()).collect();
+    let mut sorted = names.clone();
+    sorted.sort();
+    assert_eq!(names, sorted);
+
+    assert!(find("openrouter").is_default);
+    assert_eq!(
+        find("openrouter").key,
+        KeyStatus::Missing("OPENROUTER_API_KEY".into())
+    );
+    assert_eq!(find("groq").key, KeyStatus::Inline);
+    assert_eq!(find("openai").key, KeyStatus::Env("OPENAI_API_KEY".into()));
+    assert_eq!(find("ollama").key, KeyStatus::LocalPlaceholder);
+    assert_eq!(find("codex").key, KeyStatus::NotRequired);
+    assert_eq!(rows.iter().filter(|r| r.is_default).count(), 1);
+}
+
+#[test]
+#[serial]
+fn qq_list_profiles_marks_default_without_network() {
+    let home = tempdir().expect("temp dir");
+    let mut cmd = cargo_bin_cmd!("qq");
+    let output = cmd
+        .arg("--list-profiles")
+        .env("HOME", home.path())
+        .env_remove("OPENROUTER_API_KEY")
+        .output()
+        .expect("run qq --list-profiles");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let default_line = stdout
+        .lines()
+        .find(|l| l.starts_with('*'))
+        .expect("default profile marked");
+    assert!(default_line.contains("openrouter"));
+    assert!(default_line.contains("key: missing (set OPENROUTER_API_KEY)"));
+    assert!(stdout.contains("ollama"));
+}
+
+#[test]
+fn qq_print_default_config_emits_parseable_defaults_without_writing() {
+    let home = tempdir().expect("temp dir");
+    let mut cmd = cargo_bin_cmd!("qq");
+    let output = cmd
+        .arg("--print-default-config")
+        .env("HOME", home.path())
+        .output()
+        .expect("run qq --print-default-config");
+    assert!(output.status.success(), "{output:?}");
+    let printed: Config = serde_json::from_slice(&output.stdout).expect("config JSON");
+    let defaults = Config::default();
+    assert_eq!(printed.default_profile, defaults.default_profile);
+    assert_eq!(
+        printed.model_providers.len(),
+        defaults.model_providers.len()
+    );
+    assert_eq!(printed.profiles.len(), defaults.profiles.len());
+    assert!(!home.path().join(".qq").exists());
+}
+
+#[test]
+#[serial]
+fn qq_uses_default_raw_and_no_stream_from_config_unless_overridden() {
+    if sandbox_blocks_binding() {
+        eprintln!("[skip] sandbox blocks binding to 127.0.0.1; skipping httpmock test");
+        return;
+    }
+    let server = httpmock::MockServer::start();
+    let mock = server.mock(|when, then| {
+        when.method(httpmock::Method::POST)
+            .path("/chat/completions")
+            .matches(|req| {
+                let body: serde_json::Value =
+                    serde_json::from_slice(req.body.as_deref().unwrap_or_default())
+                        .unwrap_or_default();
+                body.get("stream").is_none()
+            });
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(r#"{"choices":[{"message":{"content":"<cmd>ls -la</cmd>"}}]}"#);
+    });
+
+    let mut cfg = ollama_config(&server.base_url());
+    cfg.default_raw = true;
+    cfg.default_no_stream = true;
+    let home = home_with(&cfg);
+
+    let run = |extra: &[&str]| {
+        let mut cmd = cargo_bin_cmd!("qq");
+        let output = cmd
+            .args(extra)
+            .args(["--no-history", "--no-copy-command", "list files"])
+            .env("HOME", home.path())
+            .output()
+            .expect("run qq");
+        assert!(output.status.success(), "{:?}", output);
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    // default_raw selects the plain format: tags stripped.
+    let plain = run(&[]);
+    assert!(plain.contains("ls -la"), "{plain}");
+    assert!(!plain.contains("<cmd>"), "{plain}");
+    let markdown = run(&["--format", "markdown"]);
+    assert!(markdown.contains("`ls -la`"), "{markdown}");
+    mock.assert_hits(2);
+}
+
+#[test]
+#[serial]
+fn qq_falls_back_to_the_next_profile_on_a_server_error() {
+    if sandbox_blocks_binding() {
+        eprintln!("[skip] sandbox blocks binding to 127.0.0.1; skipping httpmock test");
+        return;
+    }
+    let server = httpmock::MockServer::start();
+    let mut primary = server.mock(|when, then| {
+        when.method(httpmock::Method::POST)
+            .path("/chat/completions")
+            .body_contains("primary-model");
+        then.status(500).body("upstream exploded");
+    });
+    let backup = server.mock(|when, then| {
+        when.method(httpmock::Method::POST)
+            .path("/chat/completions")
+            .body_contains("backup-model");
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(r#"{"choices":[{"message":{"content":"answer from backup"}}]}"#);
+    });
+
+    let mut cfg = ollama_config(&server.base_url());
+    let mut backup_profile = cfg.profiles["ollama"].clone();
+    backup_profile.model = "backup-model".into();
+    cfg.profiles.insert("backup".into(), backup_profile);
+    let profile = cfg.profiles.get_mut("ollama").unwrap();
+    profile.model = "primary-model".into();
+    profile.fallback_profiles = vec!["backup".into()];
+    let home = home_with(&cfg);
+
+    let mut cmd = cargo_bin_cmd!("qq");
+    let output = cmd
+        .args(["--no-history", "--no-stream", "--debug", "list files"])
+        .env("HOME", home.path())
+        .env_remove("QQQA_MODEL")
+        .output()
+        .expect("run qq");
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("answer from backup"), "{stdout}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("trying fallback profile 'backup'"),
+        "{stderr}"
+    );
+    assert!(
+        stderr.contains("Answered by fallback profile 'backup'"),
+        "{stderr}"
+    );
+    primary.assert_hits(1);
+    backup.assert_hits(1);
+
+    // A request the provider rejects as malformed is the caller's problem: no fallback.
+    primary.delete();
+    let bad_request = server.mock(|when, then| {
+        when.method(httpmock::Method::POST)
+            .path("/chat/completions")
+            .body_contains("primary-model");
+        then.status(400).body("bad request");
+    });
+    let mut cmd = cargo_bin_cmd!("qq");
+    let output = cmd
+        .args(["--no-history", "--no-stream", "list files"])
+        .env("HOME", home.path())
+        .env_remove("QQQA_MODEL")
+        .output()
+        .expect("run qq");
+    assert!(!output.status.success());
+    bad_request.assert_hits(1);
+    backup.assert_hits(1);
+}
+
+#[test]
+#[serial]
+fn qq_batch_answers_each_line_and_emits_json() {
+    if sandbox_blocks_binding() {
+        eprintln!("[skip] sandbox blocks binding to 127.0.0.1; skipping httpmock test");
+        return;
+    }
+    let server = httpmock::MockServer::start();
+    let alpha = server.mock(|when, then| {
+        when.method(httpmock::Method::POST)
+            .path("/chat/completions")
+            .body_contains("question alpha");
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(r#"{"choices":[{"message":{"content":"answer alpha"}}]}"#);
+    });
+    let beta = server.mock(|when, then| {
+        when.method(httpmock::Method::POST)
+            .path("/chat/completions")
+            .body_contains("question beta");
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(r#"{"choices":[{"message":{"content":"answer beta"}}]}"#);
+    });
+
+    let home = home_with_config(&server.base_url());
+    let batch = home.path().join("questions.txt");
+    fs::write(&batch, "question alpha\n\n  question beta  \n").unwrap();
+
+    let mut cmd = cargo_bin_cmd!("qq");
+    let output = cmd
+        .args(["--no-history", "--json", "--batch"])
+        .arg(&batch)
+        .env("HOME", home.path())
+        .output()
+        .expect("run qq");
+    assert!(output.status.success(), "{:?}", output);
+    let results: serde_json::Value = serde_json::from_slice(&output.stdout).expect("json output");
+    assert_eq!(
+        results,
+        serde_json::json!([
+            {"question": "question alpha", "answer": "answer alpha"},
+            {"question": "question beta", "answer": "answer beta"}
+        ])
+    );
+    alpha.assert();
+    beta.assert();
+}
+
+#[test]
+#[serial]
+fn api_base_override_behaves_the_same_for_qq_and_qa() {
+    if sandbox_blocks_binding() {
+        eprintln!("[skip] sandbox blocks binding to 127.0.0.1; skipping httpmock test");
+        return;
+    }
+    let server = httpmock::MockServer::start();
+    let mock = server.mock(|when, then| {
+        when.method(httpmock::Method::POST)
+            .path("/chat/completions");
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(r#"{"choices":[{"message":{"content":"overridden base answered"}}]}"#);
+    });
+
+    // Unreachable on purpose: only the override should be contacted.
+    let home = home_with_config("http://127.0.0.1:9/v1");
+
+    for (bin, flag) in [
+        ("qq", "--api-base"),
+        ("qa", "--api-base"),
+        ("qa", "--base-url"),
+    ] {
+        let mut cmd = if bin == "qq" {
+            cargo_bin_cmd!("qq")
+        } else {
+            cargo_bin_cmd!("qa")
+        };
+        if bin == "qq" {
+            cmd.arg("--no-stream");
+        }
+        let output = cmd
+            .args(["--no-history", flag, &server.base_url(), "say hi"])
+            .env("HOME", home.path())
+            .output()
+            .expect("run binary");
+        assert!(output.status.success(), "{} {}: {:?}", bin, flag, output);
+        assert!(
+            String::from_utf8_lossy(&output.stdout).contains("overridden base answered"),
+            "{}: {:?}",
+            bin,
+            output
+        );
+
+        let mut cmd = if bin == "qq" {
+            cargo_bin_cmd!("qq")
+        } else {
+            cargo_bin_cmd!("qa")
+        };
+        let output = cmd
+            .args(["-p", "codex", flag, "http://localhost:1/v1", "say hi"])
+            .env("HOME", home.path())
+            .output()
+            .expect("run binary");
+        assert!(!output.status.success());
+        assert!(
+            String::from_utf8_lossy(&output.stderr)
+                .contains("--api-base override is only supported for HTTP providers"),
+            "{}: {:?}",
+            bin,
+            output
+        );
+    }
+    mock.assert_hits(3);
+}
+
+#[test]
+#[serial]
+fn gpt5_temperature_warning_prints_unless_quiet() {
+    if sandbox_blocks_binding() {
+        eprintln!("[skip] sandbox blocks binding to 127.0.0.1; skipping httpmock test");
+        return;
+    }
+    let server = httpmock::MockServer::start();
+    server.mock(|when, then| {
+        when.method(httpmock::Method::POST)
+            .path("/chat/completions");
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(r#"{"choices":[{"message":{"content":"ok"}}]}"#);
+    });
+
+    let home = home_with_config(&server.base_url());
+
+    let stderr_for = |extra: &[&str]| {
+        let mut cmd = cargo_bin_cmd!("qq");
+        let output = cmd
+            .args(["--no-history", "--no-stream", "-m", "gpt-5-mini"])
+            .args(extra)
+            .arg("hi")
+            .env("HOME", home.path())
+            .output()
+            .expect("run qq");
+        assert!(output.status.success(), "{:?}", output);
+        String::from_utf8_lossy(&output.stderr).into_owned()
+    };
+
+    let warned = stderr_for(&["--temperature", "0.3"]);
+    assert_eq!(
+        warned
+            .matches("[warn] GPT-5 model 'gpt-5-mini' ignores --temperature 0.3")
+            .count(),
+        1,
+        "{}",
+        warned
+    );
+    assert!(!stderr_for(&["--temperature", "0.3", "--quiet"]).contains("[warn]"));
+    assert!(!stderr_for(&[]).contains("[warn]"));
+}
+
+#[test]
+#[serial]
+fn authorization_header_warning_prints_unless_quiet() {
+    if sandbox_blocks_binding() {
+        eprintln!("[skip] sandbox blocks binding to 127.0.0.1; skipping httpmock test");
+        return;
+    }
+    let server = httpmock::MockServer::start();
+    server.mock(|when, then| {
+        when.method(httpmock::Method::POST)
+            .path("/chat/completions")
+            .header("authorization", "Basic Z2F0ZXdheQ==");
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(r#"{"choices":[{"message":{"content":"ok"}}]}"#);
+    });
+
+    let home = home_with_config(&server.base_url());
+
+    let stderr_for = |extra: &[&str]| {
+        let mut cmd = cargo_bin_cmd!("qq");
+        let output = cmd
+            .args(["--no-history", "--no-stream"])
+            .args(["--header", "Authorization: Basic Z2F0ZXdheQ=="])
+            .args(extra)
+            .arg("hi")
+            .env("HOME", home.path())
+            .output()
+            .expect("run qq");
+        assert!(output.status.success(), "{:?}", output);
+        String::from_utf8_lossy(&output.stderr).into_owned()
+    };
+
+    let warned = stderr_for(&[]);
+    assert_eq!(
+        warned
+            .matches("[warn] --header replaces the Authorization header")
+            .count(),
+        1,
+        "{}",
+        warned
+    );
+    assert!(!stderr_for(&["--quiet"]).contains("[warn]"));
+}
+
+#[test]
+#[serial]
+fn qq_dry_run_prints_messages_without_calling_provider() {
+    // Nothing listens here; a real request would fail the run.
+    let home = home_with_config("http://127.0.0.1:9/v1");
+
+    let mut cmd = cargo_bin_cmd!("qq");
+    let output = cmd
+        .args(["--no-history", "--dry-run", "--json", "explain this"])
+        .env("HOME", home.path())
+        .write_stdin("piped context")
+        .output()
+        .expect("run qq");
+    assert!(output.status.success(), "{:?}", output);
+    let dump: serde_json::Value = serde_json::from_slice(&output.stdout).expect("json output");
+    assert_eq!(dump["provider"], "ollama");
+    assert_eq!(dump["model"], "llama3.1");
+    let messages = dump["messages"].as_array().unwrap();
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[0]["role"], "system");
+    let user = messages[1]["content"].as_str().unwrap();
+    assert!(user.contains("piped context") && user.contains("explain this"));
+
+    let mut cmd = cargo_bin_cmd!("qq");
+    let output = cmd
+        .args(["--no-history", "--dry-run", "explain this"])
+        .env("HOME", home.path())
+        .output()
+        .expect("run qq");
+    assert!(output.status.success(), "{:?}", output);
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("--- system ---") && stderr.contains("--- user ---"));
+
+    let mut cmd = cargo_bin_cmd!("qq");
+    cmd.args(["--json", "hi"]).env("HOME", home.path());
+    cmd.assert().failure();
+}
+
+#[test]
+fn qq_shell_flag_overrides_detected_shell_in_prompt() {
+    let home = home_with_config("http://127.0.0.1:9/v1");
+
+    let mut cmd = cargo_bin_cmd!("qq");
+    let output = cmd
+        .args([
+            "--no-history",
+            "--dry-run",
+            "--debug",
+            "--shell",
+            "powershell",
+            "list files",
+        ])
+        .env("HOME", home.path())
+        .output()
+        .expect("run qq");
+    assert!(output.status.success(), "{:?}", output);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Windows PowerShell"), "{stderr}");
+    assert!(stderr.contains("shell=powershell"), "{stderr}");
+    assert!(stderr.contains("from --shell"), "{stderr}");
+
+    let mut cmd = cargo_bin_cmd!("qq");
+    cmd.args(["--shell", "fish", "hi"]).env("HOME", home.path());
+    cmd.assert().failure();
+}
+
+#[test]
+fn windows_output_encoding_parses_or_rejects_config_values() {
+    use qqqa::tools::output_encoding::OutputEncoding;
+
+    let mut cfg = Config::default();
+    assert_eq!(cfg.windows_output_encoding().unwrap(), OutputEncoding::Utf8);
+    cfg.windows_output_encoding = Some("CP850".into());
+    assert_eq!(
+        cfg.windows_output_encoding().unwrap(),
+        OutputEncoding::Cp850
+    );
+    cfg.windows_output_encoding = Some("klingon".into());
+    let err = cfg.windows_output_encoding().unwrap_err().to_string();
+    assert!(err.contains("windows_output_encoding 'klingon'"), "{err}");
+}
+
+#[test]
+#[serial]
+fn qa_explain_prints_reason_before_running_tool() {
+    if sandbox_blocks_binding() {
+        eprintln!("[skip] sandbox blocks binding to 127.0.0.1; skipping httpmock test");
+        return;
+    }
+    let server = httpmock::MockServer::start();
+    let mock = server.mock(|when, then| {
+        when.method(httpmock::Method::POST)
+            .path("/chat/completions")
+            .body_contains("include a short \\\"reason\\\" string")
+            .body_contains(r#""reason":{"#);
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(
+                r#"{"choices":[{"message":{"tool_calls":[{"id":"c1","type":"function","function":{"name":"read_file","arguments":"{\"path\":\"note.txt\",\"reason\":\"Check what the note says.\"}"}}]}}]}"#,
+            );
+    });
+
+    let home = home_with_config(&server.base_url());
+    fs::write(home.path().join("note.txt"), "remember the milk\n").unwrap();
+
+    let mut cmd = cargo_bin_cmd!("qa");
+    let output = cmd
+        .args(["--no-history", "--explain", "what does my note say"])
+        .env("HOME", home.path())
+        .current_dir(home.path())
+        .output()
+        .expect("run qa");
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let why = stdout
+        .find("why: Check what the note says.")
+        .expect("reason line");
+    let tool = stdout.find("[tool:read_file]").expect("tool header");
+    assert!(why < tool, "{stdout}");
+    assert!(stdout.contains("remember the milk"), "{stdout}");
+    mock.assert();
+}
+
+#[test]
+fn qq_answers_from_mock_response_file_without_network_or_key() {
+    // Default openrouter profile with no key configured anywhere.
+    let home = home_with(&Config::default());
+    let plain = home.path().join("answer.txt");
+    fs::write(&plain, "canned plain answer\n").unwrap();
+    let sse = home.path().join("answer.sse");
+    fs::write(
+        &sse,
+        "data: {\"choices\":[{\"delta\":{\"content\":\"streamed \"}}]}\n\ndata: {\"choices\":[{\"delta\":{\"content\":\"canned\"}}]}\n\ndata: [DONE]\n",
+    )
+    .unwrap();
+
+    for (file, extra, expected) in [
+        (&plain, "--no-stream", "canned plain answer"),
+        (&plain, "--stream", "canned plain answer"),
+        (&sse, "--stream", "streamed canned"),
+        (&sse, "--no-stream", "streamed canned"),
+    ] {
+        let mut cmd = cargo_bin_cmd!("qq");
+        let output = cmd
+            .args(["--no-history", "--raw", extra, "anything"])
+            .env("HOME", home.path())
+            .env_remove("OPENROUTER_API_KEY")
+            .env_remove("QQQA_API_KEY")
+            .env("QQQA_MOCK_RESPONSE_FILE", file)
+            .output()
+            .expect("run qq");
+        assert!(output.status.success(), "{extra} {file:?}: {output:?}");
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(stdout.contains(expected), "{extra} {file:?}: {stdout}");
+    }
+
+    let mut cmd = cargo_bin_cmd!("qq");
+    let output = cmd
+        .args(["--no-history", "anything"])
+        .env("HOME", home.path())
+        .env("QQQA_MOCK_RESPONSE_FILE", home.path().join("missing.txt"))
+        .output()
+        .expect("run qq");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("QQQA_MOCK_RESPONSE_FILE"));
+}
+
+#[test]
+fn qq_command_only_prints_just_the_first_command() {
+    let home = home_with(&Config::default());
+    let answer = home.path().join("answer.txt");
+    fs::write(
+        &answer,
+        "Use tar:\n<cmd>tar -czf dir.tgz dir &amp;&amp; ls</cmd>\nor <cmd>zip -r dir.zip dir</cmd>\n",
+    )
+    .unwrap();
+    let no_cmd = home.path().join("plain.txt");
+    fs::write(&no_cmd, "I cannot help with that.\n").unwrap();
+
+    let run = |file: &Path| {
+        let mut cmd = cargo_bin_cmd!("qq");
+        cmd.args(["--no-history", "--command-only", "compress this dir"])
+            .env("HOME", home.path())
+            .env("QQQA_MOCK_RESPONSE_FILE", file)
+            .output()
+            .expect("run qq")
+    };
+
+    let output = run(&answer);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "tar -czf dir.tgz dir && ls\n"
+    );
+
+    let output = run(&no_cmd);
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("no <cmd> block"));
+}
+
+#[test]
+fn qq_replay_reruns_the_last_question_with_its_flags() {
+    let home = tempdir().expect("temp dir");
+    let run = |args: &[&str]| {
+        let mut cmd = cargo_bin_cmd!("qq");
+        cmd.args(args)
+            .env("HOME", home.path())
+            .env("QQQA_API_KEY", "test-key")
+            .output()
+            .expect("run qq")
+    };
+
+    let output = run(&["--replay"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("No previous qq run to replay"));
+
+    let output = run(&[
+        "--no-history",
+        "--dry-run",
+        "-m",
+        "first-model",
+        "hello",
+        "there",
+    ]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(home.path().join(".qq/last_session.json").exists());
+
+    let output = run(&["--replay"]);
+    assert!(output.status.success(), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Replaying: qq --no-history --dry-run -m first-model 'hello there'"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("first-model"), "{stderr}");
+    assert!(stderr.contains("hello there"), "{stderr}");
+
+    // Flags passed with --replay are added after the stored ones and win.
+    let output = run(&["--replay", "-m", "second-model"]);
+    assert!(output.status.success(), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("second-model"), "{stderr}");
+    assert!(stderr.contains("hello there"), "{stderr}");
+}
+
+#[test]
+fn auto_copy_reports_missing_clipboard_helper_only_once() {
+    let mut cfg = Config::default();
+    cfg.set_copy_first_command(true);
+    let home = home_with(&cfg);
+    let answer = home.path().join("answer.txt");
+    fs::write(&answer, "<cmd>ls -la</cmd>\n").unwrap();
+    // An empty PATH leaves no clipboard helper to find.
+    let empty_path = home.path().join("bin");
+    fs::create_dir_all(&empty_path).unwrap();
+
+    let run = |extra: &[&str]| {
+        let mut cmd = cargo_bin_cmd!("qq");
+        let output = cmd
+            .args(["--no-history", "--raw", "--no-stream"])
+            .args(extra)
+            .arg("list files")
+            .env("HOME", home.path())
+            .env("PATH", &empty_path)
+            .env("QQQA_MOCK_RESPONSE_FILE", &answer)
+            .output()
+            .expect("run qq");
+        assert!(output.status.success(), "{output:?}");
+        String::from_utf8_lossy(&output.stderr).into_owned()
+    };
+
+    let first = run(&[]);
+    assert!(first.contains("Auto-copy skipped"), "{first}");
+    assert!(first.contains("shown once"), "{first}");
+    assert!(home.path().join(".qq/clipboard-missing").exists());
+
+    let second = run(&[]);
+    assert!(!second.contains("clipboard"), "{second}");
+    let debug = run(&["--debug"]);
+    assert!(debug.contains("[debug] Auto-copy skipped"), "{debug}");
+
+    let explicit = run(&["--copy-command"]);
+    assert!(
+        explicit.contains("Failed to copy first command to clipboard"),
+        "{explicit}"
+    );
+}
+
+#[test]
+fn qq_redacts_secrets_in_piped_stdin_unless_no_redact() {
+    let home = home_with_config("http://127.0.0.1:9/v1");
+
+    let mut cmd = cargo_bin_cmd!("qq");
+    let output = cmd
+        .args(["--no-history", "--dry-run", "--debug", "what is this"])
+        .env("HOME", home.path())
+        .write_stdin("DB_PASSWORD=hunter2\nUSER=bob\n")
+        .output()
+        .expect("run qq");
+    assert!(output.status.success(), "{:?}", output);
+    // --dry-run prints the request to stderr.
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!stderr.contains("hunter2"), "{stderr}");
+    assert!(stderr.contains("DB_PASSWORD=[REDACTED]"), "{stderr}");
+    assert!(stderr.contains("USER=bob"), "{stderr}");
+    assert!(
+        stderr.contains("Redaction masked 1 likely secret(s) in piped stdin"),
+        "{stderr}"
+    );
+
+    let mut cmd = cargo_bin_cmd!("qq");
+    let output = cmd
+        .args(["--no-history", "--dry-run", "--no-redact", "what is this"])
+        .env("HOME", home.path())
+        .write_stdin("DB_PASSWORD=hunter2\n")
+        .output()
+        .expect("run qq");
+    assert!(output.status.success(), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("DB_PASSWORD=hunter2"));
+}
+
+#[test]
+#[serial]
+fn qq_temperature_zero_reaches_request_body() {
+    if sandbox_blocks_binding() {
+        eprintln!("[skip] sandbox blocks binding to 127.0.0.1; skipping httpmock test");
+        return;
+    }
+    let server = httpmock::MockServer::start();
+    let mock = server.mock(|when, then| {
+        when.method(httpmock::Method::POST)
+            .path("/chat/completions")
+            .body_contains(r#""temperature":0.0"#);
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(r#"{"choices":[{"message":{"content":"cold answer"}}]}"#);
+    });
+
+    let home = home_with_config(&server.base_url());
+
+    let mut cmd = cargo_bin_cmd!("qq");
+    let output = cmd
+        .args(["--no-history", "--no-stream", "--temperature", "0", "hi"])
+        .env("HOME", home.path())
+        .output()
+        .expect("run qq");
+    assert!(output.status.success(), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("cold answer"));
+    mock.assert();
+}
+
+#[test]
+fn qq_run_sends_first_command_through_qa_safety_checks() {
+    let home = home_with(&Config::default());
+    let blocked = home.path().join("blocked.txt");
+    fs::write(&blocked, "Try this: <cmd>sudo reboot</cmd>\n").unwrap();
+    let no_cmd = home.path().join("no_cmd.txt");
+    fs::write(&no_cmd, "Nothing to run here.\n").unwrap();
+
+    let mut cmd = cargo_bin_cmd!("qq");
+    let output = cmd
+        .args(["--no-history", "--raw", "--no-stream", "--run", "reboot"])
+        .env("HOME", home.path())
+        .env("QQQA_MOCK_RESPONSE_FILE", &blocked)
+        .output()
+        .expect("run qq");
+    assert!(!output.status.success(), "{:?}", output);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Proposed command: sudo reboot"), "{stderr}");
+    assert!(stderr.contains("[blocked]"), "{stderr}");
+
+    let mut cmd = cargo_bin_cmd!("qq");
+    let output = cmd
+        .args(["--no-history", "--raw", "--no-stream", "--run", "hello"])
+        .env("HOME", home.path())
+        .env("QQQA_MOCK_RESPONSE_FILE", &no_cmd)
+        .output()
+        .expect("run qq");
+    assert!(output.status.success(), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("nothing to run"));
+}
+
+#[test]
+fn qa_plan_prints_steps_without_running_tools() {
+    let home = home_with(&Config::default());
+    let reply = home.path().join("plan.json");
+    fs::write(
+        &reply,
+        r#"[{"step":"Create the notes file","tool":"write_file","arguments":{"path":"notes.txt","content":"hi"}},{"step":"Show it","tool":"execute_command","arguments":{"command":"cat notes.txt"}}]"#,
+    )
+    .unwrap();
+
+    let mut cmd = cargo_bin_cmd!("qa");
+    let output = cmd
+        .args([
+            "--no-history",
+            "--raw",
+            "--plan",
+            "write hi to notes.txt and show it",
+        ])
+        .env("HOME", home.path())
+        .env("QQQA_MOCK_RESPONSE_FILE", &reply)
+        .current_dir(home.path())
+        .output()
+        .expect("run qa");
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Plan (nothing was executed):"), "{stdout}");
+    assert!(stdout.contains("1. Create the notes file"), "{stdout}");
+    assert!(
+        stdout.contains(r#"   execute_command {"command":"cat notes.txt"}"#),
+        "{stdout}"
+    );
+    assert!(!home.path().join("notes.txt").exists());
+}
+
+#[test]
+fn qq_stream_exits_cleanly_when_stdout_reader_closes() {
+    use std::io::Read;
+    use std::process::Stdio;
+
+    let home = home_with(&Config::default());
+    // Far more output than a pipe buffer holds, so writes fail once the reader is gone.
+    let mut sse = String::new();
+    for i in 0..20_000 {
+        sse.push_str(&format!(
+            "data: {{\"choices\":[{{\"delta\":{{\"content\":\"token{} \"}}}}]}}\n\n",
+            i
+        ));
+    }
+    sse.push_str("data: [DONE]\n");
+    let transcript = home.path().join("long.sse");
+    fs::write(&transcript, sse).unwrap();
+
+    let mut child = std::process::Command::new(env!("CARGO_BIN_EXE_qq"))
+        .args(["--no-history", "--raw", "--stream", "ramble"])
+        .env("HOME", home.path())
+        .env("QQQA_MOCK_RESPONSE_FILE", &transcript)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("spawn qq");
+    let mut stdout = child.stdout.take().unwrap();
+    let mut first = [0u8; 16];
+    stdout.read_exact(&mut first).unwrap();
+    drop(stdout);
+
+    let output = child.wait_with_output().expect("wait for qq");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{:?} {stderr}", output.status);
+    assert!(!stderr.contains("panicked"), "{stderr}");
+}
+
+#[test]
+fn qq_count_tokens_prints_breakdown_without_network() {
+    // Nothing listens here; any request would fail the run.
+    let home = home_with_config("http://127.0.0.1:9/v1");
+
+    let mut cmd = cargo_bin_cmd!("qq");
+    let output = cmd
+        .args(["--no-history", "--count-tokens", "what does this log say"])
+        .env("HOME", home.path())
+        .write_stdin("x".repeat(400))
+        .output()
+        .expect("run qq");
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.starts_with("Estimated prompt tokens"), "{stdout}");
+    assert!(stdout.contains("  stdin         100\n"), "{stdout}");
+    assert!(stdout.contains("  history         0\n"), "{stdout}");
+    assert!(stdout.contains("  total    "), "{stdout}");
+}
+
+#[test]
+fn qq_persona_appends_directive_to_system_prompt() {
+    let home = home_with_config("http://127.0.0.1:9/v1");
+
+    let mut cmd = cargo_bin_cmd!("qq");
+    let output = cmd
+        .args([
+            "--no-history",
+            "--dry-run",
+            "--persona",
+            "scripts-only",
+            "list files",
+        ])
+        .env("HOME", home.path())
+        .output()
+        .expect("run qq");
+    assert!(output.status.success(), "{:?}", output);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let system = stderr.split("--- user ---").next().unwrap();
+    assert!(system.contains("Persona: scripts-only."), "{stderr}");
+
+    let mut cmd = cargo_bin_cmd!("qq");
+    let output = cmd
+        .args(["--dry-run", "--persona", "pirate", "hi"])
+        .env("HOME", home.path())
+        .output()
+        .expect("run qq");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("available: concise, verbose"));
+}
+
+#[test]
+fn qa_no_tool_headers_prints_only_the_tool_result() {
+    let home = home_with(&Config::default());
+    fs::write(home.path().join("note.txt"), "remember the milk\n").unwrap();
+    let reply = home.path().join("reply.json");
+    fs::write(
+        &reply,
+        r#"{"choices":[{"message":{"tool_calls":[{"id":"c1","type":"function","function":{"name":"read_file","arguments":"{\"path\":\"note.txt\"}"}}]}}]}"#,
+    )
+    .unwrap();
+
+    let run = |extra: &[&str]| {
+        let mut cmd = cargo_bin_cmd!("qa");
+        cmd.args(["--no-history", "--raw"])
+            .args(extra)
+            .arg("what does my note say")
+            .env("HOME", home.path())
+            .env("QQQA_MOCK_RESPONSE_FILE", &reply)
+            .current_dir(home.path())
+            .output()
+            .expect("run qa")
+    };
+    let output = run(&["--no-tool-headers"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "remember the milk\n"
+    );
+
+    let output = run(&[]);
+    assert!(
+        String::from_utf8_lossy(&output.stdout).contains("[tool:read_file]\nremember the milk")
+    );
+}
+
+#[test]
+fn debug_lines_appear_only_with_debug_flag() {
+    let home = tempdir().expect("temp dir");
+    let answer = home.path().join("answer.txt");
+    fs::write(&answer, "All good.\n").unwrap();
+
+    let run = |extra: &[&str]| {
+        let mut cmd = cargo_bin_cmd!("qq");
+        let output = cmd
+            .args(["--no-history", "--raw", "--no-stream"])
+            .args(extra)
+            .arg("status?")
+            .env("HOME", home.path())
+            .env("OPENROUTER_API_KEY", "test-key")
+            .env("QQQA_MOCK_RESPONSE_FILE", &answer)
+            .output()
+            .expect("run qq");
+        assert!(output.status.success(), "{output:?}");
+        String::from_utf8_lossy(&output.stderr).into_owned()
+    };
+
+    let quiet = run(&[]);
+    assert!(!quiet.contains("[debug]"), "{quiet}");
+    let debug = run(&["--debug"]);
+    assert!(debug.contains("[debug] Environment: os="), "{debug}");
+    assert!(debug.contains("[debug] POST "), "{debug}");
+    assert!(
+        debug.contains("[debug] QQQA_MOCK_RESPONSE_FILE set"),
+        "{debug}"
+    );
+}
+
+#[test]
+fn save_profile_copies_base_and_refuses_overwrite_without_force() {
+    let mut cfg = Config::default();
+    let base = cfg.profiles[&cfg.default_profile].clone();
+    let draft = qqqa::config::ProfileDraft {
+        model: Some("gpt-5-mini"),
+        temperature: Some(0.2),
+        ..Default::default()
+    };
+    assert_eq!(
+        cfg.save_profile("quick", &draft, false).unwrap(),
+        base.model_provider
+    );
+    let saved = &cfg.profiles["quick"];
+    assert_eq!(saved.model, "gpt-5-mini");
+    assert_eq!(saved.temperature, Some(0.2));
+    assert_eq!(saved.model_provider, base.model_provider);
+
+    let err = cfg.save_profile("quick", &draft, false).unwrap_err();
+    assert!(err.to_string().contains("--force"), "{err}");
+
+    let moved = qqqa::config::ProfileDraft {
+        base: Some("quick"),
+        api_base: Some("http://127.0.0.1:9999/v1"),
+        ..Default::default()
+    };
+    assert_eq!(cfg.save_profile("quick", &moved, true).unwrap(), "quick");
+    assert_eq!(cfg.profiles["quick"].model, "gpt-5-mini");
+    assert_eq!(
+        cfg.model_providers["quick"].base_url,
+        "http://127.0.0.1:9999/v1"
+    );
+}
+
+#[test]
+fn qq_save_profile_writes_config_and_exits() {
+    let home = tempdir().expect("temp dir");
+    let run = |extra: &[&str]| {
+        let mut cmd = cargo_bin_cmd!("qq");
+        cmd.args([
+            "--save-profile",
+            "precise",
+            "-m",
+            "gpt-5",
+            "--param",
+            "seed=7",
+        ])
+        .args(extra)
+        .env("HOME", home.path())
+        .output()
+        .expect("run qq")
+    };
+
+    let output = run(&[]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Saved profile 'precise'"));
+    let cfg = read_config_from_home(home.path());
+    assert_eq!(cfg.profiles["precise"].model, "gpt-5");
+    assert_eq!(
+        cfg.profiles["precise"].extra_body,
+        Some(serde_json::json!({"seed": 7}))
+    );
+
+    let again = run(&[]);
+    assert!(!again.status.success());
+    assert!(String::from_utf8_lossy(&again.stderr).contains("already exists"));
+    assert!(run(&["--force"]).status.success());
+}
+
+#[test]
+fn qq_stdin_only_takes_the_question_from_stdin_and_rejects_words() {
+    let home = tempdir().expect("temp dir");
+    let run = |extra: &[&str]| {
+        let mut cmd = cargo_bin_cmd!("qq");
+        cmd.args(["--no-history", "--dry-run", "--stdin-only"])
+            .args(extra)
+            .env("HOME", home.path())
+            .env("QQQA_API_KEY", "test-key")
+            .write_stdin("why is the sky blue\n")
+            .output()
+            .expect("run qq")
+    };
+
+    let output = run(&[]);
+    assert!(output.status.success(), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Question: why is the sky blue"), "{stderr}");
+    assert!(!stderr.contains("Input from pipe"), "{stderr}");
+
+    let output = run(&["oops"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--stdin-only"));
+}
+
+#[test]
+fn qq_exits_3_when_the_answer_is_only_a_refusal() {
+    let home = home_with(&Config::default());
+    let refusal = home.path().join("refusal.txt");
+    fs::write(
+        &refusal,
+        "<warn>I can only help with terminal and technical tasks, but here's some fun tech trivia...</warn><br/>\n<info>Unix time started on a Thursday.</info>\n",
+    )
+    .unwrap();
+    let batch = home.path().join("questions.txt");
+    fs::write(&batch, "what is love\n").unwrap();
+
+    let mut cmd = cargo_bin_cmd!("qq");
+    let output = cmd
+        .args(["--no-history", "--no-stream", "what is love"])
+        .env("HOME", home.path())
+        .env("QQQA_MOCK_RESPONSE_FILE", &refusal)
+        .output()
+        .expect("run qq");
+    assert_eq!(output.status.code(), Some(3), "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Unix time started"));
+
+    let mut cmd = cargo_bin_cmd!("qq");
+    let output = cmd
+        .args(["--no-history", "--json", "--batch"])
+        .arg(&batch)
+        .env("HOME", home.path())
+        .env("QQQA_MOCK_RESPONSE_FILE", &refusal)
+        .output()
+        .expect("run qq");
+    assert_eq!(output.status.code(), Some(3), "{output:?}");
+    let results: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(results[0]["refused"], true);
+}
+
+#[test]
+fn qqqa_model_env_overrides_the_profile_model_but_not_the_flag() {
+    let home = home_with(&Config {
+        default_profile: "ollama".into(),
+        ..Default::default()
+    });
+
+    let dry_run_model = |extra: &[&str]| {
+        let mut cmd = cargo_bin_cmd!("qq");
+        let output = cmd
+            .args(["--no-history", "--dry-run", "--json", "--debug"])
+            .args(extra)
+            .arg("hi")
+            .env("HOME", home.path())
+            .env("QQQA_MODEL", " qwen2.5-coder ")
+            .output()
+            .expect("run qq");
+        assert!(output.status.success(), "{output:?}");
+        let dump: serde_json::Value = serde_json::from_slice(&output.stdout).expect("json");
+        let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+        (dump["model"].as_str().unwrap().to_string(), stderr)
+    };
+
+    let (model, stderr) = dry_run_model(&[]);
+    assert_eq!(model, "qwen2.5-coder");
+    assert!(
+        stderr.contains("Using model 'qwen2.5-coder' from QQQA_MODEL"),
+        "{stderr}"
+    );
+    let (model, stderr) = dry_run_model(&["-m", "llama3.2"]);
+    assert_eq!(model, "llama3.2");
+    assert!(!stderr.contains("QQQA_MODEL"), "{stderr}");
+}
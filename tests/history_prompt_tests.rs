@@ -1,5 +1,10 @@
-use qqqa::history::read_recent_history;
-use qqqa::prompt::{build_qa_system_prompt, build_qa_user_message, build_qq_prompt};
+use qqqa::config::{Config, FormattingInstructions};
+use qqqa::history::{read_recent_history, read_recent_history_since};
+use qqqa::prompt::{
+    CONTEXT_TRUNCATED_MARKER, ContextOptions, build_output_summary_message, build_qa_system_prompt,
+    build_qa_user_message, build_qq_prompt, build_qq_system_prompt, build_qq_system_prompt_with,
+    build_qq_user_message, is_topic_refusal,
+};
 
 use serial_test::serial;
 
@@ -48,6 +53,76 @@ fn history_reader_prefers_existing_files_and_limits() {
     );
 }
 
+#[test]
+#[serial]
+fn fish_history_parses_when_blocks_and_unescapes_commands() {
+    let dir = tempfile::tempdir().unwrap();
+    unsafe {
+        std::env::set_var("HOME", dir.path());
+    }
+    let fish_dir = dir.path().join(".local/share/fish");
+    std::fs::create_dir_all(&fish_dir).unwrap();
+    std::fs::write(
+        fish_dir.join("fish_history"),
+        r#"- cmd: qq how do I list open ports
+  when: 1696971992
+- cmd: cd ~/src
+  when: 1696971993
+  paths:
+    - ~/src
+- cmd: qa write a loop\nfor f in *.log\n  echo $f\nend
+  when: 1696971994
+- cmd: qq what does C:\\tmp mean
+  when: 1696971995
+"#,
+    )
+    .unwrap();
+
+    let got = read_recent_history(10, false);
+    assert_eq!(
+        got,
+        vec![
+            "qq how do I list open ports",
+            "qa write a loop\nfor f in *.log\n  echo $f\nend",
+            "qq what does C:\\tmp mean",
+        ]
+    );
+
+    // `when:` lines make fish entries subject to --history-since.
+    let recent = read_recent_history_since(10, Some(std::time::Duration::from_secs(60)), false);
+    assert!(recent.is_empty(), "{recent:?}");
+}
+
+#[test]
+#[serial]
+fn history_since_drops_old_timestamped_entries() {
+    let dir = tempfile::tempdir().unwrap();
+    unsafe {
+        std::env::set_var("HOME", dir.path());
+    }
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    std::fs::write(
+        dir.path().join(".zsh_history"),
+        format!(
+            ": {}:0;qq from yesterday\n: {}:0;qa five minutes ago\nqq no timestamp\n: {}:0;qq just now\n",
+            now - 86_400,
+            now - 300,
+            now
+        ),
+    )
+    .unwrap();
+
+    let got = read_recent_history_since(10, Some(std::time::Duration::from_secs(600)), false);
+    assert_eq!(
+        got,
+        vec!["qa five minutes ago", "qq no timestamp", "qq just now"]
+    );
+    assert_eq!(read_recent_history(10, false).len(), 4);
+}
+
 #[test]
 #[serial]
 fn prompt_builders_include_sections() {
@@ -73,10 +148,99 @@ fn prompt_builders_include_sections() {
         &hist,
         stdin_block,
         "Do the thing",
+        &ContextOptions::default(),
     );
     assert!(user.contains("Question: Do the thing"));
 }
 
+#[test]
+fn max_context_bytes_keeps_most_recent_context() {
+    let hist = vec!["old-cmd".to_string(), "new-cmd".to_string()];
+    let stdin_block = "first line\nsecond line\nlast line\n";
+
+    let untouched = build_qq_user_message(
+        None,
+        None,
+        &hist,
+        Some(stdin_block),
+        "Q",
+        &ContextOptions::default(),
+    );
+    assert!(!untouched.contains(CONTEXT_TRUNCATED_MARKER));
+    assert!(untouched.contains("old-cmd"));
+
+    // Stdin fits; only the newest history entry fits alongside it.
+    let budget = ContextOptions {
+        max_context_bytes: Some(stdin_block.len() + "new-cmd".len() + 1),
+        ..Default::default()
+    };
+    let msg = build_qq_user_message(None, None, &hist, Some(stdin_block), "Q", &budget);
+    assert!(msg.contains(CONTEXT_TRUNCATED_MARKER));
+    assert!(msg.contains("1. new-cmd"));
+    assert!(!msg.contains("old-cmd"));
+    assert!(msg.contains("first line"));
+
+    // Oversized stdin keeps its tail and drops history entirely.
+    let budget = ContextOptions {
+        max_context_bytes: Some(15),
+        ..Default::default()
+    };
+    let user = build_qa_user_message(None, None, &hist, Some(stdin_block), "Task", &budget);
+    assert!(user.contains(CONTEXT_TRUNCATED_MARKER));
+    assert!(user.contains("last line"));
+    assert!(!user.contains("first line"));
+    assert!(!user.contains("Terminal History"));
+    assert!(user.contains("Question: Task"));
+}
+
+#[test]
+fn stdin_label_replaces_default_pipe_label() {
+    let block = Some("{\"a\": 1}");
+    let default = build_qq_user_message(None, None, &[], block, "Q", &ContextOptions::default());
+    assert!(default.contains("[Input from pipe:\n"));
+
+    let labeled = ContextOptions {
+        stdin_label: Some("JSON payload:"),
+        ..Default::default()
+    };
+    let msg = build_qq_user_message(None, None, &[], block, "Q", &labeled);
+    assert!(msg.contains("[JSON payload:\n{\"a\": 1}"));
+    assert!(!msg.contains("Input from pipe"));
+
+    let user = build_qa_user_message(None, None, &[], block, "Task", &labeled);
+    assert!(user.contains("JSON payload:\n"));
+}
+
+#[test]
+fn local_time_option_stamps_offset_instead_of_utc() {
+    fn stamp<'a>(msg: &'a str, prefix: &str) -> &'a str {
+        msg.lines()
+            .find_map(|line| line.strip_prefix(prefix))
+            .expect("timestamp line")
+    }
+    let utc = build_qq_user_message(None, None, &[], None, "Q", &ContextOptions::default());
+    assert!(stamp(&utc, "Current date/time: ").ends_with('Z'), "{utc}");
+
+    let local = ContextOptions {
+        local_time: true,
+        ..Default::default()
+    };
+    let msg = build_qq_user_message(None, None, &[], None, "Q", &local);
+    let ts = stamp(&msg, "Current date/time: ");
+    let offset = &ts[ts.len() - 6..];
+    assert!(
+        offset.starts_with(['+', '-']) && offset.as_bytes()[3] == b':',
+        "{ts}"
+    );
+    assert!(chrono::DateTime::parse_from_rfc3339(ts).is_ok(), "{ts}");
+
+    let user = build_qa_user_message(None, None, &[], None, "Task", &local);
+    assert!(
+        !stamp(&user, "Timestamp (local): ").ends_with('Z'),
+        "{user}"
+    );
+}
+
 #[test]
 fn coalesce_prompt_inputs_uses_piped_text_when_args_empty() {
     let prepared = qqqa::prompt::coalesce_prompt_inputs(
@@ -101,3 +265,119 @@ fn coalesce_prompt_inputs_uses_piped_text_when_args_empty() {
     assert!(prompt.contains("Question: Show me the full contents of this directory"));
     assert!(!prompt.contains("Input from pipe:"));
 }
+
+#[test]
+fn decode_stdin_lossy_replaces_invalid_utf8() {
+    let (text, lossy) = qqqa::prompt::decode_stdin_lossy(b"plain text\n".to_vec());
+    assert_eq!(text, "plain text\n");
+    assert!(!lossy);
+
+    let (text, lossy) = qqqa::prompt::decode_stdin_lossy(vec![b'o', b'k', 0xff, 0xfe, b'\n']);
+    assert!(lossy);
+    assert_eq!(text, "ok\u{FFFD}\u{FFFD}\n");
+}
+
+#[test]
+fn persona_directive_matches_case_insensitively_and_lists_names_on_error() {
+    let concise = qqqa::prompt::persona_directive("Concise").unwrap();
+    assert!(concise.contains("Persona: concise."));
+    assert!(
+        qqqa::prompt::persona_directive(" scripts-only ")
+            .unwrap()
+            .contains("<cmd></cmd>")
+    );
+    let err = qqqa::prompt::persona_directive("pirate").unwrap_err();
+    assert_eq!(
+        err,
+        "unknown persona 'pirate' (available: concise, verbose, scripts-only)"
+    );
+}
+
+#[test]
+fn topic_refusal_is_detected_only_without_a_real_answer() {
+    let refusal = "<warn>I can only help with terminal and technical tasks, but here's some fun tech trivia...</warn><br/>\n<info>The first computer bug was an actual moth.</info>\n";
+    assert!(is_topic_refusal(refusal));
+    assert!(is_topic_refusal(
+        "<warn>Sorry, I can only help with technical tasks.</warn>"
+    ));
+    assert!(!is_topic_refusal(&format!("{refusal}<cmd>ls -la</cmd>")));
+    assert!(!is_topic_refusal(
+        "<warn>This deletes files.</warn><cmd>rm -r build</cmd>"
+    ));
+    assert!(!is_topic_refusal("<cmd>ls</cmd>"));
+    assert!(!is_topic_refusal(""));
+}
+
+#[test]
+fn cwd_listing_adds_a_current_directory_section() {
+    let listing = "/work/app\n- src (dir)\n- Cargo.toml (file)\n";
+    let context = ContextOptions {
+        cwd_listing: Some(listing),
+        ..Default::default()
+    };
+    let msg = build_qq_user_message(None, None, &[], Some("piped"), "Q", &context);
+    assert!(
+        msg.contains("[Current directory:\n/work/app\n- src (dir)\n- Cargo.toml (file)\n]\n\n[Input from pipe:"),
+        "{msg}"
+    );
+    let user = build_qa_user_message(None, None, &[], None, "Task", &context);
+    assert!(user.contains("Current directory:\n/work/app\n"), "{user}");
+
+    let plain = build_qq_user_message(None, None, &[], None, "Q", &ContextOptions::default());
+    assert!(!plain.contains("Current directory"));
+}
+
+#[test]
+fn formatting_instructions_trim_the_tag_guide() {
+    let full = build_qq_system_prompt_with(FormattingInstructions::Full);
+    assert_eq!(full, build_qq_system_prompt());
+    assert!(full.contains("<bold>heading</bold>"));
+
+    let minimal = build_qq_system_prompt_with(FormattingInstructions::Minimal);
+    assert!(minimal.contains("<cmd>ffmpeg -i input.mp4 output.mp3</cmd>"));
+    assert!(!minimal.contains("<bold>"));
+    assert!(!minimal.contains("<br/>"));
+    assert!(!minimal.contains("<warn>"));
+
+    let none = build_qq_system_prompt_with(FormattingInstructions::None);
+    assert!(none.contains("ffmpeg -i input.mp4 output.mp3"));
+    assert!(!none.contains('<'), "{none}");
+
+    let mut value = serde_json::to_value(Config::default()).unwrap();
+    value["formatting_instructions"] = "minimal".into();
+    let cfg: Config = serde_json::from_value(value.clone()).unwrap();
+    assert_eq!(cfg.formatting_instructions, FormattingInstructions::Minimal);
+    value["formatting_instructions"] = "some".into();
+    assert!(serde_json::from_value::<Config>(value).is_err());
+}
+
+#[test]
+fn omit_os_info_drops_os_and_timestamp_lines() {
+    let context = ContextOptions {
+        omit_os_info: true,
+        ..Default::default()
+    };
+    let msg = build_qq_user_message(None, Some("bash"), &[], None, "Q", &context);
+    assert_eq!(msg, "Default Shell: bash\n\nQuestion: Q");
+    let user = build_qa_user_message(None, None, &[], None, "Task", &context);
+    assert_eq!(user, "Question: Task");
+
+    let full = build_qq_user_message(None, None, &[], None, "Q", &ContextOptions::default());
+    assert!(full.contains("Operating System: "), "{full}");
+    assert!(full.contains("Current date/time: "), "{full}");
+}
+
+#[test]
+fn output_summary_message_cuts_long_output_from_the_middle() {
+    let short =
+        build_output_summary_message("list files", "Exit code: 0\n--- stdout ---\na\n", 100);
+    assert!(short.starts_with("Task: list files\n\nCommand output:\nExit code: 0\n"));
+    assert!(short.ends_with("a\n\nSummarize this output for the task above."));
+
+    let long = format!("HEAD{}TAIL", "x".repeat(1000));
+    let msg = build_output_summary_message("t", &long, 20);
+    assert!(
+        msg.contains("HEADxxxxxx\n[... 988 bytes of output omitted ...]\nxxxxxxTAIL"),
+        "{msg}"
+    );
+}
@@ -1,6 +1,8 @@
 use qqqa::perms::{
-    CommandDisposition, ensure_safe_command, ensure_safe_path, set_custom_allowlist,
+    CommandDisposition, ensure_safe_command, ensure_safe_path, extend_custom_allowlist,
+    set_allowed_exec_roots, set_custom_allowlist, set_dangerous_patterns, set_safe_pipeline_shapes,
 };
+use qqqa::tools::execute_command::sanitize_cwd_path;
 use serial_test::serial;
 use std::path::Path;
 
@@ -54,13 +56,13 @@ fn ensure_safe_command_blocks_connectors_and_inplace_edits() {
         std::env::remove_var("QQQA_ALLOW_UNSAFE_COMMANDS");
     }
     assert!(matches!(
-        ensure_safe_command("ls | grep foo").unwrap(),
+        ensure_safe_command("ls && grep foo Cargo.toml").unwrap(),
         CommandDisposition::NeedsConfirmation { .. }
     ));
     assert!(ensure_safe_command("sed -i '' 's/a/b/' file.txt").is_err());
     assert!(ensure_safe_command("find . -delete").is_err());
     assert!(matches!(
-        ensure_safe_command("rg foo | wc -l").unwrap(),
+        ensure_safe_command("rg foo | awk '{print $1}'").unwrap(),
         CommandDisposition::NeedsConfirmation { .. }
     ));
     assert!(matches!(
@@ -116,6 +118,24 @@ fn ensure_safe_command_respects_custom_allowlist() {
     set_custom_allowlist(Vec::new());
 }
 
+#[test]
+#[serial]
+fn extend_custom_allowlist_keeps_existing_entries() {
+    unsafe {
+        std::env::remove_var("QQQA_ALLOW_UNSAFE_COMMANDS");
+    }
+    set_custom_allowlist(vec!["ffmpeg".to_string()]);
+    extend_custom_allowlist(["jq".to_string()]);
+    for cmd in ["ffmpeg -version", "jq .name package.json"] {
+        assert!(matches!(
+            ensure_safe_command(cmd).unwrap(),
+            CommandDisposition::Allowed
+        ));
+    }
+    set_custom_allowlist(Vec::new());
+    assert!(ensure_safe_command("jq .name package.json").is_err());
+}
+
 #[test]
 #[serial]
 fn ensure_safe_path_allows_workspace_children() {
@@ -166,3 +186,151 @@ fn ensure_safe_path_blocks_symlink_escape() {
     let err = ensure_safe_path(Path::new("etc_link/qqqa-test.conf"));
     assert!(err.is_err(), "expected symlink escape to be blocked");
 }
+
+#[test]
+#[serial]
+fn allowed_exec_roots_extend_path_and_cwd_sandbox() {
+    let temp = tempfile::tempdir().unwrap();
+    let outside = tempfile::tempdir().unwrap();
+    unsafe {
+        std::env::set_var("HOME", temp.path());
+    }
+    let workspace = temp.path().join("workspace");
+    std::fs::create_dir_all(&workspace).unwrap();
+    std::env::set_current_dir(&workspace).unwrap();
+    let base = workspace.canonicalize().unwrap();
+    let sibling = outside.path().canonicalize().unwrap();
+    let target = sibling.join("notes.txt");
+
+    set_allowed_exec_roots(Vec::new());
+    assert!(ensure_safe_path(&target).is_err());
+    let (cwd, fell_back) = sanitize_cwd_path(Some(sibling.to_string_lossy().as_ref()), &base);
+    assert_eq!(cwd, base);
+    assert!(fell_back);
+
+    set_allowed_exec_roots(vec![sibling.clone()]);
+    assert!(ensure_safe_path(&target).is_ok());
+    let (cwd, fell_back) = sanitize_cwd_path(Some(sibling.to_string_lossy().as_ref()), &base);
+    assert_eq!(cwd, sibling);
+    assert!(!fell_back);
+
+    set_allowed_exec_roots(Vec::new());
+}
+
+#[test]
+#[serial]
+fn ensure_safe_command_skips_confirmation_for_read_only_pipelines() {
+    set_custom_allowlist(Vec::new());
+    set_safe_pipeline_shapes(Vec::new());
+    unsafe {
+        std::env::remove_var("QQQA_ALLOW_UNSAFE_COMMANDS");
+    }
+    for cmd in [
+        "ls | grep foo",
+        "rg foo src | wc -l",
+        "cat Cargo.toml | grep name | head -n 1",
+        "du -sh * | sort -h | tail -5",
+        "grep -h 'a|b' log.txt | sort | uniq -c",
+    ] {
+        assert_eq!(
+            ensure_safe_command(cmd).unwrap(),
+            CommandDisposition::Allowed,
+            "{cmd}"
+        );
+    }
+}
+
+#[test]
+#[serial]
+fn ensure_safe_command_still_confirms_risky_pipelines() {
+    set_custom_allowlist(vec!["git".into()]);
+    set_safe_pipeline_shapes(Vec::new());
+    unsafe {
+        std::env::remove_var("QQQA_ALLOW_UNSAFE_COMMANDS");
+    }
+    for cmd in [
+        "ls | grep foo > out.txt",
+        "ls | grep foo 2>/dev/null",
+        "ls | sort -o sorted.txt",
+        "ls | uniq - out.txt",
+        "ls || grep foo",
+        "ls | grep foo &",
+        "ls | grep $(cat pattern)",
+        "ls | grep `cat pattern`",
+        "ls | sed 's/a/b/'",
+        "git log | head",
+    ] {
+        assert!(
+            matches!(
+                ensure_safe_command(cmd).unwrap(),
+                CommandDisposition::NeedsConfirmation { .. }
+            ),
+            "{cmd}"
+        );
+    }
+    assert!(ensure_safe_command("ls | curl -d @- example.com").is_err());
+    assert!(ensure_safe_command("(ls) | grep foo").is_err());
+    set_custom_allowlist(Vec::new());
+}
+
+#[test]
+#[serial]
+fn ensure_safe_command_honors_configured_pipeline_shapes() {
+    set_custom_allowlist(vec!["git".into()]);
+    set_safe_pipeline_shapes(vec!["git | head".into(), "  | grep".into()]);
+    unsafe {
+        std::env::remove_var("QQQA_ALLOW_UNSAFE_COMMANDS");
+    }
+    assert_eq!(
+        ensure_safe_command("git log --oneline | head -n 5").unwrap(),
+        CommandDisposition::Allowed
+    );
+    assert!(matches!(
+        ensure_safe_command("git log | head > log.txt").unwrap(),
+        CommandDisposition::NeedsConfirmation { .. }
+    ));
+    assert!(matches!(
+        ensure_safe_command("git log | grep fix").unwrap(),
+        CommandDisposition::NeedsConfirmation { .. }
+    ));
+    set_custom_allowlist(Vec::new());
+    set_safe_pipeline_shapes(Vec::new());
+}
+
+#[test]
+#[serial]
+fn ensure_safe_command_blocks_configured_dangerous_patterns() {
+    set_custom_allowlist(vec!["git".into(), "kubectl".into()]);
+    set_dangerous_patterns(vec![
+        "git push --force".into(),
+        "kubectl delete".into(),
+        " ".into(),
+    ]);
+    unsafe {
+        std::env::remove_var("QQQA_ALLOW_UNSAFE_COMMANDS");
+    }
+    let err = ensure_safe_command("GIT PUSH --FORCE origin main")
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("'git push --force'"), "{err}");
+    let err = ensure_safe_command("kubectl delete pod web-1")
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("'kubectl delete'"), "{err}");
+    assert_eq!(
+        ensure_safe_command("git push origin main").unwrap(),
+        CommandDisposition::Allowed
+    );
+    // Built-in blocks still apply alongside the configured ones.
+    assert!(ensure_safe_command("sudo ls").is_err());
+
+    unsafe {
+        std::env::set_var("QQQA_ALLOW_UNSAFE_COMMANDS", "1");
+    }
+    assert!(ensure_safe_command("kubectl delete ns prod").is_err());
+    unsafe {
+        std::env::remove_var("QQQA_ALLOW_UNSAFE_COMMANDS");
+    }
+    set_dangerous_patterns(Vec::new());
+    set_custom_allowlist(Vec::new());
+}
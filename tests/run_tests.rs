@@ -0,0 +1,800 @@
+use httpmock::Method::POST;
+use httpmock::MockServer;
+use qqqa::config::Config;
+use qqqa::run::{QaHooks, run_qa_with_hooks};
+use qqqa::{QaOptions, QaOutcome, QqOptions, run_qa, run_qq};
+use serial_test::serial;
+use std::net::TcpListener;
+
+fn sandbox_blocks_binding() -> bool {
+    TcpListener::bind("127.0.0.1:0").is_err()
+}
+
+/// Default config with the local ollama profile pointed at the mock server.
+fn mock_config(base_url: String) -> Config {
+    let mut cfg = Config {
+        default_profile: "ollama".into(),
+        ..Default::default()
+    };
+    cfg.model_providers
+        .get_mut("ollama")
+        .expect("ollama provider")
+        .base_url = base_url;
+    cfg
+}
+
+#[tokio::test]
+#[serial]
+async fn run_qq_returns_answer_text() {
+    if sandbox_blocks_binding() {
+        eprintln!("[skip] sandbox blocks binding to 127.0.0.1; skipping httpmock test");
+        return;
+    }
+    let server = MockServer::start();
+    let mock = server.mock(|when, then| {
+        when.method(POST)
+            .path("/chat/completions")
+            .body_contains("what is 2+2");
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(r#"{"choices":[{"message":{"content":"4"}}]}"#);
+    });
+
+    let answer = run_qq(QqOptions {
+        question: "what is 2+2".into(),
+        history: Some(false),
+        config: Some(mock_config(server.base_url())),
+        ..Default::default()
+    })
+    .await
+    .expect("run_qq should succeed");
+    assert_eq!(answer, "4");
+    mock.assert();
+}
+
+#[tokio::test]
+#[serial]
+async fn run_qa_dispatches_tool_call() {
+    if sandbox_blocks_binding() {
+        eprintln!("[skip] sandbox blocks binding to 127.0.0.1; skipping httpmock test");
+        return;
+    }
+    let temp = tempfile::tempdir().unwrap();
+    unsafe {
+        std::env::set_var("HOME", temp.path());
+    }
+    let file = temp.path().join("notes.txt");
+    std::fs::write(&file, "remember the milk").unwrap();
+
+    let arguments = serde_json::json!({ "path": file.to_string_lossy() }).to_string();
+    let body = serde_json::json!({
+        "choices": [{
+            "message": {
+                "tool_calls": [{
+                    "type": "function",
+                    "function": { "name": "read_file", "arguments": arguments }
+                }]
+            }
+        }]
+    });
+    let server = MockServer::start();
+    let mock = server.mock(|when, then| {
+        when.method(POST).path("/chat/completions");
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(body.to_string());
+    });
+
+    let outcome = run_qa(QaOptions {
+        task: "read my notes".into(),
+        history: Some(false),
+        config: Some(mock_config(server.base_url())),
+        ..Default::default()
+    })
+    .await
+    .expect("run_qa should succeed");
+    assert_eq!(
+        outcome,
+        QaOutcome::ToolOutput {
+            tool: "read_file".into(),
+            output: "remember the milk".into(),
+        }
+    );
+    mock.assert();
+}
+
+#[tokio::test]
+#[serial]
+async fn run_qa_summarize_sends_command_output_back() {
+    if sandbox_blocks_binding() {
+        eprintln!("[skip] sandbox blocks binding to 127.0.0.1; skipping httpmock test");
+        return;
+    }
+    let temp = tempfile::tempdir().unwrap();
+    unsafe {
+        std::env::set_var("HOME", temp.path());
+    }
+    let arguments = serde_json::json!({ "command": "echo summarize-me" }).to_string();
+    let body = serde_json::json!({
+        "choices": [{
+            "message": {
+                "tool_calls": [{
+                    "type": "function",
+                    "function": { "name": "execute_command", "arguments": arguments }
+                }]
+            }
+        }]
+    });
+    let server = MockServer::start();
+    let tool_mock = server.mock(|when, then| {
+        when.method(POST)
+            .path("/chat/completions")
+            .body_contains("\"tools\"");
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(body.to_string());
+    });
+    let summary_mock = server.mock(|when, then| {
+        when.method(POST)
+            .path("/chat/completions")
+            .body_contains("Task: say hello")
+            .body_contains("summarize-me")
+            .body_contains("Summarize this output");
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(r#"{"choices":[{"message":{"content":"It printed a greeting.\n"}}]}"#);
+    });
+
+    let outcome = run_qa(QaOptions {
+        task: "say hello".into(),
+        history: Some(false),
+        yes: true,
+        summarize: true,
+        config: Some(mock_config(server.base_url())),
+        ..Default::default()
+    })
+    .await
+    .expect("run_qa should succeed");
+    match outcome {
+        QaOutcome::CommandSummary { output, summary } => {
+            assert!(output.contains("summarize-me"), "{output}");
+            assert_eq!(summary, "It printed a greeting.");
+        }
+        other => panic!("expected a command summary, got {:?}", other),
+    }
+    tool_mock.assert();
+    summary_mock.assert();
+}
+
+#[tokio::test]
+#[serial]
+async fn run_qa_rejects_tools_outside_filter() {
+    if sandbox_blocks_binding() {
+        return;
+    }
+    let temp = tempfile::tempdir().unwrap();
+    unsafe {
+        std::env::set_var("HOME", temp.path());
+    }
+    let file = temp.path().join("notes.txt");
+    std::fs::write(&file, "secret").unwrap();
+
+    let arguments = serde_json::json!({ "path": file.to_string_lossy() }).to_string();
+    let body = serde_json::json!({
+        "choices": [{
+            "message": {
+                "tool_calls": [{
+                    "type": "function",
+                    "function": { "name": "read_file", "arguments": arguments }
+                }]
+            }
+        }]
+    });
+    let server = MockServer::start();
+    let mock = server.mock(|when, then| {
+        when.method(POST)
+            .path("/chat/completions")
+            .body_contains("write_file")
+            .body_contains("Only these tools are enabled for this run: write_file");
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(body.to_string());
+    });
+
+    let outcome = run_qa(QaOptions {
+        task: "read my notes".into(),
+        history: Some(false),
+        config: Some(mock_config(server.base_url())),
+        tools: Some(vec!["write_file".into()]),
+        ..Default::default()
+    })
+    .await
+    .expect("run_qa should succeed");
+    match outcome {
+        QaOutcome::ToolError { tool, error } => {
+            assert_eq!(tool, "read_file");
+            assert!(error.contains("not enabled"), "error: {}", error);
+        }
+        other => panic!("unexpected outcome: {:?}", other),
+    }
+    mock.assert();
+}
+
+/// A native call to the `json` wrapper tool with `arguments` as its payload.
+fn json_wrapper_call(arguments: serde_json::Value) -> String {
+    serde_json::json!({
+        "choices": [{
+            "message": {
+                "tool_calls": [{
+                    "type": "function",
+                    "function": { "name": "json", "arguments": arguments.to_string() }
+                }]
+            }
+        }]
+    })
+    .to_string()
+}
+
+/// Run `run_qa` from a temp HOME and cwd holding `hello.txt`, answering with `body`.
+async fn run_qa_in_temp_dir(body: String) -> QaOutcome {
+    let dir = tempfile::tempdir().unwrap();
+    unsafe {
+        std::env::set_var("HOME", dir.path());
+    }
+    let prev_cwd = std::env::current_dir().unwrap();
+    std::env::set_current_dir(dir.path()).unwrap();
+    std::fs::write(dir.path().join("hello.txt"), "hi there").unwrap();
+
+    let server = MockServer::start();
+    server.mock(|when, then| {
+        when.method(POST).path("/chat/completions");
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(body);
+    });
+    let outcome = run_qa(QaOptions {
+        task: "read hello".into(),
+        history: Some(false),
+        config: Some(mock_config(server.base_url())),
+        ..Default::default()
+    })
+    .await;
+    std::env::set_current_dir(prev_cwd).unwrap();
+    outcome.expect("run_qa should succeed")
+}
+
+#[tokio::test]
+#[serial]
+async fn run_qa_dispatches_json_wrapper_call() {
+    if sandbox_blocks_binding() {
+        return;
+    }
+    let body = json_wrapper_call(serde_json::json!({
+        "tool": "read_file",
+        "arguments": { "path": "hello.txt" }
+    }));
+    assert_eq!(
+        run_qa_in_temp_dir(body).await,
+        QaOutcome::ToolOutput {
+            tool: "read_file".into(),
+            output: "hi there".into(),
+        },
+        "json wrapper should dispatch an inner tool"
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn run_qa_dispatches_nested_json_wrapper_call() {
+    if sandbox_blocks_binding() {
+        return;
+    }
+    let body = json_wrapper_call(serde_json::json!({
+        "tool": "json",
+        "arguments": { "tool": "read_file", "arguments": { "path": "hello.txt" } }
+    }));
+    assert_eq!(
+        run_qa_in_temp_dir(body).await,
+        QaOutcome::ToolOutput {
+            tool: "read_file".into(),
+            output: "hi there".into(),
+        },
+        "nested json wrapper should dispatch an inner tool"
+    );
+}
+
+/// Records what [`run_qa_with_hooks`] reports.
+#[derive(Default)]
+struct RecordingHooks {
+    events: Vec<String>,
+}
+
+impl QaHooks for RecordingHooks {
+    fn notice(&mut self, message: &str) {
+        self.events.push(format!("notice: {}", message));
+    }
+
+    fn tool_requested(&mut self, name: &str, preamble: Option<&str>, reason: Option<&str>) {
+        self.events
+            .push(format!("requested {} {:?} {:?}", name, preamble, reason));
+    }
+
+    fn tool_output(&mut self, tool: &str, output: &str, streamed_live: bool) {
+        self.events
+            .push(format!("output {} {:?} {}", tool, output, streamed_live));
+    }
+
+    fn tool_error(&mut self, tool: &str, error: &str) {
+        self.events.push(format!("error {} {}", tool, error));
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn run_qa_with_hooks_reports_the_reason_and_tool_output() {
+    if sandbox_blocks_binding() {
+        return;
+    }
+    let temp = tempfile::tempdir().unwrap();
+    unsafe {
+        std::env::set_var("HOME", temp.path());
+    }
+    let file = temp.path().join("notes.txt");
+    std::fs::write(&file, "remember the milk").unwrap();
+
+    let arguments =
+        serde_json::json!({ "path": file.to_string_lossy(), "reason": "Notes live here" })
+            .to_string();
+    let body = serde_json::json!({
+        "choices": [{
+            "message": {
+                "content": "Reading your notes.",
+                "tool_calls": [{
+                    "type": "function",
+                    "function": { "name": "read_file", "arguments": arguments }
+                }]
+            }
+        }]
+    });
+    let server = MockServer::start();
+    let mock = server.mock(|when, then| {
+        when.method(POST)
+            .path("/chat/completions")
+            .body_contains("reason");
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(body.to_string());
+    });
+
+    let mut hooks = RecordingHooks::default();
+    let outcome = run_qa_with_hooks(
+        QaOptions {
+            task: "read my notes".into(),
+            history: Some(false),
+            explain: true,
+            config: Some(mock_config(server.base_url())),
+            ..Default::default()
+        },
+        &mut hooks,
+    )
+    .await
+    .expect("run_qa_with_hooks should succeed");
+    assert_eq!(
+        outcome,
+        QaOutcome::ToolOutput {
+            tool: "read_file".into(),
+            output: "remember the milk".into(),
+        }
+    );
+    assert_eq!(
+        hooks.events,
+        vec![
+            r#"requested read_file Some("Reading your notes.") Some("Notes live here")"#,
+            r#"output read_file "remember the milk" false"#,
+        ]
+    );
+    mock.assert();
+}
+
+#[tokio::test]
+#[serial]
+async fn run_qa_plan_returns_the_reply_without_running_a_tool() {
+    if sandbox_blocks_binding() {
+        return;
+    }
+    let plan = r#"[{"description":"Read it","tool":"read_file","arguments":{"path":"a"}}]"#;
+    let server = MockServer::start();
+    let mock = server.mock(|when, then| {
+        when.method(POST).path("/chat/completions");
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(
+                serde_json::json!({ "choices": [{ "message": { "content": plan } }] }).to_string(),
+            );
+    });
+
+    let mut hooks = RecordingHooks::default();
+    let outcome = run_qa_with_hooks(
+        QaOptions {
+            task: "read a".into(),
+            history: Some(false),
+            plan: true,
+            config: Some(mock_config(server.base_url())),
+            ..Default::default()
+        },
+        &mut hooks,
+    )
+    .await
+    .expect("run_qa_with_hooks should succeed");
+    assert_eq!(outcome, QaOutcome::Plan(Some(plan.into())));
+    assert!(hooks.events.is_empty(), "{:?}", hooks.events);
+    mock.assert();
+}
+
+/// One read_file tool call, optionally with a `usage` block.
+fn read_file_tool_call(usage: Option<serde_json::Value>) -> String {
+    let mut body = serde_json::json!({
+        "choices": [{
+            "message": {
+                "tool_calls": [{
+                    "type": "function",
+                    "function": {
+                        "name": "read_file",
+                        "arguments": r#"{"path":"notes.txt"}"#
+                    }
+                }]
+            }
+        }]
+    });
+    if let Some(usage) = usage {
+        body["usage"] = usage;
+    }
+    body.to_string()
+}
+
+#[tokio::test]
+#[serial]
+async fn run_qa_stops_at_max_tokens_total() {
+    if sandbox_blocks_binding() {
+        eprintln!("[skip] sandbox blocks binding to 127.0.0.1; skipping httpmock test");
+        return;
+    }
+    let server = MockServer::start();
+    let body = read_file_tool_call(Some(serde_json::json!({ "total_tokens": 500 })));
+    let mock = server.mock(|when, then| {
+        when.method(POST).path("/chat/completions");
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(body);
+    });
+
+    let outcome = run_qa(QaOptions {
+        task: "read my notes".into(),
+        history: Some(false),
+        max_tokens_total: Some(100),
+        config: Some(mock_config(server.base_url())),
+        ..Default::default()
+    })
+    .await
+    .expect("run_qa should succeed");
+    assert_eq!(
+        outcome,
+        QaOutcome::BudgetExceeded {
+            reason: "qa stopped before the tool call: --max-tokens-total 100 exceeded \
+                     (used 1 request, 500 tokens, 0 tool calls)."
+                .into(),
+            output: None,
+        }
+    );
+    mock.assert();
+}
+
+#[tokio::test]
+#[serial]
+async fn run_qa_max_steps_applies_without_usage() {
+    if sandbox_blocks_binding() {
+        eprintln!("[skip] sandbox blocks binding to 127.0.0.1; skipping httpmock test");
+        return;
+    }
+    let server = MockServer::start();
+    let body = read_file_tool_call(None);
+    server.mock(|when, then| {
+        when.method(POST).path("/chat/completions");
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(body);
+    });
+
+    // No usage in the response: the token limit cannot trip, the step limit still does.
+    let outcome = run_qa(QaOptions {
+        task: "read my notes".into(),
+        history: Some(false),
+        max_steps: Some(0),
+        max_tokens_total: Some(1),
+        config: Some(mock_config(server.base_url())),
+        ..Default::default()
+    })
+    .await
+    .expect("run_qa should succeed");
+    match outcome {
+        QaOutcome::BudgetExceeded { reason, output } => {
+            assert!(reason.contains("--max-steps 0 reached"), "{reason}");
+            assert!(reason.contains("tokens not reported"), "{reason}");
+            assert_eq!(output, None);
+        }
+        other => panic!("expected the step limit to stop the run, got {:?}", other),
+    }
+}
+
+#[test]
+#[serial]
+fn api_key_override_beats_env_and_config() {
+    use qqqa::run::{API_KEY_ENV, ProfileOverrides, resolve_run_profile};
+    let mut cfg = Config::default();
+    let provider = cfg.model_providers.get_mut("openai").unwrap();
+    provider.api_key = None;
+    let env_key = provider.env_key.clone();
+    unsafe {
+        std::env::remove_var(&env_key);
+        std::env::remove_var(API_KEY_ENV);
+    }
+    let overrides = ProfileOverrides {
+        profile: Some("openai"),
+        ..Default::default()
+    };
+    assert!(resolve_run_profile(&cfg, None, &overrides).is_err());
+
+    unsafe {
+        std::env::set_var(API_KEY_ENV, "from-generic-env");
+    }
+    let eff = resolve_run_profile(&cfg, None, &overrides).expect("generic env key");
+    assert_eq!(eff.http().unwrap().api_key, "from-generic-env");
+
+    let eff = resolve_run_profile(
+        &cfg,
+        None,
+        &ProfileOverrides {
+            api_key: Some("from-flag"),
+            ..overrides
+        },
+    )
+    .expect("flag key");
+    assert_eq!(eff.http().unwrap().api_key, "from-flag");
+    unsafe {
+        std::env::remove_var(API_KEY_ENV);
+    }
+}
+
+#[test]
+#[serial]
+fn header_overrides_merge_case_insensitively_and_validate() {
+    use qqqa::run::{ProfileOverrides, parse_header_flag, resolve_run_profile};
+    let mut cfg = Config::default();
+    cfg.model_providers.get_mut("openrouter").unwrap().api_key = Some("sk-test".into());
+    let headers = vec![
+        "x-title: debug-run".to_string(),
+        "X-Trace-Id: abc123".to_string(),
+    ];
+    let eff = resolve_run_profile(
+        &cfg,
+        None,
+        &ProfileOverrides {
+            profile: Some("openrouter"),
+            headers: &headers,
+            ..Default::default()
+        },
+    )
+    .expect("headers apply");
+    let http = eff.http().unwrap();
+    assert_eq!(
+        http.headers.get("x-title").map(String::as_str),
+        Some("debug-run")
+    );
+    assert!(!http.headers.contains_key("X-Title"));
+    assert_eq!(
+        http.headers.get("X-Trace-Id").map(String::as_str),
+        Some("abc123")
+    );
+    assert!(http.headers.contains_key("HTTP-Referer"));
+
+    assert_eq!(
+        parse_header_flag("Accept:  application/json ").unwrap(),
+        ("Accept".to_string(), "application/json".to_string())
+    );
+    assert!(parse_header_flag("no colon here").is_err());
+    assert!(parse_header_flag("Bad Name: value").is_err());
+    assert!(parse_header_flag("X-Ok: line\nbreak").is_err());
+
+    let err = resolve_run_profile(
+        &cfg,
+        None,
+        &ProfileOverrides {
+            profile: Some("codex"),
+            headers: &headers,
+            ..Default::default()
+        },
+    )
+    .unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("--header is only supported for HTTP providers")
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn authorization_header_override_replaces_bearer_key() {
+    if sandbox_blocks_binding() {
+        eprintln!("[skip] sandbox blocks binding to 127.0.0.1; skipping httpmock test");
+        return;
+    }
+    let server = MockServer::start();
+    let mock = server.mock(|when, then| {
+        when.method(POST)
+            .path("/chat/completions")
+            .header("authorization", "Basic Z2F0ZXdheQ==")
+            .header("x-trace-id", "t-1");
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(r#"{"choices":[{"message":{"content":"via gateway"}}]}"#);
+    });
+
+    let answer = run_qq(QqOptions {
+        question: "hi".into(),
+        history: Some(false),
+        headers: vec![
+            "Authorization: Basic Z2F0ZXdheQ==".into(),
+            "X-Trace-Id: t-1".into(),
+        ],
+        config: Some(mock_config(server.base_url())),
+        ..Default::default()
+    })
+    .await
+    .expect("run_qq with headers");
+    assert_eq!(answer, "via gateway");
+    mock.assert();
+}
+
+#[test]
+#[serial]
+fn connect_timeout_flag_overrides_provider_network_setting() {
+    use qqqa::run::{ProfileOverrides, resolve_run_profile};
+    let mut cfg = Config::default();
+    let provider = cfg.model_providers.get_mut("openrouter").unwrap();
+    provider.api_key = Some("sk-test".into());
+    provider.network = Some(serde_json::from_str(r#"{"connect_timeout_secs": 30}"#).unwrap());
+    let overrides = ProfileOverrides {
+        profile: Some("openrouter"),
+        ..Default::default()
+    };
+    let eff = resolve_run_profile(&cfg, None, &overrides).expect("config value");
+    assert_eq!(eff.http().unwrap().network.connect_timeout_secs, Some(30));
+
+    let eff = resolve_run_profile(
+        &cfg,
+        None,
+        &ProfileOverrides {
+            connect_timeout_secs: Some(2),
+            ..overrides
+        },
+    )
+    .expect("flag value");
+    assert_eq!(eff.http().unwrap().network.connect_timeout_secs, Some(2));
+
+    let err = resolve_run_profile(
+        &cfg,
+        None,
+        &ProfileOverrides {
+            profile: Some("codex"),
+            connect_timeout_secs: Some(2),
+            ..Default::default()
+        },
+    )
+    .unwrap_err();
+    assert!(err.to_string().contains("--connect-timeout"), "{err}");
+}
+
+#[test]
+fn param_flags_parse_json_values_and_guard_core_fields() {
+    use qqqa::run::parse_param_flag;
+    use serde_json::json;
+    assert_eq!(
+        parse_param_flag("top_p=0.9").unwrap(),
+        ("top_p".to_string(), json!(0.9))
+    );
+    assert_eq!(
+        parse_param_flag("logprobs=true").unwrap(),
+        ("logprobs".to_string(), json!(true))
+    );
+    assert_eq!(
+        parse_param_flag("user=bob smith").unwrap(),
+        ("user".to_string(), json!("bob smith"))
+    );
+    assert_eq!(
+        parse_param_flag("stop=[\"\\n\"]").unwrap(),
+        ("stop".to_string(), json!(["\n"]))
+    );
+    assert!(parse_param_flag("no-equals").is_err());
+    assert!(parse_param_flag("=1").is_err());
+    let err = parse_param_flag("model=gpt-x").unwrap_err();
+    assert!(err.to_string().contains("cannot override 'model'"), "{err}");
+    assert!(parse_param_flag("messages=[]").is_err());
+}
+
+#[tokio::test]
+#[serial]
+async fn param_flags_reach_the_request_body() {
+    if sandbox_blocks_binding() {
+        eprintln!("[skip] sandbox blocks binding to 127.0.0.1; skipping httpmock test");
+        return;
+    }
+    let server = MockServer::start();
+    let mock = server.mock(|when, then| {
+        when.method(POST)
+            .path("/chat/completions")
+            .body_contains("\"top_p\":0.9")
+            .body_contains("\"logprobs\":true")
+            .body_contains("\"user\":\"bob\"")
+            .body_contains("\"max_tokens\":7");
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(r#"{"choices":[{"message":{"content":"with params"}}]}"#);
+    });
+
+    let answer = run_qq(QqOptions {
+        question: "hi".into(),
+        history: Some(false),
+        params: vec![
+            "top_p=0.9".into(),
+            "logprobs=true".into(),
+            "user=bob".into(),
+            "max_tokens=7".into(),
+        ],
+        config: Some(mock_config(server.base_url())),
+        ..Default::default()
+    })
+    .await
+    .expect("run_qq with params");
+    assert_eq!(answer, "with params");
+    mock.assert();
+}
+
+#[test]
+fn shared_setup_helpers_apply_config_fallbacks() {
+    use qqqa::run::{ContextFlags, command_timeout, context_options, prompt_history};
+    use std::time::Duration;
+
+    let mut cfg = Config {
+        command_timeout_secs: Some(30),
+        max_context_bytes: Some(4096),
+        prompt_local_time: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        command_timeout(None, &cfg).unwrap(),
+        Duration::from_secs(30)
+    );
+    assert_eq!(
+        command_timeout(Some(5), &cfg).unwrap(),
+        Duration::from_secs(5)
+    );
+    assert!(command_timeout(Some(0), &cfg).is_err());
+    cfg.command_timeout_secs = Some(0);
+    assert!(command_timeout(None, &cfg).is_err());
+
+    let context = context_options(
+        &cfg,
+        ContextFlags {
+            stdin_label: Some("JSON payload"),
+            no_os_line: true,
+            ..Default::default()
+        },
+        None,
+    );
+    assert_eq!(context.max_context_bytes, Some(4096));
+    assert_eq!(context.stdin_label, Some("JSON payload"));
+    assert!(context.local_time);
+    assert!(context.omit_os_info);
+
+    assert!(prompt_history(&cfg, Some(false), Some(10), false, false).is_empty());
+}
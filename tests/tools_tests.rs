@@ -1,11 +1,17 @@
-use qqqa::perms::{ensure_safe_path, set_custom_allowlist};
+use qqqa::perms::{
+    WRITE_FILE_MAX_BYTES, ensure_safe_path, set_custom_allowlist, set_write_file_max_bytes,
+};
 use qqqa::shell::ShellKind;
+use qqqa::tools::append_file;
+use qqqa::tools::execute_command::DEFAULT_COMMAND_TIMEOUT_SECS;
 use qqqa::tools::parse_tool_call;
 use qqqa::tools::read_file;
+use qqqa::tools::read_files;
 use qqqa::tools::write_file;
 use serial_test::serial;
 use std::path::{Path, PathBuf};
 use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::time::Duration;
 
 struct TempCwdGuard {
     previous: PathBuf,
@@ -95,10 +101,38 @@ fn read_and_write_file_tools_and_path_safety() {
     // read_file tool
     let content = read_file::run(read_file::Args {
         path: "dir/file.txt".into(),
+        with_metadata: false,
     })
     .unwrap();
     assert_eq!(content, "hello");
 
+    // read_file with metadata header
+    std::fs::write(temp.path().join("dir/crlf.txt"), "a\r\nb\r\n").unwrap();
+    let content = read_file::run(read_file::Args {
+        path: "dir/crlf.txt".into(),
+        with_metadata: true,
+    })
+    .unwrap();
+    assert!(content.contains("crlf.txt\nsize: 6 bytes\n"), "{}", content);
+    assert!(content.contains("\nmodified: "));
+    assert!(content.contains("\nencoding: utf-8\nline_endings: crlf\n"));
+    assert!(content.ends_with("---\na\r\nb\r\n"));
+
+    // binary files are refused with a hint instead of a UTF-8 error
+    std::fs::write(temp.path().join("dir/blob.bin"), b"\x00\x01\x02").unwrap();
+    let err = read_file::run(read_file::Args {
+        path: "dir/blob.bin".into(),
+        with_metadata: false,
+    })
+    .unwrap_err()
+    .to_string();
+    assert!(
+        err.starts_with("Refusing to read binary file: dir/blob.bin"),
+        "{}",
+        err
+    );
+    assert!(err.contains("hexdump"));
+
     // unsafe path outside HOME/CWD (unix only; skip on others)
     #[cfg(unix)]
     {
@@ -129,6 +163,88 @@ fn read_and_write_file_tools_and_path_safety() {
     }
 }
 
+#[test]
+#[serial]
+fn append_file_appends_and_reports_total_size() {
+    let temp = tempfile::tempdir().unwrap();
+    set_custom_allowlist(Vec::new());
+    unsafe {
+        std::env::set_var("HOME", temp.path());
+    }
+    let _cwd_guard = TempCwdGuard::new(temp.path());
+
+    let first = append_file::run(append_file::Args {
+        path: "logs/run.log".into(),
+        content: "one\n".into(),
+    })
+    .unwrap();
+    assert!(first.contains("Appended 4 bytes"), "{}", first);
+    assert!(first.contains("now 4 bytes"), "{}", first);
+
+    let second = append_file::run(append_file::Args {
+        path: "logs/run.log".into(),
+        content: "two\n".into(),
+    })
+    .unwrap();
+    assert!(second.contains("now 8 bytes"), "{}", second);
+    assert_eq!(
+        std::fs::read_to_string(temp.path().join("logs/run.log")).unwrap(),
+        "one\ntwo\n"
+    );
+
+    let traversal = append_file::run(append_file::Args {
+        path: "../../outside.log".into(),
+        content: "nope".into(),
+    });
+    assert!(
+        traversal.is_err(),
+        "expected traversal attempt to be blocked"
+    );
+
+    let call = parse_tool_call(r#"{"tool":"append_file","arguments":{"path":"a","content":"b"}}"#)
+        .expect("append_file should parse");
+    assert!(matches!(call, qqqa::tools::ToolCall::AppendFile(_)));
+}
+
+#[test]
+#[serial]
+fn read_files_concatenates_with_headers_and_skips_failures() {
+    let temp = tempfile::tempdir().unwrap();
+    set_custom_allowlist(Vec::new());
+    unsafe {
+        std::env::set_var("HOME", temp.path());
+    }
+    let _cwd_guard = TempCwdGuard::new(temp.path());
+    std::fs::write(temp.path().join("a.txt"), "alpha").unwrap();
+    std::fs::write(temp.path().join("b.txt"), "beta\n").unwrap();
+
+    let out = read_files::run(read_files::Args {
+        paths: vec![
+            "a.txt".into(),
+            "missing.txt".into(),
+            "../../outside.txt".into(),
+            "b.txt".into(),
+        ],
+    })
+    .unwrap();
+    assert!(
+        out.starts_with("=== a.txt ===\nalpha\n=== missing.txt ===\n[skipped: "),
+        "{}",
+        out
+    );
+    assert!(
+        out.contains("=== ../../outside.txt ===\n[skipped: "),
+        "{}",
+        out
+    );
+    assert!(out.ends_with("=== b.txt ===\nbeta\n"), "{}", out);
+
+    assert!(read_files::run(read_files::Args { paths: Vec::new() }).is_err());
+    let call = parse_tool_call(r#"{"tool":"read_files","arguments":{"paths":["a.txt"]}}"#)
+        .expect("read_files should parse");
+    assert!(matches!(call, qqqa::tools::ToolCall::ReadFiles(_)));
+}
+
 #[test]
 fn parse_tool_call_valid_and_invalid() {
     let json = r#"{"tool":"read_file","arguments":{"path":"a.txt"}}"#;
@@ -142,6 +258,117 @@ fn parse_tool_call_valid_and_invalid() {
     assert!(parse_tool_call(bad).is_err());
 }
 
+#[cfg(unix)]
+#[tokio::test]
+#[serial]
+async fn auto_confirm_safe_runs_allowed_commands_without_asking() {
+    use qqqa::tools::execute_command::{auto_confirm_safe, set_auto_confirm_safe};
+    let temp = tempfile::tempdir().unwrap();
+    unsafe {
+        std::env::set_var("HOME", temp.path());
+    }
+    let _cwd_guard = TempCwdGuard::new(temp.path());
+
+    set_auto_confirm_safe(true);
+    assert!(auto_confirm_safe());
+    // auto_yes is off, so only auto_confirm_safe lets this run without a prompt.
+    let res = qqqa::tools::execute_command::run(
+        qqqa::tools::execute_command::Args {
+            command: "echo safe123".into(),
+            cwd: None,
+        },
+        false,
+        false,
+        ShellKind::Posix,
+        Duration::from_secs(DEFAULT_COMMAND_TIMEOUT_SECS),
+        None,
+    )
+    .await;
+    set_auto_confirm_safe(false);
+    assert!(res.unwrap().contains("safe123"));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+#[serial]
+async fn allow_cwd_outside_home_skips_only_the_home_prompt() {
+    use qqqa::tools::execute_command::{set_allow_cwd_outside_home, set_auto_confirm_safe};
+    let home = tempfile::tempdir().unwrap();
+    let outside = tempfile::tempdir().unwrap();
+    unsafe {
+        std::env::set_var("HOME", home.path());
+    }
+    let _cwd_guard = TempCwdGuard::new(home.path());
+
+    // auto_confirm_safe covers "Execute?"; the outside-home prompt is left to the new setting.
+    set_auto_confirm_safe(true);
+    set_allow_cwd_outside_home(true);
+    let run = |command: &str| {
+        qqqa::tools::execute_command::run(
+            qqqa::tools::execute_command::Args {
+                command: command.into(),
+                cwd: Some(outside.path().to_string_lossy().into_owned()),
+            },
+            false,
+            false,
+            ShellKind::Posix,
+            Duration::from_secs(DEFAULT_COMMAND_TIMEOUT_SECS),
+            None,
+        )
+    };
+    let res = run("pwd").await;
+    let blocked = run("rm -rf /").await;
+    set_allow_cwd_outside_home(false);
+    set_auto_confirm_safe(false);
+    let dir_name = outside.path().file_name().unwrap().to_string_lossy();
+    assert!(
+        res.unwrap().contains(&*dir_name),
+        "command should run in the outside directory"
+    );
+    assert!(blocked.is_err(), "safety checks still apply");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+#[serial]
+async fn execute_command_reports_a_missing_working_directory() {
+    use qqqa::tools::execute_command::sanitize_execute_args;
+    let temp = tempfile::tempdir().unwrap();
+    unsafe {
+        std::env::set_var("HOME", temp.path());
+    }
+    let _cwd_guard = TempCwdGuard::new(temp.path());
+    let base = temp.path().canonicalize().unwrap();
+    std::fs::write(base.join("notes.txt"), "x").unwrap();
+
+    for (requested, expected) in [
+        ("build/out", "Working directory does not exist: "),
+        ("notes.txt", "Working directory is not a directory: "),
+    ] {
+        let args = sanitize_execute_args(
+            qqqa::tools::execute_command::Args {
+                command: "ls".into(),
+                cwd: Some(requested.into()),
+            },
+            &base,
+            false,
+        );
+        let err = qqqa::tools::execute_command::run(
+            args,
+            true,
+            false,
+            ShellKind::Posix,
+            Duration::from_secs(DEFAULT_COMMAND_TIMEOUT_SECS),
+            None,
+        )
+        .await
+        .unwrap_err()
+        .to_string();
+        assert!(err.starts_with(expected), "{}", err);
+        assert!(err.ends_with(requested), "{}", err);
+    }
+}
+
 #[cfg(unix)]
 #[tokio::test]
 #[serial]
@@ -160,6 +387,7 @@ async fn execute_command_runs_and_captures_output() {
         true,
         true,
         ShellKind::Posix,
+        Duration::from_secs(DEFAULT_COMMAND_TIMEOUT_SECS),
         None,
     )
     .await
@@ -189,6 +417,7 @@ async fn execute_command_honors_pty_force_flag() {
             true,
             false,
             ShellKind::Posix,
+            Duration::from_secs(DEFAULT_COMMAND_TIMEOUT_SECS),
             None,
         )
         .await
@@ -206,6 +435,7 @@ async fn execute_command_honors_pty_force_flag() {
             true,
             false,
             ShellKind::Posix,
+            Duration::from_secs(DEFAULT_COMMAND_TIMEOUT_SECS),
             None,
         )
         .await
@@ -219,6 +449,81 @@ async fn execute_command_honors_pty_force_flag() {
     );
 }
 
+#[cfg(unix)]
+#[tokio::test]
+#[serial]
+async fn pty_mode_takes_precedence_over_pty_env_vars() {
+    use qqqa::tools::execute_command::{PtyMode, set_pty_mode};
+    let temp = tempfile::tempdir().unwrap();
+    unsafe {
+        std::env::set_var("HOME", temp.path());
+    }
+    let _cwd_guard = TempCwdGuard::new(temp.path());
+
+    async fn probe() -> String {
+        qqqa::tools::execute_command::run(
+            qqqa::tools::execute_command::Args {
+                command: "env sh -lc '[ -t 1 ] && echo is-tty || echo no-tty'".into(),
+                cwd: None,
+            },
+            true,
+            false,
+            ShellKind::Posix,
+            Duration::from_secs(DEFAULT_COMMAND_TIMEOUT_SECS),
+            None,
+        )
+        .await
+        .expect("probe should run")
+    }
+
+    let disabled = {
+        let _force_guard = EnvVarGuard::set("QQQA_FORCE_PTY", Some("1"));
+        set_pty_mode(PtyMode::DisablePty);
+        probe().await
+    };
+    let forced = {
+        let _disable_guard = EnvVarGuard::set("QQQA_DISABLE_PTY", Some("1"));
+        set_pty_mode(PtyMode::ForcePty);
+        probe().await
+    };
+    set_pty_mode(PtyMode::Auto);
+
+    assert!(disabled.contains("no-tty"), "{}", disabled);
+    assert!(forced.contains("is-tty"), "{}", forced);
+    assert_eq!(PtyMode::from_flag(None), PtyMode::Auto);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+#[serial]
+async fn pty_size_and_term_come_from_env_overrides() {
+    let temp = tempfile::tempdir().unwrap();
+    unsafe {
+        std::env::set_var("HOME", temp.path());
+    }
+    let _cwd_guard = TempCwdGuard::new(temp.path());
+    let _force_guard = EnvVarGuard::set("QQQA_FORCE_PTY", Some("1"));
+    let _rows_guard = EnvVarGuard::set("QQQA_PTY_ROWS", Some("40"));
+    let _cols_guard = EnvVarGuard::set("QQQA_PTY_COLS", Some("132"));
+    let _term_guard = EnvVarGuard::set("TERM", None);
+
+    let out = qqqa::tools::execute_command::run(
+        qqqa::tools::execute_command::Args {
+            command: "env sh -c 'stty size && echo term=$TERM'".into(),
+            cwd: None,
+        },
+        true,
+        false,
+        ShellKind::Posix,
+        Duration::from_secs(DEFAULT_COMMAND_TIMEOUT_SECS),
+        None,
+    )
+    .await
+    .expect("execute_command in a PTY should succeed");
+    assert!(out.contains("40 132"), "{}", out);
+    assert!(out.contains("term=xterm-256color"), "{}", out);
+}
+
 #[cfg(unix)]
 #[tokio::test]
 #[serial]
@@ -240,6 +545,7 @@ async fn execute_command_respects_disable_flag() {
             true,
             false,
             ShellKind::Posix,
+            Duration::from_secs(DEFAULT_COMMAND_TIMEOUT_SECS),
             None,
         )
         .await
@@ -256,6 +562,7 @@ async fn execute_command_respects_disable_flag() {
             true,
             false,
             ShellKind::Posix,
+            Duration::from_secs(DEFAULT_COMMAND_TIMEOUT_SECS),
             None,
         )
         .await
@@ -294,6 +601,7 @@ async fn execute_command_streams_stdout_chunks() {
         true,
         false,
         ShellKind::Posix,
+        Duration::from_secs(DEFAULT_COMMAND_TIMEOUT_SECS),
         Some(&mut printer),
     )
     .await
@@ -304,3 +612,247 @@ async fn execute_command_streams_stdout_chunks() {
     assert!(seen.iter().any(|line| line.contains("first")));
     assert!(seen.iter().any(|line| line.contains("second")));
 }
+
+#[cfg(unix)]
+#[tokio::test]
+#[serial]
+async fn execute_command_honors_custom_timeout() {
+    let temp = tempfile::tempdir().unwrap();
+    unsafe {
+        std::env::set_var("HOME", temp.path());
+    }
+    let _cwd_guard = TempCwdGuard::new(temp.path());
+    let _disable_guard = EnvVarGuard::set("QQQA_DISABLE_PTY", Some("1"));
+
+    let err = qqqa::tools::execute_command::run(
+        qqqa::tools::execute_command::Args {
+            command: "env sh -lc 'sleep 5'".into(),
+            cwd: None,
+        },
+        true,
+        false,
+        ShellKind::Posix,
+        Duration::from_secs(1),
+        None,
+    )
+    .await
+    .expect_err("sleep should exceed the 1s timeout");
+    assert!(err.to_string().contains("timed out after 1s"), "{}", err);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+#[serial]
+async fn execute_command_uses_configured_posix_shell() {
+    use qqqa::tools::execute_command::{posix_shell, set_posix_shell};
+
+    if !Path::new("/bin/bash").exists() && !Path::new("/usr/bin/bash").exists() {
+        eprintln!("[skip] bash not installed");
+        return;
+    }
+    let temp = tempfile::tempdir().unwrap();
+    unsafe {
+        std::env::set_var("HOME", temp.path());
+    }
+    let _cwd_guard = TempCwdGuard::new(temp.path());
+    let _disable_guard = EnvVarGuard::set("QQQA_DISABLE_PTY", Some("1"));
+
+    set_posix_shell(Some("bash".into()));
+    let res = qqqa::tools::execute_command::run(
+        qqqa::tools::execute_command::Args {
+            command: "echo $0".into(),
+            cwd: None,
+        },
+        true,
+        false,
+        ShellKind::Posix,
+        Duration::from_secs(DEFAULT_COMMAND_TIMEOUT_SECS),
+        None,
+    )
+    .await;
+    set_posix_shell(None);
+    let res = res.expect("execute_command should run under bash");
+    assert!(res.contains("bash"), "expected $0 to be bash, got: {}", res);
+    assert_eq!(posix_shell(), "sh");
+}
+
+#[test]
+fn parse_tool_filter_validates_names() {
+    use qqqa::tools::parse_tool_filter;
+    assert_eq!(
+        parse_tool_filter("read_file, execute_command").unwrap(),
+        vec!["read_file".to_string(), "execute_command".to_string()]
+    );
+    let err = parse_tool_filter("read_file,list_directory").unwrap_err();
+    assert!(err.to_string().contains("list_directory"));
+    assert!(parse_tool_filter(" , ").is_err());
+}
+
+#[test]
+fn tool_enabled_respects_the_tools_filter() {
+    use qqqa::tools::tool_enabled;
+    let allowed = vec!["read_file".to_string()];
+    assert!(tool_enabled(None, "execute_command"));
+    assert!(tool_enabled(Some(&allowed), "read_file"));
+    assert!(!tool_enabled(Some(&allowed), "execute_command"));
+}
+
+#[test]
+fn tools_spec_filtered_keeps_named_tools_and_json_wrapper() {
+    use qqqa::tools::tools_spec_filtered;
+    let allowed = vec!["read_file".to_string()];
+    let spec = tools_spec_filtered(Some(&allowed));
+    let names: Vec<&str> = spec
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|t| t["function"]["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(names, vec!["read_file", "json"]);
+    let all = tools_spec_filtered(None);
+    assert_eq!(all.as_array().unwrap().len(), 6);
+}
+
+#[test]
+fn every_registered_tool_is_in_the_spec_and_parses_its_own_schema() {
+    use qqqa::tools::{TOOL_NAMES, parse_tool_call, resolve_function_call, tools_spec};
+    let spec = tools_spec();
+    for name in TOOL_NAMES {
+        let entry = spec
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|t| t["function"]["name"] == name)
+            .unwrap_or_else(|| panic!("{name} missing from tools_spec"));
+        // Fill every required property with a string (or a list of one), as the schema declares.
+        let params = &entry["function"]["parameters"];
+        let mut args = serde_json::Map::new();
+        for key in params["required"].as_array().unwrap() {
+            let key = key.as_str().unwrap();
+            let value = if params["properties"][key]["type"] == "array" {
+                serde_json::json!(["x"])
+            } else {
+                "x".into()
+            };
+            args.insert(key.into(), value);
+        }
+        let args = serde_json::Value::Object(args);
+        let envelope = serde_json::json!({"tool": name, "arguments": args}).to_string();
+        assert_eq!(parse_tool_call(&envelope).unwrap().name(), name);
+        let resolved = resolve_function_call(name, &args.to_string())
+            .unwrap()
+            .expect("known tool");
+        assert_eq!(resolved.name(), name);
+    }
+    assert!(parse_tool_call(r#"{"tool":"list_directory","arguments":{}}"#).is_err());
+    assert!(
+        resolve_function_call("list_directory", "{}")
+            .unwrap()
+            .is_none()
+    );
+}
+
+#[test]
+#[serial]
+fn write_file_refuses_content_over_the_size_cap() {
+    let temp = tempfile::tempdir().unwrap();
+    unsafe {
+        std::env::set_var("HOME", temp.path());
+    }
+    let _cwd_guard = TempCwdGuard::new(temp.path());
+
+    set_write_file_max_bytes(Some(8));
+    let err = write_file::run(write_file::Args {
+        path: "big.txt".into(),
+        content: "0123456789".into(),
+    })
+    .unwrap_err();
+    let too_large = err
+        .downcast_ref::<write_file::WriteTooLargeError>()
+        .expect("size error");
+    assert_eq!((too_large.size, too_large.limit), (10, 8));
+    assert!(err.to_string().contains("10 bytes"), "{err}");
+    assert!(!temp.path().join("big.txt").exists());
+
+    // A confirmed write bypasses the cap.
+    write_file::run_without_size_limit(write_file::Args {
+        path: "big.txt".into(),
+        content: "0123456789".into(),
+    })
+    .unwrap();
+    assert_eq!(
+        std::fs::read_to_string(temp.path().join("big.txt")).unwrap(),
+        "0123456789"
+    );
+
+    set_write_file_max_bytes(None);
+    assert_eq!(qqqa::perms::write_file_max_bytes(), WRITE_FILE_MAX_BYTES);
+    write_file::run(write_file::Args {
+        path: "small.txt".into(),
+        content: "0123456789".into(),
+    })
+    .unwrap();
+}
+
+#[test]
+fn tool_call_reason_reads_envelope_or_arguments() {
+    use qqqa::tools::tool_call_reason;
+
+    assert_eq!(
+        tool_call_reason(r#"{"tool":"read_file","arguments":{"path":"a"},"reason":" Look. "}"#)
+            .as_deref(),
+        Some("Look.")
+    );
+    assert_eq!(
+        tool_call_reason(r#"{"path":"a","reason":"Inside args"}"#).as_deref(),
+        Some("Inside args")
+    );
+    assert_eq!(
+        tool_call_reason(r#"{"tool":"json","arguments":{"reason":"Wrapped"}}"#).as_deref(),
+        Some("Wrapped")
+    );
+    assert!(tool_call_reason(r#"{"path":"a","reason":"  "}"#).is_none());
+    assert!(tool_call_reason("not json").is_none());
+}
+
+#[test]
+fn tools_spec_with_reason_adds_optional_reason_property() {
+    let spec = qqqa::tools::tools_spec_with_reason(qqqa::tools::tools_spec());
+    for entry in spec.as_array().unwrap() {
+        let name = entry["function"]["name"].as_str().unwrap();
+        let reason = entry.pointer("/function/parameters/properties/reason");
+        if name == "json" {
+            continue;
+        }
+        assert!(reason.is_some(), "{name} lacks reason");
+        let required = entry["function"]["parameters"]["required"]
+            .as_array()
+            .unwrap();
+        assert!(!required.iter().any(|r| r == "reason"));
+    }
+}
+
+#[test]
+fn parse_plan_accepts_arrays_fences_and_wrapped_objects() {
+    use qqqa::tools::parse_plan;
+
+    let steps = parse_plan(
+        "```json\n[{\"step\":\"Read the manifest\",\"tool\":\"read_file\",\"arguments\":{\"path\":\"Cargo.toml\"}},{\"step\":2,\"tool\":\"execute_command\",\"arguments\":{\"command\":\"cargo test\"}}]\n```",
+    )
+    .unwrap();
+    assert_eq!(steps.len(), 2);
+    assert_eq!(steps[0].description, "Read the manifest");
+    assert_eq!(steps[0].tool, "read_file");
+    assert_eq!(steps[0].arguments["path"], "Cargo.toml");
+    assert_eq!(steps[1].description, "");
+    assert_eq!(steps[1].arguments["command"], "cargo test");
+
+    let wrapped = parse_plan(r#"{"plan":[{"tool":"read_file"}]}"#).unwrap();
+    assert_eq!(wrapped[0].arguments, serde_json::json!({}));
+
+    let err = parse_plan(r#"[{"step":"no tool"}]"#)
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("step 1"), "{err}");
+    assert!(parse_plan("I would read the file first.").is_err());
+}